@@ -24,6 +24,8 @@ pub enum Atom {
 #[derive(Debug, Clone)]
 pub enum Expr {
     FunctionDefinition,
+    LambdaDefinition,
+    MacroDefinition,
     VariableDefinition,
     PersistantStateDefinition,
     Constant(Atom),
@@ -0,0 +1,214 @@
+//! Time-based automation of global parameters, like tempo changes or
+//! reverb fades. Ramps run on their own thread (or are stepped by the
+//! offline renderer), independent of any generator.
+
+use std::f32::consts::PI;
+use std::thread;
+use std::time::Duration;
+
+use ruffbox_synth::building_blocks::{SynthParameterLabel, SynthParameterValue};
+
+use crate::commands;
+use crate::parameter::DynVal;
+use crate::session::Session;
+
+const AUTOMATION_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RampTarget {
+    Bpm,
+    Tmod,
+    Ruffbox(SynthParameterLabel),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RampCurve {
+    Linear,
+    // constant ratio per time, sounds even for tempo and frequencies
+    Exponential,
+    // slow start and end
+    Sine,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RampLength {
+    Seconds(f64),
+    // beats at the tempo when the ramp starts
+    Beats(f64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ramp {
+    pub target: RampTarget,
+    pub from: f32,
+    pub to: f32,
+    pub length: RampLength,
+    pub curve: RampCurve,
+}
+
+impl RampCurve {
+    /// the value at position `t` (0 to 1) of the way from `from` to `to`
+    pub fn interpolate(&self, from: f32, to: f32, t: f32) -> f32 {
+        match self {
+            RampCurve::Exponential if from > 0.0 && to > 0.0 => from * (to / from).powf(t),
+            RampCurve::Sine => from + (to - from) * (0.5 - 0.5 * (PI * t).cos()),
+            // exponential ramps can't cross or touch zero
+            RampCurve::Linear | RampCurve::Exponential => from + (to - from) * t,
+        }
+    }
+}
+
+/// a ramp in progress, times in seconds (stream time)
+pub struct ActiveRamp {
+    ramp: Ramp,
+    start: f64,
+    length: f64,
+    // the tempo the running generators are at, as their durations
+    // are fixed, tempo ramps are applied as a time modifier
+    base_bpm: f32,
+}
+
+impl ActiveRamp {
+    /// the value to set at the given time, finished ramps end on their final value
+    fn value_at(&self, now: f64) -> f32 {
+        let t = if self.length > 0.0 {
+            ((now - self.start) / self.length).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let value = self
+            .ramp
+            .curve
+            .interpolate(self.ramp.from, self.ramp.to, t as f32);
+        match self.ramp.target {
+            RampTarget::Bpm => self.base_bpm / value.max(1.0),
+            _ => value,
+        }
+    }
+}
+
+impl RampTarget {
+    /// tempo and tmod ramps both set the global time modifier
+    fn conflicts_with(&self, other: &RampTarget) -> bool {
+        match (self, other) {
+            (RampTarget::Bpm | RampTarget::Tmod, RampTarget::Bpm | RampTarget::Tmod) => true,
+            _ => self == other,
+        }
+    }
+}
+
+fn apply<const BUFSIZE: usize, const NCHAN: usize>(
+    target: RampTarget,
+    value: f32,
+    session: &Session<BUFSIZE, NCHAN>,
+) {
+    match target {
+        RampTarget::Bpm | RampTarget::Tmod => {
+            commands::set_global_tmod(&session.globals, DynVal::with_value(value))
+        }
+        RampTarget::Ruffbox(label) => {
+            let value = SynthParameterValue::ScalarF32(value);
+            session.stems.set_master_parameter(label, value.clone());
+            session.ruffbox.set_master_parameter(label, value)
+        }
+    }
+}
+
+/// Start a ramp, replacing a running one with the same target.
+pub fn start_ramp<const BUFSIZE: usize, const NCHAN: usize>(
+    ramp: Ramp,
+    session: &Session<BUFSIZE, NCHAN>,
+) {
+    let default_duration = commands::get_default_duration(&session.globals);
+    let length = match ramp.length {
+        RampLength::Seconds(s) => s,
+        RampLength::Beats(b) => b * default_duration / 1000.0,
+    };
+
+    let active = ActiveRamp {
+        ramp,
+        start: session.ruffbox.get_now(),
+        length,
+        base_bpm: (60000.0 / default_duration) as f32,
+    };
+    apply(active.ramp.target, active.value_at(active.start), session);
+
+    let mut ramps = session.ramps.lock();
+    ramps.retain(|r| !r.ramp.target.conflicts_with(&active.ramp.target));
+    ramps.push(active);
+}
+
+pub fn stop_ramps<const BUFSIZE: usize, const NCHAN: usize>(session: &Session<BUFSIZE, NCHAN>) {
+    session.ramps.lock().clear();
+}
+
+/// Set the ramped parameters to their values at the given time,
+/// finished ramps end on their final value.
+pub fn run_ramps_until<const BUFSIZE: usize, const NCHAN: usize>(
+    session: &Session<BUFSIZE, NCHAN>,
+    now: f64,
+) {
+    let mut ramps = session.ramps.lock();
+    for r in ramps.iter() {
+        apply(r.ramp.target, r.value_at(now), session);
+    }
+    ramps.retain(|r| now < r.start + r.length);
+}
+
+/// the dedicated automation thread
+pub fn start_automation_thread<const BUFSIZE: usize, const NCHAN: usize>(
+    session: Session<BUFSIZE, NCHAN>,
+) -> thread::JoinHandle<()> {
+    thread::Builder::new()
+        .name("automation".to_string())
+        .spawn(move || loop {
+            run_ramps_until(&session, session.ruffbox.get_now());
+            thread::sleep(AUTOMATION_INTERVAL);
+        })
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ramp_curves() {
+        for curve in [RampCurve::Linear, RampCurve::Exponential, RampCurve::Sine] {
+            assert_eq!(curve.interpolate(120.0, 140.0, 0.0), 120.0);
+            assert!((curve.interpolate(120.0, 140.0, 1.0) - 140.0).abs() < 0.001);
+        }
+        assert_eq!(RampCurve::Linear.interpolate(0.0, 1.0, 0.25), 0.25);
+        assert!((RampCurve::Exponential.interpolate(100.0, 400.0, 0.5) - 200.0).abs() < 0.001);
+        // falls back to linear through zero
+        assert_eq!(RampCurve::Exponential.interpolate(-1.0, 1.0, 0.5), 0.0);
+    }
+
+    #[test]
+    fn test_bpm_ramp_tmod() {
+        // running generators at 120 bpm, ramped from 120 to 180 bpm
+        let ramp = ActiveRamp {
+            ramp: Ramp {
+                target: RampTarget::Bpm,
+                from: 120.0,
+                to: 180.0,
+                length: RampLength::Seconds(2.0),
+                curve: RampCurve::Linear,
+            },
+            start: 10.0,
+            length: 2.0,
+            base_bpm: 120.0,
+        };
+
+        assert_eq!(ramp.value_at(10.0), 1.0);
+        assert!((ramp.value_at(11.0) - 120.0 / 150.0).abs() < 0.0001);
+        assert!((ramp.value_at(12.0) - 120.0 / 180.0).abs() < 0.0001);
+        // stays on the final tempo
+        assert!((ramp.value_at(20.0) - 120.0 / 180.0).abs() < 0.0001);
+
+        assert!(RampTarget::Bpm.conflicts_with(&RampTarget::Tmod));
+        assert!(
+            !RampTarget::Bpm.conflicts_with(&RampTarget::Ruffbox(SynthParameterLabel::ReverbMix))
+        );
+    }
+}
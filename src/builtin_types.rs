@@ -1,8 +1,13 @@
+use crate::ast_types::Expr;
+use crate::automation::Ramp;
+use crate::eval::EvaluatedExpr;
 use crate::event::*;
 use crate::generator::{GenModFun, Generator};
 use crate::generator_processor::GeneratorProcessor;
 use crate::markov_sequence_generator::Rule;
+use crate::midi_file::ExportLength;
 use crate::parameter::*;
+use crate::score_export::ScoreFormat;
 
 use core::fmt;
 use dashmap::DashMap;
@@ -65,6 +70,18 @@ pub enum TypedEntity {
     GeneratorProcessorOrModifierList(Vec<GeneratorProcessorOrModifier>),
     GeneratorModifierList(Vec<GeneratorProcessorOrModifier>),
     LazyArithmetic(LazyArithmetic),
+    // first-class functions and code
+    Function(Lambda),
+    Quoted(Expr),
+}
+
+/// An anonymous function, along with the local
+/// variables visible where it was created.
+#[derive(Clone, Debug)]
+pub struct Lambda {
+    pub pos_args: Vec<String>,
+    pub body: Vec<Expr>,
+    pub captured: HashMap<String, EvaluatedExpr>,
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
@@ -74,6 +91,8 @@ pub enum VariableId {
     GlobalLatency,      // latency between language and dsp
     DefaultDuration,    // default duration for two subsequent events (200ms usuallyd)
     DefaultCycleDuration, // default duration for a cycle (800ms, or four times the default event duration)
+    GlobalQuantization,   // grid that contexts start on, in beats (0 is off)
+    SpeakerCount,         // number of speakers in the speaker layout (if there's one)
     Custom(String),
     Symbol(String),
 }
@@ -115,7 +134,10 @@ pub enum Command {
     Bpm(f32),             // set default tempo in bpm
     DefaultDuration(f32), // set default duration in milliseconds
     GlobRes(f32),         // global resources for lifemodel algorithm
+    Quantize(f32),        // start contexts on a grid of this many beats
     GlobalRuffboxParams(HashMap<ParameterAddress, ParameterValue>), // global ruffbox params
+    Ramp(Ramp),           // automate a global param over time
+    RampStop,             // stop all ramps
     LoadSampleAsWavematrix(String, String, String, (usize, usize), f32), // key, path, method, matrix size, start
     ImportSampleSet(SampleResource),
     LoadSample(String, Vec<String>, String, bool), // set (events), keyword, path, downmix_stereo
@@ -132,19 +154,42 @@ pub enum Command {
     ClearAllBuffers,                               // clear live & freeze buffers
     ExportDotStatic(String, Generator),            // filename, generator
     ExportDotRunning((String, BTreeSet<String>)),  // filename, generator id
-    Once(Vec<StaticEvent>, Vec<ControlEvent>),     // execute event(s) once
-    ConnectVisualizer(BTreeSet<String>),           // connect visualizer
-    StartRecording(Option<String>, bool),          // start recording, prefix, input
-    StopRecording,                                 // stop recording ...
+    // filename, running generator ids, static generators, length
+    ExportMidi(String, BTreeSet<String>, Vec<Generator>, ExportLength),
+    // filename, format, running generator ids, static generators, length
+    ExportScore(
+        String,
+        ScoreFormat,
+        BTreeSet<String>,
+        Vec<Generator>,
+        ExportLength,
+    ),
+    Once(Vec<StaticEvent>, Vec<ControlEvent>), // execute event(s) once
+    ConnectVisualizer(BTreeSet<String>),       // connect visualizer
+    StartRecording(Option<String>, bool, Vec<String>), // start recording, prefix, input, stems
+    StopRecording,                             // stop recording ...
     OscDefineClient(String, String),
     OscSendMessage(String, String, Vec<TypedEntity>),
     OscStartReceiver(String),
     MidiStartReceiver(usize),
+    MidiStartSender(usize),
+    MidiClockStart,
+    MidiClockStop,
+    MidiClockFollow(usize),
     MidiListPorts,
+    PeerSyncStart(u16), // base port
+    PeerSyncStop,
+    EvalServerStart(u16), // port
+    EvalServerStop,
+    Seed(Option<u64>), // none means unseeded
+    SaveSession(String),
+    LoadSession(String),
+    SaveGenerator(String, BTreeSet<String>, Option<Generator>), // filename, running generator ids, static generator
     Print(TypedEntity),
     Push(VariableId, TypedEntity),
     Insert(VariableId, VariableId, TypedEntity),
     LoadFile(String),
+    Import(String, Option<String>, bool), // file, namespace, reload
 }
 
 #[derive(Clone)]
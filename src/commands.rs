@@ -7,7 +7,7 @@ use std::{
     collections::{BTreeSet, HashMap, HashSet},
     fs,
     path::Path,
-    sync,
+    sync, thread,
 };
 
 // not sure why the author deprecated the sync version,
@@ -30,16 +30,24 @@ use crate::eval::{self};
 use crate::event::*;
 use crate::event_helpers::*;
 use crate::generator::*;
+use crate::generator_file;
+use crate::interpreter;
 use crate::load_audio_file;
+use crate::midi_file;
 use crate::osc_sender::OscSender;
 use crate::parameter::*;
 use crate::real_time_streaming;
+use crate::sample_analysis;
 use crate::sample_set::SampleAndWavematrixSet;
+use crate::score_export;
 use crate::session::*;
+use crate::session_snapshot::{GeneratorState, SessionSnapshot};
+use crate::stems::StemBuses;
 use chrono::Local;
 use directories_next::ProjectDirs;
 use std::io::{prelude::*, BufReader, Cursor};
 use std::sync::atomic::Ordering;
+use std::time::Instant;
 
 use std::io;
 
@@ -56,6 +64,7 @@ fn fetch_url(url: String, file_name: String) -> anyhow::Result<()> {
 pub fn fetch_sample_set<const BUFSIZE: usize, const NCHAN: usize>(
     function_map: &sync::Arc<FunctionMap>,
     ruffbox: &sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+    stems: &StemBuses<BUFSIZE, NCHAN>,
     sample_set: SampleAndWavematrixSet,
     base_dir: String,
     resource: SampleResource,
@@ -120,9 +129,9 @@ pub fn fetch_sample_set<const BUFSIZE: usize, const NCHAN: usize>(
 
             // load sample
             let mut cmp = file_name.components();
-            // only load wav or flac ...
+            // only load audio files ...
             // will fail if zip file isn't in the right format ...
-            let load_data = if (*file.name()).ends_with("flac") || (*file.name()).ends_with("wav") {
+            let load_data = if load_audio_file::is_audio_file(file.name()) {
                 let set: String = cmp
                     .next()
                     .unwrap()
@@ -148,6 +157,7 @@ pub fn fetch_sample_set<const BUFSIZE: usize, const NCHAN: usize>(
                 load_sample(
                     function_map,
                     ruffbox,
+                    stems,
                     sample_set.clone(),
                     set,
                     &mut keyword,
@@ -174,28 +184,36 @@ pub fn fetch_sample_set<const BUFSIZE: usize, const NCHAN: usize>(
 
 pub fn clear_freeze_buffer<const BUFSIZE: usize, const NCHAN: usize>(
     ruffbox: &sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+    sample_set: &SampleAndWavematrixSet,
     freezbuf: usize,
 ) {
     ruffbox.clear_freeze_buffer(freezbuf);
+    sample_set.live_buffers().clear_freeze_buffer(freezbuf);
 }
 
 pub fn clear_live_buffer<const BUFSIZE: usize, const NCHAN: usize>(
     ruffbox: &sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+    sample_set: &SampleAndWavematrixSet,
     livebuf: usize,
 ) {
     ruffbox.clear_live_buffer(livebuf);
+    sample_set.live_buffers().clear_live_buffer(livebuf);
 }
 
 pub fn clear_all_freeze_buffers<const BUFSIZE: usize, const NCHAN: usize>(
     ruffbox: &sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+    sample_set: &SampleAndWavematrixSet,
 ) {
     ruffbox.clear_all_freeze_buffers();
+    sample_set.live_buffers().clear_all_freeze_buffers();
 }
 
 pub fn clear_all_live_buffers<const BUFSIZE: usize, const NCHAN: usize>(
     ruffbox: &sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+    sample_set: &SampleAndWavematrixSet,
 ) {
     ruffbox.clear_all_live_buffers();
+    sample_set.live_buffers().clear_all_live_buffers();
 }
 
 pub fn clear_all_buffers<const BUFSIZE: usize, const NCHAN: usize>(
@@ -206,28 +224,36 @@ pub fn clear_all_buffers<const BUFSIZE: usize, const NCHAN: usize>(
 
 pub fn freeze_buffer<const BUFSIZE: usize, const NCHAN: usize>(
     ruffbox: &sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+    sample_set: &SampleAndWavematrixSet,
     freezbuf: usize,
     inbuf: usize,
 ) {
     ruffbox.freeze_buffer(freezbuf, inbuf);
+    sample_set.live_buffers().freeze(freezbuf, inbuf, false);
 }
 
 pub fn freeze_add_buffer<const BUFSIZE: usize, const NCHAN: usize>(
     ruffbox: &sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+    sample_set: &SampleAndWavematrixSet,
     freezbuf: usize,
     inbuf: usize,
 ) {
     ruffbox.freeze_add_buffer(freezbuf, inbuf);
+    sample_set.live_buffers().freeze(freezbuf, inbuf, true);
 }
 
 pub fn freeze_after_rec<const BUFSIZE: usize, const NCHAN: usize>(
     ruffbox: &sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+    sample_set: &SampleAndWavematrixSet,
     freezbuf: usize,
     inbuf: usize,
     time_secs: f64,
     add: bool,
 ) {
     ruffbox.freeze_after_rec(freezbuf, inbuf, time_secs, add);
+    sample_set
+        .live_buffers()
+        .freeze_after_rec(freezbuf, inbuf, time_secs, add);
 }
 
 pub fn load_sample_as_wavematrix(
@@ -283,41 +309,38 @@ pub fn load_sample_as_wavematrix(
     sample_set.insert_wavematrix(key, wavematrix);
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn load_sample<const BUFSIZE: usize, const NCHAN: usize>(
     function_map: &sync::Arc<FunctionMap>,
     ruffbox: &sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+    stems: &StemBuses<BUFSIZE, NCHAN>,
     mut sample_set: SampleAndWavematrixSet,
     set: String,
     keywords: &mut Vec<String>,
     path: String,
     downmix_stereo: bool,
 ) {
-    if let Some((mut duration, samplerate, channels, mut sample_buffer)) = if path
-        .as_str()
-        .to_lowercase()
-        .as_str()
-        .trim()
-        .ends_with(".flac")
+    if let Some((mut duration, orig_samplerate, channels, mut sample_buffer)) =
+        load_audio_file::load(&path)
     {
-        load_audio_file::load_flac(&path, ruffbox.samplerate)
-    } else if path
-        .as_str()
-        .to_lowercase()
-        .as_str()
-        .trim()
-        .ends_with(".wav")
-    {
-        load_audio_file::load_wav(&path, ruffbox.samplerate)
-    } else {
-        None
-    } {
-        // max duration ten seconds
+        // max duration ten seconds, cut before resampling, so
+        // that long files don't get resampled as a whole
         if duration > 10000 {
             duration = 10000;
+            sample_buffer.truncate(orig_samplerate as usize * 10 * channels as usize);
         }
 
+        // resample to the rate of the synth
+        let samplerate = ruffbox.samplerate;
+        let sample_buffer = load_audio_file::resample(
+            sample_buffer,
+            channels as usize,
+            orig_samplerate,
+            samplerate,
+        );
+
         // downmix
-        let bufnum = if channels != 1 {
+        let (mono_or_left, right) = if channels != 1 {
             if channels == 2 && !downmix_stereo {
                 // load stereo sample
                 let mut left = Vec::new();
@@ -327,18 +350,49 @@ pub fn load_sample<const BUFSIZE: usize, const NCHAN: usize>(
                     left.push(*l);
                     right.push(*r);
                 }
-                ruffbox.load_stereo_sample(&mut left, &mut right, true, samplerate)
+                (left, Some(right))
             } else {
                 // downmix to mono (default case)
-                let mut downmix_buffer = sample_buffer
+                let downmix_buffer = sample_buffer
                     .chunks(channels.try_into().unwrap())
                     .map(|x| x.iter().sum::<f32>() / channels as f32)
                     .collect();
-                ruffbox.load_mono_sample(&mut downmix_buffer, true, samplerate)
+                (downmix_buffer, None)
             }
         } else {
             // load mono as-is
-            ruffbox.load_mono_sample(&mut sample_buffer, true, samplerate)
+            (sample_buffer, None)
+        };
+
+        let load_into = |ruffbox: &RuffboxControls<BUFSIZE, NCHAN>| {
+            let mut mono_or_left = mono_or_left.clone();
+            if let Some(mut right) = right.clone() {
+                ruffbox.load_stereo_sample(&mut mono_or_left, &mut right, true, samplerate)
+            } else {
+                ruffbox.load_mono_sample(&mut mono_or_left, true, samplerate)
+            }
+        };
+
+        // the stem buses get a copy of the sample, in the same buffer
+        let _loading = stems.loading();
+        let bufnum = load_into(ruffbox);
+        for (idx, bus) in stems.buses.iter().enumerate() {
+            if load_into(&bus.ruffbox) != bufnum {
+                println!("sample buffers of stem bus {idx} and main instance don't match, disabling the bus");
+                stems.disable(idx);
+            }
+        }
+
+        // analyze the (downmixed) sample, to choose samples by sound
+        let descriptors = if let Some(right) = right.as_ref() {
+            let mono: Vec<f32> = mono_or_left
+                .iter()
+                .zip(right.iter())
+                .map(|(l, r)| (l + r) * 0.5)
+                .collect();
+            sample_analysis::analyze(&mono, samplerate)
+        } else {
+            sample_analysis::analyze(&mono_or_left, samplerate)
         };
 
         let mut keyword_set = HashSet::new();
@@ -361,12 +415,28 @@ pub fn load_sample<const BUFSIZE: usize, const NCHAN: usize>(
             path,
             channels,
             duration,
-            samplerate,
+            orig_samplerate,
             ruffbox.samplerate,
-            samplerate != ruffbox.samplerate
+            orig_samplerate != ruffbox.samplerate
+        );
+        println!(
+            "  rms: {:.1}dB centroid: {:.0}Hz pitch: {} onsets: {}",
+            descriptors.rms,
+            descriptors.centroid,
+            descriptors
+                .pitch
+                .map(|p| format!("{p:.1}Hz"))
+                .unwrap_or("-".to_string()),
+            descriptors.onsets.len()
         );
 
-        sample_set.insert(set.clone(), keyword_set, bufnum, duration);
+        sample_set.insert(
+            set.clone(),
+            keyword_set,
+            bufnum,
+            duration,
+            Some(descriptors),
+        );
         function_map
             .std_lib // add sample functions to std lib for now ...
             .insert(set, eval::events::sound::sound);
@@ -378,6 +448,7 @@ pub fn load_sample<const BUFSIZE: usize, const NCHAN: usize>(
 pub fn load_sample_set<const BUFSIZE: usize, const NCHAN: usize>(
     function_map: &sync::Arc<FunctionMap>,
     ruffbox: &sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+    stems: &StemBuses<BUFSIZE, NCHAN>,
     sample_set: SampleAndWavematrixSet,
     samples_path: &Path,
     downmix_stereo: bool,
@@ -406,11 +477,11 @@ pub fn load_sample_set<const BUFSIZE: usize, const NCHAN: usize>(
             if path.is_file() {
                 if let Some(ext) = path.extension() {
                     if let Ok(ext_str) = ext.to_os_string().into_string() {
-                        let ext_str_lc = ext_str.as_str().to_lowercase();
-                        if ext_str_lc == "flac" || ext_str_lc == "wav" {
+                        if load_audio_file::is_audio_file(&format!(".{ext_str}")) {
                             load_sample(
                                 function_map,
                                 ruffbox,
+                                stems,
                                 sample_set.clone(),
                                 set_name.clone(),
                                 &mut Vec::new(),
@@ -428,28 +499,45 @@ pub fn load_sample_set<const BUFSIZE: usize, const NCHAN: usize>(
 pub fn load_sample_set_string<const BUFSIZE: usize, const NCHAN: usize>(
     function_map: &sync::Arc<FunctionMap>,
     ruffbox: &sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+    stems: &StemBuses<BUFSIZE, NCHAN>,
     sample_set: SampleAndWavematrixSet,
     samples_path: String,
     downmix_stereo: bool,
 ) {
     let path = Path::new(&samples_path);
-    load_sample_set(function_map, ruffbox, sample_set, path, downmix_stereo);
+    load_sample_set(
+        function_map,
+        ruffbox,
+        stems,
+        sample_set,
+        path,
+        downmix_stereo,
+    );
 }
 
 pub fn load_sample_sets<const BUFSIZE: usize, const NCHAN: usize>(
     function_map: &sync::Arc<FunctionMap>,
     ruffbox: &sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+    stems: &StemBuses<BUFSIZE, NCHAN>,
     sample_set: SampleAndWavematrixSet,
     folder_path: String,
     downmix_stereo: bool,
 ) {
     let root_path = Path::new(&folder_path);
-    load_sample_sets_path(function_map, ruffbox, sample_set, root_path, downmix_stereo);
+    load_sample_sets_path(
+        function_map,
+        ruffbox,
+        stems,
+        sample_set,
+        root_path,
+        downmix_stereo,
+    );
 }
 
 pub fn load_sample_sets_path<const BUFSIZE: usize, const NCHAN: usize>(
     function_map: &sync::Arc<FunctionMap>,
     ruffbox: &sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+    stems: &StemBuses<BUFSIZE, NCHAN>,
     sample_set: SampleAndWavematrixSet,
     root_path: &Path,
     downmix_stereo: bool,
@@ -484,6 +572,7 @@ pub fn load_sample_sets_path<const BUFSIZE: usize, const NCHAN: usize>(
                 load_sample_set(
                     function_map,
                     ruffbox,
+                    stems,
                     sample_set.clone(),
                     &path,
                     downmix_stereo,
@@ -499,13 +588,17 @@ pub fn start_recording<const BUFSIZE: usize, const NCHAN: usize>(
     prefix: Option<String>,
     base_dir: String,
     rec_input: bool,
+    stems: Vec<String>,
 ) {
     let maybe_rec_ctrl = session.rec_control.lock().take();
     if let Some(mut rec_ctrl) = maybe_rec_ctrl {
         //println!("rec state {} {}", rec_ctrl.is_recording_output.load(Ordering::SeqCst) ,rec_ctrl.is_recording_input.load(Ordering::SeqCst));
 
-        // OUTPUT RECORDING
-        if rec_ctrl.is_recording_output.load(Ordering::SeqCst) {
+        if !stems.is_empty() {
+            // STEM RECORDING (the mix is recorded alongside the stems)
+            start_stem_recording(session, &mut rec_ctrl, prefix.clone(), &base_dir, &stems);
+        } else if rec_ctrl.is_recording_output.load(Ordering::SeqCst) {
+            // OUTPUT RECORDING
             println!("there's already a recording in progress, please stop first !");
         } else {
             let maybe_catch = rec_ctrl.catch_out.take();
@@ -582,11 +675,84 @@ pub fn start_recording<const BUFSIZE: usize, const NCHAN: usize>(
 }
 
 /// stop a running recording
+/// Route the generators or tags to the stem buses and start a writer
+/// for each bus, one for the rest (which is played on the main instance)
+/// and one for the mix, all in a timestamped folder.
+fn start_stem_recording<const BUFSIZE: usize, const NCHAN: usize>(
+    session: &Session<BUFSIZE, NCHAN>,
+    rec_ctrl: &mut real_time_streaming::RecordingControl<BUFSIZE, NCHAN>,
+    prefix: Option<String>,
+    base_dir: &str,
+    stems: &[String],
+) {
+    if rec_ctrl.is_recording_stems.load(Ordering::SeqCst) {
+        println!("there's already a stem recording in progress, please stop first !");
+        return;
+    }
+    if session.stems.buses.is_empty() {
+        println!("no stem buses, start megra with --stem-buses to record stems");
+        return;
+    }
+
+    let unassigned = session.stems.assign(stems);
+    if !unassigned.is_empty() {
+        println!(
+            "only {} stem buses, not recording {unassigned:?}",
+            session.stems.buses.len()
+        );
+    }
+
+    let folder = Path::new(base_dir).join("recordings").join(format!(
+        "{}_{}_stems",
+        prefix.unwrap_or_else(|| "megra_recording".to_string()),
+        Local::now().format("%Y%m%d_%H%M_%S")
+    ));
+    if let Err(e) = fs::create_dir_all(&folder) {
+        println!("can't create stem folder {} - {e}", folder.display());
+        session.stems.release();
+        return;
+    }
+
+    // the main instance plays everything that isn't routed to a bus
+    let names = ["mix".to_string(), "rest".to_string()].into_iter().chain(
+        stems
+            .iter()
+            .take(session.stems.buses.len())
+            .map(|s| s.replace('/', "_")),
+    );
+    for (idx, name) in names.enumerate() {
+        if let Some(catch) = rec_ctrl.catch_stems[idx].take() {
+            // unused buses might have been thrown to before
+            catch.clear();
+            rec_ctrl.catch_stems_handles[idx] = Some(real_time_streaming::start_writer_thread(
+                catch,
+                rec_ctrl.samplerate,
+                folder.join(format!("{name}.wav")).display().to_string(),
+            ));
+        }
+    }
+
+    rec_ctrl.is_recording_stems.store(true, Ordering::SeqCst);
+    println!("recording stems to {}", folder.display());
+}
+
 pub fn stop_recording<const BUFSIZE: usize, const NCHAN: usize>(session: &Session<BUFSIZE, NCHAN>) {
     let maybe_rec_ctrl = session.rec_control.lock().take();
     if let Some(mut rec_ctrl) = maybe_rec_ctrl {
-        //println!("rec state {} {}", rec_ctrl.is_recording_output.load(Ordering::SeqCst), rec_ctrl.is_recording_input.load(Ordering::SeqCst));
-        if rec_ctrl.is_recording_output.load(Ordering::SeqCst) {
+        if rec_ctrl.is_recording_stems.load(Ordering::SeqCst) {
+            rec_ctrl.is_recording_stems.store(false, Ordering::SeqCst);
+            for (catch, handle) in rec_ctrl
+                .catch_stems
+                .iter_mut()
+                .zip(rec_ctrl.catch_stems_handles.iter_mut())
+            {
+                if let Some(handle) = handle.take() {
+                    *catch = Some(real_time_streaming::stop_writer_thread(handle));
+                }
+            }
+            session.stems.release();
+        } else if rec_ctrl.is_recording_output.load(Ordering::SeqCst) {
+            //println!("rec state {} {}", rec_ctrl.is_recording_output.load(Ordering::SeqCst), rec_ctrl.is_recording_input.load(Ordering::SeqCst));
             let maybe_catch_handle = rec_ctrl.catch_out_handle.take();
             if let Some(catch_handle) = maybe_catch_handle {
                 rec_ctrl.is_recording_output.store(false, Ordering::SeqCst);
@@ -725,6 +891,70 @@ pub fn set_default_duration(globals: &sync::Arc<GlobalVariables>, n: f32) {
     ); // init on first attempt
 }
 
+pub fn set_global_quantization(globals: &sync::Arc<GlobalVariables>, beats: f32) {
+    globals.insert(
+        VariableId::GlobalQuantization,
+        TypedEntity::ConfigParameter(ConfigParameter::Numeric(beats)),
+    );
+}
+
+/// The grid new contexts start on, in beats (0 means they start right away).
+pub fn get_global_quantization(globals: &sync::Arc<GlobalVariables>) -> f32 {
+    if let Some(TypedEntity::ConfigParameter(ConfigParameter::Numeric(q))) = globals
+        .get(&VariableId::GlobalQuantization)
+        .map(|q| q.value().clone())
+    {
+        q
+    } else {
+        0.0
+    }
+}
+
+pub fn set_speaker_count(globals: &sync::Arc<GlobalVariables>, speakers: usize) {
+    globals.insert(
+        VariableId::SpeakerCount,
+        TypedEntity::ConfigParameter(ConfigParameter::Numeric(speakers as f32)),
+    );
+}
+
+/// The number of speakers of the speaker layout, if there is one.
+pub fn get_speaker_count(globals: &sync::Arc<GlobalVariables>) -> Option<usize> {
+    if let Some(TypedEntity::ConfigParameter(ConfigParameter::Numeric(n))) = globals
+        .get(&VariableId::SpeakerCount)
+        .map(|n| n.value().clone())
+    {
+        Some(n as usize)
+    } else {
+        None
+    }
+}
+
+/// The default duration in milliseconds (as set by `bpm` or `default-duration`),
+/// which is treated as a quarter note wherever there's a notion of beats.
+pub fn get_default_duration(globals: &sync::Arc<GlobalVariables>) -> f64 {
+    if let Some(TypedEntity::ConfigParameter(ConfigParameter::Numeric(d))) = globals
+        .get(&VariableId::DefaultDuration)
+        .map(|d| d.value().clone())
+    {
+        d as f64
+    } else {
+        200.0
+    }
+}
+
+/// The global latency in seconds. The value is cloned, so a dynamic
+/// latency isn't advanced by just looking at it.
+pub fn get_global_latency(globals: &sync::Arc<GlobalVariables>) -> f64 {
+    if let Some(TypedEntity::ConfigParameter(ConfigParameter::Dynamic(mut l))) = globals
+        .get(&VariableId::GlobalLatency)
+        .map(|l| l.value().clone())
+    {
+        l.evaluate_numerical() as f64
+    } else {
+        0.05
+    }
+}
+
 pub fn set_global_lifemodel_resources(globals: &sync::Arc<GlobalVariables>, val: f32) {
     globals.insert(
         VariableId::LifemodelGlobalResources,
@@ -734,6 +964,7 @@ pub fn set_global_lifemodel_resources(globals: &sync::Arc<GlobalVariables>, val:
 
 pub fn set_global_ruffbox_parameters<const BUFSIZE: usize, const NCHAN: usize>(
     ruffbox: &sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+    stems: &StemBuses<BUFSIZE, NCHAN>,
     globals: &sync::Arc<GlobalVariables>,
     params: &mut HashMap<ParameterAddress, ParameterValue>,
 ) {
@@ -742,7 +973,9 @@ pub fn set_global_ruffbox_parameters<const BUFSIZE: usize, const NCHAN: usize>(
             println!("can't use mégra-only parameter {k:?} for ruffbox master params");
             continue;
         };
-        ruffbox.set_master_parameter(addr.label, resolve_parameter(k, v, globals))
+        let value = resolve_parameter(k, v, globals);
+        stems.set_master_parameter(addr.label, value.clone());
+        ruffbox.set_master_parameter(addr.label, value)
     }
 }
 
@@ -785,6 +1018,294 @@ pub fn export_dot_running<const BUFSIZE: usize, const NCHAN: usize>(
     }
 }
 
+/// record the given generators and snapshots of the running ones
+fn record_for_export<const BUFSIZE: usize, const NCHAN: usize>(
+    tags: &BTreeSet<String>,
+    mut gens: Vec<Generator>,
+    length: midi_file::ExportLength,
+    session: &Session<BUFSIZE, NCHAN>,
+) -> Vec<midi_file::RecordedTrack> {
+    for sc in session.schedulers.iter() {
+        let (id_tags, (_, data)) = sc.pair();
+
+        if !tags.is_disjoint(id_tags) {
+            // get a snapshot of the generator in it's current state
+            gens.push(data.generator.lock().clone());
+        }
+    }
+
+    gens.iter_mut()
+        .map(|gen| {
+            midi_file::record_generator(
+                gen,
+                length,
+                &session.globals,
+                &session.functions,
+                session.sample_set.clone(),
+                session.output_mode,
+            )
+        })
+        .collect()
+}
+
+/// Export the event stream of the running generators with matching tags
+/// to a standard midi file, one track per generator. The events the
+/// schedulers emit are recorded until each generator has run for the given
+/// length, static generators (which aren't running) are stepped directly.
+pub fn export_midi<const BUFSIZE: usize, const NCHAN: usize>(
+    filename: &str,
+    tags: &BTreeSet<String>,
+    mut gens: Vec<Generator>,
+    length: midi_file::ExportLength,
+    session: &Session<BUFSIZE, NCHAN>,
+) {
+    let recorded: Vec<midi_file::RecordedTrack> = gens
+        .iter_mut()
+        .map(|gen| {
+            midi_file::record_generator(
+                gen,
+                length,
+                &session.globals,
+                &session.functions,
+                session.sample_set.clone(),
+                session.output_mode,
+            )
+        })
+        .collect();
+
+    let running: Vec<BTreeSet<String>> = session
+        .schedulers
+        .iter()
+        .filter(|sc| !tags.is_disjoint(sc.key()))
+        .map(|sc| sc.key().clone())
+        .collect();
+
+    if running.is_empty() && recorded.is_empty() {
+        println!("export-midi - no generators to export");
+        return;
+    }
+
+    let recording = midi_file::MidiRecording::new(
+        filename,
+        &running,
+        recorded,
+        length,
+        get_default_duration(&session.globals),
+        session.ruffbox.get_now(),
+    );
+
+    if recording.is_complete() {
+        write_midi_recording(recording);
+        return;
+    }
+
+    if let Some(old) = session.midi_recording.lock().replace(recording) {
+        println!("export-midi - dropping the recording to {}", old.filename);
+    }
+    println!("export-midi - recording {running:?} to {filename}");
+}
+
+/// record one step of a running generator, see `Session.midi_recording`
+pub fn record_midi_step<const BUFSIZE: usize, const NCHAN: usize>(
+    session: &Session<BUFSIZE, NCHAN>,
+    tags: &BTreeSet<String>,
+    time: f64,
+    dur: f64,
+    events: Vec<StaticEvent>,
+    last: bool,
+) {
+    let mut recording = session.midi_recording.lock();
+    if let Some(rec) = recording.as_mut() {
+        rec.record_step(tags, time, dur, events, last);
+        if rec.is_complete() {
+            write_midi_recording(recording.take().unwrap());
+        }
+    }
+}
+
+/// a stopped generator won't emit any more events, so its track is complete
+pub fn stop_midi_recording<const BUFSIZE: usize, const NCHAN: usize>(
+    session: &Session<BUFSIZE, NCHAN>,
+    tags: &BTreeSet<String>,
+) {
+    let mut recording = session.midi_recording.lock();
+    if let Some(rec) = recording.as_mut() {
+        rec.stop(tags);
+        if rec.is_complete() {
+            write_midi_recording(recording.take().unwrap());
+        }
+    }
+}
+
+// don't write files on the timing thread
+fn write_midi_recording(recording: midi_file::MidiRecording) {
+    thread::spawn(move || {
+        let filename = recording.filename.clone();
+        let beat_duration_ms = recording.beat_duration_ms;
+        match midi_file::write_smf(&filename, &recording.into_tracks(), beat_duration_ms) {
+            Ok(_) => println!("export to {filename}"),
+            Err(e) => println!("export-midi - can't write {filename}: {e}"),
+        }
+    });
+}
+
+pub fn export_score<const BUFSIZE: usize, const NCHAN: usize>(
+    filename: &str,
+    format: score_export::ScoreFormat,
+    tags: &BTreeSet<String>,
+    gens: Vec<Generator>,
+    length: midi_file::ExportLength,
+    session: &Session<BUFSIZE, NCHAN>,
+) {
+    let tracks = record_for_export(tags, gens, length, session);
+
+    match score_export::write_score(
+        filename,
+        format,
+        &tracks,
+        get_default_duration(&session.globals),
+    ) {
+        Ok(_) => println!("export to {filename}"),
+        Err(e) => println!("export-score - can't export {filename}: {e}"),
+    }
+}
+
+fn session_file(name: &str, base_dir: &str) -> std::path::PathBuf {
+    Path::new(base_dir)
+        .join("sessions")
+        .join(format!("{name}.json"))
+}
+
+pub fn save_session<const BUFSIZE: usize, const NCHAN: usize>(
+    name: &str,
+    session: &Session<BUFSIZE, NCHAN>,
+    base_dir: String,
+) {
+    let running = session
+        .schedulers
+        .iter()
+        .map(|sc| {
+            let (_, (_, data)) = sc.pair();
+            GeneratorState::capture(&data.generator.lock())
+        })
+        .collect();
+
+    let mut globals = Vec::new();
+    for var in session.globals.iter() {
+        let (id, val) = var.pair();
+        let states = match val {
+            TypedEntity::Generator(g) => vec![GeneratorState::capture(g)],
+            TypedEntity::GeneratorList(gl) => gl.iter().map(GeneratorState::capture).collect(),
+            _ => continue,
+        };
+        globals.push((format!("{id:?}"), states));
+    }
+
+    let snapshot = SessionSnapshot {
+        sources: session.journal.lock().sources(),
+        running,
+        globals,
+    };
+
+    let path = session_file(name, &base_dir);
+    match snapshot.write(&path) {
+        Ok(_) => println!("saved session to {}", path.display()),
+        Err(e) => println!("save-session - can't write {}: {e}", path.display()),
+    }
+}
+
+/// Restore a saved session. The current session is cleared, the saved source
+/// is evaluated again, and the generators continue from their saved state.
+pub fn load_session<const BUFSIZE: usize, const NCHAN: usize>(
+    name: &str,
+    session: &Session<BUFSIZE, NCHAN>,
+    base_dir: String,
+) {
+    let path = session_file(name, &base_dir);
+    let snapshot = match SessionSnapshot::read(&path) {
+        Ok(s) => s,
+        Err(e) => {
+            println!("load-session - can't read {}: {e}", path.display());
+            return;
+        }
+    };
+
+    Session::clear_session(session.clone());
+    session.journal.lock().clear();
+    // so the imports are evaluated again
+    session.imports.lock().clear();
+
+    for src in snapshot.sources.iter() {
+        match eval::parse_and_eval_from_str(
+            src,
+            &session.functions,
+            &session.globals,
+            session.sample_set.clone(),
+            session.output_mode,
+        ) {
+            Ok(expr) => interpreter::interpret_source(src, expr, session.clone(), base_dir.clone()),
+            Err(e) => println!("load-session - can't evaluate {src}: {e}"),
+        }
+    }
+
+    for state in snapshot.running.iter() {
+        if let Some(sc) = session.schedulers.get(&state.id_tags) {
+            let (_, data) = sc.value();
+            state.apply(&mut data.generator.lock());
+        }
+    }
+
+    for mut var in session.globals.iter_mut() {
+        let (id, val) = var.pair_mut();
+        let Some((_, states)) = snapshot
+            .globals
+            .iter()
+            .find(|(name, _)| *name == format!("{id:?}"))
+        else {
+            continue;
+        };
+        let gens = match val {
+            TypedEntity::Generator(g) => std::slice::from_mut(g),
+            TypedEntity::GeneratorList(gl) => gl.as_mut_slice(),
+            _ => continue,
+        };
+        for gen in gens.iter_mut() {
+            if let Some(state) = states.iter().find(|s| s.id_tags == gen.id_tags) {
+                state.apply(gen);
+            }
+        }
+    }
+
+    println!("loaded session from {}", path.display());
+}
+
+/// Write a generator to a file, either a static one or a
+/// snapshot of a running one.
+pub fn save_generator<const BUFSIZE: usize, const NCHAN: usize>(
+    filename: &str,
+    tags: &BTreeSet<String>,
+    gen: Option<Generator>,
+    session: &Session<BUFSIZE, NCHAN>,
+) {
+    let gen = gen.or_else(|| {
+        session
+            .schedulers
+            .iter()
+            .find(|sc| !tags.is_disjoint(sc.key()))
+            .map(|sc| sc.value().1.generator.lock().clone())
+    });
+
+    let Some(gen) = gen else {
+        println!("save-generator - no generator {tags:?} running");
+        return;
+    };
+
+    match generator_file::write_generator(filename, &gen) {
+        Ok(_) => println!("saved generator {:?} to {filename}", gen.id_tags),
+        Err(e) => println!("save-generator - can't save {filename}: {e}"),
+    }
+}
+
 pub fn once<const BUFSIZE: usize, const NCHAN: usize>(
     session: &Session<BUFSIZE, NCHAN>,
     sound_events: &mut [StaticEvent],
@@ -794,7 +1315,7 @@ pub fn once<const BUFSIZE: usize, const NCHAN: usize>(
         if let Some(mut contexts) = cev.ctx.clone() {
             // this is the worst clone ....
             for mut sx in contexts.drain(..) {
-                Session::handle_context(&mut sx, session);
+                Session::handle_context(&mut sx, session, None);
             }
         }
         if let Some(mut commands) = cev.cmd.clone() {
@@ -804,7 +1325,12 @@ pub fn once<const BUFSIZE: usize, const NCHAN: usize>(
             for c in commands.drain(..) {
                 match c {
                     Command::FreezeBuffer(freezbuf, inbuf) => {
-                        commands::freeze_buffer(&session.ruffbox, freezbuf, inbuf);
+                        commands::freeze_buffer(
+                            &session.ruffbox,
+                            &session.sample_set,
+                            freezbuf,
+                            inbuf,
+                        );
                         //println!("freeze buffer");
                     }
                     Command::Tmod(p) => {
@@ -822,6 +1348,7 @@ pub fn once<const BUFSIZE: usize, const NCHAN: usize>(
                     Command::GlobalRuffboxParams(mut m) => {
                         commands::set_global_ruffbox_parameters(
                             &session.ruffbox,
+                            &session.stems,
                             &session.globals,
                             &mut m,
                         );
@@ -876,49 +1403,39 @@ pub fn once<const BUFSIZE: usize, const NCHAN: usize>(
     }
 
     for s in sound_events.iter_mut() {
-        // notes are currently not interpreted (there's no midi out currently),
-        // they are only here for mappers
-        if s.name == "silence" || s.name == "note" {
+        if s.name == "silence" {
             continue;
         }
 
-        // if this is a sampler event and contains a sample lookup,
-        // resolve it NOW ... at the very end, finally ...
-        let mut bufnum: usize = 0;
-
-        if s.name == "frozensampler" {
-            if let Some(SynthParameterValue::ScalarUsize(b)) = s
-                .params
-                .get(&SynthParameterLabel::SampleBufferNumber.into())
-            {
-                bufnum = *b;
-            }
-        }
-
-        if let Some(lookup) = s.sample_lookup.as_ref() {
-            if let Some((res_bufnum, duration)) = session.sample_set.resolve_lookup(lookup) {
-                bufnum = res_bufnum;
-                // is this really needed ??
-                s.params.insert(
-                    SynthParameterLabel::SampleBufferNumber.into(),
-                    SynthParameterValue::ScalarUsize(bufnum),
+        // notes go to the midi output, if there is one,
+        // otherwise they are only here for mappers
+        if s.name == "note" {
+            if let Some(midi_out) = session.midi_out.read().as_ref() {
+                midi_out.send_note_event(
+                    s,
+                    crate::midi_clock::current_beat_duration(&session.globals),
+                    Instant::now(),
                 );
-
-                s.params
-                    .entry(SynthParameterLabel::Sustain.into())
-                    .or_insert_with(|| SynthParameterValue::ScalarF32((duration - 2) as f32));
             }
+            continue;
         }
 
+        // if this is a sampler event and contains a sample lookup,
+        // resolve it NOW ... at the very end, finally ...
+        let bufnum = session.sample_set.resolve_sample(s);
+
         // prepare a single, self-contained envelope from
         // the available information ...
         s.build_envelope();
 
+        if let Some(layout) = session.speaker_layout.as_ref() {
+            layout.pan(&mut s.params);
+        }
+
         // latency 0.05, should be made configurable later ...
+        let ruffbox = session.stems.select(&session.ruffbox, None, s);
         if let Some(mut inst) =
-            session
-                .ruffbox
-                .prepare_instance(map_synth_type(&s.name, &s.params), 0.0, bufnum)
+            ruffbox.prepare_instance(map_synth_type(&s.name, &s.params), 0.0, bufnum)
         {
             // set parameters and trigger instance
             for (addr, v) in s.params.iter() {
@@ -971,7 +1488,7 @@ pub fn once<const BUFSIZE: usize, const NCHAN: usize>(
                     _ => inst.set_instance_parameter(*addr, v),
                 }
             }
-            session.ruffbox.trigger(inst);
+            ruffbox.trigger(inst);
         } else {
             println!("can't prepare this instance !");
         }
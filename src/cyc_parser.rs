@@ -543,7 +543,7 @@ mod tests {
         // mock sample
         let mut keys = HashSet::new();
         keys.insert("a3".to_string());
-        sample_set.insert("piano".to_string(), keys, 3, 100);
+        sample_set.insert("piano".to_string(), keys, 3, 100, None);
 
         let template_events = Vec::new();
         let event_mappings = HashMap::new();
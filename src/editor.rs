@@ -10,6 +10,7 @@ mod megra_editor;
 use megra_editor::{EditorFont, MegraEditor};
 
 use crate::interpreter;
+use crate::megra_error::MegraError;
 
 use crate::session::Session;
 
@@ -24,23 +25,36 @@ pub fn run_editor<const BUFSIZE: usize, const NCHAN: usize>(
     let globals2 = sync::Arc::clone(&session.globals);
     let base_dir_2 = base_dir.clone();
 
-    let callback_ref: sync::Arc<Mutex<dyn FnMut(&String)>> =
+    let callback_ref: sync::Arc<Mutex<dyn FnMut(&String) -> Result<(), MegraError>>> =
         sync::Arc::new(Mutex::new(move |text: &String| {
-            let pfa_in = crate::eval::parse_and_eval_from_str(
-                text,
-                &session.functions,
-                &globals2,
-                session.sample_set.clone(),
-                session.output_mode,
-            );
-            match pfa_in {
-                Ok(pfa) => {
-                    interpreter::interpret(pfa, session.clone(), base_dir_2.to_string());
+            let text = text.clone();
+            let session = session.clone();
+            let globals2 = sync::Arc::clone(&globals2);
+            let base_dir_2 = base_dir_2.clone();
+            crate::eval::on_evaluating_thread(move || {
+                let pfa_in = crate::eval::parse_and_eval_from_str(
+                    &text,
+                    &session.functions,
+                    &globals2,
+                    session.sample_set.clone(),
+                    session.output_mode,
+                );
+                match pfa_in {
+                    Ok(pfa) => {
+                        interpreter::interpret_source(
+                            &text,
+                            pfa,
+                            session.clone(),
+                            base_dir_2.to_string(),
+                        );
+                        Ok(())
+                    }
+                    Err(e) => {
+                        println!("{}", e.caret_diagnostic(&text));
+                        Err(e)
+                    }
                 }
-                Err(e) => {
-                    println!("could not parse this! {text} {e}")
-                }
-            }
+            })
         }));
 
     let ifont = match font {
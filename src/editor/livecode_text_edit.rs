@@ -9,6 +9,7 @@ use egui::text::{CCursorRange, CursorRange};
 use parking_lot::Mutex;
 
 use crate::file_interpreter;
+use crate::megra_error::MegraError;
 
 /// The text edit state stored between frames.
 #[derive(Clone, Default)]
@@ -34,6 +35,12 @@ pub struct LivecodeTextEditState {
     #[serde(skip)]
     pub closing_paren_range: Option<CursorRange>, // mark parenthesis
 
+    #[serde(skip)]
+    pub error_range: Option<CCursorRange>, // mark the erroneous part of the last evaluation
+
+    #[serde(skip)]
+    pub error_message: Option<String>,
+
     /// Wrapped in Arc for cheaper clones.
     #[cfg_attr(feature = "serde", serde(skip))]
     pub undoer: Arc<Mutex<Undoer>>,
@@ -64,6 +71,11 @@ impl LivecodeTextEditState {
         self.opening_paren_range = None;
         self.closing_paren_range = None;
     }
+
+    pub fn clear_error(&mut self) {
+        self.error_range = None;
+        self.error_message = None;
+    }
 }
 
 /// The output from a `TextEdit`.
@@ -123,7 +135,7 @@ pub struct LivecodeTextEdit<'t> {
     desired_width: Option<f32>,
     desired_height_rows: usize,
     cursor_at_end: bool,
-    eval_callback: Option<Arc<Mutex<dyn FnMut(&String)>>>,
+    eval_callback: Option<Arc<Mutex<dyn FnMut(&String) -> Result<(), MegraError>>>>,
     karl_yerkes_mode: bool,
 }
 
@@ -157,7 +169,10 @@ impl<'t> LivecodeTextEdit<'t> {
         self.font(FontId::monospace(15.0))
     }
 
-    pub fn eval_callback(mut self, callback: &Arc<Mutex<dyn FnMut(&String)>>) -> Self {
+    pub fn eval_callback(
+        mut self,
+        callback: &Arc<Mutex<dyn FnMut(&String) -> Result<(), MegraError>>>,
+    ) -> Self {
         self.eval_callback = Some(Arc::clone(callback));
         self
     }
@@ -444,6 +459,34 @@ impl<'t> LivecodeTextEdit<'t> {
         if ui.is_rect_visible(rect) {
             painter.galley(text_draw_pos, galley.clone(), Color32::from_rgb(0, 0, 0));
 
+            if let (Some(error_range), Some(message)) =
+                (state.error_range, state.error_message.as_ref())
+            {
+                let error_cursors = CursorRange {
+                    primary: galley.from_ccursor(error_range.primary),
+                    secondary: galley.from_ccursor(error_range.secondary),
+                };
+                paint_cursor_selection(
+                    ui,
+                    &painter,
+                    text_draw_pos,
+                    &galley,
+                    &error_cursors,
+                    Some(Color32::from_rgba_unmultiplied(200, 30, 30, 120)),
+                );
+                // the message goes right below the marked part
+                let marker_pos = galley
+                    .pos_from_cursor(&error_cursors.secondary)
+                    .translate(text_draw_pos.to_vec2());
+                painter.text(
+                    marker_pos.left_bottom(),
+                    Align2::LEFT_TOP,
+                    message,
+                    FontId::monospace(row_height * 0.7),
+                    Color32::from_rgb(230, 60, 60),
+                );
+            }
+
             if ui.memory(|mem| mem.has_focus(id)) {
                 if let Some(cursor_range) = state.cursor_range(&galley) {
                     // We paint the cursor on top of the text, in case
@@ -538,7 +581,7 @@ fn call_callback(
     cursor_range: &CursorRange,
     text: &dyn TextBuffer,
     galley: &Galley,
-    eval_callback: &Option<Arc<Mutex<dyn FnMut(&String)>>>,
+    eval_callback: &Option<Arc<Mutex<dyn FnMut(&String) -> Result<(), MegraError>>>>,
     flash: bool,
 ) {
     if let Some(sexp_cursors) = find_toplevel_sexp(text.as_str(), cursor_range) {
@@ -557,7 +600,27 @@ fn call_callback(
 
         if let Some(cb) = eval_callback {
             let mut cb_loc = cb.lock();
-            cb_loc(&sel.to_string());
+            match cb_loc(&sel.to_string()) {
+                Ok(_) => state.clear_error(),
+                Err(e) => {
+                    // the span is in bytes, relative to the evaluated expression
+                    let offset = sexp_cursors.primary.index.min(sexp_cursors.secondary.index);
+                    let (start, end) = e
+                        .span
+                        .map(|s| (s.start, s.end.max(s.start + 1)))
+                        .unwrap_or((0, sel.len()));
+                    let to_chars =
+                        |b: usize| sel.char_indices().take_while(|(i, _)| *i < b).count();
+                    // mark at least one char
+                    let start = to_chars(start).min(sel.chars().count().saturating_sub(1));
+                    let end = to_chars(end).max(start + 1);
+                    state.error_range = Some(CCursorRange::two(
+                        CCursor::new(offset + start),
+                        CCursor::new(offset + end),
+                    ));
+                    state.error_message = Some(e.to_string());
+                }
+            }
         } else {
             println!("no callback!");
         }
@@ -574,7 +637,7 @@ fn livecode_events(
     layouter: &mut dyn FnMut(&Ui, &str, f32) -> Arc<Galley>,
     wrap_width: f32,
     default_cursor_range: CursorRange,
-    eval_callback: Option<Arc<Mutex<dyn FnMut(&String)>>>,
+    eval_callback: Option<Arc<Mutex<dyn FnMut(&String) -> Result<(), MegraError>>>>,
 ) -> (bool, CursorRange) {
     let mut cursor_range = state.cursor_range(&*galley).unwrap_or(default_cursor_range);
 
@@ -906,6 +969,7 @@ fn livecode_events(
 
         if let Some(new_ccursor_range) = did_mutate_text {
             any_change = true;
+            state.clear_error();
 
             // Layout again to avoid frame delay, and to keep `text` and `galley` in sync.
             *galley = layouter(ui, text.as_str(), wrap_width);
@@ -8,6 +8,7 @@ use epaint::text::{FontData, FontDefinitions, FontFamily};
 // custom text edit window
 use crate::editor::livecode_text_edit::LivecodeTextEdit;
 use crate::editor::syntax_highlighting::*;
+use crate::megra_error::MegraError;
 
 #[derive(PartialEq)]
 enum SketchNumber {
@@ -25,7 +26,7 @@ pub enum EditorFont {
 pub struct MegraEditor {
     content: String,
     #[serde(skip)]
-    callback: Option<Arc<Mutex<dyn FnMut(&String)>>>,
+    callback: Option<Arc<Mutex<dyn FnMut(&String) -> Result<(), MegraError>>>>,
     #[serde(skip)]
     sketch_list: Vec<String>,
     #[serde(skip)]
@@ -65,7 +66,10 @@ impl MegraEditor {
         self.font_size = *font_size;
     }
 
-    pub fn set_callback(&mut self, callback: Arc<Mutex<dyn FnMut(&String)>>) {
+    pub fn set_callback(
+        &mut self,
+        callback: Arc<Mutex<dyn FnMut(&String) -> Result<(), MegraError>>>,
+    ) {
         self.callback = Some(callback);
     }
 
@@ -167,6 +167,7 @@ fn is_function(word: &str) -> bool {
             | "once"
             | "cmp"
             | "chop"
+            | "slice"
             | "inhibit"
             | "exhibit"
             | "inh"
@@ -183,6 +184,8 @@ fn is_command(word: &str) -> bool {
         "tmod"
             | "midi-callback"
             | "export-dot"
+            | "export-midi"
+            | "export-score"
             | "step-part"
             | "latency"
             | "global-resources"
@@ -190,6 +193,13 @@ fn is_command(word: &str) -> bool {
             | "reverb"
             | "default-duration"
             | "bpm"
+            | "seed"
+            | "eval-server"
+            | "eval-server-stop"
+            | "save-session"
+            | "load-session"
+            | "save-generator"
+            | "load-generator"
             | "defpart"
             | "keep-state"
             | "clear"
@@ -208,6 +218,15 @@ fn is_command(word: &str) -> bool {
             | "pow"
             | "fun"
             | "callback"
+            | "lambda"
+            | "defmacro"
+            | "quote"
+            | "quasiquote"
+            | "unquote"
+            | "if"
+            | "cond"
+            | "dotimes"
+            | "for"
             | "osc-send"
             | "osc-sender"
             | "osc-receiver"
@@ -217,6 +236,9 @@ fn is_command(word: &str) -> bool {
             | "let"
             | "progn"
             | "match"
+            | "filter"
+            | "reduce"
+            | "call"
             | "print"
             | "concat"
             | "insert"
@@ -230,6 +252,14 @@ fn is_command(word: &str) -> bool {
             | "mtosym"
             | "veltodyn"
             | "load-file"
+            | "import"
+            | "running"
+            | "contexts"
+            | "inspect"
+            | "ramp"
+            | "ramp-bpm"
+            | "ramp-stop"
+            | "quantize"
             | "<="
             | "<"
             | "=="
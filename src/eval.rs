@@ -1,17 +1,20 @@
 use anyhow::{anyhow, bail, Result};
+use crossbeam::channel::{bounded, unbounded, Sender};
 use dashmap::DashMap;
-use nom::error::VerboseError;
+use parking_lot::RwLock;
 
 use regex::Regex;
 use std::{cell::RefCell, collections::HashMap};
 use std::{fmt, sync};
 
+use crate::megra_error::{self, MegraError};
 use crate::{
     ast_types::*, Command, GlobalVariables, OutputMode, SampleAndWavematrixSet, TypedEntity,
 };
 use crate::{
-    builtin_types::{Comparable, Comparator, VariableId},
+    builtin_types::{Comparable, Comparator, Lambda, VariableId},
     session::SyncContext,
+    session_view::SessionView,
 };
 
 pub mod arithmetic;
@@ -19,9 +22,11 @@ pub mod commands;
 pub mod comparison;
 pub mod compose;
 pub mod constructors;
+pub mod control_flow;
 pub mod dynpar;
 pub mod event_getters;
 pub mod events;
+pub mod functional;
 pub mod generator_list;
 pub mod generator_modifier;
 pub mod generator_processor;
@@ -52,12 +57,13 @@ pub enum EvaluatedExpr {
     Command(Command),
     SyncContext(SyncContext),
     Progn(Vec<EvaluatedExpr>),
-    // I don't really have an idea how to make functions,
-    // so for now I'll just store the non-evaluated Exprs
-    // and reduce them once the user calls the function ...
-    // that might make them macros rather than functions?
-    // Not sure ...
+    // named functions store the non-evaluated Exprs
+    // and reduce them once the user calls the function,
+    // anonymous functions (lambdas) are typed entities
     FunctionDefinition(String, Vec<String>, Vec<Expr>),
+    // macros receive their arguments as quoted code,
+    // the code they return is evaluated in place of the call
+    MacroDefinition(String, Vec<String>, Vec<Expr>),
     VariableDefinition(VariableId, TypedEntity, bool),
     // everything else is a typed entity
     Typed(TypedEntity),
@@ -79,6 +85,9 @@ impl fmt::Debug for EvaluatedExpr {
             EvaluatedExpr::FunctionDefinition(_, _, _) => {
                 write!(f, "EvaluatedExpr::FunctionDefinition")
             }
+            EvaluatedExpr::MacroDefinition(_, _, _) => {
+                write!(f, "EvaluatedExpr::MacroDefinition")
+            }
             EvaluatedExpr::VariableDefinition(_, _, _) => {
                 write!(f, "EvaluatedExpr::VariableDefinition")
             }
@@ -96,6 +105,7 @@ impl fmt::Debug for EvaluatedExpr {
 // usr_lib is for user-defined functions ...
 pub struct FunctionMap {
     pub usr_lib: DashMap<String, (Vec<String>, Vec<Expr>)>,
+    pub macros: DashMap<String, (Vec<String>, Vec<Expr>)>,
     pub std_lib: DashMap<
         String,
         fn(
@@ -106,6 +116,8 @@ pub struct FunctionMap {
             OutputMode,
         ) -> anyhow::Result<EvaluatedExpr>,
     >,
+    // what's running, for the inspection commands
+    pub session_view: RwLock<Option<Box<dyn SessionView>>>,
 }
 
 impl FunctionMap {
@@ -113,10 +125,110 @@ impl FunctionMap {
         FunctionMap {
             std_lib: DashMap::new(),
             usr_lib: DashMap::new(),
+            macros: DashMap::new(),
+            session_view: RwLock::new(None),
         }
     }
 }
 
+// user-defined functions, lambdas and macros can call each other
+// (or themselves), this keeps them from exhausting the stack
+const MAX_CALL_DEPTH: usize = 100;
+
+// the stack size of all threads that evaluate code, enough for
+// MAX_CALL_DEPTH nested calls (even in debug builds, where each
+// call takes a lot more space)
+pub const EVAL_STACK_SIZE: usize = 32 * 1024 * 1024;
+
+type EvalRequest = Box<dyn FnOnce() + Send>;
+
+// the evaluating thread, started with the first request
+static EVALUATOR: sync::OnceLock<Sender<EvalRequest>> = sync::OnceLock::new();
+
+/// Spawn a long-running thread that evaluates code (i.e. the timing
+/// thread or an input handler). The call depth limit is tuned for the
+/// stack size, so code is only ever evaluated on threads spawned through
+/// this, or on the evaluating thread (see `on_evaluating_thread`).
+pub fn spawn_evaluating<F, T>(name: &str, f: F) -> std::io::Result<std::thread::JoinHandle<T>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    std::thread::Builder::new()
+        .name(name.to_string())
+        .stack_size(EVAL_STACK_SIZE)
+        .spawn(move || {
+            ON_EVAL_STACK.with(|e| e.set(true));
+            f()
+        })
+}
+
+/// Evaluate on the evaluating thread and wait for the result, for the
+/// requests from the repl, the editor or the eval server. There's only one
+/// evaluating thread, so the requests are evaluated one after another.
+/// On a thread that has the evaluation stack size already, `f` is called
+/// directly.
+pub fn on_evaluating_thread<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    if ON_EVAL_STACK.with(|e| e.get()) {
+        return f();
+    }
+
+    let evaluator = EVALUATOR.get_or_init(|| {
+        let (sender, receiver) = unbounded::<EvalRequest>();
+        spawn_evaluating("megra-eval", move || {
+            for request in receiver.iter() {
+                request();
+            }
+        })
+        .expect("can't spawn evaluating thread");
+        sender
+    });
+
+    // a panic is handed back to the caller, so the evaluating thread keeps running
+    let (result_sender, result_receiver) = bounded(1);
+    evaluator
+        .send(Box::new(move || {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+            let _ = result_sender.send(result);
+        }))
+        .expect("evaluating thread is gone");
+
+    match result_receiver.recv().expect("evaluating thread is gone") {
+        Ok(result) => result,
+        Err(e) => std::panic::resume_unwind(e),
+    }
+}
+
+thread_local! {
+    static CALL_DEPTH: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    static ON_EVAL_STACK: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+// counts the nested calls as long as it lives
+struct CallDepthGuard;
+
+impl CallDepthGuard {
+    fn enter() -> Result<Self> {
+        CALL_DEPTH.with(|depth| {
+            if depth.get() >= MAX_CALL_DEPTH {
+                bail!("maximum call depth ({MAX_CALL_DEPTH}) exceeded, is there a recursion without end?");
+            }
+            depth.set(depth.get() + 1);
+            Ok(CallDepthGuard)
+        })
+    }
+}
+
+impl Drop for CallDepthGuard {
+    fn drop(&mut self) {
+        CALL_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
 #[derive(Debug)]
 pub struct LocalVariables {
     pub pos_args: HashMap<String, EvaluatedExpr>,
@@ -178,9 +290,12 @@ pub fn eval_usr_fun(
         );
     }
 
-    // FIRST, eval local args,
-    // manual zip
-    for (i, expr) in tail[..fun_arg_names.len()].iter().enumerate() {
+    let _depth = CallDepthGuard::enter()?;
+
+    // FIRST, eval local args, all of them before binding any,
+    // so they see the variables of the caller
+    let mut arg_vals = Vec::new();
+    for expr in tail[..fun_arg_names.len()].iter() {
         let mut res = eval_expression(
             expr,
             functions,
@@ -197,23 +312,26 @@ pub fn eval_usr_fun(
             }
         }
 
-        locals
-            .borrow_mut()
-            .pos_args
-            .insert(fun_arg_names[i].clone(), res);
+        arg_vals.push(res);
     }
 
+    let mut rest_vals = Vec::new();
     for expr in tail[fun_arg_names.len()..].iter() {
-        let res = eval_expression(
+        rest_vals.push(eval_expression(
             expr,
             functions,
             globals,
             Some(locals.clone()),
             sample_set.clone(),
             out_mode,
-        )?;
-        locals.borrow_mut().rest.push(res);
+        )?);
+    }
+
+    // manual zip
+    for (name, res) in fun_arg_names.iter().zip(arg_vals) {
+        locals.borrow_mut().pos_args.insert(name.clone(), res);
     }
+    locals.borrow_mut().rest = rest_vals;
 
     // THIRD
     let mut fun_tail: Vec<EvaluatedExpr> = Vec::new();
@@ -303,6 +421,8 @@ pub fn eval_usr_fun_evaluated_tail(
         );
     }
 
+    let _depth = CallDepthGuard::enter()?;
+
     // FIRST, eval local args,
     // manual zip
     for (i, expr) in tail.drain(..fun_arg_names.len()).enumerate() {
@@ -312,10 +432,8 @@ pub fn eval_usr_fun_evaluated_tail(
             .insert(fun_arg_names[i].clone(), expr);
     }
 
-    if !tail.is_empty() {
-        for expr in tail.drain(fun_arg_names.len()..) {
-            locals.borrow_mut().rest.push(expr);
-        }
+    for expr in tail.drain(..) {
+        locals.borrow_mut().rest.push(expr);
     }
 
     // THIRD
@@ -342,6 +460,270 @@ pub fn eval_usr_fun_evaluated_tail(
     fun_tail.pop().ok_or(anyhow!("usr fun result empty"))
 }
 
+// the positional argument names of a definition, if there's
+// an argument list, like `(a b c)`, or `()` for no arguments
+fn positional_args(e: Option<&Expr>) -> Result<Option<Vec<String>>> {
+    match e {
+        Some(Expr::Application(head, fun_tail)) => {
+            let mut args = Vec::new();
+            if let Ok(EvaluatedExpr::Identifier(f)) = eval_as_arg(head) {
+                args.push(f);
+            }
+            // reduce tail args ...
+            let reduced_tail = fun_tail
+                .iter()
+                .map(eval_as_arg)
+                .collect::<Result<Vec<EvaluatedExpr>>>()?;
+
+            for eexpr in reduced_tail {
+                if let EvaluatedExpr::Identifier(f) = eexpr {
+                    args.push(f);
+                }
+            }
+            Ok(Some(args))
+        }
+        // the empty list
+        Some(Expr::Constant(Atom::Boolean(false))) => Ok(Some(Vec::new())),
+        _ => Ok(None),
+    }
+}
+
+// evaluate the arguments of a function call, resolving global variables
+fn eval_args(
+    tail: &[Expr],
+    functions: &FunctionMap,
+    globals: &sync::Arc<GlobalVariables>,
+    locals: Option<std::rc::Rc<RefCell<LocalVariables>>>,
+    sample_set: SampleAndWavematrixSet,
+    out_mode: OutputMode,
+) -> Result<Vec<EvaluatedExpr>> {
+    let mut args = Vec::new();
+    for (idx, expr) in tail.iter().enumerate() {
+        let e = eval_expression(
+            expr,
+            functions,
+            globals,
+            locals.clone(),
+            sample_set.clone(),
+            out_mode,
+        )
+        .map_err(|e| megra_error::at_tail_index(e, idx))?;
+        // the list field exists only to be flattened
+        if let EvaluatedExpr::EvaluatedExprList(mut l) = e {
+            args.append(&mut l);
+        } else {
+            args.push(e);
+        }
+    }
+
+    for arg in args.iter_mut() {
+        if let EvaluatedExpr::Identifier(i) = arg {
+            if let Some(var) = globals.get(&VariableId::Custom(i.clone())) {
+                *arg = EvaluatedExpr::Typed(var.value().clone());
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+// a lambda stored in a global variable
+fn global_function(name: &str, globals: &sync::Arc<GlobalVariables>) -> Option<EvaluatedExpr> {
+    globals
+        .get(&VariableId::Custom(name.to_string()))
+        .and_then(|var| match var.value() {
+            TypedEntity::Function(l) => {
+                Some(EvaluatedExpr::Typed(TypedEntity::Function(l.clone())))
+            }
+            _ => None,
+        })
+}
+
+/// Whether the expression can be called, that is, if it's a lambda or
+/// the name of a built-in function, a user-defined function or a global lambda.
+pub fn is_function(
+    fun: &EvaluatedExpr,
+    functions: &FunctionMap,
+    globals: &sync::Arc<GlobalVariables>,
+) -> bool {
+    match fun {
+        EvaluatedExpr::Typed(TypedEntity::Function(_)) => true,
+        EvaluatedExpr::Identifier(f) => {
+            functions.std_lib.contains_key(f)
+                || functions.usr_lib.contains_key(f)
+                || global_function(f, globals).is_some()
+        }
+        _ => false,
+    }
+}
+
+/// Call a function (see `is_function`) with already evaluated arguments.
+pub fn apply_function(
+    fun: &EvaluatedExpr,
+    mut args: Vec<EvaluatedExpr>,
+    functions: &FunctionMap,
+    globals: &sync::Arc<GlobalVariables>,
+    sample_set: SampleAndWavematrixSet,
+    out_mode: OutputMode,
+) -> Result<EvaluatedExpr> {
+    match fun {
+        EvaluatedExpr::Typed(TypedEntity::Function(lambda)) => eval_usr_fun_evaluated_tail(
+            lambda.pos_args.clone(),
+            lambda.body.clone(),
+            args,
+            functions,
+            globals,
+            std::rc::Rc::new(RefCell::new(LocalVariables {
+                pos_args: lambda.captured.clone(),
+                rest: Vec::new(),
+            })),
+            sample_set,
+            out_mode,
+        ),
+        EvaluatedExpr::Identifier(f) => {
+            if let Some(std_fun) = functions.std_lib.get(f).map(|fun| *fun) {
+                // push function name
+                args.insert(0, EvaluatedExpr::Identifier(f.clone()));
+                std_fun(functions, &mut args, globals, sample_set, out_mode)
+            } else if let Some(usr_fun) = functions.usr_lib.get(f).map(|fun| fun.clone()) {
+                let (fun_arg_names, fun_expr) = usr_fun;
+                eval_usr_fun_evaluated_tail(
+                    fun_arg_names,
+                    fun_expr,
+                    args,
+                    functions,
+                    globals,
+                    std::rc::Rc::new(RefCell::new(LocalVariables::new())),
+                    sample_set,
+                    out_mode,
+                )
+            } else if let Some(lambda) = global_function(f, globals) {
+                apply_function(&lambda, args, functions, globals, sample_set, out_mode)
+            } else {
+                bail!("{f} isn't a function")
+            }
+        }
+        _ => bail!("can't call {fun:?}, it isn't a function"),
+    }
+}
+
+// the macro arguments are passed as quoted code,
+// the result usually is code as well
+fn expand_macro(
+    macro_arg_names: Vec<String>,
+    macro_expr: Vec<Expr>,
+    tail: &[Expr],
+    functions: &FunctionMap,
+    globals: &sync::Arc<GlobalVariables>,
+    sample_set: SampleAndWavematrixSet,
+    out_mode: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let args = tail
+        .iter()
+        .map(|e| EvaluatedExpr::Typed(TypedEntity::Quoted(e.clone())))
+        .collect();
+    eval_usr_fun_evaluated_tail(
+        macro_arg_names,
+        macro_expr,
+        args,
+        functions,
+        globals,
+        std::rc::Rc::new(RefCell::new(LocalVariables::new())),
+        sample_set,
+        out_mode,
+    )
+}
+
+// code from a value, to fill in quasiquoted expressions
+fn value_to_expr(val: EvaluatedExpr) -> Result<Expr> {
+    Ok(match val {
+        EvaluatedExpr::Typed(TypedEntity::Quoted(e)) => e,
+        EvaluatedExpr::Typed(TypedEntity::Comparable(c)) => Expr::Constant(match c {
+            Comparable::Float(f) => Atom::Float(f),
+            Comparable::Double(f) => Atom::Float(f as f32),
+            Comparable::Int32(i) => Atom::Float(i as f32),
+            Comparable::Int64(i) => Atom::Float(i as f32),
+            Comparable::String(s) => Atom::String(s),
+            Comparable::Symbol(s) => Atom::Symbol(s),
+            Comparable::Boolean(b) => Atom::Boolean(b),
+            _ => bail!("quasiquote - can't insert {c:?}"),
+        }),
+        EvaluatedExpr::Keyword(k) => Expr::Constant(Atom::Keyword(k)),
+        EvaluatedExpr::Identifier(i) => Expr::Constant(Atom::Identifier(i)),
+        _ => bail!("quasiquote - can't insert {val:?}"),
+    })
+}
+
+// fill in the unquoted parts of a quasiquoted expression,
+// lists (i.e. `,@rest`) are spliced in
+fn unquote(
+    e: &Expr,
+    functions: &FunctionMap,
+    globals: &sync::Arc<GlobalVariables>,
+    locals: Option<std::rc::Rc<RefCell<LocalVariables>>>,
+    sample_set: SampleAndWavematrixSet,
+    out_mode: OutputMode,
+) -> Result<Vec<Expr>> {
+    match e {
+        Expr::Application(head, tail) => {
+            if let (Expr::Constant(Atom::Identifier(u)), [unquoted]) = (&**head, tail.as_slice()) {
+                if u == "unquote" {
+                    return match eval_expression(
+                        unquoted, functions, globals, locals, sample_set, out_mode,
+                    )? {
+                        EvaluatedExpr::EvaluatedExprList(l) => {
+                            l.into_iter().map(value_to_expr).collect()
+                        }
+                        val => Ok(vec![value_to_expr(val)?]),
+                    };
+                }
+            }
+
+            let mut filled_head = unquote(
+                head,
+                functions,
+                globals,
+                locals.clone(),
+                sample_set.clone(),
+                out_mode,
+            )?;
+            if filled_head.len() != 1 {
+                bail!("quasiquote - can't splice into the head of an expression");
+            }
+            let mut filled_tail = Vec::new();
+            for texpr in tail.iter() {
+                filled_tail.append(&mut unquote(
+                    texpr,
+                    functions,
+                    globals,
+                    locals.clone(),
+                    sample_set.clone(),
+                    out_mode,
+                )?);
+            }
+            Ok(vec![Expr::Application(
+                Box::new(filled_head.remove(0)),
+                filled_tail,
+            )])
+        }
+        Expr::Definition(head, tail) => {
+            let mut filled_tail = Vec::new();
+            for texpr in tail.iter() {
+                filled_tail.append(&mut unquote(
+                    texpr,
+                    functions,
+                    globals,
+                    locals.clone(),
+                    sample_set.clone(),
+                    out_mode,
+                )?);
+            }
+            Ok(vec![Expr::Definition(head.clone(), filled_tail)])
+        }
+        _ => Ok(vec![e.clone()]),
+    }
+}
+
 /// This one reduces the abstract syntax tree ...
 /// does not resolve global variables at this point,
 /// as there might be different points in time where it makes
@@ -420,22 +802,74 @@ pub fn eval_expression(
             // in some context it might be nice to have the head procedurally generated as well,
             // but it'd cause a whole lotta trouble right now and I have no desire currently to use it ...
             // there's more explicit ways to do the whole thing ...
-            let EvaluatedExpr::Identifier(f) = eval_expression(
+            let f = match eval_expression(
                 head,
                 functions,
                 globals,
                 locals.clone(), // clones reference
                 sample_set.clone(),
                 out_mode,
-            )?
-            else {
-                bail!("eval - head isn't an identifier")
+            )? {
+                EvaluatedExpr::Identifier(f) => f,
+                // a lambda, or a local variable holding one
+                lambda @ EvaluatedExpr::Typed(TypedEntity::Function(_)) => {
+                    let args = eval_args(
+                        tail,
+                        functions,
+                        globals,
+                        locals,
+                        sample_set.clone(),
+                        out_mode,
+                    )?;
+                    return apply_function(&lambda, args, functions, globals, sample_set, out_mode)
+                        .map_err(megra_error::without_position);
+                }
+                _ => bail!("eval - head isn't an identifier"),
             };
 
+            // special forms, the tail isn't evaluated (or only partially)
+            match f.as_str() {
+                "quote" => {
+                    let [quoted] = tail.as_slice() else {
+                        bail!("quote - needs exactly one expression");
+                    };
+                    return Ok(EvaluatedExpr::Typed(TypedEntity::Quoted(quoted.clone())));
+                }
+                "quasiquote" => {
+                    let [quoted] = tail.as_slice() else {
+                        bail!("quasiquote - needs exactly one expression");
+                    };
+                    let mut filled =
+                        unquote(quoted, functions, globals, locals, sample_set, out_mode)?;
+                    if filled.len() != 1 {
+                        bail!("quasiquote - can't splice at the top level");
+                    }
+                    return Ok(EvaluatedExpr::Typed(TypedEntity::Quoted(filled.remove(0))));
+                }
+                "unquote" => bail!("unquote - only valid within quasiquote"),
+                // control flow, only the taken branches are evaluated
+                "if" => {
+                    return control_flow::eval_if(
+                        tail, functions, globals, locals, sample_set, out_mode,
+                    )
+                }
+                "cond" => {
+                    return control_flow::eval_cond(
+                        tail, functions, globals, locals, sample_set, out_mode,
+                    )
+                }
+                "dotimes" | "for" => {
+                    return control_flow::eval_loop(
+                        &f, tail, functions, globals, locals, sample_set, out_mode,
+                    )
+                }
+                _ => {}
+            }
+
             // check if we have this function ...
             if functions.std_lib.contains_key(&f) {
                 let mut reduced_tail: Vec<EvaluatedExpr> = Vec::new();
-                for expr in tail {
+                for (idx, expr) in tail.iter().enumerate() {
                     let eexpr = eval_expression(
                         expr,
                         functions,
@@ -465,7 +899,7 @@ pub fn eval_expression(
                                 )))
                             } else {
                                 // otherwise jump out if expression can't be evaluated
-                                bail!("evaluation error\n,- expr isn't match\n- error: {e}")
+                                return Err(megra_error::at_tail_index(e, idx));
                             }
                         }
                     }
@@ -489,22 +923,62 @@ pub fn eval_expression(
                     tail,
                     functions,
                     globals,
-                    // pass on local variables if they already exist,
-                    // otherwise create new container
-                    if let Some(loc) = locals {
-                        loc
-                    } else {
-                        std::rc::Rc::new(RefCell::new(LocalVariables::new()))
-                    },
+                    // the variables of the caller stay visible, but the
+                    // call gets its own container, so (recursive) calls
+                    // don't overwrite the arguments of the caller
+                    std::rc::Rc::new(RefCell::new(
+                        locals
+                            .map(|loc| {
+                                let loc = loc.borrow();
+                                LocalVariables {
+                                    pos_args: loc.pos_args.clone(),
+                                    rest: loc.rest.clone(),
+                                }
+                            })
+                            .unwrap_or_else(LocalVariables::new),
+                    )),
                     sample_set,
                     out_mode,
                 )
+                // the body isn't part of the evaluated source
+                .map_err(megra_error::without_position)
+            } else if functions.macros.contains_key(&f) {
+                let (macro_arg_names, macro_expr) = functions.macros.get(&f).unwrap().clone();
+                match expand_macro(
+                    macro_arg_names,
+                    macro_expr,
+                    tail,
+                    functions,
+                    globals,
+                    sample_set.clone(),
+                    out_mode,
+                )
+                .map_err(megra_error::without_position)?
+                {
+                    // evaluate the expansion in place of the call
+                    EvaluatedExpr::Typed(TypedEntity::Quoted(expansion)) => eval_expression(
+                        &expansion, functions, globals, locals, sample_set, out_mode,
+                    )
+                    .map_err(megra_error::without_position),
+                    res => Ok(res),
+                }
+            } else if let Some(lambda) = global_function(&f, globals) {
+                let args = eval_args(
+                    tail,
+                    functions,
+                    globals,
+                    locals,
+                    sample_set.clone(),
+                    out_mode,
+                )?;
+                apply_function(&lambda, args, functions, globals, sample_set, out_mode)
+                    .map_err(megra_error::without_position)
             } else {
                 bail!("unknown function {f}");
             }
         }
         Expr::Definition(head, tail) => match **head {
-            Expr::FunctionDefinition => {
+            Expr::FunctionDefinition | Expr::MacroDefinition => {
                 let id = match eval_expression(
                     &tail[0],
                     functions,
@@ -524,31 +998,14 @@ pub fn eval_expression(
                 // remove function name
                 tail_clone.remove(0);
 
-                let mut positional_args = Vec::new();
-                let mut rem_args = false;
-
                 // evaluate positional arguments ...
-                if let Some(Expr::Application(head, fun_tail)) = tail_clone.first() {
-                    if let Ok(EvaluatedExpr::Identifier(f)) = eval_as_arg(head) {
-                        positional_args.push(f);
-                    }
-                    // reduce tail args ...
-                    let reduced_tail = fun_tail
-                        .iter()
-                        .map(eval_as_arg)
-                        .collect::<Result<Vec<EvaluatedExpr>>>()?;
-
-                    for eexpr in reduced_tail {
-                        if let EvaluatedExpr::Identifier(f) = eexpr {
-                            positional_args.push(f);
-                        }
+                let positional_args = match positional_args(tail_clone.first())? {
+                    Some(args) => {
+                        tail_clone.remove(0);
+                        args
                     }
-                    rem_args = true;
-                }
-
-                if rem_args {
-                    tail_clone.remove(0);
-                }
+                    None => Vec::new(),
+                };
 
                 // if the function is defined by another function, repelace some argruments
                 // (yes, the line between functions and macros in megra is very, very vague ...)
@@ -558,11 +1015,33 @@ pub fn eval_expression(
                     }
                 }
 
-                Ok(EvaluatedExpr::FunctionDefinition(
-                    id,
-                    positional_args,
-                    tail_clone,
-                ))
+                if let Expr::MacroDefinition = **head {
+                    Ok(EvaluatedExpr::MacroDefinition(
+                        id,
+                        positional_args,
+                        tail_clone,
+                    ))
+                } else {
+                    Ok(EvaluatedExpr::FunctionDefinition(
+                        id,
+                        positional_args,
+                        tail_clone,
+                    ))
+                }
+            }
+            Expr::LambdaDefinition => {
+                let Some(pos_args) = positional_args(tail.first())? else {
+                    bail!("lambda - missing argument list");
+                };
+
+                // the local variables are captured by value
+                Ok(EvaluatedExpr::Typed(TypedEntity::Function(Lambda {
+                    pos_args,
+                    body: tail[1..].to_vec(),
+                    captured: locals
+                        .map(|loc| loc.borrow().pos_args.clone())
+                        .unwrap_or_default(),
+                })))
             }
             Expr::VariableDefinition => {
                 let id = match eval_expression(
@@ -589,7 +1068,7 @@ pub fn eval_expression(
                 };
 
                 let mut reduced_tail: Vec<EvaluatedExpr> = Vec::new();
-                for expr in tail {
+                for (idx, expr) in tail.iter().enumerate() {
                     let e = eval_expression(
                         expr,
                         functions,
@@ -597,7 +1076,8 @@ pub fn eval_expression(
                         locals.clone(),
                         sample_set.clone(),
                         out_mode,
-                    )?;
+                    )
+                    .map_err(|e| megra_error::at_tail_index(e, idx))?;
                     // the list field exists only to be flattened
                     if let EvaluatedExpr::EvaluatedExprList(mut l) = e {
                         reduced_tail.append(&mut l);
@@ -637,7 +1117,7 @@ pub fn eval_expression(
                 };
 
                 let mut reduced_tail: Vec<EvaluatedExpr> = Vec::new();
-                for expr in tail {
+                for (idx, expr) in tail.iter().enumerate() {
                     let e = eval_expression(
                         expr,
                         functions,
@@ -645,7 +1125,8 @@ pub fn eval_expression(
                         locals.clone(),
                         sample_set.clone(),
                         out_mode,
-                    )?;
+                    )
+                    .map_err(|e| megra_error::at_tail_index(e, idx))?;
                     // the list field exists only to be flattened
                     if let EvaluatedExpr::EvaluatedExprList(mut l) = e {
                         reduced_tail.append(&mut l);
@@ -666,20 +1147,30 @@ pub fn eval_expression(
     }
 }
 
-/// invokes the parser and subsequently evaluates the exprs
+/// invokes the parser and subsequently evaluates the exprs,
+/// errors carry their position in the source
+pub fn parse_from_str(src: &str) -> std::result::Result<Expr, MegraError> {
+    // preprocessing - remove all comments, keeping the positions intact ...
+    let re = Regex::new(r";[^\n]+\n").unwrap();
+    let src_nocomment = re.replace_all(src, |caps: &regex::Captures| {
+        format!("{}\n", " ".repeat(caps[0].len() - 1))
+    });
+    let (_, exp) = crate::parser::parse_expr(&src_nocomment)
+        .map_err(|e| MegraError::parse(&src_nocomment, e))?;
+    Ok(exp)
+}
+
 pub fn parse_and_eval_from_str(
     src: &str,
     functions: &FunctionMap,
     globals: &sync::Arc<GlobalVariables>,
     sample_set: SampleAndWavematrixSet,
     out_mode: OutputMode,
-) -> Result<EvaluatedExpr> {
-    // preprocessing - remove all comments ...
-    let re = Regex::new(r";[^\n]+\n").unwrap();
-    let src_nocomment = re.replace_all(src, "\n");
-    crate::parser::parse_expr(&src_nocomment)
-        .map_err(|e: nom::Err<VerboseError<&str>>| anyhow!("parser error - {e:#?}"))
-        .and_then(|(_, exp)| eval_expression(&exp, functions, globals, None, sample_set, out_mode))
+) -> std::result::Result<EvaluatedExpr, MegraError> {
+    let exp = parse_from_str(src)?;
+    // the tokenizer skips comments, so the positions are the same
+    eval_expression(&exp, functions, globals, None, sample_set, out_mode)
+        .map_err(|e| MegraError::eval(src, e))
 }
 
 #[cfg(test)]
@@ -805,4 +1296,127 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_lambdas_and_macros() {
+        let functions = crate::standard_library::define_standard_library();
+        let globals = sync::Arc::new(GlobalVariables::new());
+
+        let eval = |src: &str| {
+            parse_and_eval_from_str(
+                src,
+                &functions,
+                &globals,
+                SampleAndWavematrixSet::new(),
+                OutputMode::Stereo,
+            )
+            .unwrap()
+        };
+        let float = |res: EvaluatedExpr| match res {
+            EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(f))) => f,
+            _ => panic!("not a float"),
+        };
+
+        assert_eq!(
+            float(eval(
+                "(reduce add 0 (map (lambda (x) (mul x 2)) (vec 1 2 3)))"
+            )),
+            12.0
+        );
+
+        // closures
+        if let EvaluatedExpr::FunctionDefinition(name, args, body) =
+            eval("(fun adder (n) (lambda (x) (add x n)))")
+        {
+            functions.usr_lib.insert(name, (args, body));
+        }
+        assert_eq!(float(eval("(call (adder 3) 4)")), 7.0);
+
+        // macros
+        if let EvaluatedExpr::MacroDefinition(name, args, body) =
+            eval("(defmacro twice (e) `(add ,e ,e))")
+        {
+            functions.macros.insert(name, (args, body));
+        }
+        assert_eq!(float(eval("(twice (mul 2 3))")), 12.0);
+    }
+
+    #[test]
+    fn test_single_evaluating_thread() {
+        let id = || std::thread::current().id();
+        let first = on_evaluating_thread(id);
+        assert_ne!(first, id());
+
+        // a panic reaches the caller, the thread keeps evaluating ...
+        let panicked = std::panic::catch_unwind(|| on_evaluating_thread(|| panic!("eval panic")));
+        assert!(panicked.is_err());
+        assert_eq!(on_evaluating_thread(id), first);
+
+        // ... and doesn't send requests to itself
+        assert_eq!(
+            on_evaluating_thread(move || on_evaluating_thread(id)),
+            first
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        // on an evaluating thread, as the recursion limit is tuned for that
+        on_evaluating_thread(|| {
+            let functions = crate::standard_library::define_standard_library();
+            let globals = sync::Arc::new(GlobalVariables::new());
+
+            let eval = |src: &str| {
+                parse_and_eval_from_str(
+                    src,
+                    &functions,
+                    &globals,
+                    SampleAndWavematrixSet::new(),
+                    OutputMode::Stereo,
+                )
+            };
+            let float = |res: EvaluatedExpr| match res {
+                EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(f))) => f,
+                _ => panic!("not a float"),
+            };
+
+            assert_eq!(float(eval("(if (> 2 1) 1 (undefined))").unwrap()), 1.0);
+            assert_eq!(
+                float(eval("(cond ((< 2 1) 1) ((== 2 2) 2) (else 3))").unwrap()),
+                2.0
+            );
+
+            let Ok(EvaluatedExpr::Typed(TypedEntity::Vec(v))) =
+                eval("(for (x (vec 1 2 3)) (mul x x))")
+            else {
+                panic!("for doesn't collect a vector");
+            };
+            assert_eq!(v.len(), 3);
+
+            let Ok(EvaluatedExpr::Progn(p)) = eval("(dotimes (i 4) (add i 1))") else {
+                panic!("dotimes doesn't return a progn");
+            };
+            assert_eq!(p.len(), 4);
+
+            // recursion, the argument is used after the recursive call
+            for src in [
+                "(fun fact (n) (if (<= n 1) 1 (mul (fact (sub n 1)) n)))",
+                "(fun forever (n) (forever n))",
+                "(fun deep (n) (if (> n 0) (mul (deep (sub n 1)) 1) n))",
+            ] {
+                if let Ok(EvaluatedExpr::FunctionDefinition(name, args, body)) = eval(src) {
+                    functions.usr_lib.insert(name, (args, body));
+                }
+            }
+            assert_eq!(float(eval("(fact 5)").unwrap()), 120.0);
+            assert!(eval("(deep 1000)")
+                .unwrap_err()
+                .message
+                .contains("maximum call depth"));
+            assert!(eval("(forever 1)")
+                .unwrap_err()
+                .message
+                .contains("maximum call depth"));
+        });
+    }
 }
@@ -1,7 +1,13 @@
 use std::collections::HashMap;
 
+use crate::automation::{Ramp, RampCurve, RampLength, RampTarget};
 use crate::builtin_types::*;
+use crate::generator::Generator;
+use crate::generator_file;
+use crate::midi_file::ExportLength;
+use crate::offline_render::parse_duration_secs;
 use crate::parameter::*;
+use crate::score_export::ScoreFormat;
 
 use std::collections::BTreeSet;
 
@@ -611,6 +617,143 @@ pub fn default_duration(
     )))
 }
 
+fn ramp_target(name: &str) -> Option<RampTarget> {
+    Some(match name {
+        "bpm" => RampTarget::Bpm,
+        "tmod" => RampTarget::Tmod,
+        "reverb-mix" => RampTarget::Ruffbox(SynthParameterLabel::ReverbMix),
+        "reverb-roomsize" => RampTarget::Ruffbox(SynthParameterLabel::ReverbRoomsize),
+        "reverb-damp" => RampTarget::Ruffbox(SynthParameterLabel::ReverbDampening),
+        "delay-mix" => RampTarget::Ruffbox(SynthParameterLabel::DelayMix),
+        "delay-feedback" => RampTarget::Ruffbox(SynthParameterLabel::DelayFeedback),
+        "delay-time" => RampTarget::Ruffbox(SynthParameterLabel::DelayTime),
+        "delay-rate" => RampTarget::Ruffbox(SynthParameterLabel::DelayRate),
+        "delay-damp-freq" => RampTarget::Ruffbox(SynthParameterLabel::DelayDampeningFrequency),
+        _ => return None,
+    })
+}
+
+// from, to and the options of a ramp
+fn ramp_command(
+    name: &str,
+    target: RampTarget,
+    tail_drain: &mut impl Iterator<Item = EvaluatedExpr>,
+) -> Result<EvaluatedExpr> {
+    let (
+        Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(mut from)))),
+        Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(mut to)))),
+    ) = (tail_drain.next(), tail_drain.next())
+    else {
+        bail!("{name} - needs a start and an end value");
+    };
+
+    let mut length = RampLength::Seconds(8.0);
+    let mut curve = RampCurve::Linear;
+
+    while let Some(c) = tail_drain.next() {
+        if let EvaluatedExpr::Keyword(k) = c {
+            match k.as_str() {
+                // seconds, or a string like "32s", "500ms" or "16b" (beats)
+                "over" => {
+                    length = match tail_drain.next() {
+                        Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
+                            f,
+                        )))) => RampLength::Seconds(f as f64),
+                        Some(EvaluatedExpr::Typed(TypedEntity::Comparable(
+                            Comparable::String(s),
+                        ))) => {
+                            if let Some(beats) =
+                                s.strip_suffix('b').and_then(|b| b.trim().parse().ok())
+                            {
+                                RampLength::Beats(beats)
+                            } else if let Some(secs) = parse_duration_secs(&s) {
+                                RampLength::Seconds(secs)
+                            } else {
+                                bail!("{name} - invalid length {s}");
+                            }
+                        }
+                        _ => bail!("{name} - invalid length"),
+                    }
+                }
+                "curve" => {
+                    curve = match tail_drain.next() {
+                        Some(EvaluatedExpr::Typed(TypedEntity::Comparable(
+                            Comparable::Symbol(s),
+                        ))) => match s.as_str() {
+                            "lin" => RampCurve::Linear,
+                            "exp" => RampCurve::Exponential,
+                            "sine" => RampCurve::Sine,
+                            _ => bail!("{name} - unknown curve {s}, try 'lin, 'exp or 'sine"),
+                        },
+                        _ => bail!("{name} - invalid curve"),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // same units as in the delay function
+    if matches!(
+        target,
+        RampTarget::Ruffbox(SynthParameterLabel::DelayTime | SynthParameterLabel::DelayRate)
+    ) {
+        from /= 1000.0;
+        to /= 1000.0;
+    }
+
+    Ok(EvaluatedExpr::Command(Command::Ramp(Ramp {
+        target,
+        from,
+        to,
+        length,
+        curve,
+    })))
+}
+
+/// `(ramp 'reverb-mix 0.1 0.6 :over 16 :curve 'sine)`
+pub fn ramp(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let mut tail_drain = tail.drain(..).skip(1);
+    let target = match tail_drain.next() {
+        Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Symbol(s)))) => {
+            match ramp_target(&s) {
+                Some(t) => t,
+                None => bail!("ramp - can't ramp {s}"),
+            }
+        }
+        _ => bail!("ramp - needs a parameter to ramp"),
+    };
+    ramp_command("ramp", target, &mut tail_drain)
+}
+
+/// `(ramp-bpm 120 140 :over "32s")`
+pub fn ramp_bpm(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let mut tail_drain = tail.drain(..).skip(1);
+    ramp_command("ramp-bpm", RampTarget::Bpm, &mut tail_drain)
+}
+
+pub fn ramp_stop(
+    _: &FunctionMap,
+    _: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    Ok(EvaluatedExpr::Command(Command::RampStop))
+}
+
 pub fn globres(
     _: &FunctionMap,
     tail: &mut Vec<EvaluatedExpr>,
@@ -842,6 +985,85 @@ pub fn export_dot(
     }
 }
 
+/// filename, running generator ids, static generators and length,
+/// shared by the midi and score exports
+fn export_args(
+    name: &str,
+    tail: &mut Vec<EvaluatedExpr>,
+) -> Result<(String, BTreeSet<String>, Vec<Generator>, ExportLength)> {
+    let mut tail_drain = tail.drain(..).skip(1);
+
+    let filename =
+        if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::String(s)))) =
+            tail_drain.next()
+        {
+            s
+        } else {
+            bail!("{name} - missing filename");
+        };
+
+    let mut id_tags = BTreeSet::new();
+    let mut gens = Vec::new();
+    let mut length = ExportLength::Steps(64);
+
+    while let Some(c) = tail_drain.next() {
+        match c {
+            EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Symbol(s))) => {
+                id_tags.insert(s);
+            }
+            EvaluatedExpr::Typed(TypedEntity::Generator(g)) => {
+                gens.push(g);
+            }
+            EvaluatedExpr::Keyword(k) => {
+                let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(n)))) =
+                    tail_drain.next()
+                else {
+                    bail!("{name} - missing value for :{k}");
+                };
+                length = match k.as_str() {
+                    "steps" => ExportLength::Steps(n as usize),
+                    "secs" => ExportLength::Seconds(n as f64),
+                    _ => bail!("{name} - keyword arg {k} invalid"),
+                };
+            }
+            _ => bail!("{name} - invalid argument"),
+        }
+    }
+
+    Ok((filename, id_tags, gens, length))
+}
+
+/// export the event stream of generators to a standard midi file, either
+/// running ones (by tag) or ones passed directly
+pub fn export_midi(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let (filename, id_tags, gens, length) = export_args("export-midi", tail)?;
+    Ok(EvaluatedExpr::Command(Command::ExportMidi(
+        filename, id_tags, gens, length,
+    )))
+}
+
+pub fn export_score(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let (filename, id_tags, gens, length) = export_args("export-score", tail)?;
+    let Some(format) = ScoreFormat::from_filename(&filename) else {
+        bail!("export-score - can't tell the format of {filename}, use .ly or .musicxml");
+    };
+    Ok(EvaluatedExpr::Command(Command::ExportScore(
+        filename, format, id_tags, gens, length,
+    )))
+}
+
 pub fn once(
     _: &FunctionMap,
     tail: &mut Vec<EvaluatedExpr>,
@@ -934,31 +1156,57 @@ pub fn start_recording(
     _: SampleAndWavematrixSet,
     _: OutputMode,
 ) -> Result<EvaluatedExpr> {
-    let mut tail_drain = tail.drain(..).skip(1);
-    let prefix = if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::String(s)))) =
-        tail_drain.next()
+    let mut tail_drain = tail.drain(..).skip(1).peekable();
+    // the prefix is optional, so the keywords might come first
+    let prefix = if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::String(_)))) =
+        tail_drain.peek()
     {
-        Some(s)
+        match tail_drain.next() {
+            Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::String(s)))) => Some(s),
+            _ => None,
+        }
     } else {
         None
     };
 
     let mut rec_input = false;
+    let mut stems = Vec::new();
     while let Some(c) = tail_drain.next() {
         if let EvaluatedExpr::Keyword(k) = c {
-            if k.as_str() == "input" {
-                // default is zero ...
-                if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Boolean(b)))) =
-                    tail_drain.next()
-                {
-                    rec_input = b;
+            match k.as_str() {
+                "input" => {
+                    // default is zero ...
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(
+                        Comparable::Boolean(b),
+                    ))) = tail_drain.next()
+                    {
+                        rec_input = b;
+                    }
+                }
+                "stems" => {
+                    // generator ids or event tags, up to the next keyword
+                    while let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(
+                        Comparable::Symbol(_) | Comparable::String(_),
+                    ))) = tail_drain.peek()
+                    {
+                        if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(
+                            Comparable::Symbol(s) | Comparable::String(s),
+                        ))) = tail_drain.next()
+                        {
+                            stems.push(s);
+                        }
+                    }
+                    if stems.is_empty() {
+                        bail!("rec - :stems needs at least one generator id or tag");
+                    }
                 }
+                _ => {}
             }
         }
     }
 
     Ok(EvaluatedExpr::Command(Command::StartRecording(
-        prefix, rec_input,
+        prefix, rec_input, stems,
     )))
 }
 
@@ -989,6 +1237,52 @@ pub fn load_file(
     }
 }
 
+/// `(import "drums" :as d)`, the definitions of the file end up
+/// in the namespace `d`, i.e. `d/kick`
+pub fn import(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let mut tail_drain = tail.drain(..).skip(1);
+    let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::String(file)))) =
+        tail_drain.next()
+    else {
+        bail!("import - missing or invalid filename");
+    };
+
+    let mut namespace = None;
+    let mut reload = false;
+    while let Some(c) = tail_drain.next() {
+        if let EvaluatedExpr::Keyword(k) = c {
+            match k.as_str() {
+                "as" => match tail_drain.next() {
+                    Some(EvaluatedExpr::Identifier(n))
+                    | Some(EvaluatedExpr::Typed(TypedEntity::Comparable(
+                        Comparable::Symbol(n) | Comparable::String(n),
+                    ))) => namespace = Some(n),
+                    _ => bail!("import - invalid namespace"),
+                },
+                "reload" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(
+                        Comparable::Boolean(b),
+                    ))) = tail_drain.next()
+                    {
+                        reload = b;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(EvaluatedExpr::Command(Command::Import(
+        file, namespace, reload,
+    )))
+}
+
 pub fn print(
     _: &FunctionMap,
     tail: &mut Vec<EvaluatedExpr>,
@@ -1006,3 +1300,190 @@ pub fn print(
         bail!("print - missing or invalid entity")
     }
 }
+
+pub fn peer_sync(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let mut tail_drain = tail.drain(..).skip(1);
+
+    let mut base_port = crate::peer_sync::DEFAULT_BASE_PORT;
+    while let Some(c) = tail_drain.next() {
+        if let EvaluatedExpr::Keyword(k) = c {
+            if k.as_str() == "port" {
+                if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(f)))) =
+                    tail_drain.next()
+                {
+                    base_port = f as u16;
+                } else {
+                    bail!("peer-sync - invalid port");
+                }
+            }
+        }
+    }
+
+    Ok(EvaluatedExpr::Command(Command::PeerSyncStart(base_port)))
+}
+
+pub fn peer_sync_stop(
+    _: &FunctionMap,
+    _: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    Ok(EvaluatedExpr::Command(Command::PeerSyncStop))
+}
+
+pub fn eval_server(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let mut tail_drain = tail.drain(..).skip(1);
+
+    let mut port = crate::eval_server::DEFAULT_PORT;
+    while let Some(c) = tail_drain.next() {
+        if let EvaluatedExpr::Keyword(k) = c {
+            if k.as_str() == "port" {
+                if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(f)))) =
+                    tail_drain.next()
+                {
+                    port = f as u16;
+                } else {
+                    bail!("eval-server - invalid port");
+                }
+            }
+        }
+    }
+
+    Ok(EvaluatedExpr::Command(Command::EvalServerStart(port)))
+}
+
+pub fn eval_server_stop(
+    _: &FunctionMap,
+    _: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    Ok(EvaluatedExpr::Command(Command::EvalServerStop))
+}
+
+/// seed the session rng, to make things reproducible,
+/// without an argument, randomness is back to being random
+pub fn seed(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let mut tail_drain = tail.drain(..).skip(1);
+
+    match tail_drain.next() {
+        Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(f)))) => {
+            Ok(EvaluatedExpr::Command(Command::Seed(Some(f as u64))))
+        }
+        None => Ok(EvaluatedExpr::Command(Command::Seed(None))),
+        _ => bail!("seed - invalid seed"),
+    }
+}
+
+// the session name, as string or symbol
+fn session_name(name: &str, tail: &mut Vec<EvaluatedExpr>) -> Result<String> {
+    match tail.drain(..).nth(1) {
+        Some(EvaluatedExpr::Typed(TypedEntity::Comparable(
+            Comparable::String(s) | Comparable::Symbol(s),
+        ))) => Ok(s),
+        _ => bail!("{name} - missing session name"),
+    }
+}
+
+pub fn save_session(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    Ok(EvaluatedExpr::Command(Command::SaveSession(session_name(
+        "save-session",
+        tail,
+    )?)))
+}
+
+pub fn load_session(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    Ok(EvaluatedExpr::Command(Command::LoadSession(session_name(
+        "load-session",
+        tail,
+    )?)))
+}
+
+pub fn save_generator(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let mut tail_drain = tail.drain(..).skip(1);
+
+    let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::String(filename)))) =
+        tail_drain.next()
+    else {
+        bail!("save-generator - missing filename");
+    };
+
+    let mut id_tags = BTreeSet::new();
+    let mut gen = None;
+
+    for c in tail_drain {
+        match c {
+            EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Symbol(s))) => {
+                id_tags.insert(s);
+            }
+            EvaluatedExpr::Typed(TypedEntity::Generator(g)) => {
+                gen = Some(g);
+            }
+            _ => bail!("save-generator - invalid argument"),
+        }
+    }
+
+    if id_tags.is_empty() && gen.is_none() {
+        bail!("save-generator - missing generator");
+    }
+
+    Ok(EvaluatedExpr::Command(Command::SaveGenerator(
+        filename, id_tags, gen,
+    )))
+}
+
+pub fn load_generator(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::String(filename)))) =
+        tail.drain(..).nth(1)
+    else {
+        bail!("load-generator - missing filename");
+    };
+
+    Ok(EvaluatedExpr::Typed(TypedEntity::Generator(
+        generator_file::read_generator(&filename)?,
+    )))
+}
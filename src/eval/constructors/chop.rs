@@ -5,6 +5,7 @@ use crate::event::*;
 use crate::generator::Generator;
 use crate::markov_sequence_generator::MarkovSequenceGenerator;
 use crate::parameter::*;
+use crate::random::SeededRng;
 use crate::{OutputMode, SampleAndWavematrixSet};
 
 use anyhow::bail;
@@ -63,6 +64,7 @@ pub fn chop(
     let mut keep_root = false;
     let mut events = Vec::new();
     let mut time_shift = 0;
+    let mut seed = None;
 
     while let Some(c) = tail_drain.next() {
         match c {
@@ -103,6 +105,14 @@ pub fn chop(
                         max_repetitions = n;
                     }
                 }
+                "seed" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
+                        n,
+                    )))) = tail_drain.next()
+                    {
+                        seed = Some(n as u64);
+                    }
+                }
                 "shift" => {
                     if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
                         n,
@@ -258,5 +268,6 @@ pub fn chop(
         time_mods: Vec::new(),
         time_shift,
         keep_root,
+        rng: seed.map(SeededRng::new),
     })))
 }
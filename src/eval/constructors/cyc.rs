@@ -4,6 +4,7 @@ use crate::event::*;
 use crate::generator::Generator;
 use crate::markov_sequence_generator::MarkovSequenceGenerator;
 use crate::parameter::*;
+use crate::random::SeededRng;
 use crate::sample_set::SampleAndWavematrixSet;
 use crate::session::OutputMode;
 use anyhow::bail;
@@ -68,6 +69,7 @@ pub fn cyc(
     let mut ev_vecs = Vec::new();
     let mut keep_root = false;
     let mut time_shift = 0;
+    let mut seed = None;
 
     while let Some(c) = tail_drain.next() {
         if collect_template {
@@ -155,6 +157,15 @@ pub fn cyc(
                         tail_drain.next();
                     }
                 }
+                "seed" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
+                        n,
+                    )))) = tail_drain.peek()
+                    {
+                        seed = Some(*n as u64);
+                        tail_drain.next();
+                    }
+                }
                 "shift" => {
                     if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
                         n,
@@ -391,5 +402,6 @@ pub fn cyc(
         time_mods: Vec::new(),
         time_shift,
         keep_root,
+        rng: seed.map(SeededRng::new),
     })))
 }
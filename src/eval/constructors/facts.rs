@@ -196,5 +196,6 @@ pub fn facts(
         time_mods: Vec::new(),
         time_shift: 0,
         keep_root,
+        rng: None,
     })))
 }
@@ -4,6 +4,7 @@ use crate::event::*;
 use crate::generator::Generator;
 use crate::markov_sequence_generator::MarkovSequenceGenerator;
 use crate::parameter::*;
+use crate::random::SeededRng;
 
 use anyhow::bail;
 use anyhow::Result;
@@ -50,6 +51,7 @@ pub fn flower(
     let mut last_char: char = 'a'; // label chars
     let mut petal_labels = Vec::new();
     let mut time_shift = 0;
+    let mut seed = None;
 
     let mut dur: DynVal = if let TypedEntity::ConfigParameter(ConfigParameter::Numeric(d)) = globals
         .entry(VariableId::DefaultDuration)
@@ -147,6 +149,14 @@ pub fn flower(
                         num_layers = n as usize;
                     }
                 }
+                "seed" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
+                        n,
+                    )))) = tail_drain.next()
+                    {
+                        seed = Some(n as u64);
+                    }
+                }
                 "shift" => {
                     if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
                         n,
@@ -396,5 +406,6 @@ pub fn flower(
         time_mods: Vec::new(),
         time_shift,
         keep_root,
+        rng: seed.map(SeededRng::new),
     })))
 }
@@ -3,6 +3,7 @@ use crate::event::*;
 use crate::generator::Generator;
 use crate::markov_sequence_generator::MarkovSequenceGenerator;
 use crate::parameter::*;
+use crate::random::SeededRng;
 
 use anyhow::bail;
 use anyhow::Result;
@@ -46,6 +47,7 @@ pub fn friendship(
     let mut last_char: char = '1'; // label chars
     let mut friends_labels = Vec::new();
     let mut time_shift = 0;
+    let mut seed = None;
 
     let mut dur: DynVal = if let TypedEntity::ConfigParameter(ConfigParameter::Numeric(d)) = globals
         .entry(VariableId::DefaultDuration)
@@ -151,6 +153,14 @@ pub fn friendship(
                         randomize_chance = n;
                     }
                 }
+                "seed" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
+                        n,
+                    )))) = tail_drain.next()
+                    {
+                        seed = Some(n as u64);
+                    }
+                }
                 "shift" => {
                     if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
                         n,
@@ -372,5 +382,6 @@ pub fn friendship(
         time_mods: Vec::new(),
         time_shift,
         keep_root,
+        rng: seed.map(SeededRng::new),
     })))
 }
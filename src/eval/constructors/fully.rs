@@ -3,6 +3,7 @@ use crate::event::*;
 use crate::generator::Generator;
 use crate::markov_sequence_generator::MarkovSequenceGenerator;
 use crate::parameter::*;
+use crate::random::SeededRng;
 use anyhow::bail;
 use anyhow::Result;
 use std::collections::{BTreeMap, BTreeSet, HashMap};
@@ -45,6 +46,7 @@ pub fn fully(
     let mut last_char: char = 'a'; // label chars
     let mut labels = Vec::new();
     let mut time_shift = 0;
+    let mut seed = None;
 
     let mut dur: DynVal = if let TypedEntity::ConfigParameter(ConfigParameter::Numeric(d)) = globals
         .entry(VariableId::DefaultDuration)
@@ -127,6 +129,14 @@ pub fn fully(
                 //    collect_labeled = true;
                 //    continue;
                 //}
+                "seed" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
+                        n,
+                    )))) = tail_drain.next()
+                    {
+                        seed = Some(n as u64);
+                    }
+                }
                 "shift" => {
                     if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
                         n,
@@ -189,5 +199,6 @@ pub fn fully(
         time_mods: Vec::new(),
         time_shift,
         keep_root,
+        rng: seed.map(SeededRng::new),
     })))
 }
@@ -5,6 +5,7 @@ use crate::event::*;
 use crate::generator::Generator;
 use crate::markov_sequence_generator::{MarkovSequenceGenerator, Rule};
 use crate::parameter::*;
+use crate::random::SeededRng;
 
 use anyhow::bail;
 use anyhow::Result;
@@ -113,6 +114,7 @@ pub fn infer(
     let mut collect_events = false;
     let mut collect_rules = false;
     let mut time_shift = 0;
+    let mut seed = None;
 
     let mut dur: DynVal = if let TypedEntity::ConfigParameter(ConfigParameter::Numeric(d)) = globals
         .entry(VariableId::DefaultDuration)
@@ -205,6 +207,14 @@ pub fn infer(
                     }
                     _ => {}
                 },
+                "seed" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
+                        n,
+                    )))) = tail_drain.next()
+                    {
+                        seed = Some(n as u64);
+                    }
+                }
                 "shift" => {
                     if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
                         n,
@@ -256,5 +266,6 @@ pub fn infer(
         time_mods: Vec::new(),
         time_shift,
         keep_root,
+        rng: seed.map(SeededRng::new),
     })))
 }
@@ -4,6 +4,7 @@ use crate::event::*;
 use crate::generator::Generator;
 use crate::markov_sequence_generator::MarkovSequenceGenerator;
 use crate::parameter::*;
+use crate::random::SeededRng;
 
 use anyhow::bail;
 use anyhow::Result;
@@ -46,6 +47,7 @@ pub fn learn(
     let mut epsilon = 0.01;
     let mut pfa_size = 30;
     let mut time_shift = 0;
+    let mut seed = None;
 
     // flag to see whether we allow long names in sample
     let mut longnames = false;
@@ -393,6 +395,14 @@ pub fn learn(
                         pfa_size = n as usize;
                     }
                 }
+                "seed" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
+                        n,
+                    )))) = tail_drain.next()
+                    {
+                        seed = Some(n as u64);
+                    }
+                }
                 "shift" => {
                     if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
                         n,
@@ -522,5 +532,6 @@ pub fn learn(
         time_mods: Vec::new(),
         time_shift,
         keep_root,
+        rng: seed.map(SeededRng::new),
     })))
 }
@@ -124,5 +124,6 @@ pub fn linear(
         time_mods: Vec::new(),
         time_shift: 0,
         keep_root: false,
+        rng: None,
     })))
 }
@@ -9,5 +9,6 @@ pub mod learn;
 pub mod linear;
 pub mod r#loop;
 pub mod nuc;
+pub mod slice;
 pub mod stages;
 pub mod vals;
@@ -0,0 +1,488 @@
+use crate::builtin_types::*;
+use crate::eval::resolver::resolve_globals;
+use crate::eval::{EvaluatedExpr, FunctionMap};
+use crate::event::*;
+use crate::generator::Generator;
+use crate::markov_sequence_generator::MarkovSequenceGenerator;
+use crate::parameter::*;
+use crate::random::SeededRng;
+use crate::sample_set::SampleLookup;
+use crate::{OutputMode, SampleAndWavematrixSet};
+
+use anyhow::bail;
+use anyhow::Result;
+use ruffbox_synth::building_blocks::SynthParameterLabel;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync;
+use vom_rs::pfa::{Pfa, Rule};
+
+// the labels of the slices, in order, which also limits their number
+const SLICE_LABELS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// Slice a sample (or a freeze buffer) at its onsets, the slices are labeled
+/// 'a', 'b', 'c' ... 'z', 'A' ... 'Z' in order, so they can be
+/// used in a sample to learn from.
+pub fn slice(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    globals: &sync::Arc<GlobalVariables>,
+    sample_set: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    // eval-time resolve
+    // ignore function name
+    resolve_globals(&mut tail[1..], globals);
+    let mut tail_drain = tail.drain(1..);
+
+    // name is the first symbol
+    let name = if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Symbol(n)))) =
+        tail_drain.next()
+    {
+        n
+    } else {
+        bail!("slice - missing name");
+    };
+
+    let mut dur: DynVal = if let TypedEntity::ConfigParameter(ConfigParameter::Numeric(d)) = globals
+        .entry(VariableId::DefaultDuration)
+        .or_insert(TypedEntity::ConfigParameter(ConfigParameter::Numeric(
+            200.0,
+        )))
+        .value()
+    {
+        DynVal::with_value(*d)
+    } else {
+        bail!("slice - global default duration not present");
+    };
+
+    let mut event = None;
+    let mut learn_sample: Option<String> = None;
+    let mut bound = 3;
+    let mut epsilon = 0.01;
+    let mut pfa_size = 30;
+    let mut randomize_chance: f32 = 0.0;
+    let mut fit = false;
+    let mut keep_root = false;
+    let mut time_shift = 0;
+    let mut seed = None;
+
+    while let Some(c) = tail_drain.next() {
+        match c {
+            EvaluatedExpr::Typed(TypedEntity::SoundEvent(e)) => {
+                event = Some(e);
+            }
+            EvaluatedExpr::Keyword(k) => match k.as_str() {
+                "dur" => match tail_drain.next() {
+                    Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(n)))) => {
+                        dur = DynVal::with_value(n);
+                    }
+                    Some(EvaluatedExpr::Typed(TypedEntity::Parameter(p))) => {
+                        dur = p;
+                    }
+                    _ => {}
+                },
+                "learn" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(
+                        Comparable::String(s),
+                    ))) = tail_drain.next()
+                    {
+                        learn_sample = Some(s);
+                    }
+                }
+                "bound" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
+                        n,
+                    )))) = tail_drain.next()
+                    {
+                        bound = n as usize;
+                    }
+                }
+                "epsilon" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
+                        n,
+                    )))) = tail_drain.next()
+                    {
+                        epsilon = n;
+                    }
+                }
+                "size" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
+                        n,
+                    )))) = tail_drain.next()
+                    {
+                        pfa_size = n as usize;
+                    }
+                }
+                "rnd" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
+                        n,
+                    )))) = tail_drain.next()
+                    {
+                        randomize_chance = n;
+                    }
+                }
+                "fit" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(
+                        Comparable::Boolean(b),
+                    ))) = tail_drain.next()
+                    {
+                        fit = b;
+                    }
+                }
+                "seed" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
+                        n,
+                    )))) = tail_drain.next()
+                    {
+                        seed = Some(n as u64);
+                    }
+                }
+                "shift" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
+                        n,
+                    )))) = tail_drain.next()
+                    {
+                        time_shift = n as i32;
+                        tail_drain.next();
+                    }
+                }
+                "keep" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(
+                        Comparable::Boolean(b),
+                    ))) = tail_drain.next()
+                    {
+                        keep_root = b;
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    let Some(event) = event else {
+        bail!("slice - missing sample event");
+    };
+
+    // the live buffers keep changing, a freeze buffer is analyzed
+    // from megra's copy of its content
+    if event.name == "livesampler" {
+        bail!("slice - can't analyze live buffers, freeze them first");
+    }
+
+    // fix the sample, so that all slices come from the same one
+    let (source, sample, descriptors) = if event.name == "frozensampler" {
+        let bufnum = if let Some(ParameterValue::Scalar(b)) = event
+            .params
+            .get(&SynthParameterLabel::SampleBufferNumber.into())
+        {
+            b.static_val as usize
+        } else {
+            0
+        };
+        let Some(descriptors) = sample_set.live_buffers().freeze_buffer_descriptors(bufnum) else {
+            bail!("slice - no freeze buffer {}", bufnum + 1);
+        };
+        (format!("freeze buffer {}", bufnum + 1), None, descriptors)
+    } else {
+        let Some(lookup) = event.sample_lookup.as_ref() else {
+            bail!("slice - {} is not a sample event", event.name);
+        };
+        let set = lookup.set().to_string();
+        let Some((bufnum, duration)) = sample_set.resolve_lookup(lookup) else {
+            bail!("slice - no sample set with name {set} available");
+        };
+        let Some(descriptors) = sample_set.descriptors(&set, bufnum) else {
+            bail!("slice - sample from {set} hasn't been analyzed");
+        };
+        (
+            format!("sample from {set}"),
+            Some((set, bufnum, duration)),
+            descriptors,
+        )
+    };
+
+    if descriptors.onsets.is_empty() {
+        bail!("slice - no onsets found in {source}");
+    }
+    if descriptors.onsets.len() > SLICE_LABELS.len() {
+        println!(
+            "slice - {} onsets found in {source}, using the first {}",
+            descriptors.onsets.len(),
+            SLICE_LABELS.len()
+        );
+    }
+
+    // slices are shorter at higher rates
+    let rate = if let Some(ParameterValue::Scalar(r)) =
+        event.params.get(&SynthParameterLabel::PlaybackRate.into())
+    {
+        r.static_val.abs().max(0.01)
+    } else {
+        1.0
+    };
+
+    let mut event_mapping = BTreeMap::<char, (Vec<SourceEvent>, Event)>::new();
+    let mut labels = Vec::new();
+
+    for ((i, start), label) in descriptors
+        .onsets
+        .iter()
+        .enumerate()
+        .zip(SLICE_LABELS.chars())
+    {
+        let end = descriptors.onsets.get(i + 1).copied().unwrap_or(1.0);
+        let length = (end - start) * descriptors.length / rate;
+
+        let mut slice_event = event.clone();
+        if let Some((set, bufnum, duration)) = sample.as_ref() {
+            slice_event.sample_lookup =
+                Some(SampleLookup::FixedRandom(set.clone(), (*bufnum, *duration)));
+        }
+        slice_event.params.insert(
+            SynthParameterLabel::PlaybackStart.into(),
+            ParameterValue::Scalar(DynVal::with_value(*start)),
+        );
+        // leave some space for attack and release
+        slice_event.params.insert(
+            SynthParameterLabel::Sustain.into(),
+            ParameterValue::Scalar(DynVal::with_value((length - 2.0).max(1.0))),
+        );
+
+        let transition = if fit {
+            DynVal::with_value(length)
+        } else {
+            dur.clone()
+        };
+        event_mapping.insert(
+            label,
+            (
+                vec![SourceEvent::Sound(slice_event)],
+                Event::transition(transition),
+            ),
+        );
+        labels.push(label);
+    }
+
+    let mut pfa = if keep_root {
+        Pfa::<char>::new()
+    } else if let Some(learn_sample) = learn_sample {
+        // learn the order from the sample, ignoring unknown labels
+        let s_v: Vec<char> = learn_sample
+            .chars()
+            .filter(|c| event_mapping.contains_key(c))
+            .collect();
+        if s_v.is_empty() {
+            bail!(
+                "slice - no slice labels in {learn_sample:?}, use 'a' to '{}'",
+                labels[labels.len() - 1]
+            );
+        }
+        Pfa::<char>::learn(s_v, bound, epsilon, pfa_size)
+    } else {
+        // play the slices in order
+        let mut rules = Vec::new();
+        for (i, label) in labels.iter().enumerate() {
+            rules.push(Rule {
+                source: vec![*label],
+                symbol: labels[(i + 1) % labels.len()],
+                probability: 1.0,
+            });
+        }
+        let mut tmp = Pfa::<char>::infer_from_rules(&mut rules, true);
+        if randomize_chance > 0.0 {
+            tmp.randomize_edges(randomize_chance, randomize_chance);
+            tmp.rebalance();
+        }
+        tmp
+    };
+
+    pfa.restart_when_stuck = true;
+
+    let mut id_tags = BTreeSet::new();
+    id_tags.insert(name.clone());
+
+    Ok(EvaluatedExpr::Typed(TypedEntity::Generator(Generator {
+        id_tags,
+        root_generator: MarkovSequenceGenerator {
+            name,
+            generator: pfa,
+            event_mapping,
+            label_mapping: None,
+            override_durations: None,
+            modified: true,
+            symbol_ages: HashMap::new(),
+            default_duration: dur.static_val as u64,
+            last_transition: None,
+            last_symbol: None,
+        },
+        processors: Vec::new(),
+        time_mods: Vec::new(),
+        time_shift,
+        keep_root,
+        rng: seed.map(SeededRng::new),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample_analysis::SampleDescriptors;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_eval_slice() {
+        let snippet = "(slice 'br (break) :learn \"abcab\" :fit #t)";
+        let functions = FunctionMap::new();
+        let mut sample_set = SampleAndWavematrixSet::new();
+        sample_set.insert(
+            "break".to_string(),
+            HashSet::new(),
+            1,
+            1000,
+            Some(SampleDescriptors {
+                onsets: vec![0.0, 0.25, 0.5],
+                length: 1000.0,
+                ..Default::default()
+            }),
+        );
+
+        functions
+            .std_lib
+            .insert("slice".to_string(), crate::eval::constructors::slice::slice);
+        functions
+            .std_lib
+            .insert("break".to_string(), crate::eval::events::sound::sound);
+
+        let globals = sync::Arc::new(GlobalVariables::new());
+
+        match crate::eval::parse_and_eval_from_str(
+            snippet,
+            &functions,
+            &globals,
+            sample_set,
+            OutputMode::Stereo,
+        ) {
+            Ok(EvaluatedExpr::Typed(TypedEntity::Generator(g))) => {
+                let mapping = &g.root_generator.event_mapping;
+                assert_eq!(mapping.len(), 3);
+                let (events, transition) = mapping.get(&'c').unwrap();
+                let SourceEvent::Sound(ev) = &events[0] else {
+                    panic!();
+                };
+                assert!(matches!(
+                    ev.params.get(&SynthParameterLabel::PlaybackStart.into()),
+                    Some(ParameterValue::Scalar(s)) if s.static_val == 0.5
+                ));
+                assert!(matches!(
+                    transition.params.get(&SynthParameterLabel::Duration.into()),
+                    Some(ParameterValue::Scalar(d)) if d.static_val == 500.0
+                ));
+            }
+            Ok(_) => panic!(),
+            Err(e) => {
+                println!("err {e}");
+                panic!();
+            }
+        }
+    }
+
+    #[test]
+    fn test_eval_slice_labels() {
+        let functions = FunctionMap::new();
+        let mut sample_set = SampleAndWavematrixSet::new();
+        sample_set.insert(
+            "break".to_string(),
+            HashSet::new(),
+            1,
+            1000,
+            Some(SampleDescriptors {
+                onsets: (0..60).map(|i| i as f32 / 60.0).collect(),
+                length: 1000.0,
+                ..Default::default()
+            }),
+        );
+
+        functions
+            .std_lib
+            .insert("slice".to_string(), crate::eval::constructors::slice::slice);
+        functions
+            .std_lib
+            .insert("break".to_string(), crate::eval::events::sound::sound);
+
+        let globals = sync::Arc::new(GlobalVariables::new());
+
+        let Ok(EvaluatedExpr::Typed(TypedEntity::Generator(g))) =
+            crate::eval::parse_and_eval_from_str(
+                "(slice 'br (break))",
+                &functions,
+                &globals,
+                sample_set,
+                OutputMode::Stereo,
+            )
+        else {
+            panic!();
+        };
+
+        // one slice per label, the rest of the onsets are dropped
+        let mapping = &g.root_generator.event_mapping;
+        assert_eq!(mapping.len(), SLICE_LABELS.len());
+        assert!(mapping.contains_key(&'A'));
+        assert!(mapping.contains_key(&'Z'));
+        assert!(!mapping.contains_key(&'{'));
+    }
+
+    #[test]
+    fn test_eval_slice_freeze_buffer() {
+        let functions = FunctionMap::new();
+        let live_buffers =
+            sync::Arc::new(crate::live_buffers::LiveBuffers::new(1, 2, 1.0, 44100.0));
+        let sample_set = SampleAndWavematrixSet::with_live_buffers(live_buffers.clone());
+
+        // a click halfway through the live input, frozen to the second buffer
+        let mut input = vec![0.0; 44100];
+        for s in input[22050..22500].iter_mut() {
+            *s = 0.8;
+        }
+        live_buffers.write(&input, 1);
+        live_buffers.freeze(1, 0, false);
+
+        functions
+            .std_lib
+            .insert("slice".to_string(), crate::eval::constructors::slice::slice);
+        functions
+            .std_lib
+            .insert("freezr".to_string(), crate::eval::events::sound::sound);
+
+        let globals = sync::Arc::new(GlobalVariables::new());
+
+        let Ok(EvaluatedExpr::Typed(TypedEntity::Generator(g))) =
+            crate::eval::parse_and_eval_from_str(
+                "(slice 'fr (freezr 2))",
+                &functions,
+                &globals,
+                sample_set,
+                OutputMode::Stereo,
+            )
+        else {
+            panic!();
+        };
+
+        // the slices play from the freeze buffer, starting at the click
+        let found = g.root_generator.event_mapping.values().any(|(events, _)| {
+            let SourceEvent::Sound(ev) = &events[0] else {
+                return false;
+            };
+            ev.name == "frozensampler"
+                && matches!(
+                    ev.params.get(&SynthParameterLabel::SampleBufferNumber.into()),
+                    Some(ParameterValue::Scalar(b)) if b.static_val == 1.0
+                )
+                && matches!(
+                    ev.params.get(&SynthParameterLabel::PlaybackStart.into()),
+                    Some(ParameterValue::Scalar(s)) if (s.static_val - 0.5).abs() < 0.02
+                )
+        });
+        assert!(found);
+    }
+}
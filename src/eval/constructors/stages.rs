@@ -4,6 +4,7 @@ use crate::event::*;
 use crate::generator::Generator;
 use crate::markov_sequence_generator::MarkovSequenceGenerator;
 use crate::parameter::*;
+use crate::random::SeededRng;
 
 use anyhow::bail;
 use anyhow::Result;
@@ -56,6 +57,7 @@ pub fn stages(
     let mut pprev: f32 = 0.0;
     let mut cyclical = false;
     let mut time_shift = 0;
+    let mut seed = None;
 
     while let Some(c) = tail_drain.next() {
         match c {
@@ -77,6 +79,14 @@ pub fn stages(
                     }
                     _ => {}
                 },
+                "seed" => {
+                    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
+                        n,
+                    )))) = tail_drain.next()
+                    {
+                        seed = Some(n as u64);
+                    }
+                }
                 "shift" => {
                     if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(
                         n,
@@ -288,5 +298,6 @@ pub fn stages(
         time_mods: Vec::new(),
         time_shift,
         keep_root,
+        rng: seed.map(SeededRng::new),
     })))
 }
@@ -223,5 +223,6 @@ pub fn vals(
         time_mods: Vec::new(),
         time_shift: 0,
         keep_root,
+        rng: None,
     })))
 }
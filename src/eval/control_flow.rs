@@ -0,0 +1,279 @@
+//! Conditionals and loops. Unlike regular functions, these get the
+//! unevaluated expressions, so only the branches taken are evaluated.
+
+use anyhow::{anyhow, bail, Result};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync;
+
+use crate::ast_types::{Atom, Expr};
+use crate::builtin_types::*;
+use crate::eval::{eval_expression, EvaluatedExpr, FunctionMap, LocalVariables};
+use crate::megra_error::at_tail_index;
+use crate::{OutputMode, SampleAndWavematrixSet};
+
+fn boolean(b: bool) -> EvaluatedExpr {
+    EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Boolean(b)))
+}
+
+// everything but #f (or the empty list) counts as true
+fn is_true(val: &EvaluatedExpr, globals: &sync::Arc<GlobalVariables>) -> Result<bool> {
+    match val {
+        EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Boolean(b))) => Ok(*b),
+        EvaluatedExpr::Identifier(i) => match globals.get(&VariableId::Custom(i.clone())) {
+            Some(var) => Ok(!matches!(
+                var.value(),
+                TypedEntity::Comparable(Comparable::Boolean(false))
+            )),
+            None => bail!("unknown variable {i}"),
+        },
+        _ => Ok(true),
+    }
+}
+
+/// `(if condition then else)`, the else branch is optional
+pub fn eval_if(
+    tail: &[Expr],
+    functions: &FunctionMap,
+    globals: &sync::Arc<GlobalVariables>,
+    locals: Option<Rc<RefCell<LocalVariables>>>,
+    sample_set: SampleAndWavematrixSet,
+    out_mode: OutputMode,
+) -> Result<EvaluatedExpr> {
+    if tail.len() < 2 || tail.len() > 3 {
+        bail!("if - needs a condition, a branch and (optionally) an else branch");
+    }
+
+    let condition = eval_expression(
+        &tail[0],
+        functions,
+        globals,
+        locals.clone(),
+        sample_set.clone(),
+        out_mode,
+    )
+    .map_err(|e| at_tail_index(e, 0))?;
+
+    let branch = if is_true(&condition, globals).map_err(|e| at_tail_index(e, 0))? {
+        1
+    } else {
+        2
+    };
+
+    if let Some(expr) = tail.get(branch) {
+        eval_expression(expr, functions, globals, locals, sample_set, out_mode)
+            .map_err(|e| at_tail_index(e, branch))
+    } else {
+        Ok(boolean(false))
+    }
+}
+
+/// `(cond (test expr ...) ...)`, evaluates the expressions of the first
+/// clause whose test is true, `else` can be used as the last test
+pub fn eval_cond(
+    tail: &[Expr],
+    functions: &FunctionMap,
+    globals: &sync::Arc<GlobalVariables>,
+    locals: Option<Rc<RefCell<LocalVariables>>>,
+    sample_set: SampleAndWavematrixSet,
+    out_mode: OutputMode,
+) -> Result<EvaluatedExpr> {
+    for (idx, clause) in tail.iter().enumerate() {
+        let Expr::Application(test, body) = clause else {
+            return Err(at_tail_index(
+                anyhow!("cond - clauses need to look like (test expr ...)"),
+                idx,
+            ));
+        };
+
+        let result = if let Expr::Constant(Atom::Identifier(i)) = &**test {
+            if i == "else" {
+                boolean(true)
+            } else {
+                EvaluatedExpr::Identifier(i.clone())
+            }
+        } else {
+            eval_expression(
+                test,
+                functions,
+                globals,
+                locals.clone(),
+                sample_set.clone(),
+                out_mode,
+            )
+            .map_err(|e| at_tail_index(e, idx))?
+        };
+
+        if !is_true(&result, globals).map_err(|e| at_tail_index(e, idx))? {
+            continue;
+        }
+
+        // a clause without expressions returns the test result
+        let mut result = result;
+        for (body_idx, expr) in body.iter().enumerate() {
+            result = eval_expression(
+                expr,
+                functions,
+                globals,
+                locals.clone(),
+                sample_set.clone(),
+                out_mode,
+            )
+            .map_err(|e| at_tail_index(at_tail_index(e, body_idx), idx))?;
+        }
+        return Ok(result);
+    }
+
+    Ok(boolean(false))
+}
+
+// evaluate the loop body once for each value of the loop variable
+#[allow(clippy::too_many_arguments)]
+fn run_loop(
+    var: &str,
+    items: Vec<EvaluatedExpr>,
+    body: &[Expr],
+    functions: &FunctionMap,
+    globals: &sync::Arc<GlobalVariables>,
+    locals: &Rc<RefCell<LocalVariables>>,
+    sample_set: SampleAndWavematrixSet,
+    out_mode: OutputMode,
+) -> Result<Vec<EvaluatedExpr>> {
+    let mut results = Vec::new();
+    for item in items {
+        locals.borrow_mut().pos_args.insert(var.to_string(), item);
+
+        // the result of the last expression is kept, cl-style
+        let mut result = None;
+        for (idx, expr) in body.iter().enumerate() {
+            result = Some(
+                eval_expression(
+                    expr,
+                    functions,
+                    globals,
+                    Some(Rc::clone(locals)),
+                    sample_set.clone(),
+                    out_mode,
+                )
+                // the body starts after the binding
+                .map_err(|e| at_tail_index(e, idx + 1))?,
+            );
+        }
+        results.extend(result);
+    }
+    Ok(results)
+}
+
+/// `(dotimes (i 4) expr ...)` evaluates the expressions with `i` from 0 to 3,
+/// `(for (x (vec ...)) expr ...)` with `x` bound to each element and
+/// collects the results.
+pub fn eval_loop(
+    name: &str,
+    tail: &[Expr],
+    functions: &FunctionMap,
+    globals: &sync::Arc<GlobalVariables>,
+    locals: Option<Rc<RefCell<LocalVariables>>>,
+    sample_set: SampleAndWavematrixSet,
+    out_mode: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let binding = match tail.first() {
+        Some(Expr::Application(var, range)) => match (&**var, range.as_slice()) {
+            (Expr::Constant(Atom::Identifier(var)), [range]) => Some((var, range)),
+            _ => None,
+        },
+        _ => None,
+    };
+    let Some((var, range)) = binding else {
+        bail!("{name} - needs a binding like (i 4) as first argument");
+    };
+
+    let range = match eval_expression(
+        range,
+        functions,
+        globals,
+        locals.clone(),
+        sample_set.clone(),
+        out_mode,
+    )
+    .map_err(|e| at_tail_index(e, 0))?
+    {
+        EvaluatedExpr::Identifier(i) => match globals.get(&VariableId::Custom(i.clone())) {
+            Some(var) => EvaluatedExpr::Typed(var.value().clone()),
+            None => return Err(at_tail_index(anyhow!("unknown variable {i}"), 0)),
+        },
+        range => range,
+    };
+
+    let items: Vec<EvaluatedExpr> = match range {
+        EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(n))) => {
+            let n = n.max(0.0) as usize;
+            (0..n)
+                .map(|i| EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(i as f32))))
+                .collect()
+        }
+        EvaluatedExpr::Typed(TypedEntity::Vec(v)) if name == "for" => {
+            v.into_iter().map(|e| EvaluatedExpr::Typed(*e)).collect()
+        }
+        _ => {
+            return Err(at_tail_index(
+                anyhow!("{name} - can't iterate over this"),
+                0,
+            ))
+        }
+    };
+
+    let locals = locals.unwrap_or_else(|| Rc::new(RefCell::new(LocalVariables::new())));
+
+    // the loop variable shadows a local variable of the same name
+    let shadowed = locals.borrow().pos_args.get(var).cloned();
+    let results = run_loop(
+        var,
+        items,
+        &tail[1..],
+        functions,
+        globals,
+        &locals,
+        sample_set,
+        out_mode,
+    );
+    if let Some(shadowed) = shadowed {
+        locals.borrow_mut().pos_args.insert(var.clone(), shadowed);
+    } else {
+        locals.borrow_mut().pos_args.remove(var);
+    }
+    let results = results?;
+
+    if name == "dotimes" {
+        return Ok(EvaluatedExpr::Progn(results));
+    }
+
+    // generators are collected in a list, so they can be used in a
+    // sync context, other values in a vector
+    if results
+        .iter()
+        .all(|r| matches!(r, EvaluatedExpr::Typed(TypedEntity::Generator(_))))
+    {
+        Ok(EvaluatedExpr::Typed(TypedEntity::GeneratorList(
+            results
+                .into_iter()
+                .filter_map(|r| match r {
+                    EvaluatedExpr::Typed(TypedEntity::Generator(g)) => Some(g),
+                    _ => None,
+                })
+                .collect(),
+        )))
+    } else if results.iter().all(|r| matches!(r, EvaluatedExpr::Typed(_))) {
+        Ok(EvaluatedExpr::Typed(TypedEntity::Vec(
+            results
+                .into_iter()
+                .filter_map(|r| match r {
+                    EvaluatedExpr::Typed(t) => Some(Box::new(t)),
+                    _ => None,
+                })
+                .collect(),
+        )))
+    } else {
+        // i.e. commands
+        Ok(EvaluatedExpr::Progn(results))
+    }
+}
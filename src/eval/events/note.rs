@@ -27,7 +27,9 @@ pub fn event_note(
     let mut ev = Event::with_name("note".to_string());
 
     match tail_drain.next() {
-        Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::String(s)))) => {
+        Some(EvaluatedExpr::Typed(TypedEntity::Comparable(
+            Comparable::String(s) | Comparable::Symbol(s),
+        ))) => {
             ev.params.insert(
                 NoteParameterLabel::Pitch.into(),
                 crate::parameter::ParameterValue::Symbolic(s),
@@ -40,7 +42,7 @@ pub fn event_note(
             );
         }
         _ => {
-            bail!("note - first arg bust be string or number")
+            bail!("note - first arg bust be string, symbol or number")
         }
     }
 
@@ -100,6 +102,34 @@ pub fn event_note(
                         bail!("note - arg for keyword {k} missing!")
                     }
                 },
+                "lvl" => match tail_drain.next() {
+                    Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(f)))) => {
+                        ev.params.insert(
+                            SynthParameterLabel::EnvelopeLevel.into(),
+                            crate::parameter::ParameterValue::Scalar(DynVal::with_value(f)),
+                        );
+                    }
+                    Some(EvaluatedExpr::Typed(TypedEntity::Parameter(p))) => {
+                        ev.params.insert(
+                            SynthParameterLabel::EnvelopeLevel.into(),
+                            crate::parameter::ParameterValue::Scalar(p),
+                        );
+                    }
+                    _ => {
+                        bail!("note - invalid arg type for keyword {k}")
+                    }
+                },
+                "ch" | "channel" => match tail_drain.next() {
+                    Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(f)))) => {
+                        ev.params.insert(
+                            NoteParameterLabel::Channel.into(),
+                            crate::parameter::ParameterValue::Scalar(DynVal::with_value(f)),
+                        );
+                    }
+                    _ => {
+                        bail!("note - invalid arg type for keyword {k}")
+                    }
+                },
                 _ => {
                     bail!("note - invalid keyword {k}")
                 }
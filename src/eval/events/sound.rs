@@ -4,7 +4,7 @@ use crate::event::{Event, EventOperation};
 use crate::event_helpers::map_parameter;
 use crate::music_theory;
 use crate::parameter::{DynVal, ParameterValue};
-use crate::sample_set::SampleLookup;
+use crate::sample_set::{SampleLookup, SampleQuery};
 use crate::{GlobalVariables, OutputMode, SampleAndWavematrixSet, VariableId};
 
 use anyhow::{bail, Result};
@@ -75,6 +75,66 @@ pub fn map_symbolic_param_value(sym: &str) -> Option<ParameterValue> {
     }
 }
 
+/// the inverse of `map_symbolic_param_value`
+pub fn symbolic_param_name(val: &ParameterValue) -> Option<&'static str> {
+    match val {
+        ParameterValue::FilterType(t) => match t {
+            FilterType::BiquadHpf12dB => Some("hpf12"),
+            FilterType::BiquadHpf24dB => Some("hpf24"),
+            FilterType::BiquadLpf12dB => Some("lpf12"),
+            FilterType::BiquadLpf24dB => Some("lpf24"),
+            FilterType::Lpf18 => Some("lpf18"),
+            FilterType::ButterworthLpf(2) => Some("butter2lpf"),
+            FilterType::ButterworthLpf(4) => Some("butter4lpf"),
+            FilterType::ButterworthLpf(6) => Some("butter6lpf"),
+            FilterType::ButterworthLpf(8) => Some("butter8lpf"),
+            FilterType::ButterworthLpf(10) => Some("butter10lpf"),
+            FilterType::ButterworthHpf(2) => Some("butter2hpf"),
+            FilterType::ButterworthHpf(4) => Some("butter4hpf"),
+            FilterType::ButterworthHpf(6) => Some("butter6hpf"),
+            FilterType::ButterworthHpf(8) => Some("butter8hpf"),
+            FilterType::ButterworthHpf(10) => Some("butter10hpf"),
+            FilterType::PeakEQ => Some("peak"),
+            FilterType::Dummy => Some("none"),
+            // other orders can't be expressed symbolically
+            FilterType::ButterworthLpf(_) | FilterType::ButterworthHpf(_) => None,
+        },
+        ParameterValue::EnvelopeSegmentType(t) => match t {
+            // "sin" is an alias of "lin"
+            EnvelopeSegmentType::Lin => Some("lin"),
+            EnvelopeSegmentType::Cos => Some("cos"),
+            EnvelopeSegmentType::Log => Some("log"),
+            EnvelopeSegmentType::Exp => Some("exp"),
+            EnvelopeSegmentType::Constant => Some("const"),
+            EnvelopeSegmentType::Sin => None,
+        },
+        ParameterValue::OscillatorType(t) => Some(match t {
+            OscillatorType::Sine => "sine",
+            OscillatorType::LFTri => "tri",
+            OscillatorType::LFSquare => "sqr",
+            OscillatorType::LFSaw => "saw",
+            OscillatorType::LFRsaw => "rsaw",
+            OscillatorType::WTSaw => "wsaw",
+            OscillatorType::FMSquare => "fmsqr",
+            OscillatorType::FMSaw => "fmsaw",
+            OscillatorType::FMTri => "fmtri",
+            OscillatorType::NaiveBlit => "blit",
+            OscillatorType::LFCub => "cub",
+            OscillatorType::WhiteNoise => "white",
+            OscillatorType::BrownNoise => "brown",
+            OscillatorType::Wavetable => "wtab",
+            OscillatorType::Wavematrix => "wmat",
+        }),
+        ParameterValue::BitcrusherMode(m) => Some(match m {
+            BitcrusherMode::Cast => "cast",
+            BitcrusherMode::Floor => "floor",
+            BitcrusherMode::Round => "round",
+            BitcrusherMode::Ceil => "ceil",
+        }),
+        _ => None,
+    }
+}
+
 pub fn resolve_vector(vec: Vec<Box<TypedEntity>>) -> ParameterValue {
     let mut pvec = Vec::new();
     for x in vec.into_iter() {
@@ -375,6 +435,36 @@ fn nofilter_defaults(ev: &mut Event) {
     );
 }
 
+/// the keywords that choose samples by their descriptors
+pub const SAMPLE_QUERIES: &[&str] = &["loudest", "quietest", "brightest", "darkest", "near-pitch"];
+
+/// sample lookups by sound character, like `:loudest` or `:near-pitch 'a4`
+fn get_sample_query(
+    k: &str,
+    tail_drain: &mut std::iter::Peekable<std::vec::Drain<EvaluatedExpr>>,
+) -> Result<SampleQuery> {
+    Ok(match k {
+        "loudest" => SampleQuery::Loudest,
+        "quietest" => SampleQuery::Quietest,
+        "brightest" => SampleQuery::Brightest,
+        "darkest" => SampleQuery::Darkest,
+        "near-pitch" => match tail_drain.next() {
+            Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(f)))) => {
+                SampleQuery::NearPitch(f)
+            }
+            Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Symbol(s)))) => {
+                let note = music_theory::from_string(&s)?;
+                SampleQuery::NearPitch(music_theory::to_freq(
+                    note,
+                    music_theory::Tuning::EqualTemperament,
+                ))
+            }
+            _ => bail!("sound event - :near-pitch needs a note or a frequency"),
+        },
+        _ => bail!("sound event - unknown sample query {k}"),
+    })
+}
+
 pub fn sound(
     _: &FunctionMap,
     tail: &mut Vec<EvaluatedExpr>,
@@ -588,6 +678,11 @@ pub fn sound(
                 ev.tags.insert(s.clone());
                 tail_drain.next();
             }
+        } else if SAMPLE_QUERIES.contains(&k.as_str()) {
+            let Some(lookup) = ev.sample_lookup.take() else {
+                bail!("sound event - :{k} only works with samples");
+            };
+            ev.sample_lookup = Some(lookup.with_query(get_sample_query(&k, &mut tail_drain)?));
         } else if k.starts_with("wm") {
             // use start_with to account for possible indices
             // wavematrix lookup
@@ -0,0 +1,161 @@
+use anyhow::{bail, Result};
+
+use crate::builtin_types::*;
+use crate::eval::{apply_function, is_function, EvaluatedExpr, FunctionMap};
+use crate::{OutputMode, SampleAndWavematrixSet};
+
+use super::resolver::resolve_globals;
+
+use std::sync;
+
+// the function and the vector to work on
+fn function_and_vec(
+    name: &str,
+    functions: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    globals: &sync::Arc<GlobalVariables>,
+) -> Result<(EvaluatedExpr, Vec<TypedEntity>)> {
+    resolve_globals(tail, globals);
+
+    let mut tail_drain = tail.drain(1..);
+
+    let Some(fun) = tail_drain
+        .next()
+        .filter(|f| is_function(f, functions, globals))
+    else {
+        bail!("{name} - first argument needs to be a function");
+    };
+
+    let Some(EvaluatedExpr::Typed(TypedEntity::Vec(v))) = tail_drain.next() else {
+        bail!("{name} - second argument needs to be a vector");
+    };
+
+    Ok((fun, v.into_iter().map(|e| *e).collect()))
+}
+
+/// `(map fun (vec ...))`, apply a function to each element of a vector
+pub fn map(
+    functions: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    globals: &sync::Arc<GlobalVariables>,
+    sample_set: SampleAndWavematrixSet,
+    out_mode: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let (fun, v) = function_and_vec("map", functions, tail, globals)?;
+
+    let mut mapped = Vec::new();
+    for elem in v {
+        match apply_function(
+            &fun,
+            vec![EvaluatedExpr::Typed(elem)],
+            functions,
+            globals,
+            sample_set.clone(),
+            out_mode,
+        )? {
+            EvaluatedExpr::Typed(t) => mapped.push(Box::new(t)),
+            _ => bail!("map - function result can't be stored in a vector"),
+        }
+    }
+
+    Ok(EvaluatedExpr::Typed(TypedEntity::Vec(mapped)))
+}
+
+/// `(filter fun (vec ...))`, keep the elements for which the function returns true
+pub fn filter(
+    functions: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    globals: &sync::Arc<GlobalVariables>,
+    sample_set: SampleAndWavematrixSet,
+    out_mode: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let (fun, v) = function_and_vec("filter", functions, tail, globals)?;
+
+    let mut filtered = Vec::new();
+    for elem in v {
+        match apply_function(
+            &fun,
+            vec![EvaluatedExpr::Typed(elem.clone())],
+            functions,
+            globals,
+            sample_set.clone(),
+            out_mode,
+        )? {
+            EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Boolean(true))) => {
+                filtered.push(Box::new(elem))
+            }
+            EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Boolean(false))) => {}
+            _ => bail!("filter - function needs to return a boolean"),
+        }
+    }
+
+    Ok(EvaluatedExpr::Typed(TypedEntity::Vec(filtered)))
+}
+
+/// `(reduce fun (vec ...))` or `(reduce fun init (vec ...))`, combine the
+/// elements of a vector, from left to right
+pub fn reduce(
+    functions: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    globals: &sync::Arc<GlobalVariables>,
+    sample_set: SampleAndWavematrixSet,
+    out_mode: OutputMode,
+) -> Result<EvaluatedExpr> {
+    resolve_globals(tail, globals);
+
+    // the initial value is optional
+    let init = if tail.len() > 3 {
+        Some(tail.remove(2))
+    } else {
+        None
+    };
+
+    let (fun, v) = function_and_vec("reduce", functions, tail, globals)?;
+
+    let mut elems = v.into_iter().map(EvaluatedExpr::Typed);
+    let Some(mut acc) = init.or_else(|| elems.next()) else {
+        bail!("reduce - can't reduce an empty vector without initial value");
+    };
+
+    for elem in elems {
+        acc = apply_function(
+            &fun,
+            vec![acc, elem],
+            functions,
+            globals,
+            sample_set.clone(),
+            out_mode,
+        )?;
+    }
+
+    Ok(acc)
+}
+
+/// `(call fun args ...)`, call a function value
+pub fn call(
+    functions: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    globals: &sync::Arc<GlobalVariables>,
+    sample_set: SampleAndWavematrixSet,
+    out_mode: OutputMode,
+) -> Result<EvaluatedExpr> {
+    resolve_globals(&mut tail[1..], globals);
+
+    let mut tail_drain = tail.drain(1..);
+
+    let Some(fun) = tail_drain
+        .next()
+        .filter(|f| is_function(f, functions, globals))
+    else {
+        bail!("call - first argument needs to be a function");
+    };
+
+    apply_function(
+        &fun,
+        tail_drain.collect(),
+        functions,
+        globals,
+        sample_set,
+        out_mode,
+    )
+}
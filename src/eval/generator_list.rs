@@ -40,7 +40,7 @@ pub fn generator_list(
 pub fn spread_list(
     _: &FunctionMap,
     tail: &mut Vec<EvaluatedExpr>,
-    _: &sync::Arc<GlobalVariables>,
+    globals: &sync::Arc<GlobalVariables>,
     _: SampleAndWavematrixSet,
     out_mode: OutputMode,
 ) -> Result<EvaluatedExpr> {
@@ -63,7 +63,7 @@ pub fn spread_list(
         }
     }
 
-    spread_gens(&mut gen_list, &out_mode);
+    spread_gens(&mut gen_list, &out_mode, globals);
 
     Ok(EvaluatedExpr::Typed(TypedEntity::GeneratorList(gen_list)))
 }
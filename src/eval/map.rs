@@ -5,16 +5,25 @@ use anyhow::{anyhow, bail, Result};
 
 use crate::builtin_types::*;
 
-use crate::eval::{EvaluatedExpr, FunctionMap};
+use crate::eval::{functional, is_function, EvaluatedExpr, FunctionMap};
 use crate::{OutputMode, SampleAndWavematrixSet};
 
 pub fn map(
-    _: &FunctionMap,
+    functions: &FunctionMap,
     tail: &mut Vec<EvaluatedExpr>,
-    _: &sync::Arc<GlobalVariables>,
-    _: SampleAndWavematrixSet,
-    _: OutputMode,
+    globals: &sync::Arc<GlobalVariables>,
+    sample_set: SampleAndWavematrixSet,
+    out_mode: OutputMode,
 ) -> Result<EvaluatedExpr> {
+    // (map fun (vec ...)) maps a function over a vector,
+    // otherwise a map is created from pairs
+    if tail
+        .get(1)
+        .is_some_and(|f| is_function(f, functions, globals))
+    {
+        return functional::map(functions, tail, globals, sample_set, out_mode);
+    }
+
     let tail_drain = tail.drain(..).skip(1);
 
     let mut pmap = HashMap::new();
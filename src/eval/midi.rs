@@ -40,3 +40,67 @@ pub fn open_midi_port(
         Err(anyhow!("can't open midi port - invalid port"))
     }
 }
+
+pub fn open_midi_out_port(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let mut tail_drain = tail.drain(1..);
+
+    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(port)))) =
+        tail_drain.next()
+    {
+        Ok(EvaluatedExpr::Command(
+            crate::builtin_types::Command::MidiStartSender(port as usize),
+        ))
+    } else {
+        Err(anyhow!("can't open midi out port - invalid port"))
+    }
+}
+
+pub fn midi_clock_start(
+    _: &FunctionMap,
+    _: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    Ok(EvaluatedExpr::Command(
+        crate::builtin_types::Command::MidiClockStart,
+    ))
+}
+
+pub fn midi_clock_stop(
+    _: &FunctionMap,
+    _: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    Ok(EvaluatedExpr::Command(
+        crate::builtin_types::Command::MidiClockStop,
+    ))
+}
+
+pub fn midi_clock_follow(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let mut tail_drain = tail.drain(1..);
+
+    if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(port)))) =
+        tail_drain.next()
+    {
+        Ok(EvaluatedExpr::Command(
+            crate::builtin_types::Command::MidiClockFollow(port as usize),
+        ))
+    } else {
+        Err(anyhow!("can't follow midi clock - invalid port"))
+    }
+}
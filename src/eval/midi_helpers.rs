@@ -1,8 +1,8 @@
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, Result};
 
 use crate::builtin_types::{Comparable, TypedEntity};
 use crate::eval::{EvaluatedExpr, FunctionMap};
-use crate::music_theory::{from_string, to_freq};
+use crate::music_theory::{from_string, pitch_class_symbol, to_freq};
 use crate::{GlobalVariables, OutputMode, SampleAndWavematrixSet};
 
 use std::sync;
@@ -48,23 +48,7 @@ pub fn mtosym(
     if let Some(EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(note)))) =
         tail_drain.next()
     {
-        let pclass = match note as usize % 12 {
-            0 => "c",
-            1 => "cs",
-            2 => "d",
-            3 => "ds",
-            4 => "e",
-            5 => "f",
-            6 => "fs",
-            7 => "g",
-            8 => "gs",
-            9 => "a",
-            10 => "as",
-            11 => "b",
-            _ => {
-                bail!("mtosym - complete failure")
-            }
-        };
+        let pclass = pitch_class_symbol((note as usize % 12) as u8);
 
         let oct = (note / 12.0).floor() as usize;
 
@@ -4,66 +4,77 @@ use std::collections::{BTreeSet, HashMap};
 use std::sync;
 
 use crate::builtin_types::*;
+use crate::commands;
 use crate::event::{Event, EventOperation};
 use crate::generator::Generator;
 use crate::generator_processor::{GeneratorWrapperProcessor, PearProcessor};
 use crate::parameter::{DynVal, ParameterValue};
+use crate::speaker_layout::SpeakerLayout;
 
 use crate::eval::{EvaluatedExpr, FunctionMap};
 use crate::{OutputMode, SampleAndWavematrixSet};
 
 use super::resolver::resolve_globals;
 
-pub type GenSpreader = fn(&mut [Generator], &OutputMode);
-
-pub fn spread_gens(gens: &mut [Generator], out_mode: &OutputMode) {
-    let positions = match out_mode {
-        OutputMode::Stereo => {
-            if gens.len() == 1 {
-                vec![0.0]
-            } else {
-                let mut p = Vec::new();
-                for i in 0..gens.len() {
-                    let val = (i as f32 * (2.0 / (gens.len() as f32 - 1.0))) - 1.0;
-                    p.push(val);
+pub type GenSpreader = fn(&mut [Generator], &OutputMode, &sync::Arc<GlobalVariables>);
+
+pub fn spread_gens(
+    gens: &mut [Generator],
+    out_mode: &OutputMode,
+    globals: &sync::Arc<GlobalVariables>,
+) {
+    // with a speaker layout, the generators go around all the speakers
+    let positions = if let Some(speakers) = commands::get_speaker_count(globals) {
+        SpeakerLayout::spread_positions(speakers, gens.len())
+    } else {
+        match out_mode {
+            OutputMode::Stereo => {
+                if gens.len() == 1 {
+                    vec![0.0]
+                } else {
+                    let mut p = Vec::new();
+                    for i in 0..gens.len() {
+                        let val = (i as f32 * (2.0 / (gens.len() as f32 - 1.0))) - 1.0;
+                        p.push(val);
+                    }
+                    p
                 }
-                p
             }
-        }
-        OutputMode::FourChannel => {
-            if gens.len() == 1 {
-                vec![0.0]
-            } else {
-                let mut p = Vec::new();
-                for i in 0..gens.len() {
-                    let val = 1.0 + (i as f32 * (3.0 / (gens.len() as f32 - 1.0)));
-                    p.push(val);
+            OutputMode::FourChannel => {
+                if gens.len() == 1 {
+                    vec![0.0]
+                } else {
+                    let mut p = Vec::new();
+                    for i in 0..gens.len() {
+                        let val = 1.0 + (i as f32 * (3.0 / (gens.len() as f32 - 1.0)));
+                        p.push(val);
+                    }
+                    p
                 }
-                p
             }
-        }
-        OutputMode::EightChannel => {
-            if gens.len() == 1 {
-                vec![0.0]
-            } else {
-                let mut p = Vec::new();
-                for i in 0..gens.len() {
-                    let val = 1.0 + (i as f32 * (7.0 / (gens.len() as f32 - 1.0)));
-                    p.push(val);
+            OutputMode::EightChannel => {
+                if gens.len() == 1 {
+                    vec![0.0]
+                } else {
+                    let mut p = Vec::new();
+                    for i in 0..gens.len() {
+                        let val = 1.0 + (i as f32 * (7.0 / (gens.len() as f32 - 1.0)));
+                        p.push(val);
+                    }
+                    p
                 }
-                p
             }
-        }
-        OutputMode::SixteenChannel => {
-            if gens.len() == 1 {
-                vec![0.0]
-            } else {
-                let mut p = Vec::new();
-                for i in 0..gens.len() {
-                    let val = 1.0 + (i as f32 * (15.0 / (gens.len() as f32 - 1.0)));
-                    p.push(val);
+            OutputMode::SixteenChannel => {
+                if gens.len() == 1 {
+                    vec![0.0]
+                } else {
+                    let mut p = Vec::new();
+                    for i in 0..gens.len() {
+                        let val = 1.0 + (i as f32 * (15.0 / (gens.len() as f32 - 1.0)));
+                        p.push(val);
+                    }
+                    p
                 }
-                p
             }
         }
     };
@@ -152,7 +163,7 @@ pub fn eval_multiplyer(
                 gens.push(pclone);
             }
             gens.push(g);
-            gen_spread(&mut gens, &out_mode);
+            gen_spread(&mut gens, &out_mode, globals);
             EvaluatedExpr::Typed(TypedEntity::GeneratorList(gens))
         }
         Some(EvaluatedExpr::Typed(TypedEntity::GeneratorList(mut gl))) => {
@@ -199,7 +210,7 @@ pub fn eval_multiplyer(
                     gens.push(pclone);
                 }
                 gens.push(gen);
-                gen_spread(&mut gens, &out_mode);
+                gen_spread(&mut gens, &out_mode, globals);
             }
             EvaluatedExpr::Typed(TypedEntity::GeneratorList(gens))
         }
@@ -226,5 +237,5 @@ pub fn eval_xdup(
     out_mode: OutputMode,
 ) -> Result<EvaluatedExpr> {
     resolve_globals(&mut tail[1..], globals);
-    eval_multiplyer(|_, _| {}, tail, out_mode, globals)
+    eval_multiplyer(|_, _, _| {}, tail, out_mode, globals)
 }
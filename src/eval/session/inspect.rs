@@ -0,0 +1,81 @@
+use anyhow::{anyhow, bail, Result};
+
+use crate::builtin_types::*;
+use crate::eval::{EvaluatedExpr, FunctionMap};
+use crate::session_view::SessionView;
+use crate::{OutputMode, SampleAndWavematrixSet};
+
+use std::sync;
+
+fn with_view<T>(
+    name: &str,
+    functions: &FunctionMap,
+    f: impl Fn(&dyn SessionView) -> T,
+) -> Result<T> {
+    let view = functions.session_view.read();
+    let view = view
+        .as_ref()
+        .ok_or_else(|| anyhow!("{name} - no running session to inspect"))?;
+    Ok(f(view.as_ref()))
+}
+
+/// `(running)`, the state of all running generators
+pub fn running(
+    functions: &FunctionMap,
+    _: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let generators = with_view("running", functions, |v| v.generators())?;
+    Ok(EvaluatedExpr::Typed(TypedEntity::Vec(
+        generators.iter().map(|g| Box::new(g.to_map())).collect(),
+    )))
+}
+
+/// `(contexts)`, all sync contexts and their generators
+pub fn contexts(
+    functions: &FunctionMap,
+    _: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let contexts = with_view("contexts", functions, |v| v.contexts())?;
+    Ok(EvaluatedExpr::Typed(TypedEntity::Vec(
+        contexts.iter().map(|c| Box::new(c.to_map())).collect(),
+    )))
+}
+
+/// `(inspect 'name)`, a running generator with that tag,
+/// or else the context with that name
+pub fn inspect(
+    functions: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    _: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    let name = match tail.drain(1..).next() {
+        Some(EvaluatedExpr::Typed(TypedEntity::Comparable(
+            Comparable::Symbol(s) | Comparable::String(s),
+        ))) => s,
+        _ => bail!("inspect - needs the name of a generator or context"),
+    };
+
+    let found = with_view("inspect", functions, |v| {
+        if let Some(g) = v.generators().iter().find(|g| g.id_tags.contains(&name)) {
+            Some(g.to_map())
+        } else {
+            v.contexts()
+                .iter()
+                .find(|c| c.name == name)
+                .map(|c| c.to_map())
+        }
+    })?;
+
+    match found {
+        Some(map) => Ok(EvaluatedExpr::Typed(map)),
+        None => bail!("inspect - nothing called {name} is running"),
+    }
+}
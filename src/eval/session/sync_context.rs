@@ -50,6 +50,7 @@ pub fn sync_context(
             block_tags: BTreeSet::new(),
             solo_tags: BTreeSet::new(),
             resync: false,
+            quantize: None,
         }));
     }
 
@@ -61,6 +62,7 @@ pub fn sync_context(
     let mut block_tags: BTreeSet<String> = BTreeSet::new();
     let mut solo_tags: BTreeSet<String> = BTreeSet::new();
     let mut resync = false;
+    let mut quantize = None;
 
     while let Some(c) = tail_drain.next() {
         match c {
@@ -95,6 +97,14 @@ pub fn sync_context(
                             shift = f as i32;
                         }
                     }
+                    "quant" => {
+                        collect_solo_tags = false;
+                        collect_block_tags = false;
+                        quantize = tail_drain.next().as_ref().and_then(quantization);
+                        if quantize.is_none() {
+                            bail!("sx - invalid quantization, try a number of beats, 'beat, 'bar or #f");
+                        }
+                    }
                     "solo" => {
                         collect_block_tags = false;
                         collect_solo_tags = true;
@@ -140,9 +150,40 @@ pub fn sync_context(
         block_tags,
         solo_tags,
         resync,
+        quantize,
     }))
 }
 
+/// a quantization grid in beats, given as number, `'beat`, `'bar` or `#f` (off)
+fn quantization(expr: &EvaluatedExpr) -> Option<f32> {
+    match expr {
+        EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Float(f))) => Some(f.max(0.0)),
+        EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Symbol(s))) => match s.as_str() {
+            "beat" => Some(1.0),
+            "bar" => Some(4.0),
+            _ => None,
+        },
+        EvaluatedExpr::Typed(TypedEntity::Comparable(Comparable::Boolean(false))) => Some(0.0),
+        _ => None,
+    }
+}
+
+/// `(quantize 'bar)`, contexts start on the next bar (or beat, or
+/// number of beats), unless they say otherwise
+pub fn quantize(
+    _: &FunctionMap,
+    tail: &mut Vec<EvaluatedExpr>,
+    globals: &sync::Arc<GlobalVariables>,
+    _: SampleAndWavematrixSet,
+    _: OutputMode,
+) -> Result<EvaluatedExpr> {
+    resolve_globals(&mut tail[1..], globals);
+    match tail.drain(1..).next().as_ref().and_then(quantization) {
+        Some(q) => Ok(EvaluatedExpr::Command(Command::Quantize(q))),
+        None => bail!("quantize - needs a number of beats, 'beat, 'bar or #f"),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
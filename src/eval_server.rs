@@ -0,0 +1,469 @@
+//! A local network server to evaluate Mégra code from other programs,
+//! mostly meant for editor plugins.
+//!
+//! Plain TCP clients send one request per line, either as JSON
+//! (`{"id": 1, "code": "(sx ...)"}`) or as bare code, and get one JSON line
+//! back per request. WebSocket clients connect to the same port and send the
+//! same requests as text messages.
+
+use anyhow::{bail, Result};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::{sync, thread};
+
+use crate::builtin_types::{Comparable, TypedEntity, VariableId};
+use crate::eval::{self, EvaluatedExpr};
+use crate::interpreter;
+use crate::megra_error::{ErrorKind, MegraError};
+use crate::midi_file::track_name;
+use crate::parser::{tokenize, TokenKind};
+use crate::session::Session;
+
+pub const DEFAULT_PORT: u16 = 57200;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// more than enough for any code, anything larger is refused
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+pub struct EvalServer {
+    pub port: u16,
+    running: sync::Arc<AtomicBool>,
+}
+
+impl EvalServer {
+    /// Listen on the given port on localhost. Only local clients
+    /// can connect, as the code is evaluated without any further checks.
+    pub fn start<const BUFSIZE: usize, const NCHAN: usize>(
+        session: Session<BUFSIZE, NCHAN>,
+        base_dir: String,
+        port: u16,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+
+        let running = sync::Arc::new(AtomicBool::new(true));
+        let running2 = sync::Arc::clone(&running);
+
+        thread::Builder::new()
+            .name("megra-eval-server".to_string())
+            .spawn(move || {
+                while running2.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let session = session.clone();
+                            let base_dir = base_dir.clone();
+                            let running = sync::Arc::clone(&running2);
+                            let spawned = thread::Builder::new()
+                                .name("megra-eval-connection".to_string())
+                                .spawn(move || {
+                                    if let Err(e) =
+                                        handle_client(stream, session, base_dir, running)
+                                    {
+                                        println!("eval server - connection closed: {e}");
+                                    }
+                                });
+                            if let Err(e) = spawned {
+                                println!("eval server - can't handle connection: {e}");
+                            }
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                            thread::sleep(POLL_INTERVAL);
+                        }
+                        Err(e) => {
+                            println!("eval server - can't accept connection: {e}");
+                            thread::sleep(POLL_INTERVAL);
+                        }
+                    }
+                }
+            })?;
+
+        println!("eval server listening on 127.0.0.1:{port}");
+
+        Ok(EvalServer { port, running })
+    }
+
+    /// Stop accepting connections, open connections are
+    /// closed once they send their next request.
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Drop for EvalServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// a short description of an evaluation result
+fn describe(expr: &EvaluatedExpr) -> String {
+    match expr {
+        EvaluatedExpr::Typed(TypedEntity::Comparable(c)) => match c {
+            Comparable::Float(f) => f.to_string(),
+            Comparable::Double(f) => f.to_string(),
+            Comparable::Int32(i) => i.to_string(),
+            Comparable::Int64(i) => i.to_string(),
+            Comparable::UInt128(i) => i.to_string(),
+            Comparable::String(s) => format!("\"{s}\""),
+            Comparable::Symbol(s) => format!("'{s}"),
+            Comparable::Character(c) => c.to_string(),
+            Comparable::Boolean(b) => if *b { "#t" } else { "#f" }.to_string(),
+        },
+        EvaluatedExpr::Typed(TypedEntity::Generator(g)) => {
+            format!("generator {}", track_name(&g.id_tags))
+        }
+        EvaluatedExpr::Typed(TypedEntity::GeneratorList(gl)) => {
+            format!("{} generators", gl.len())
+        }
+        EvaluatedExpr::Typed(TypedEntity::SoundEvent(ev)) => format!("event {}", ev.name),
+        EvaluatedExpr::Typed(TypedEntity::ControlEvent(_)) => "control event".to_string(),
+        EvaluatedExpr::Typed(TypedEntity::Vec(v)) => format!("vector of {}", v.len()),
+        EvaluatedExpr::Typed(TypedEntity::Map(m)) => format!("map of {}", m.len()),
+        EvaluatedExpr::Typed(TypedEntity::Matrix(m)) => format!("matrix of {} rows", m.len()),
+        EvaluatedExpr::Typed(TypedEntity::Function(_)) => "function".to_string(),
+        EvaluatedExpr::Typed(TypedEntity::Quoted(_)) => "quoted code".to_string(),
+        EvaluatedExpr::Typed(_) => "value".to_string(),
+        EvaluatedExpr::SyncContext(sx) => format!("sync context {}", sx.name),
+        EvaluatedExpr::Command(_) => "command".to_string(),
+        EvaluatedExpr::Progn(p) => format!("progn of {}", p.len()),
+        EvaluatedExpr::FunctionDefinition(name, _, _) => format!("function {name}"),
+        EvaluatedExpr::MacroDefinition(name, _, _) => format!("macro {name}"),
+        EvaluatedExpr::VariableDefinition(id, _, _) => match id {
+            VariableId::Custom(s) => format!("variable {s}"),
+            VariableId::Symbol(s) => format!("variable '{s}"),
+            _ => "variable".to_string(),
+        },
+        EvaluatedExpr::Keyword(k) => format!(":{k}"),
+        EvaluatedExpr::Identifier(i) => i.clone(),
+        EvaluatedExpr::EvaluatedExprList(l) => format!("list of {}", l.len()),
+        EvaluatedExpr::Comparator(_) => "comparator".to_string(),
+    }
+}
+
+fn error_json(src: &str, offset: usize, err: &MegraError) -> Value {
+    let kind = match err.kind {
+        ErrorKind::Parse => "parse",
+        ErrorKind::Incomplete => "incomplete",
+        ErrorKind::Eval => "eval",
+    };
+
+    // positions relative to the whole request
+    let (line, column) = if let Some(span) = err.span {
+        let before = &src[..offset];
+        let line = before.matches('\n').count() + span.line;
+        let column = if span.line == 1 {
+            let line_start = before.rfind('\n').map(|p| p + 1).unwrap_or(0);
+            before[line_start..].chars().count() + span.column
+        } else {
+            span.column
+        };
+        (Some(line), Some(column))
+    } else {
+        (None, None)
+    };
+
+    json!({ "kind": kind, "message": err.message, "line": line, "column": column })
+}
+
+// the start and end of each top level expression,
+// an unclosed one extends to the end
+fn top_level_ranges(src: &str) -> Vec<(usize, usize)> {
+    let Ok(tokens) = tokenize(src) else {
+        return vec![(0, src.len())];
+    };
+
+    let mut ranges = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for t in tokens.iter() {
+        match t.kind {
+            TokenKind::Open => {
+                if depth == 0 {
+                    start = t.start;
+                }
+                depth += 1;
+            }
+            TokenKind::Close if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    ranges.push((start, t.end));
+                }
+            }
+            // plain values or variables
+            TokenKind::Atom | TokenKind::String if depth == 0 => {
+                ranges.push((t.start, t.end));
+            }
+            _ => {}
+        }
+    }
+    if depth > 0 {
+        ranges.push((start, src.len()));
+    }
+    ranges
+}
+
+/// Evaluate all expressions in the code and describe the results.
+pub fn evaluate<const BUFSIZE: usize, const NCHAN: usize>(
+    code: &str,
+    session: &Session<BUFSIZE, NCHAN>,
+    base_dir: &str,
+) -> Value {
+    let mut results = Vec::new();
+    let mut ok = true;
+
+    for (start, end) in top_level_ranges(code) {
+        let src = &code[start..end];
+        match eval::parse_and_eval_from_str(
+            src,
+            &session.functions,
+            &session.globals,
+            session.sample_set.clone(),
+            session.output_mode,
+        ) {
+            Ok(expr) => {
+                let value = describe(&expr);
+                interpreter::interpret_source(src, expr, session.clone(), base_dir.to_string());
+                results.push(json!({ "ok": true, "value": value }));
+            }
+            Err(e) => {
+                ok = false;
+                results.push(json!({ "ok": false, "error": error_json(code, start, &e) }));
+            }
+        }
+    }
+
+    json!({ "ok": ok, "results": results })
+}
+
+fn handle_request<const BUFSIZE: usize, const NCHAN: usize>(
+    request: &str,
+    session: &Session<BUFSIZE, NCHAN>,
+    base_dir: &str,
+) -> String {
+    // bare code is fine as well
+    let (id, code) = match serde_json::from_str::<Value>(request) {
+        Ok(Value::Object(req)) => (
+            req.get("id").cloned().unwrap_or(Value::Null),
+            req.get("code")
+                .and_then(|c| c.as_str())
+                .unwrap_or("")
+                .to_string(),
+        ),
+        _ => (Value::Null, request.to_string()),
+    };
+
+    let session = session.clone();
+    let base_dir = base_dir.to_string();
+    let mut response = eval::on_evaluating_thread(move || evaluate(&code, &session, &base_dir));
+    response["id"] = id;
+    response.to_string()
+}
+
+fn handle_client<const BUFSIZE: usize, const NCHAN: usize>(
+    stream: TcpStream,
+    session: Session<BUFSIZE, NCHAN>,
+    base_dir: String,
+    running: sync::Arc<AtomicBool>,
+) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    let mut first = String::new();
+    if reader.read_line(&mut first)? == 0 {
+        return Ok(());
+    }
+
+    if first.starts_with("GET ") {
+        return handle_websocket(reader, writer, session, base_dir, running);
+    }
+
+    let mut line = first;
+    loop {
+        if !running.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        if !line.trim().is_empty() {
+            let response = handle_request(line.trim(), &session, &base_dir);
+            writer.write_all(response.as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+    }
+}
+
+fn handle_websocket<const BUFSIZE: usize, const NCHAN: usize>(
+    mut reader: BufReader<TcpStream>,
+    mut writer: TcpStream,
+    session: Session<BUFSIZE, NCHAN>,
+    base_dir: String,
+    running: sync::Arc<AtomicBool>,
+) -> Result<()> {
+    let mut key = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(());
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let Some(key) = key else {
+        writer.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")?;
+        bail!("not a websocket request");
+    };
+
+    write!(
+        writer,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        websocket_accept(&key)
+    )?;
+
+    let mut message = Vec::new();
+    while running.load(Ordering::SeqCst) {
+        let (fin, opcode, payload) = read_frame(&mut reader)?;
+        match opcode {
+            // text or continuation
+            0x1 | 0x0 => {
+                if message.len() + payload.len() > MAX_MESSAGE_SIZE {
+                    bail!("message exceeds the maximum of {MAX_MESSAGE_SIZE} bytes");
+                }
+                message.extend_from_slice(&payload);
+                if fin {
+                    let request = String::from_utf8_lossy(&message).to_string();
+                    message.clear();
+                    let response = handle_request(request.trim(), &session, &base_dir);
+                    write_frame(&mut writer, 0x1, response.as_bytes())?;
+                }
+            }
+            // close
+            0x8 => {
+                write_frame(&mut writer, 0x8, &payload)?;
+                return Ok(());
+            }
+            // ping
+            0x9 => write_frame(&mut writer, 0xA, &payload)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn read_frame(reader: &mut impl Read) -> Result<(bool, u8, Vec<u8>)> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head)?;
+    let fin = head[0] & 0x80 != 0;
+    let opcode = head[0] & 0x0F;
+    let masked = head[1] & 0x80 != 0;
+
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf)?;
+            u16::from_be_bytes(buf) as usize
+        }
+        127 => {
+            let mut buf = [0u8; 8];
+            reader.read_exact(&mut buf)?;
+            u64::from_be_bytes(buf).try_into().unwrap_or(usize::MAX)
+        }
+        l => l as usize,
+    };
+    if len > MAX_MESSAGE_SIZE {
+        bail!("frame of {len} bytes exceeds the maximum of {MAX_MESSAGE_SIZE}");
+    }
+
+    let mut mask = [0u8; 4];
+    if masked {
+        reader.read_exact(&mut mask)?;
+    }
+
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    if masked {
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+    }
+
+    Ok((fin, opcode, payload))
+}
+
+// servers send unmasked frames
+fn write_frame(writer: &mut impl Write, opcode: u8, payload: &[u8]) -> Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    if payload.len() < 126 {
+        frame.push(payload.len() as u8);
+    } else if payload.len() <= u16::MAX as usize {
+        frame.push(126);
+        frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    } else {
+        frame.push(127);
+        frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)?;
+    Ok(())
+}
+
+fn websocket_accept(key: &str) -> String {
+    let hash = Sha1::digest(format!("{key}{WEBSOCKET_GUID}").as_bytes());
+    BASE64_STANDARD.encode(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_websocket_accept() {
+        // the example from RFC 6455
+        assert_eq!(
+            websocket_accept("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_oversized_frame() {
+        // a masked text frame claiming a 64 bit length
+        let mut frame = vec![0x81, 0xFF];
+        frame.extend_from_slice(&u64::MAX.to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0, 0]);
+        assert!(read_frame(&mut frame.as_slice()).is_err());
+
+        let mut frame = vec![0x81, 0x83, 0, 0, 0, 0];
+        frame.extend_from_slice(b"abc");
+        let (fin, opcode, payload) = read_frame(&mut frame.as_slice()).unwrap();
+        assert!(fin);
+        assert_eq!(opcode, 0x1);
+        assert_eq!(payload, b"abc");
+    }
+
+    #[test]
+    fn test_top_level_ranges() {
+        let src = "(sx 'a #t (nuc 'b (bd)))\n;; (comment)\n(once (sn))\n(sx 'c";
+        let ranges = top_level_ranges(src);
+        assert_eq!(ranges.len(), 3);
+        assert_eq!(&src[ranges[1].0..ranges[1].1], "(once (sn))");
+        assert_eq!(ranges[2].1, src.len());
+    }
+}
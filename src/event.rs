@@ -133,7 +133,7 @@ impl StaticEvent {
             (Some(my_lookup), Some(SampleLookup::Key(_, other_keys))) => {
                 match my_lookup {
                     // key "arithmetic"
-                    SampleLookup::Key(_, my_keys) => {
+                    SampleLookup::Key(_, my_keys) | SampleLookup::Descriptor(_, my_keys, _) => {
                         match other.op {
                             EventOperation::Add => {
                                 // add all keys from the other set
@@ -183,6 +183,9 @@ impl StaticEvent {
                     SampleLookup::N(fname, _) => {
                         *my_lookup = SampleLookup::Random(fname.to_string());
                     }
+                    SampleLookup::Descriptor(fname, _, _) => {
+                        *my_lookup = SampleLookup::Random(fname.to_string());
+                    }
                 }
             }
             (Some(my_lookup), Some(SampleLookup::N(_, n))) => {
@@ -198,6 +201,9 @@ impl StaticEvent {
                     SampleLookup::Random(fname) => {
                         *my_lookup = SampleLookup::N(fname.to_string(), *n);
                     }
+                    SampleLookup::Descriptor(fname, _, _) => {
+                        *my_lookup = SampleLookup::N(fname.to_string(), *n);
+                    }
                     SampleLookup::N(_, original_n) => match other.op {
                         EventOperation::Add => {
                             *original_n += *n;
@@ -221,6 +227,26 @@ impl StaticEvent {
                     },
                 }
             }
+            (Some(my_lookup), Some(SampleLookup::Descriptor(_, other_keys, query))) => {
+                // choose by sound from now on, keys are combined as above
+                let mut lookup = my_lookup.clone().with_query(*query);
+                if let SampleLookup::Descriptor(_, my_keys, _) = &mut lookup {
+                    match other.op {
+                        EventOperation::Add => {
+                            my_keys.extend(other_keys.iter().cloned());
+                        }
+                        EventOperation::Subtract => {
+                            for key in other_keys.iter() {
+                                my_keys.remove(key);
+                            }
+                        }
+                        _ => {
+                            *my_keys = other_keys.clone();
+                        }
+                    }
+                }
+                *my_lookup = lookup;
+            }
             _ => {}
         }
     }
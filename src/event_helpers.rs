@@ -467,6 +467,8 @@ pub fn map_parameter(name: &str) -> ParameterAddress {
         "nharm" => SynthParameterLabel::NumHarmonics.into(),
         "art" | "articulation" => NoteParameterLabel::Articulation.into(),
         "syl" | "syllable" => NoteParameterLabel::Syllable.into(),
+        // start at the nth onset of a sample, resolved along with the sample
+        "onset" => ParameterAddress::Custom("onset".to_string()),
         _ => SynthParameterLabel::PitchFrequency.into(),
     };
 
@@ -486,3 +488,35 @@ pub fn map_parameter(name: &str) -> ParameterAddress {
         address
     }
 }
+
+// the canonical names of the parameters, the first one that
+// maps to a label is used when writing parameters back out
+pub const PARAMETER_NAMES: &[&str] = &[
+    "freq", "osc", "note", "atk", "atkt", "atkp", "dec", "dect", "rel", "relt", "sus", "env",
+    "pos", "lvl", "amp", "dur", "lpf", "lpd", "lpq", "lpt", "hpf", "hpq", "hpt", "pff", "pfbw",
+    "pfg", "pw", "rate", "start", "loop", "bufnum", "rev", "del", "azi", "ele", "wt", "wm", "ti",
+    "dist", "delfb", "deldf", "delft", "ft", "bcmix", "bcbits", "bcdown", "bcmode", "nharm", "art",
+    "syl", "onset",
+];
+
+/// The inverse of `map_parameter`, custom parameters other than the
+/// known ones don't have a name.
+pub fn parameter_name(address: &ParameterAddress) -> Option<String> {
+    match address {
+        ParameterAddress::Ruffbox(addr) => {
+            let name = PARAMETER_NAMES.iter().find(|n| {
+                matches!(map_parameter(n), ParameterAddress::Ruffbox(a) if a.label == addr.label)
+            })?;
+            // indices start at one in the language
+            Some(if let Some(idx) = addr.idx {
+                format!("{name}{}", idx + 1)
+            } else {
+                name.to_string()
+            })
+        }
+        ParameterAddress::Note(_) | ParameterAddress::Custom(_) => PARAMETER_NAMES
+            .iter()
+            .find(|n| map_parameter(n) == *address)
+            .map(|n| n.to_string()),
+    }
+}
@@ -1,7 +1,19 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::{fs, path};
 
+use crate::ast_types::{Atom, Expr};
+use crate::eval::{self, eval_expression};
+use crate::megra_error::MegraError;
 use crate::{interpreter, session::Session};
 
+thread_local! {
+    // the files that are currently being loaded, innermost last,
+    // to resolve relative imports and to detect import cycles
+    static FILE_STACK: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
 pub fn find_closing_paren(text: &str, mut pos: usize) -> Option<usize> {
     let mut par_lvl = 1;
 
@@ -90,6 +102,11 @@ pub fn parse_file<const BUFSIZE: usize, const NCHAN: usize>(
     match fs::read_to_string(p) {
         Ok(s) => {
             let expressions = segment_expressions(s);
+            // so imports in the file are relative to it
+            let canonical = p.canonicalize().ok();
+            if let Some(p) = canonical.clone() {
+                FILE_STACK.with(|stack| stack.borrow_mut().push(p));
+            }
 
             for expr in expressions {
                 let res = {
@@ -102,10 +119,17 @@ pub fn parse_file<const BUFSIZE: usize, const NCHAN: usize>(
                     )
                 };
 
-                if let Ok(res) = res {
-                    interpreter::interpret(res, session.clone(), base_dir.clone());
+                match res {
+                    Ok(res) => {
+                        interpreter::interpret_source(&expr, res, session.clone(), base_dir.clone())
+                    }
+                    Err(e) => println!("{}", e.caret_diagnostic(&expr)),
                 }
             }
+
+            if canonical.is_some() {
+                FILE_STACK.with(|stack| stack.borrow_mut().pop());
+            }
         }
         Err(e) => {
             println!("couldn't parse file {path:?} - {e}");
@@ -113,10 +137,172 @@ pub fn parse_file<const BUFSIZE: usize, const NCHAN: usize>(
     }
 }
 
+/// find a file to import, relative to the importing file, the base folder
+/// or the sketchbook, the `.megra3` extension can be left out
+fn resolve_import(name: &str, base_dir: &str) -> Option<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(dir) = FILE_STACK.with(|stack| {
+        stack
+            .borrow()
+            .last()
+            .and_then(|f| f.parent().map(Path::to_path_buf))
+    }) {
+        dirs.push(dir);
+    }
+    dirs.push(PathBuf::from(base_dir));
+    dirs.push(Path::new(base_dir).join("sketchbook"));
+
+    for dir in dirs {
+        // absolute paths replace the dir when joined
+        for candidate in [dir.join(name), dir.join(format!("{name}.megra3"))] {
+            if candidate.is_file() {
+                return candidate.canonicalize().ok();
+            }
+        }
+    }
+    None
+}
+
+// the names a file defines with fun, defmacro or let
+fn defined_names<'a>(exprs: impl Iterator<Item = &'a Expr>) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for expr in exprs {
+        if let Expr::Definition(head, tail) = expr {
+            if matches!(
+                **head,
+                Expr::FunctionDefinition
+                    | Expr::MacroDefinition
+                    | Expr::VariableDefinition
+                    | Expr::PersistantStateDefinition
+            ) {
+                if let Some(Expr::Constant(Atom::Identifier(name))) = tail.first() {
+                    names.insert(name.clone());
+                }
+            }
+        }
+    }
+    names
+}
+
+// put all uses of the given names into the namespace
+fn prefix_names(expr: &mut Expr, namespace: &str, names: &HashSet<String>) {
+    match expr {
+        Expr::Constant(Atom::Identifier(name)) if names.contains(name) => {
+            *name = format!("{namespace}/{name}");
+        }
+        Expr::Application(head, tail) | Expr::Definition(head, tail) => {
+            prefix_names(head, namespace, names);
+            for expr in tail.iter_mut() {
+                prefix_names(expr, namespace, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Import a file once, with its definitions in a namespace, which is
+/// the file name if none is given. `kick` becomes `drums/kick`.
+pub fn import_file<const BUFSIZE: usize, const NCHAN: usize>(
+    name: &str,
+    namespace: Option<String>,
+    reload: bool,
+    session: &Session<BUFSIZE, NCHAN>,
+    base_dir: String,
+) {
+    let Some(path) = resolve_import(name, &base_dir) else {
+        println!("import - can't find {name}");
+        return;
+    };
+
+    let namespace = namespace.unwrap_or_else(|| {
+        path.file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| name.to_string())
+    });
+
+    let cycle = FILE_STACK.with(|stack| {
+        let stack = stack.borrow();
+        stack.iter().position(|f| *f == path).map(|pos| {
+            stack[pos..]
+                .iter()
+                .chain(std::iter::once(&path))
+                .map(|f| f.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ")
+        })
+    });
+    if let Some(cycle) = cycle {
+        println!("import - import cycle: {cycle}");
+        return;
+    }
+
+    let key = (path.clone(), namespace.clone());
+    if !reload && session.imports.lock().contains(&key) {
+        return;
+    }
+
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) => {
+            println!("import - couldn't read {} - {e}", path.display());
+            return;
+        }
+    };
+
+    // parse everything first, to know which names go into the namespace
+    let mut exprs = Vec::new();
+    for src in segment_expressions(text) {
+        match eval::parse_from_str(&src) {
+            Ok(expr) => exprs.push((src, expr)),
+            Err(e) => println!("{}", e.caret_diagnostic(&src)),
+        }
+    }
+
+    let names = defined_names(exprs.iter().map(|(_, e)| e));
+
+    FILE_STACK.with(|stack| stack.borrow_mut().push(path.clone()));
+    for (src, mut expr) in exprs {
+        prefix_names(&mut expr, &namespace, &names);
+        match eval_expression(
+            &expr,
+            &session.functions,
+            &session.globals,
+            None,
+            session.sample_set.clone(),
+            session.output_mode,
+        ) {
+            // not journaled, replaying the import takes care of that
+            Ok(res) => interpreter::interpret(res, session.clone(), base_dir.clone()),
+            Err(e) => println!("{}", MegraError::eval(&src, e).caret_diagnostic(&src)),
+        }
+    }
+    FILE_STACK.with(|stack| stack.borrow_mut().pop());
+
+    session.imports.lock().insert(key);
+    println!("imported {} as {namespace}", path.display());
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_namespace_prefix() {
+        let src = "(fun kick (x) (sx 'k #t (nuc x (bd)) (hat)))";
+        let mut expr = eval::parse_from_str(src).unwrap();
+        let names = defined_names(std::iter::once(&expr));
+        assert!(names.contains("kick") && names.len() == 1);
+
+        prefix_names(&mut expr, "d", &names);
+        let Expr::Definition(_, tail) = expr else {
+            panic!()
+        };
+        assert!(matches!(&tail[0], Expr::Constant(Atom::Identifier(n)) if n == "d/kick"));
+
+        // namespaced names can be parsed
+        assert!(eval::parse_from_str("(d/kick 1)").is_ok());
+    }
+
     #[test]
     fn test_file_segmentation() {
         let a = ";; hi
@@ -4,6 +4,7 @@ use crate::{
     event::{EventOperation, InterpretableEvent, StaticEvent},
     generator_processor::GeneratorProcessor,
     markov_sequence_generator::MarkovSequenceGenerator,
+    random::SeededRng,
     sample_set::SampleAndWavematrixSet,
     session::OutputMode,
 };
@@ -57,6 +58,10 @@ pub struct Generator {
     // the keep_root flag determines whether we replace the root at
     // subsequent evaluations ...
     pub keep_root: bool,
+
+    // the rng for everything random that happens while
+    // evaluating this generator, if it has been seeded
+    pub rng: Option<SeededRng>,
 }
 
 impl fmt::Debug for Generator {
@@ -3,7 +3,7 @@ use std::collections::{HashMap, HashSet};
 
 use crate::{
     builtin_types::ConfigParameter, generator::modifier_functions_raw::*, generator::Generator,
-    parameter::DynVal, GlobalVariables,
+    parameter::DynVal, random, GlobalVariables,
 };
 
 pub type GenModFun = fn(
@@ -110,7 +110,7 @@ pub fn shrink(
         .root_generator
         .generator
         .alphabet
-        .choose(&mut rand::thread_rng())
+        .choose(&mut random::CurrentRng)
     {
         let r2 = *random_symbol;
         shrink_raw(&mut gen.root_generator, r2, true);
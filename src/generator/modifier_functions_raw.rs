@@ -6,7 +6,7 @@ use crate::{
     parameter::{DynVal, ParameterAddress},
     pfa_growth::*,
     pfa_reverse::*,
-    GlobalVariables,
+    random, GlobalVariables,
 };
 use rand::seq::SliceRandom;
 use std::collections::HashSet;
@@ -95,12 +95,12 @@ pub fn grow_raw(
                     for t in result.added_transitions.iter() {
                         let mut label = t.source.clone();
                         label.push(t.symbol);
-                        let dur_val = durations.choose(&mut rand::thread_rng()).unwrap().clone();
+                        let dur_val = durations.choose(&mut random::CurrentRng).unwrap().clone();
                         add_leaf(dur_tree, &label, Some(dur_val.static_val as u64));
                     }
                 }
 
-                let dur_val = durations.choose(&mut rand::thread_rng()).unwrap().clone();
+                let dur_val = durations.choose(&mut random::CurrentRng).unwrap().clone();
                 gen.event_mapping
                     .insert(added_sym, (new_evs, Event::transition(dur_val)));
             } else {
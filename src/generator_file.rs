@@ -0,0 +1,574 @@
+//! Writing generators to files and reading them back, to keep grown,
+//! learned or otherwise evolved generators across sessions.
+//!
+//! The structure of the generator (PFA, events, labels and durations) is
+//! saved, as well as processors that don't hold generator modifier functions
+//! (pear, every, lifemodel, mapper and wrapped generators). Parameter modifiers
+//! are frozen to their current value. Control events and the remaining
+//! processors, which may contain just about anything, can't be saved.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+
+use crate::eval::events::sound::{map_symbolic_param_value, symbolic_param_name};
+use crate::event::{Event, EventOperation, SourceEvent};
+use crate::event_helpers::{map_parameter, parameter_name};
+use crate::generator::Generator;
+use crate::generator_processor::{
+    EveryProcessor, GeneratorProcessor, GeneratorWrapperProcessor, LifemodelProcessor,
+    MapperProcessor, PearProcessor,
+};
+use crate::markov_sequence_generator::MarkovSequenceGenerator;
+use crate::parameter::{DynVal, ParameterAddress, ParameterValue};
+use crate::random::SeededRng;
+use crate::sample_set::{SampleLookup, SampleQuery};
+use crate::session_snapshot::{durations_from_list, durations_to_list, PfaState};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ParamData {
+    Scalar(f32),
+    Vector(Vec<f32>),
+    Matrix(Vec<Vec<f32>>),
+    // symbolic values, i.e. filter or oscillator types
+    Symbol(String),
+    Text(String),
+}
+
+impl ParamData {
+    fn from_value(val: &ParameterValue) -> Option<Self> {
+        match val {
+            ParameterValue::Scalar(d) => Some(ParamData::Scalar(d.val)),
+            ParameterValue::Vector(v) => Some(ParamData::Vector(v.iter().map(|d| d.val).collect())),
+            ParameterValue::Matrix(m) => Some(ParamData::Matrix(
+                m.iter()
+                    .map(|r| r.iter().map(|d| d.val).collect())
+                    .collect(),
+            )),
+            ParameterValue::Symbolic(s) => Some(ParamData::Text(s.clone())),
+            _ => symbolic_param_name(val).map(|s| ParamData::Symbol(s.to_string())),
+        }
+    }
+
+    fn to_value(&self) -> Option<ParameterValue> {
+        match self {
+            ParamData::Scalar(v) => Some(ParameterValue::Scalar(DynVal::with_value(*v))),
+            ParamData::Vector(v) => Some(ParameterValue::Vector(
+                v.iter().map(|v| DynVal::with_value(*v)).collect(),
+            )),
+            ParamData::Matrix(m) => Some(ParameterValue::Matrix(
+                m.iter()
+                    .map(|r| r.iter().map(|v| DynVal::with_value(*v)).collect())
+                    .collect(),
+            )),
+            ParamData::Symbol(s) => map_symbolic_param_value(s),
+            ParamData::Text(s) => Some(ParameterValue::Symbolic(s.clone())),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SampleData {
+    Key(String, BTreeSet<String>),
+    N(String, usize),
+    Random(String),
+    FixedRandom(String, (usize, usize)),
+    Descriptor(String, BTreeSet<String>, QueryData),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum QueryData {
+    Loudest,
+    Quietest,
+    Brightest,
+    Darkest,
+    NearPitch(f32),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum OperationData {
+    Replace,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EventData {
+    pub name: String,
+    pub tags: BTreeSet<String>,
+    pub op: OperationData,
+    pub sample: Option<SampleData>,
+    pub params: BTreeMap<String, ParamData>,
+}
+
+impl EventData {
+    pub fn from_event(ev: &Event) -> Self {
+        let mut params = BTreeMap::new();
+        for (addr, val) in ev.params.iter() {
+            let (Some(name), Some(data)) = (parameter_name(addr), ParamData::from_value(val))
+            else {
+                println!("can't save parameter {addr:?} of {}", ev.name);
+                continue;
+            };
+            params.insert(name, data);
+        }
+
+        EventData {
+            name: ev.name.clone(),
+            tags: ev.tags.clone(),
+            op: match ev.op {
+                EventOperation::Replace => OperationData::Replace,
+                EventOperation::Add => OperationData::Add,
+                EventOperation::Subtract => OperationData::Subtract,
+                EventOperation::Multiply => OperationData::Multiply,
+                EventOperation::Divide => OperationData::Divide,
+            },
+            sample: ev.sample_lookup.as_ref().map(|l| match l {
+                SampleLookup::Key(s, k) => SampleData::Key(s.clone(), k.iter().cloned().collect()),
+                SampleLookup::N(s, n) => SampleData::N(s.clone(), *n),
+                SampleLookup::Random(s) => SampleData::Random(s.clone()),
+                SampleLookup::FixedRandom(s, r) => SampleData::FixedRandom(s.clone(), *r),
+                SampleLookup::Descriptor(s, k, q) => SampleData::Descriptor(
+                    s.clone(),
+                    k.iter().cloned().collect(),
+                    match q {
+                        SampleQuery::Loudest => QueryData::Loudest,
+                        SampleQuery::Quietest => QueryData::Quietest,
+                        SampleQuery::Brightest => QueryData::Brightest,
+                        SampleQuery::Darkest => QueryData::Darkest,
+                        SampleQuery::NearPitch(f) => QueryData::NearPitch(*f),
+                    },
+                ),
+            }),
+            params,
+        }
+    }
+
+    pub fn to_event(&self) -> Event {
+        let mut params: HashMap<ParameterAddress, ParameterValue> = HashMap::new();
+        for (name, data) in self.params.iter() {
+            if let Some(val) = data.to_value() {
+                params.insert(map_parameter(name), val);
+            }
+        }
+
+        Event {
+            name: self.name.clone(),
+            params,
+            tags: self.tags.clone(),
+            op: match self.op {
+                OperationData::Replace => EventOperation::Replace,
+                OperationData::Add => EventOperation::Add,
+                OperationData::Subtract => EventOperation::Subtract,
+                OperationData::Multiply => EventOperation::Multiply,
+                OperationData::Divide => EventOperation::Divide,
+            },
+            sample_lookup: self.sample.as_ref().map(|l| match l {
+                SampleData::Key(s, k) => {
+                    SampleLookup::Key(s.clone(), k.iter().cloned().collect::<HashSet<String>>())
+                }
+                SampleData::N(s, n) => SampleLookup::N(s.clone(), *n),
+                SampleData::Random(s) => SampleLookup::Random(s.clone()),
+                SampleData::FixedRandom(s, r) => SampleLookup::FixedRandom(s.clone(), *r),
+                SampleData::Descriptor(s, k, q) => SampleLookup::Descriptor(
+                    s.clone(),
+                    k.iter().cloned().collect::<HashSet<String>>(),
+                    match q {
+                        QueryData::Loudest => SampleQuery::Loudest,
+                        QueryData::Quietest => SampleQuery::Quietest,
+                        QueryData::Brightest => SampleQuery::Brightest,
+                        QueryData::Darkest => SampleQuery::Darkest,
+                        QueryData::NearPitch(f) => SampleQuery::NearPitch(*f),
+                    },
+                ),
+            }),
+        }
+    }
+}
+
+/// events applied by pear or every, along with their filter and whether they're
+/// applied to matching events only
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FilteredEventsData {
+    pub filter: Vec<String>,
+    pub mode: bool,
+    pub events: Vec<EventData>,
+}
+
+impl FilteredEventsData {
+    pub fn from_map(map: &HashMap<Vec<String>, (bool, Vec<Event>)>) -> Vec<Self> {
+        let mut data: Vec<Self> = map
+            .iter()
+            .map(|(filter, (mode, events))| FilteredEventsData {
+                filter: filter.clone(),
+                mode: *mode,
+                events: events.iter().map(EventData::from_event).collect(),
+            })
+            .collect();
+        // keep the files stable
+        data.sort_by(|a, b| a.filter.cmp(&b.filter));
+        data
+    }
+
+    pub fn to_map(data: &[Self]) -> HashMap<Vec<String>, (bool, Vec<Event>)> {
+        data.iter()
+            .map(|d| {
+                (
+                    d.filter.clone(),
+                    (d.mode, d.events.iter().map(EventData::to_event).collect()),
+                )
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LifemodelData {
+    pub step_count: usize,
+    pub growth_cycle: usize,
+    pub growth_method: String,
+    pub variance: f32,
+    pub node_lifespan: usize,
+    pub node_lifespan_variance: f32,
+    pub apoptosis: bool,
+    pub autophagia: bool,
+    pub local_resources: f32,
+    pub growth_cost: f32,
+    pub apoptosis_regain: f32,
+    pub autophagia_regain: f32,
+    pub durations: Vec<f32>,
+    pub dont_let_die: bool,
+    pub keep_param: BTreeSet<String>,
+    pub global_contrib: bool,
+    pub solidify_chance: f32,
+    pub solidify_len: usize,
+    pub rnd_chance: f32,
+}
+
+/// a processor, as written to a file (see `GeneratorProcessor::to_data`)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ProcessorData {
+    // probability and events
+    Pear(Vec<(f32, Vec<FilteredEventsData>)>),
+    Every {
+        id: Option<String>,
+        step_count: usize,
+        // step and events
+        things: Vec<(f32, Vec<FilteredEventsData>)>,
+    },
+    Lifemodel(LifemodelData),
+    Mapper {
+        fun: String,
+        keep: bool,
+    },
+    Wrapper(Box<GeneratorData>),
+}
+
+impl ProcessorData {
+    pub fn to_processor(&self) -> Box<dyn GeneratorProcessor + Send + Sync> {
+        match self {
+            ProcessorData::Pear(events) => {
+                let mut proc = PearProcessor::new();
+                proc.events_to_be_applied = events
+                    .iter()
+                    .map(|(prob, evs)| (DynVal::with_value(*prob), FilteredEventsData::to_map(evs)))
+                    .collect();
+                Box::new(proc)
+            }
+            ProcessorData::Every {
+                id,
+                step_count,
+                things,
+            } => {
+                let mut proc = EveryProcessor::new();
+                proc.id = id.clone();
+                proc.step_count = *step_count;
+                proc.things_to_be_applied = things
+                    .iter()
+                    .map(|(step, evs)| {
+                        (
+                            DynVal::with_value(*step),
+                            FilteredEventsData::to_map(evs),
+                            Vec::new(),
+                        )
+                    })
+                    .collect();
+                Box::new(proc)
+            }
+            ProcessorData::Lifemodel(lm) => Box::new(LifemodelProcessor {
+                step_count: lm.step_count,
+                growth_cycle: lm.growth_cycle,
+                growth_method: lm.growth_method.clone(),
+                variance: lm.variance,
+                node_lifespan: lm.node_lifespan,
+                node_lifespan_variance: lm.node_lifespan_variance,
+                apoptosis: lm.apoptosis,
+                autophagia: lm.autophagia,
+                local_resources: lm.local_resources,
+                growth_cost: lm.growth_cost,
+                apoptosis_regain: lm.apoptosis_regain,
+                autophagia_regain: lm.autophagia_regain,
+                durations: lm
+                    .durations
+                    .iter()
+                    .map(|d| DynVal::with_value(*d))
+                    .collect(),
+                dont_let_die: lm.dont_let_die,
+                keep_param: lm.keep_param.iter().map(|p| map_parameter(p)).collect(),
+                global_contrib: lm.global_contrib,
+                solidify_chance: lm.solidify_chance,
+                solidify_len: lm.solidify_len,
+                rnd_chance: lm.rnd_chance,
+            }),
+            ProcessorData::Mapper { fun, keep } => Box::new(MapperProcessor {
+                fun: fun.clone(),
+                keep: *keep,
+            }),
+            ProcessorData::Wrapper(gen) => Box::new(GeneratorWrapperProcessor::with_generator(
+                gen.to_generator(),
+            )),
+        }
+    }
+}
+
+/// a generator, as written to a file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GeneratorData {
+    pub id_tags: BTreeSet<String>,
+    pub name: String,
+    pub pfa: PfaState,
+    // the sound events and the duration event of each symbol
+    pub events: BTreeMap<char, (Vec<EventData>, EventData)>,
+    pub label_mapping: Option<BTreeMap<char, String>>,
+    pub override_durations: Option<Vec<(Vec<char>, u64)>>,
+    pub symbol_ages: HashMap<char, u64>,
+    pub default_duration: u64,
+    pub time_shift: i32,
+    pub keep_root: bool,
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub processors: Vec<ProcessorData>,
+}
+
+impl GeneratorData {
+    pub fn from_generator(gen: &Generator) -> Result<Self> {
+        let root = &gen.root_generator;
+
+        if root
+            .event_mapping
+            .values()
+            .any(|(evs, _)| evs.iter().any(|e| matches!(e, SourceEvent::Control(_))))
+        {
+            bail!(
+                "{:?} contains control events, which can't be saved",
+                gen.id_tags
+            );
+        }
+
+        let mut processors = Vec::new();
+        for proc in gen.processors.iter() {
+            processors.push(proc.to_data()?);
+        }
+
+        Ok(GeneratorData {
+            id_tags: gen.id_tags.clone(),
+            name: root.name.clone(),
+            pfa: PfaState::capture(&root.generator),
+            events: root
+                .event_mapping
+                .iter()
+                .map(|(sym, (evs, dur))| {
+                    (
+                        *sym,
+                        (
+                            evs.iter()
+                                .filter_map(|e| match e {
+                                    SourceEvent::Sound(e) => Some(EventData::from_event(e)),
+                                    SourceEvent::Control(_) => None,
+                                })
+                                .collect(),
+                            EventData::from_event(dur),
+                        ),
+                    )
+                })
+                .collect(),
+            label_mapping: root.label_mapping.clone(),
+            override_durations: root.override_durations.as_ref().map(durations_to_list),
+            symbol_ages: root.symbol_ages.clone(),
+            default_duration: root.default_duration,
+            time_shift: gen.time_shift,
+            keep_root: gen.keep_root,
+            seed: gen.rng.as_ref().map(|r| r.seed),
+            processors,
+        })
+    }
+
+    pub fn to_generator(&self) -> Generator {
+        Generator {
+            id_tags: self.id_tags.clone(),
+            root_generator: MarkovSequenceGenerator {
+                name: self.name.clone(),
+                generator: self.pfa.restore(),
+                event_mapping: self
+                    .events
+                    .iter()
+                    .map(|(sym, (evs, dur))| {
+                        (
+                            *sym,
+                            (
+                                evs.iter()
+                                    .map(|e| SourceEvent::Sound(e.to_event()))
+                                    .collect(),
+                                dur.to_event(),
+                            ),
+                        )
+                    })
+                    .collect(),
+                label_mapping: self.label_mapping.clone(),
+                override_durations: self
+                    .override_durations
+                    .as_ref()
+                    .map(|d| durations_from_list(d)),
+                modified: true,
+                symbol_ages: self.symbol_ages.clone(),
+                default_duration: self.default_duration,
+                last_transition: None,
+                last_symbol: None,
+            },
+            processors: self.processors.iter().map(|p| p.to_processor()).collect(),
+            time_mods: Vec::new(),
+            time_shift: self.time_shift,
+            keep_root: self.keep_root,
+            rng: self.seed.map(SeededRng::new),
+        }
+    }
+}
+
+pub fn write_generator(filename: &str, gen: &Generator) -> Result<()> {
+    fs::write(
+        filename,
+        serde_json::to_string_pretty(&GeneratorData::from_generator(gen)?)?,
+    )?;
+    Ok(())
+}
+
+pub fn read_generator(filename: &str) -> Result<Generator> {
+    let Ok(content) = fs::read_to_string(filename) else {
+        bail!("can't read {filename}");
+    };
+    let data: GeneratorData = serde_json::from_str(&content)?;
+    Ok(data.to_generator())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator_processor::AppleProcessor;
+    use ruffbox_synth::building_blocks::{FilterType, OscillatorType, SynthParameterLabel};
+    use vom_rs::pfa::{Pfa, Rule};
+
+    #[test]
+    fn test_event_roundtrip() {
+        let mut ev = Event::with_name("saw".to_string());
+        ev.params.insert(
+            SynthParameterLabel::PitchFrequency.into(),
+            ParameterValue::Scalar(DynVal::with_value(110.0)),
+        );
+        ev.params.insert(
+            SynthParameterLabel::LowpassFilterType.into(),
+            ParameterValue::FilterType(FilterType::Lpf18),
+        );
+        ev.params.insert(
+            SynthParameterLabel::OscillatorAmplitude
+                .with_index(1)
+                .into(),
+            ParameterValue::Scalar(DynVal::with_value(0.3)),
+        );
+
+        let data = EventData::from_event(&ev);
+        assert_eq!(data.params.get("freq"), Some(&ParamData::Scalar(110.0)));
+        assert_eq!(data.params.get("amp2"), Some(&ParamData::Scalar(0.3)));
+        assert_eq!(
+            data.params.get("lpt"),
+            Some(&ParamData::Symbol("lpf18".to_string()))
+        );
+
+        assert_eq!(EventData::from_event(&data.to_event()), data);
+    }
+
+    #[test]
+    fn test_symbolic_param_names() {
+        for name in ["lpf18", "butter4hpf", "const", "fmsaw", "floor"] {
+            let val = map_symbolic_param_value(name).unwrap();
+            assert_eq!(symbolic_param_name(&val), Some(name));
+        }
+        assert_eq!(
+            symbolic_param_name(&ParameterValue::OscillatorType(OscillatorType::Wavematrix)),
+            Some("wmat")
+        );
+        assert_eq!(
+            symbolic_param_name(&ParameterValue::FilterType(FilterType::ButterworthLpf(3))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_processor_roundtrip() {
+        let mut rules = vec![Rule {
+            source: "a".chars().collect(),
+            symbol: 'a',
+            probability: 1.0,
+        }];
+        let data = GeneratorData {
+            id_tags: BTreeSet::from(["gen".to_string()]),
+            name: "gen".to_string(),
+            pfa: PfaState::capture(&Pfa::<char>::infer_from_rules(&mut rules, true)),
+            events: BTreeMap::new(),
+            label_mapping: None,
+            override_durations: None,
+            symbol_ages: HashMap::new(),
+            default_duration: 200,
+            time_shift: 0,
+            keep_root: false,
+            seed: None,
+            processors: Vec::new(),
+        };
+
+        let mut pear = PearProcessor::new();
+        let mut ev = Event::with_name("lvl".to_string());
+        ev.params.insert(
+            SynthParameterLabel::EnvelopeLevel.into(),
+            ParameterValue::Scalar(DynVal::with_value(0.5)),
+        );
+        pear.events_to_be_applied.push((
+            DynVal::with_value(30.0),
+            HashMap::from([(vec!["".to_string()], (true, vec![ev]))]),
+        ));
+
+        let mut gen = data.to_generator();
+        gen.processors.push(Box::new(pear));
+        gen.processors.push(Box::new(MapperProcessor {
+            fun: "map".to_string(),
+            keep: true,
+        }));
+        gen.processors
+            .push(Box::new(GeneratorWrapperProcessor::with_generator(
+                data.to_generator(),
+            )));
+
+        let saved = GeneratorData::from_generator(&gen).unwrap();
+        assert_eq!(saved.processors.len(), 3);
+        assert!(matches!(saved.processors[0], ProcessorData::Pear(_)));
+        assert_eq!(
+            GeneratorData::from_generator(&saved.to_generator()).unwrap(),
+            saved
+        );
+
+        // apple holds generator modifier functions
+        gen.processors.push(Box::new(AppleProcessor::new()));
+        assert!(GeneratorData::from_generator(&gen).is_err());
+    }
+}
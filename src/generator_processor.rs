@@ -2,6 +2,7 @@ use std::collections::{BTreeSet, HashMap};
 use std::sync::*;
 
 use crate::eval::FunctionMap;
+use crate::generator_file::ProcessorData;
 use crate::sample_set::SampleAndWavematrixSet;
 use crate::session::OutputMode;
 use crate::visualizer_client::VisualizerClient;
@@ -31,6 +32,13 @@ pub trait GeneratorProcessor: GeneratorProcessorClone {
 
     fn collect_id_set(&self, _supplemental: &mut BTreeSet<BTreeSet<String>>) {}
 
+    /// a short name for inspection, i.e. `PearProcessor` becomes `pear`
+    fn name(&self) -> String {
+        let type_name = std::any::type_name::<Self>();
+        let type_name = type_name.rsplit("::").next().unwrap_or(type_name);
+        type_name.trim_end_matches("Processor").to_lowercase()
+    }
+
     /// some processors need some internal IDs to be updated
     /// (mostly for visualization)
     /// (so far only the GeneratorWrapperProcessor)
@@ -79,6 +87,12 @@ pub trait GeneratorProcessor: GeneratorProcessorClone {
         None
     }
 
+    /// implement this if the processor can be written to a file
+    /// (see `generator_file`)
+    fn to_data(&self) -> anyhow::Result<ProcessorData> {
+        anyhow::bail!("{} processors can't be saved", self.name())
+    }
+
     /// if the processor holds something that can be visualized
     /// such as a markov chain ...
     fn visualize_if_possible(&mut self, _vis_client: &VisualizerClient) {
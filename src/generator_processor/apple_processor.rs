@@ -2,7 +2,8 @@ use rand::*;
 use std::sync::*;
 
 use crate::{
-    builtin_types::GlobalVariables, generator::Generator, generator_processor::*, parameter::DynVal,
+    builtin_types::GlobalVariables, generator::Generator, generator_processor::*,
+    parameter::DynVal, random,
 };
 
 /// Apple-ys modifiers to the underlying processors
@@ -23,7 +24,7 @@ impl GeneratorProcessor for AppleProcessor {
     // this one only processes generators ... for the event stream processor,
     // see "pear"
     fn process_generator(&mut self, gen: &mut Generator, globals: &Arc<GlobalVariables>) {
-        let mut rng = rand::thread_rng();
+        let mut rng = random::CurrentRng;
         for (prob, gen_mods) in self.modifiers_to_be_applied.iter_mut() {
             // make sure prob is always between 0 and 100
             let cur_prob: usize = (prob.evaluate_numerical() as usize) % 101;
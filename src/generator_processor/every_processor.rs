@@ -4,6 +4,7 @@ use crate::{
     builtin_types::GlobalVariables,
     event::{InterpretableEvent, StaticEvent},
     generator::Generator,
+    generator_file::{FilteredEventsData, ProcessorData},
     generator_processor::*,
 };
 
@@ -43,6 +44,21 @@ impl GeneratorProcessor for EveryProcessor {
         Some(GeneratorProcessorState::Count(self.step_count))
     }
 
+    fn to_data(&self) -> anyhow::Result<ProcessorData> {
+        let mut things = Vec::new();
+        for (step, evs, gen_mods) in self.things_to_be_applied.iter() {
+            if !gen_mods.is_empty() {
+                anyhow::bail!("every processors with generator modifiers can't be saved");
+            }
+            things.push((step.static_val, FilteredEventsData::from_map(evs)));
+        }
+        Ok(ProcessorData::Every {
+            id: self.id.clone(),
+            step_count: self.step_count,
+            things,
+        })
+    }
+
     // this one
     fn process_events(
         &mut self,
@@ -4,6 +4,7 @@ use crate::{
     builtin_types::GlobalVariables,
     event::{InterpretableEvent, StaticEvent},
     generator::Generator,
+    generator_file::{GeneratorData, ProcessorData},
     generator_processor::*,
 };
 
@@ -63,6 +64,12 @@ impl GeneratorProcessor for GeneratorWrapperProcessor {
         ))
     }
 
+    fn to_data(&self) -> anyhow::Result<ProcessorData> {
+        Ok(ProcessorData::Wrapper(Box::new(
+            GeneratorData::from_generator(&self.wrapped_generator)?,
+        )))
+    }
+
     // another pure event-stream processor
     fn process_events(
         &mut self,
@@ -1,13 +1,19 @@
 use rand::seq::SliceRandom;
 use rand::Rng;
-use std::{collections::HashSet, sync::*};
+use std::{
+    collections::{BTreeSet, HashSet},
+    sync::*,
+};
 
 use crate::{
     builtin_types::{GlobalVariables, TypedEntity, VariableId},
+    event_helpers::parameter_name,
     generator::modifier_functions_raw::*,
     generator::Generator,
+    generator_file::{LifemodelData, ProcessorData},
     generator_processor::*,
     parameter::*,
+    random,
 };
 
 struct LifemodelDefaults;
@@ -81,6 +87,37 @@ impl LifemodelProcessor {
 impl GeneratorProcessor for LifemodelProcessor {
     // I'm a bit surprises this one's stateless ...
 
+    fn to_data(&self) -> anyhow::Result<ProcessorData> {
+        let mut keep_param = BTreeSet::new();
+        for addr in self.keep_param.iter() {
+            let Some(name) = parameter_name(addr) else {
+                anyhow::bail!("lifemodel can't save kept parameter {addr:?}");
+            };
+            keep_param.insert(name);
+        }
+        Ok(ProcessorData::Lifemodel(LifemodelData {
+            step_count: self.step_count,
+            growth_cycle: self.growth_cycle,
+            growth_method: self.growth_method.clone(),
+            variance: self.variance,
+            node_lifespan: self.node_lifespan,
+            node_lifespan_variance: self.node_lifespan_variance,
+            apoptosis: self.apoptosis,
+            autophagia: self.autophagia,
+            local_resources: self.local_resources,
+            growth_cost: self.growth_cost,
+            apoptosis_regain: self.apoptosis_regain,
+            autophagia_regain: self.autophagia_regain,
+            durations: self.durations.iter().map(|d| d.static_val).collect(),
+            dont_let_die: self.dont_let_die,
+            keep_param,
+            global_contrib: self.global_contrib,
+            solidify_chance: self.solidify_chance,
+            solidify_len: self.solidify_len,
+            rnd_chance: self.rnd_chance,
+        }))
+    }
+
     // this one only processes the generators ...
     fn process_generator(&mut self, gen: &mut Generator, globals: &Arc<GlobalVariables>) {
         // check if we need to grow ...
@@ -132,7 +169,7 @@ impl GeneratorProcessor for LifemodelProcessor {
                         .root_generator
                         .generator
                         .alphabet
-                        .choose(&mut rand::thread_rng())
+                        .choose(&mut random::CurrentRng)
                     {
                         //println!("lm auto {} {:?}", random_symbol, gen.root_generator.generator.alphabet);
                         // don't rebalance yet ...
@@ -170,7 +207,7 @@ impl GeneratorProcessor for LifemodelProcessor {
             if let Some(res) = &gen.root_generator.last_transition {
                 // helper to add some variance to the age ...
                 let add_var = |orig: f32, var: f32| -> usize {
-                    let mut rng = rand::thread_rng();
+                    let mut rng = random::CurrentRng;
                     let rand = (var * (1000.0 - rng.gen_range(0.0..2000.0))) * (orig / 1000.0);
                     (orig + rand).floor() as usize
                 };
@@ -209,7 +246,7 @@ impl GeneratorProcessor for LifemodelProcessor {
         }
 
         if something_happened && self.solidify_chance > 0.0 {
-            let mut rng = rand::thread_rng();
+            let mut rng = random::CurrentRng;
             let rand = rng.gen_range(0.0..1000.0) / 1000.0;
             if rand < self.solidify_chance {
                 gen.root_generator.generator.solidify(self.solidify_len);
@@ -2,6 +2,7 @@ use std::{cell::RefCell, sync::Arc};
 
 use crate::{
     eval::{eval_usr_fun_evaluated_tail, EvaluatedExpr, FunctionMap, LocalVariables},
+    generator_file::ProcessorData,
     sample_set::SampleAndWavematrixSet,
     session::OutputMode,
     TypedEntity,
@@ -20,6 +21,13 @@ pub struct MapperProcessor {
 }
 
 impl GeneratorProcessor for MapperProcessor {
+    fn to_data(&self) -> anyhow::Result<ProcessorData> {
+        Ok(ProcessorData::Mapper {
+            fun: self.fun.clone(),
+            keep: self.keep,
+        })
+    }
+
     fn process_events(
         &mut self,
         events: &mut Vec<crate::event::InterpretableEvent>,
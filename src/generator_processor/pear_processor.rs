@@ -5,8 +5,10 @@ use std::sync::*;
 use crate::{
     builtin_types::GlobalVariables,
     event::{InterpretableEvent, StaticEvent},
+    generator_file::{FilteredEventsData, ProcessorData},
     generator_processor::*,
     parameter::DynVal,
+    random,
 };
 
 /// Apple-ys events to the throughcoming ones
@@ -27,6 +29,15 @@ impl PearProcessor {
 
 // zip mode etc seem to be outdated ... going for any mode for now
 impl GeneratorProcessor for PearProcessor {
+    fn to_data(&self) -> anyhow::Result<ProcessorData> {
+        Ok(ProcessorData::Pear(
+            self.events_to_be_applied
+                .iter()
+                .map(|(prob, evs)| (prob.static_val, FilteredEventsData::from_map(evs)))
+                .collect(),
+        ))
+    }
+
     // this one only processes the event stream ...
     fn process_events(
         &mut self,
@@ -36,7 +47,7 @@ impl GeneratorProcessor for PearProcessor {
         _sample_set: SampleAndWavematrixSet,
         _out_mode: OutputMode,
     ) {
-        let mut rng = rand::thread_rng();
+        let mut rng = random::CurrentRng;
 
         for (cur_prob, static_events) in self.last_static.iter() {
             for (filter, inner_static) in static_events.iter() {
@@ -85,7 +96,7 @@ impl GeneratorProcessor for PearProcessor {
             self.last_static.push((cur_prob, stat_evs));
         }
 
-        let mut rng = rand::thread_rng();
+        let mut rng = random::CurrentRng;
         for (prob, filtered_events) in self.last_static.iter_mut() {
             for (filter, evs) in filtered_events.iter_mut() {
                 for (ev, mode) in evs.iter() {
@@ -1,19 +1,55 @@
 use rosc::OscType;
 
+use std::collections::HashMap;
 use std::sync;
 use std::thread;
 
+use crate::automation;
 use crate::builtin_types::*;
 
 use crate::commands;
-use crate::eval::EvaluatedExpr;
+use crate::eval::{self, EvaluatedExpr};
+use crate::eval_server::EvalServer;
 use crate::file_interpreter;
+use crate::midi_clock::{MidiClockFollower, MidiClockSender};
 use crate::midi_input;
+use crate::midi_output;
 use crate::osc_receiver::OscReceiver;
+use crate::peer_sync::PeerSync;
+use crate::random;
 
 use crate::session::Session;
 use crate::visualizer_client::VisualizerClient;
 
+// a readable form of (nested) values, i.e. the inspection results
+fn show(t: &TypedEntity) -> String {
+    match t {
+        TypedEntity::Comparable(Comparable::String(s)) => format!("\"{s}\""),
+        TypedEntity::Comparable(Comparable::Symbol(s)) => format!("'{s}"),
+        TypedEntity::Comparable(Comparable::Boolean(b)) => if *b { "#t" } else { "#f" }.to_string(),
+        TypedEntity::Comparable(Comparable::Float(f)) => f.to_string(),
+        TypedEntity::Vec(v) => format!(
+            "({})",
+            v.iter().map(|e| show(e)).collect::<Vec<_>>().join(" ")
+        ),
+        TypedEntity::Map(m) => format!("{{{}}}", show_entries(m).join(", ")),
+        _ => format!("{t:?}"),
+    }
+}
+
+fn show_entries(m: &HashMap<VariableId, TypedEntity>) -> Vec<String> {
+    let mut entries: Vec<String> = m
+        .iter()
+        .map(|(k, v)| match k {
+            VariableId::Symbol(k) => format!("'{k} {}", show(v)),
+            VariableId::Custom(k) => format!("\"{k}\" {}", show(v)),
+            _ => format!("{k:?} {}", show(v)),
+        })
+        .collect();
+    entries.sort();
+    entries
+}
+
 pub fn interpret_command<const BUFSIZE: usize, const NCHAN: usize>(
     c: Command,
     session: &Session<BUFSIZE, NCHAN>,
@@ -23,6 +59,9 @@ pub fn interpret_command<const BUFSIZE: usize, const NCHAN: usize>(
         Command::LoadFile(f) => {
             file_interpreter::parse_file(f, session, base_dir);
         }
+        Command::Import(f, namespace, reload) => {
+            file_interpreter::import_file(&f, namespace, reload, session, base_dir);
+        }
         Command::Push(id, te) => {
             commands::push(id, te, &session.globals);
         }
@@ -57,20 +96,22 @@ pub fn interpret_command<const BUFSIZE: usize, const NCHAN: usize>(
                 println!("visualizer already connected !");
             }
         }
-        Command::StartRecording(prefix, rec_input) => {
-            commands::start_recording(session, prefix, base_dir, rec_input);
+        Command::StartRecording(prefix, rec_input, stems) => {
+            commands::start_recording(session, prefix, base_dir, rec_input, stems);
         }
         Command::StopRecording => {
             commands::stop_recording(session);
         }
         Command::ImportSampleSet(resource) => {
             let ruffbox2 = sync::Arc::clone(&session.ruffbox);
+            let stems2 = sync::Arc::clone(&session.stems);
             let fmap2 = sync::Arc::clone(&session.functions);
             let session2 = session.clone();
             thread::spawn(move || {
                 commands::fetch_sample_set(
                     &fmap2,
                     &ruffbox2,
+                    &stems2,
                     session2.sample_set,
                     base_dir,
                     resource,
@@ -79,12 +120,14 @@ pub fn interpret_command<const BUFSIZE: usize, const NCHAN: usize>(
         }
         Command::LoadSample(set, mut keywords, path, downmix_stereo) => {
             let ruffbox2 = sync::Arc::clone(&session.ruffbox);
+            let stems2 = sync::Arc::clone(&session.stems);
             let fmap2 = sync::Arc::clone(&session.functions);
             let session2 = session.clone();
             thread::spawn(move || {
                 commands::load_sample(
                     &fmap2,
                     &ruffbox2,
+                    &stems2,
                     session2.sample_set,
                     set,
                     &mut keywords,
@@ -110,12 +153,14 @@ pub fn interpret_command<const BUFSIZE: usize, const NCHAN: usize>(
         }
         Command::LoadSampleSets(path, downmix_stereo) => {
             let ruffbox2 = sync::Arc::clone(&session.ruffbox);
+            let stems2 = sync::Arc::clone(&session.stems);
             let fmap2 = sync::Arc::clone(&session.functions);
             let session2 = session.clone();
             thread::spawn(move || {
                 commands::load_sample_sets(
                     &fmap2,
                     &ruffbox2,
+                    &stems2,
                     session2.sample_set,
                     path,
                     downmix_stereo,
@@ -125,12 +170,14 @@ pub fn interpret_command<const BUFSIZE: usize, const NCHAN: usize>(
         }
         Command::LoadSampleSet(path, downmix_stereo) => {
             let ruffbox2 = sync::Arc::clone(&session.ruffbox);
+            let stems2 = sync::Arc::clone(&session.stems);
             let fmap2 = sync::Arc::clone(&session.functions);
             let session2 = session.clone();
             thread::spawn(move || {
                 commands::load_sample_set_string(
                     &fmap2,
                     &ruffbox2,
+                    &stems2,
                     session2.sample_set,
                     path,
                     downmix_stereo,
@@ -139,15 +186,22 @@ pub fn interpret_command<const BUFSIZE: usize, const NCHAN: usize>(
             });
         }
         Command::FreezeBuffer(freezbuf, inbuf) => {
-            commands::freeze_buffer(&session.ruffbox, freezbuf, inbuf);
+            commands::freeze_buffer(&session.ruffbox, &session.sample_set, freezbuf, inbuf);
             println!("freeze buffer {inbuf} --> {freezbuf}");
         }
         Command::FreezeAddBuffer(freezbuf, inbuf) => {
-            commands::freeze_add_buffer(&session.ruffbox, freezbuf, inbuf);
+            commands::freeze_add_buffer(&session.ruffbox, &session.sample_set, freezbuf, inbuf);
             println!("freeze-add buffer {inbuf} --> {freezbuf} ");
         }
         Command::FreezeAfterRec(freezbuf, inbuf, time, add) => {
-            commands::freeze_after_rec(&session.ruffbox, freezbuf, inbuf, time, add);
+            commands::freeze_after_rec(
+                &session.ruffbox,
+                &session.sample_set,
+                freezbuf,
+                inbuf,
+                time,
+                add,
+            );
             println!(
                 "freeze-after-rec (loop) buffer {inbuf} --> {freezbuf}, {time} secs, add ? {add}"
             );
@@ -164,11 +218,26 @@ pub fn interpret_command<const BUFSIZE: usize, const NCHAN: usize>(
         Command::Bpm(b) => {
             commands::set_default_duration(&session.globals, b);
         }
+        Command::Quantize(q) => {
+            commands::set_global_quantization(&session.globals, q);
+        }
         Command::GlobRes(v) => {
             commands::set_global_lifemodel_resources(&session.globals, v);
         }
         Command::GlobalRuffboxParams(mut m) => {
-            commands::set_global_ruffbox_parameters(&session.ruffbox, &session.globals, &mut m);
+            commands::set_global_ruffbox_parameters(
+                &session.ruffbox,
+                &session.stems,
+                &session.globals,
+                &mut m,
+            );
+        }
+        Command::Ramp(r) => {
+            println!("ramp {:?} from {} to {}", r.target, r.from, r.to);
+            automation::start_ramp(r, session);
+        }
+        Command::RampStop => {
+            automation::stop_ramps(session);
         }
         Command::ExportDotStatic(f, g) => {
             commands::export_dot_static(&f, &g);
@@ -176,6 +245,12 @@ pub fn interpret_command<const BUFSIZE: usize, const NCHAN: usize>(
         Command::ExportDotRunning((f, t)) => {
             commands::export_dot_running(&f, &t, session);
         }
+        Command::ExportMidi(f, t, g, l) => {
+            commands::export_midi(&f, &t, g, l, session);
+        }
+        Command::ExportScore(f, fmt, t, g, l) => {
+            commands::export_score(&f, fmt, &t, g, l, session);
+        }
         Command::Once(mut s, c) => {
             commands::once(session, &mut s, &c);
         }
@@ -225,31 +300,162 @@ pub fn interpret_command<const BUFSIZE: usize, const NCHAN: usize>(
         }
         Command::MidiStartReceiver(midi_in_port) => {
             let session2 = session.clone();
-            thread::spawn(move || {
+            let spawned = eval::spawn_evaluating("midi input", move || {
                 midi_input::open_midi_input_port(midi_in_port, session2, base_dir);
             });
+            if let Err(e) = spawned {
+                println!("can't start midi input - {e}");
+            }
+        }
+        Command::MidiStartSender(midi_out_port) => {
+            match midi_output::MidiOutSender::open(midi_out_port) {
+                Ok(sender) => {
+                    *session.midi_out.write() = Some(sender);
+                }
+                Err(e) => {
+                    println!("can't open midi output port - {e}");
+                }
+            }
+        }
+        Command::MidiClockStart => {
+            let mut clock = session.midi_clock.lock();
+            if clock.is_some() {
+                println!("already sending midi clock");
+            } else {
+                match MidiClockSender::start(
+                    sync::Arc::clone(&session.midi_out),
+                    sync::Arc::clone(&session.globals),
+                ) {
+                    Ok(sender) => {
+                        *clock = Some(sender);
+                    }
+                    Err(e) => {
+                        println!("can't start midi clock - {e}");
+                    }
+                }
+            }
+        }
+        Command::MidiClockStop => {
+            if let Some(mut sender) = session.midi_clock.lock().take() {
+                sender.stop();
+            }
+            if let Some(mut follower) = session.midi_clock_follower.lock().take() {
+                follower.stop();
+            }
+        }
+        Command::MidiClockFollow(midi_in_port) => {
+            let mut follower = session.midi_clock_follower.lock();
+            if follower.is_some() {
+                println!("already following midi clock");
+            } else {
+                match MidiClockFollower::start(midi_in_port, session.clone()) {
+                    Ok(f) => {
+                        *follower = Some(f);
+                    }
+                    Err(e) => {
+                        println!("can't follow midi clock - {e}");
+                    }
+                }
+            }
+        }
+        Command::PeerSyncStart(base_port) => {
+            let mut peer_sync = session.peer_sync.write();
+            if peer_sync.is_some() {
+                println!("peer sync already running");
+            } else {
+                match PeerSync::start(sync::Arc::clone(&session.globals), base_port) {
+                    Ok(ps) => {
+                        *peer_sync = Some(ps);
+                    }
+                    Err(e) => {
+                        println!("can't start peer sync - {e}");
+                    }
+                }
+            }
+        }
+        Command::PeerSyncStop => {
+            if let Some(mut ps) = session.peer_sync.write().take() {
+                ps.stop();
+            }
+        }
+        Command::EvalServerStart(port) => {
+            let mut eval_server = session.eval_server.lock();
+            if eval_server.is_some() {
+                println!("eval server already running");
+            } else {
+                match EvalServer::start(session.clone(), base_dir, port) {
+                    Ok(es) => {
+                        *eval_server = Some(es);
+                    }
+                    Err(e) => {
+                        println!("can't start eval server - {e}");
+                    }
+                }
+            }
+        }
+        Command::EvalServerStop => {
+            if let Some(mut es) = session.eval_server.lock().take() {
+                es.stop();
+            }
+        }
+        Command::Seed(seed) => {
+            if let Some(s) = seed {
+                random::seed_session(s);
+                println!("seeded session with {s}");
+            } else {
+                random::unseed_session();
+                println!("session unseeded");
+            }
+        }
+        Command::SaveSession(name) => {
+            commands::save_session(&name, session, base_dir);
+        }
+        Command::LoadSession(name) => {
+            commands::load_session(&name, session, base_dir);
+        }
+        Command::SaveGenerator(f, t, g) => {
+            commands::save_generator(&f, &t, g, session);
         }
         Command::MidiListPorts => {
             midi_input::list_midi_input_ports();
+            midi_output::list_midi_output_ports();
         }
         Command::ClearLiveBuffer(bnum) => {
-            commands::clear_live_buffer(&session.ruffbox, bnum);
+            commands::clear_live_buffer(&session.ruffbox, &session.sample_set, bnum);
         }
         Command::ClearFreezeBuffer(bnum) => {
-            commands::clear_freeze_buffer(&session.ruffbox, bnum);
+            commands::clear_freeze_buffer(&session.ruffbox, &session.sample_set, bnum);
         }
         Command::ClearAllLiveBuffers => {
-            commands::clear_all_live_buffers(&session.ruffbox);
+            commands::clear_all_live_buffers(&session.ruffbox, &session.sample_set);
         }
         Command::ClearAllFreezeBuffers => {
-            commands::clear_all_freeze_buffers(&session.ruffbox);
+            commands::clear_all_freeze_buffers(&session.ruffbox, &session.sample_set);
         }
         Command::ClearAllBuffers => {
             commands::clear_all_buffers(&session.ruffbox);
+            session.sample_set.live_buffers().clear_all_live_buffers();
+            session.sample_set.live_buffers().clear_all_freeze_buffers();
+            // keep the sample buffers of the stem buses in line
+            for bus in session.stems.buses.iter() {
+                commands::clear_all_buffers(&bus.ruffbox);
+            }
         }
     };
 }
 
+/// Interpret an expression evaluated from the given source, keeping track
+/// of the source of everything that defines the session (to save it).
+pub fn interpret_source<const BUFSIZE: usize, const NCHAN: usize>(
+    src: &str,
+    parsed_in: EvaluatedExpr,
+    session: Session<BUFSIZE, NCHAN>,
+    base_dir: String,
+) {
+    session.journal.lock().record(src, &parsed_in);
+    interpret(parsed_in, session, base_dir);
+}
+
 pub fn interpret<const BUFSIZE: usize, const NCHAN: usize>(
     parsed_in: EvaluatedExpr,
     session: Session<BUFSIZE, NCHAN>,
@@ -295,12 +501,12 @@ pub fn interpret<const BUFSIZE: usize, const NCHAN: usize>(
                 println!("\'");
             }
         }
-        EvaluatedExpr::SyncContext(mut s) => {
+        EvaluatedExpr::SyncContext(s) => {
             println!(
                 "\n\n############### a context called \'{}\' ###############",
                 s.name
             );
-            Session::handle_context(&mut s, &session);
+            Session::handle_context_quantized(s, &session);
         }
         EvaluatedExpr::Command(c) => {
             interpret_command(c, &session, base_dir);
@@ -336,15 +542,31 @@ pub fn interpret<const BUFSIZE: usize, const NCHAN: usize>(
             println!("a boolean: {b}")
         }
         EvaluatedExpr::Typed(TypedEntity::Map(m)) => {
-            println!("a map: {m:?}")
+            println!("a map:");
+            for line in show_entries(&m) {
+                println!("  {line}");
+            }
         }
         EvaluatedExpr::Typed(TypedEntity::Vec(v)) => {
-            println!("a vec: {v:?}")
+            println!("a vec:");
+            for elem in v.iter() {
+                println!("  {}", show(elem));
+            }
         }
         EvaluatedExpr::FunctionDefinition(name, pos_args, body) => {
             println!("a function definition: {name} positional args: {pos_args:?}");
             session.functions.usr_lib.insert(name, (pos_args, body));
         }
+        EvaluatedExpr::MacroDefinition(name, pos_args, body) => {
+            println!("a macro definition: {name} positional args: {pos_args:?}");
+            session.functions.macros.insert(name, (pos_args, body));
+        }
+        EvaluatedExpr::Typed(TypedEntity::Function(l)) => {
+            println!("a function with positional args: {:?}", l.pos_args)
+        }
+        EvaluatedExpr::Typed(TypedEntity::Quoted(e)) => {
+            println!("quoted code: {e:?}")
+        }
         EvaluatedExpr::VariableDefinition(name, var, keep_state) => {
             println!("a variable definition {name:#?} keep state {keep_state}");
             // if we keep the state, we check whether we have something under that name
@@ -0,0 +1,262 @@
+use crate::sample_analysis::{self, SampleDescriptors};
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// A freeze-after-rec that waits for enough samples to be recorded.
+struct PendingFreeze {
+    freezbuf: usize,
+    inbuf: usize,
+    num_samples: usize,
+    recorded: usize,
+    add: bool,
+}
+
+/// The content of a freeze buffer, along with its analysis,
+/// which is only done once it's needed.
+#[derive(Clone, Default)]
+struct FrozenBuffer {
+    samples: Vec<f32>,
+    descriptors: Option<Arc<SampleDescriptors>>,
+    // counts the changes, so an analysis of outdated content isn't stored
+    version: usize,
+}
+
+#[derive(Default)]
+struct LiveBufferContent {
+    live: Vec<Vec<f32>>,
+    live_idx: Vec<usize>,
+    frozen: Vec<FrozenBuffer>,
+    pending: Vec<PendingFreeze>,
+    samplerate: f32,
+}
+
+/// A copy of the live input buffers (and the freeze buffers made from them),
+/// as ruffbox can't hand their content back. The live buffers are ringbuffers
+/// that are written in the same way as on the ruffbox side, and freezing copies
+/// them as a whole, so the positions in the freeze buffers match.
+#[derive(Default)]
+pub struct LiveBuffers {
+    content: Mutex<LiveBufferContent>,
+}
+
+impl LiveBuffers {
+    /// live and freeze buffers of the given length in seconds,
+    /// the same as the ones created by ruffbox
+    pub fn new(
+        num_live_buffers: usize,
+        num_freeze_buffers: usize,
+        live_buffer_time: f64,
+        samplerate: f64,
+    ) -> Self {
+        let len = (samplerate * live_buffer_time) as usize;
+        LiveBuffers {
+            content: Mutex::new(LiveBufferContent {
+                live: vec![vec![0.0; len]; num_live_buffers],
+                live_idx: vec![0; num_live_buffers],
+                frozen: vec![
+                    FrozenBuffer {
+                        samples: vec![0.0; len],
+                        ..Default::default()
+                    };
+                    num_freeze_buffers
+                ],
+                pending: Vec::new(),
+                samplerate: samplerate as f32,
+            }),
+        }
+    }
+
+    /// write interleaved input samples, one channel per live buffer
+    pub fn write(&self, data: &[f32], channels: usize) {
+        let mut c = self.content.lock();
+        let c = &mut *c;
+
+        for frame in data.chunks(channels) {
+            for (ch, s) in frame.iter().enumerate() {
+                if let Some(buf) = c.live.get_mut(ch) {
+                    if buf.is_empty() {
+                        continue;
+                    }
+                    let idx = c.live_idx[ch];
+                    buf[idx] = *s;
+                    c.live_idx[ch] = (idx + 1) % buf.len();
+                }
+            }
+        }
+
+        if c.pending.is_empty() {
+            return;
+        }
+
+        let frames = data.len() / channels.max(1);
+        for p in c.pending.iter_mut() {
+            p.recorded += frames;
+        }
+
+        // copy the last recorded samples to the beginning of the freeze buffer
+        for p in c.pending.iter().filter(|p| p.recorded >= p.num_samples) {
+            let (Some(inbuf), Some(frozen)) = (c.live.get(p.inbuf), c.frozen.get_mut(p.freezbuf))
+            else {
+                continue;
+            };
+            let len = inbuf.len();
+            let start = (c.live_idx[p.inbuf] + len - p.num_samples.min(len)) % len;
+            for i in 0..p.num_samples.min(len).min(frozen.samples.len()) {
+                let s = inbuf[(start + i) % len];
+                if p.add {
+                    frozen.samples[i] += s;
+                } else {
+                    frozen.samples[i] = s;
+                }
+            }
+            frozen.changed();
+        }
+        c.pending.retain(|p| p.recorded < p.num_samples);
+    }
+
+    /// copy a live buffer to a freeze buffer, either replacing
+    /// or adding to the content
+    pub fn freeze(&self, freezbuf: usize, inbuf: usize, add: bool) {
+        let mut c = self.content.lock();
+        let c = &mut *c;
+        if let (Some(inbuf), Some(frozen)) = (c.live.get(inbuf), c.frozen.get_mut(freezbuf)) {
+            for (f, s) in frozen.samples.iter_mut().zip(inbuf.iter()) {
+                if add {
+                    *f += s;
+                } else {
+                    *f = *s;
+                }
+            }
+            frozen.changed();
+        }
+    }
+
+    pub fn freeze_after_rec(&self, freezbuf: usize, inbuf: usize, time_secs: f64, add: bool) {
+        let mut c = self.content.lock();
+        let num_samples = (c.samplerate as f64 * time_secs).ceil() as usize;
+        c.pending.push(PendingFreeze {
+            freezbuf,
+            inbuf,
+            num_samples,
+            recorded: 0,
+            add,
+        });
+    }
+
+    pub fn clear_live_buffer(&self, bufnum: usize) {
+        if let Some(buf) = self.content.lock().live.get_mut(bufnum) {
+            buf.fill(0.0);
+        }
+    }
+
+    pub fn clear_freeze_buffer(&self, bufnum: usize) {
+        if let Some(frozen) = self.content.lock().frozen.get_mut(bufnum) {
+            frozen.samples.fill(0.0);
+            frozen.changed();
+        }
+    }
+
+    pub fn clear_all_live_buffers(&self) {
+        for buf in self.content.lock().live.iter_mut() {
+            buf.fill(0.0);
+        }
+    }
+
+    pub fn clear_all_freeze_buffers(&self) {
+        for frozen in self.content.lock().frozen.iter_mut() {
+            frozen.samples.fill(0.0);
+            frozen.changed();
+        }
+    }
+
+    /// The descriptors of a freeze buffer, analyzed the first time they're
+    /// needed after the buffer has changed. The analysis happens outside of
+    /// the lock, so the input isn't held up.
+    pub fn freeze_buffer_descriptors(&self, freezbuf: usize) -> Option<Arc<SampleDescriptors>> {
+        let (samples, version, samplerate) = {
+            let c = self.content.lock();
+            let frozen = c.frozen.get(freezbuf)?;
+            if let Some(d) = frozen.descriptors.as_ref() {
+                return Some(d.clone());
+            }
+            (frozen.samples.clone(), frozen.version, c.samplerate)
+        };
+
+        let descriptors = Arc::new(sample_analysis::analyze(&samples, samplerate));
+
+        if let Some(frozen) = self.content.lock().frozen.get_mut(freezbuf) {
+            if frozen.version == version {
+                frozen.descriptors = Some(descriptors.clone());
+            }
+        }
+
+        Some(descriptors)
+    }
+}
+
+impl FrozenBuffer {
+    fn changed(&mut self) {
+        self.descriptors = None;
+        self.version += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_freeze_copies() {
+        // two live buffers of 10 samples
+        let live = LiveBuffers::new(2, 1, 1.0, 10.0);
+
+        let data: Vec<f32> = (0..12).flat_map(|i| [i as f32, -1.0]).collect();
+        live.write(&data, 2);
+
+        // the ringbuffer wrapped around, the positions stay the same
+        live.freeze(0, 0, false);
+        assert_eq!(
+            live.content.lock().frozen[0].samples,
+            vec![10.0, 11.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]
+        );
+
+        live.freeze(0, 1, true);
+        assert_eq!(live.content.lock().frozen[0].samples[0], 9.0);
+
+        // the last 0.3 seconds, once they've been recorded
+        live.freeze_after_rec(0, 0, 0.3, false);
+        live.write(&[20.0, 0.0, 21.0, 0.0], 2);
+        assert_eq!(live.content.lock().frozen[0].samples[0], 9.0);
+        live.write(&[22.0, 0.0], 2);
+        assert_eq!(
+            live.content.lock().frozen[0].samples[0..3],
+            [20.0, 21.0, 22.0]
+        );
+        assert!(live.content.lock().pending.is_empty());
+    }
+
+    #[test]
+    fn test_freeze_buffer_descriptors() {
+        let live = LiveBuffers::new(1, 1, 1.0, 44100.0);
+
+        // silence, then a click halfway through
+        let mut data = vec![0.0; 44100];
+        for s in data[22050..22500].iter_mut() {
+            *s = 0.8;
+        }
+        live.write(&data, 1);
+        live.freeze(0, 0, false);
+
+        let d = live.freeze_buffer_descriptors(0).unwrap();
+        assert!(d.onsets.iter().any(|o| (o - 0.5).abs() < 0.02));
+        assert!(live.freeze_buffer_descriptors(1).is_none());
+
+        // analyzed only once ...
+        assert!(Arc::ptr_eq(&d, &live.freeze_buffer_descriptors(0).unwrap()));
+        live.clear_freeze_buffer(0);
+        assert!(!Arc::ptr_eq(
+            &d,
+            &live.freeze_buffer_descriptors(0).unwrap()
+        ));
+    }
+}
@@ -1,8 +1,48 @@
-pub fn load_flac(path: &str, samplerate: f32) -> Option<(usize, f32, u32, Vec<f32>)> {
+use std::f32::consts::PI;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+// zero crossings of the resampling kernel on each side, and the
+// resolution of the kernel table (per zero crossing)
+const SINC_ZEROS: usize = 16;
+const SINC_RESOLUTION: usize = 512;
+
+/// the file types that can be loaded as samples
+pub fn is_audio_file(name: &str) -> bool {
+    let name = name.trim().to_lowercase();
+    [".wav", ".flac", ".ogg", ".oga", ".mp3", ".aif", ".aiff"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+}
+
+/// Load an audio file, returns the duration in ms, the original sample rate,
+/// the number of channels and the interleaved samples.
+pub fn load(path: &str) -> Option<(usize, f32, u32, Vec<f32>)> {
+    let lc_path = path.trim().to_lowercase();
+    if lc_path.ends_with(".flac") {
+        load_flac(path)
+    } else if lc_path.ends_with(".wav") {
+        load_wav(path)
+    } else if is_audio_file(&lc_path) {
+        load_compressed_or_aiff(path)
+    } else {
+        None
+    }
+}
+
+pub fn load_flac(path: &str) -> Option<(usize, f32, u32, Vec<f32>)> {
     let mut sample_buffer: Vec<f32> = Vec::new();
 
     if let Ok(mut reader) = claxon::FlacReader::open(path) {
-        let mut duration = if let Some(samples) = reader.streaminfo().samples {
+        let duration = if let Some(samples) = reader.streaminfo().samples {
             let tmp_dur = 1000.0
                 * ((samples as f32 / reader.streaminfo().channels as f32)
                     / reader.streaminfo().sample_rate as f32);
@@ -11,12 +51,6 @@ pub fn load_flac(path: &str, samplerate: f32) -> Option<(usize, f32, u32, Vec<f3
             200
         };
 
-        if reader.streaminfo().sample_rate != samplerate as u32 {
-            println!("adapt duration");
-            duration =
-                (duration as f32 * (reader.streaminfo().sample_rate as f32 / samplerate)) as usize;
-        }
-
         // decode to f32
         let max_val = (i32::MAX >> (32 - reader.streaminfo().bits_per_sample)) as f32;
         for sample in reader.samples() {
@@ -35,7 +69,7 @@ pub fn load_flac(path: &str, samplerate: f32) -> Option<(usize, f32, u32, Vec<f3
     }
 }
 
-pub fn load_wav(path: &str, _: f32) -> Option<(usize, f32, u32, Vec<f32>)> {
+pub fn load_wav(path: &str) -> Option<(usize, f32, u32, Vec<f32>)> {
     if let Ok(reader) = hound::WavReader::open(path) {
         let duration = (reader.duration() as f32 / reader.spec().sample_rate as f32) * 1000.0;
         let channels = reader.spec().channels;
@@ -63,3 +97,181 @@ pub fn load_wav(path: &str, _: f32) -> Option<(usize, f32, u32, Vec<f32>)> {
         None
     }
 }
+
+/// OGG Vorbis, MP3 and AIFF files
+pub fn load_compressed_or_aiff(path: &str) -> Option<(usize, f32, u32, Vec<f32>)> {
+    let file = std::fs::File::open(path).ok()?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = Path::new(path).extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            // trim encoder delay and padding, so loops stay loops
+            &FormatOptions {
+                enable_gapless: true,
+                ..Default::default()
+            },
+            &MetadataOptions::default(),
+        )
+        .ok()?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)?;
+    let track_id = track.id;
+    let mut sr = track.codec_params.sample_rate;
+    let mut channels = track.codec_params.channels.map(|c| c.count());
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .ok()?;
+
+    let mut sample_buffer: Vec<f32> = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            // the stream ends with an unexpected eof ...
+            Err(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            // ... anything else means the file is broken, and
+            // would only be loaded partially
+            Err(e) => {
+                println!("can't read {path} - {e}");
+                return None;
+            }
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let spec = *decoded.spec();
+                sr = Some(spec.rate);
+                channels = Some(spec.channels.count());
+                let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+                buf.copy_interleaved_ref(decoded);
+                sample_buffer.extend_from_slice(buf.samples());
+            }
+            // skip corrupted packets
+            Err(Error::DecodeError(_)) => continue,
+            Err(e) => {
+                println!("can't decode {path} - {e}");
+                return None;
+            }
+        }
+    }
+
+    let (sr, channels) = (sr?, channels?);
+    if sample_buffer.is_empty() || channels == 0 {
+        return None;
+    }
+    let duration = (sample_buffer.len() / channels) as f32 / sr as f32 * 1000.0;
+
+    Some((duration as usize, sr as f32, channels as u32, sample_buffer))
+}
+
+/// Resample interleaved samples with a windowed sinc interpolator,
+/// (lowpass-filtered below the new nyquist frequency when downsampling).
+pub fn resample(buffer: Vec<f32>, channels: usize, from: f32, to: f32) -> Vec<f32> {
+    if from == to || buffer.is_empty() || channels == 0 {
+        return buffer;
+    }
+
+    let ratio = to as f64 / from as f64;
+    let cutoff = (ratio as f32).min(1.0);
+
+    // hann-windowed sinc, in zero crossings
+    let table: Vec<f32> = (0..=SINC_ZEROS * SINC_RESOLUTION + 1)
+        .map(|i| {
+            let x = i as f32 / SINC_RESOLUTION as f32;
+            let sinc = if i == 0 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            };
+            let window = if x < SINC_ZEROS as f32 {
+                0.5 + 0.5 * (PI * x / SINC_ZEROS as f32).cos()
+            } else {
+                0.0
+            };
+            sinc * window
+        })
+        .collect();
+    let kernel = |x: f32| {
+        let pos = x.abs() * SINC_RESOLUTION as f32;
+        let idx = pos as usize;
+        if idx >= SINC_ZEROS * SINC_RESOLUTION {
+            0.0
+        } else {
+            let frac = pos - idx as f32;
+            table[idx] * (1.0 - frac) + table[idx + 1] * frac
+        }
+    };
+
+    let frames = buffer.len() / channels;
+    let out_frames = (frames as f64 * ratio).round() as usize;
+    // taps on each side, in input samples
+    let half = (SINC_ZEROS as f32 / cutoff).ceil() as isize;
+
+    let mut out = Vec::with_capacity(out_frames * channels);
+    let mut weights = Vec::with_capacity(2 * half as usize);
+    for n in 0..out_frames {
+        let t = n as f64 / ratio;
+        let center = t.floor() as isize;
+        let frac = (t - center as f64) as f32;
+
+        let first = (center - half + 1).max(0);
+        let last = (center + half).min(frames as isize - 1);
+        weights.clear();
+        for k in first..=last {
+            weights.push(kernel(((k - center) as f32 - frac) * cutoff) * cutoff);
+        }
+
+        for ch in 0..channels {
+            let mut acc = 0.0;
+            for (k, w) in (first..=last).zip(weights.iter()) {
+                acc += buffer[k as usize * channels + ch] * w;
+            }
+            out.push(acc);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_resample() {
+        let sine = |sr: f32, len: usize| -> Vec<f32> {
+            (0..len)
+                .map(|i| (2.0 * PI * 1000.0 * i as f32 / sr).sin())
+                .collect()
+        };
+
+        let up = resample(sine(44100.0, 44100), 1, 44100.0, 48000.0);
+        assert_eq!(up.len(), 48000);
+        let expected = sine(48000.0, 48000);
+        for i in (1000..47000).step_by(997) {
+            assert_approx_eq!(up[i], expected[i], 0.01);
+        }
+
+        // stereo, downsampled
+        let interleaved: Vec<f32> = sine(48000.0, 4800).iter().flat_map(|s| [*s, -*s]).collect();
+        let down = resample(interleaved, 2, 48000.0, 44100.0);
+        assert_eq!(down.len(), 4410 * 2);
+        let expected = sine(44100.0, 4410);
+        for i in (100..4300).step_by(101) {
+            assert_approx_eq!(down[2 * i], expected[i], 0.01);
+            assert_approx_eq!(down[2 * i + 1], -expected[i], 0.01);
+        }
+    }
+}
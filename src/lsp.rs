@@ -0,0 +1,701 @@
+//! A language server for Mégra scripts, speaking the language server
+//! protocol over stdin/stdout, so any editor with an LSP client can be used.
+//!
+//! It doesn't evaluate anything and doesn't need an audio device, the
+//! information comes from the parser, the standard library, the sample
+//! folder and the definitions in the open files (and the init file).
+
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::eval::events::sound::SAMPLE_QUERIES;
+use crate::eval::FunctionMap;
+use crate::event_helpers::PARAMETER_NAMES;
+use crate::megra_error::MegraError;
+use crate::parser::{
+    self, tokenize, valid_identifier_name_char, valid_namespaced_identifier_char, TokenKind,
+};
+use crate::standard_library::define_standard_library;
+
+// the words that start a definition
+const DEFINITION_WORDS: &[&str] = &[
+    "fun",
+    "callback",
+    "defmacro",
+    "let",
+    "defpart",
+    "keep-state",
+];
+
+// evaluated by the interpreter itself, not in the standard library
+const SPECIAL_FORMS: &[&str] = &[
+    "lambda",
+    "quote",
+    "quasiquote",
+    "unquote",
+    "if",
+    "cond",
+    "else",
+    "dotimes",
+    "for",
+];
+
+// lsp constants
+const SEVERITY_ERROR: u8 = 1;
+const SEVERITY_WARNING: u8 = 2;
+const COMPLETION_FUNCTION: u8 = 3;
+const COMPLETION_VARIABLE: u8 = 6;
+const COMPLETION_MODULE: u8 = 9;
+const COMPLETION_KEYWORD: u8 = 14;
+
+/// a function or variable defined in a script
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub name: String,
+    pub function: bool,
+    pub args: Vec<String>,
+    // from the comment lines right above the definition
+    pub doc: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub start: usize,
+    pub end: usize,
+    pub severity: u8,
+    pub message: String,
+}
+
+// the comment lines right above the given position
+fn doc_comment(text: &str, pos: usize) -> String {
+    let line_start = text[..pos].rfind('\n').map(|p| p + 1).unwrap_or(0);
+    let mut lines: Vec<&str> = text[..line_start]
+        .lines()
+        .rev()
+        .map(|l| l.trim())
+        .take_while(|l| l.starts_with(';'))
+        .map(|l| l.trim_start_matches(';').trim())
+        .collect();
+    lines.reverse();
+    lines.join("\n")
+}
+
+/// all function and variable definitions in a script
+pub fn definitions(text: &str) -> Vec<Definition> {
+    let Ok(tokens) = tokenize(text) else {
+        return Vec::new();
+    };
+
+    let mut defs = Vec::new();
+    for (i, win) in tokens.windows(3).enumerate() {
+        let [open, word, name] = win else {
+            continue;
+        };
+        if open.kind != TokenKind::Open
+            || word.kind != TokenKind::Atom
+            || name.kind != TokenKind::Atom
+            || !DEFINITION_WORDS.contains(&&text[word.start..word.end])
+        {
+            continue;
+        }
+
+        // a variable holding a lambda can be called like a function
+        let is_atom = |idx: usize, s: &str| {
+            tokens
+                .get(idx)
+                .is_some_and(|t| t.kind == TokenKind::Atom && &text[t.start..t.end] == s)
+        };
+        let lambda = &text[word.start..word.end] == "let" && is_atom(i + 4, "lambda");
+        let function =
+            matches!(&text[word.start..word.end], "fun" | "callback" | "defmacro") || lambda;
+
+        // positional arguments, if any
+        let args_start = if lambda { i + 5 } else { i + 3 };
+        let mut args = Vec::new();
+        if function && tokens.get(args_start).map(|t| t.kind) == Some(TokenKind::Open) {
+            for t in tokens[args_start + 1..].iter() {
+                if t.kind != TokenKind::Atom {
+                    break;
+                }
+                args.push(text[t.start..t.end].to_string());
+            }
+        }
+
+        defs.push(Definition {
+            name: text[name.start..name.end]
+                .trim_start_matches('\'')
+                .to_string(),
+            function,
+            args,
+            doc: doc_comment(text, open.start),
+            start: name.start,
+            end: name.end,
+        });
+    }
+    defs
+}
+
+// same as the parser, but keeping the positions intact
+fn blank_comments(text: &str) -> String {
+    let mut in_comment = false;
+    let mut in_string = false;
+    text.chars()
+        .map(|c| {
+            match c {
+                '"' if !in_comment => in_string = !in_string,
+                ';' if !in_string => in_comment = true,
+                '\n' => in_comment = false,
+                _ => {}
+            }
+            if in_comment && c != '\n' {
+                ' '
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Check a script for unbalanced parens, expressions the parser can't handle,
+/// and calls to functions that are neither built-in nor known.
+pub fn diagnostics(
+    text: &str,
+    functions: &FunctionMap,
+    known: &HashSet<String>,
+) -> Vec<Diagnostic> {
+    let error = |start: usize, end: usize, message: String| Diagnostic {
+        start,
+        end,
+        severity: SEVERITY_ERROR,
+        message,
+    };
+
+    let tokens = match tokenize(text) {
+        Ok(t) => t,
+        Err(pos) => return vec![error(pos, pos + 1, "unterminated string".to_string())],
+    };
+
+    let mut diags = Vec::new();
+
+    // top level expressions
+    let mut forms = Vec::new();
+    let mut open = Vec::new();
+    for t in tokens.iter() {
+        match t.kind {
+            TokenKind::Open => open.push(t.start),
+            TokenKind::Close => match open.pop() {
+                Some(start) if open.is_empty() => forms.push((start, t.end)),
+                Some(_) => {}
+                None => diags.push(error(
+                    t.start,
+                    t.end,
+                    "unexpected closing paren".to_string(),
+                )),
+            },
+            _ => {}
+        }
+    }
+    if let Some(start) = open.first() {
+        diags.push(error(
+            *start,
+            start + 1,
+            "missing closing paren".to_string(),
+        ));
+    }
+
+    for (start, end) in forms {
+        let src = blank_comments(&text[start..end]);
+        match parser::parse_expr(&src) {
+            Ok((rest, _)) if !rest.trim().is_empty() => {
+                let pos = start + src.len() - rest.len();
+                diags.push(error(pos, end, "unexpected input".to_string()));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                let err = MegraError::parse(&src, e);
+                let (from, to) = err.span.map(|s| (s.start, s.end)).unwrap_or((0, src.len()));
+                diags.push(error(start + from, (start + to).min(end), err.message));
+            }
+        }
+    }
+
+    // function calls
+    for (i, win) in tokens.windows(2).enumerate() {
+        let [open, head] = win else {
+            continue;
+        };
+        if open.kind != TokenKind::Open || head.kind != TokenKind::Atom {
+            continue;
+        }
+        let name = &text[head.start..head.end];
+        if !name.chars().all(valid_namespaced_identifier_char)
+            // imported names can't be checked without loading the file
+            || name.contains('/')
+            || name.parse::<f32>().is_ok()
+            || DEFINITION_WORDS.contains(&name)
+            || SPECIAL_FORMS.contains(&name)
+            || functions.std_lib.contains_key(name)
+            || known.contains(name)
+        {
+            continue;
+        }
+        // the argument list of a function definition or lambda
+        if i >= 3
+            && tokens[i - 3].kind == TokenKind::Open
+            && matches!(
+                &text[tokens[i - 2].start..tokens[i - 2].end],
+                "fun" | "callback" | "defmacro"
+            )
+        {
+            continue;
+        }
+        // ... or the binding of a loop
+        if i >= 2
+            && tokens[i - 2].kind == TokenKind::Open
+            && matches!(
+                &text[tokens[i - 1].start..tokens[i - 1].end],
+                "lambda" | "dotimes" | "for"
+            )
+        {
+            continue;
+        }
+        diags.push(Diagnostic {
+            start: head.start,
+            end: head.end,
+            severity: SEVERITY_WARNING,
+            message: format!("unknown function {name}"),
+        });
+    }
+
+    diags.sort_by_key(|d| d.start);
+    diags
+}
+
+/// the names of the sample sets in the sample folder, named like `load_sample_set` does
+pub fn sample_set_names(folder: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(folder) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter(|e| e.path().is_dir())
+        .filter_map(|e| e.path().file_stem()?.to_str().map(|s| s.to_string()))
+        .map(|s| {
+            if s.starts_with(|c: char| c.is_numeric()) {
+                format!("_{s}")
+            } else {
+                s
+            }
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+// the start and end of the word at the given position
+fn word_at(text: &str, pos: usize) -> (usize, usize) {
+    let is_word = |c: char| valid_identifier_name_char(c) || c == ':' || c == '\'';
+    let start = text[..pos]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word(*c))
+        .last()
+        .map(|(p, _)| p)
+        .unwrap_or(pos);
+    let end = text[pos..]
+        .char_indices()
+        .find(|(_, c)| !is_word(*c))
+        .map(|(p, _)| pos + p)
+        .unwrap_or(text.len());
+    (start, end)
+}
+
+// lsp positions count lines and utf-16 code units
+fn position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map(|p| p + 1).unwrap_or(0);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    json!({ "line": line, "character": character })
+}
+
+fn range(text: &str, start: usize, end: usize) -> Value {
+    json!({ "start": position(text, start), "end": position(text, end) })
+}
+
+fn offset(text: &str, pos: &Value) -> usize {
+    let line = pos["line"].as_u64().unwrap_or(0) as usize;
+    let character = pos["character"].as_u64().unwrap_or(0) as usize;
+
+    let line_start = if line == 0 {
+        0
+    } else {
+        text.match_indices('\n')
+            .nth(line - 1)
+            .map(|(p, _)| p + 1)
+            .unwrap_or(text.len())
+    };
+
+    let mut units = 0;
+    for (p, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + p;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn read_message(reader: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(l) = line.strip_prefix("Content-Length:") {
+            len = Some(l.trim().parse::<usize>()?);
+        }
+    }
+
+    let Some(len) = len else {
+        bail!("lsp - message without content length");
+    };
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf)?;
+    Ok(Some(serde_json::from_slice(&buf)?))
+}
+
+fn write_message(out: &mut impl Write, msg: &Value) -> Result<()> {
+    let body = msg.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    out.flush()?;
+    Ok(())
+}
+
+struct LanguageServer {
+    functions: FunctionMap,
+    sample_sets: Vec<String>,
+    // uri to text
+    documents: HashMap<String, String>,
+    // only used for definitions, not checked
+    init_file: Option<(String, String)>,
+}
+
+impl LanguageServer {
+    fn all_definitions(&self) -> Vec<(&String, &String, Definition)> {
+        self.documents
+            .iter()
+            .chain(self.init_file.iter().map(|(u, t)| (u, t)))
+            .flat_map(|(uri, text)| definitions(text).into_iter().map(move |d| (uri, text, d)))
+            .collect()
+    }
+
+    fn known_names(&self) -> HashSet<String> {
+        self.all_definitions()
+            .into_iter()
+            .filter(|(_, _, d)| d.function)
+            .map(|(_, _, d)| d.name)
+            .chain(self.sample_sets.iter().cloned())
+            .collect()
+    }
+
+    fn publish_diagnostics(&self, uri: &str, out: &mut impl Write) -> Result<()> {
+        let text = self.documents.get(uri).map(|t| t.as_str()).unwrap_or("");
+        let known = self.known_names();
+        let diags: Vec<Value> = diagnostics(text, &self.functions, &known)
+            .into_iter()
+            .map(|d| {
+                json!({
+                    "range": range(text, d.start, d.end),
+                    "severity": d.severity,
+                    "source": "megra",
+                    "message": d.message,
+                })
+            })
+            .collect();
+
+        write_message(
+            out,
+            &json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/publishDiagnostics",
+                "params": { "uri": uri, "diagnostics": diags },
+            }),
+        )
+    }
+
+    // the text and the word at the position of a request
+    fn request_word(&self, params: &Value) -> Option<(&str, usize, usize, usize)> {
+        let text = self
+            .documents
+            .get(params["textDocument"]["uri"].as_str()?)?;
+        let pos = offset(text, &params["position"]);
+        let (start, end) = word_at(text, pos);
+        Some((text.as_str(), pos, start, end))
+    }
+
+    fn completion(&self, params: &Value) -> Value {
+        let Some((text, pos, start, _)) = self.request_word(params) else {
+            return json!([]);
+        };
+        let prefix = &text[start..pos];
+        let edit_range = range(text, start, pos);
+        let item = |label: String, kind: u8, detail: &str| {
+            json!({
+                "label": label,
+                "kind": kind,
+                "detail": detail,
+                "textEdit": { "range": edit_range, "newText": label },
+            })
+        };
+
+        let mut items = Vec::new();
+        if prefix.starts_with(':') {
+            let mut keywords: Vec<String> = PARAMETER_NAMES
+                .iter()
+                .chain(SAMPLE_QUERIES.iter())
+                .map(|k| k.to_string())
+                .collect();
+            // keywords used elsewhere in the script
+            if let Ok(tokens) = tokenize(text) {
+                for t in tokens {
+                    let word = &text[t.start..t.end];
+                    if t.kind == TokenKind::Atom && word.len() > 1 && word.starts_with(':') {
+                        keywords.push(word[1..].to_string());
+                    }
+                }
+            }
+            keywords.sort();
+            keywords.dedup();
+            for k in keywords {
+                items.push(item(format!(":{k}"), COMPLETION_KEYWORD, "keyword"));
+            }
+        } else {
+            let mut builtins: Vec<String> = self
+                .functions
+                .std_lib
+                .iter()
+                .map(|f| f.key().clone())
+                .collect();
+            builtins.sort();
+            for f in builtins {
+                items.push(item(f, COMPLETION_FUNCTION, "built-in function"));
+            }
+            for s in self.sample_sets.iter() {
+                items.push(item(s.clone(), COMPLETION_MODULE, "sample set"));
+            }
+            for (_, _, d) in self.all_definitions() {
+                if d.function {
+                    items.push(item(d.name, COMPLETION_FUNCTION, "function"));
+                } else {
+                    items.push(item(d.name, COMPLETION_VARIABLE, "variable"));
+                }
+            }
+        }
+
+        json!(items)
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let Some((text, _, start, end)) = self.request_word(params) else {
+            return Value::Null;
+        };
+        let word = &text[start..end];
+        let name = word.trim_start_matches('\'');
+
+        let contents = if let Some((_, _, d)) = self
+            .all_definitions()
+            .into_iter()
+            .find(|(_, _, d)| d.name == name)
+        {
+            let signature = if d.function {
+                format!("(fun {} ({}) ...)", d.name, d.args.join(" "))
+            } else {
+                format!("(let {} ...)", d.name)
+            };
+            if d.doc.is_empty() {
+                format!("```megra\n{signature}\n```")
+            } else {
+                format!("```megra\n{signature}\n```\n{}", d.doc)
+            }
+        } else if self.functions.std_lib.contains_key(name) {
+            format!("`{name}` - built-in function")
+        } else if self.sample_sets.iter().any(|s| s == name) {
+            format!("`{name}` - sample set")
+        } else if let Some(k) = word.strip_prefix(':') {
+            if PARAMETER_NAMES.contains(&k) {
+                format!("`{word}` - event parameter")
+            } else {
+                return Value::Null;
+            }
+        } else {
+            return Value::Null;
+        };
+
+        json!({
+            "contents": { "kind": "markdown", "value": contents },
+            "range": range(text, start, end),
+        })
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let Some((text, _, start, end)) = self.request_word(params) else {
+            return Value::Null;
+        };
+        let name = text[start..end].trim_start_matches('\'');
+
+        self.all_definitions()
+            .into_iter()
+            .find(|(_, _, d)| d.name == name)
+            .map(|(uri, text, d)| json!({ "uri": uri, "range": range(text, d.start, d.end) }))
+            .unwrap_or(Value::Null)
+    }
+
+    fn handle(&mut self, msg: &Value, out: &mut impl Write) -> Result<bool> {
+        let method = msg["method"].as_str().unwrap_or("");
+        let params = &msg["params"];
+
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1, // full
+                    "completionProvider": { "triggerCharacters": ["(", ":"] },
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                },
+                "serverInfo": { "name": "megra", "version": "0.0.17" },
+            }),
+            "shutdown" => Value::Null,
+            "exit" => return Ok(false),
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let uri = params["textDocument"]["uri"]
+                    .as_str()
+                    .unwrap_or("")
+                    .to_string();
+                // full sync, so the last change contains the whole text
+                let text = params["textDocument"]["text"]
+                    .as_str()
+                    .or_else(|| params["contentChanges"].as_array()?.last()?["text"].as_str())
+                    .unwrap_or("")
+                    .to_string();
+                self.documents.insert(uri.clone(), text);
+                self.publish_diagnostics(&uri, out)?;
+                return Ok(true);
+            }
+            "textDocument/didClose" => {
+                if let Some(uri) = params["textDocument"]["uri"].as_str() {
+                    self.documents.remove(uri);
+                }
+                return Ok(true);
+            }
+            "textDocument/completion" => self.completion(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/definition" => self.definition(params),
+            _ => {
+                // notifications don't get an answer
+                if !msg["id"].is_null() {
+                    write_message(
+                        out,
+                        &json!({
+                            "jsonrpc": "2.0",
+                            "id": msg["id"],
+                            "error": { "code": -32601, "message": format!("unknown method {method}") },
+                        }),
+                    )?;
+                }
+                return Ok(true);
+            }
+        };
+
+        write_message(
+            out,
+            &json!({ "jsonrpc": "2.0", "id": msg["id"], "result": result }),
+        )?;
+        Ok(true)
+    }
+}
+
+/// Run the language server until the client exits. Nothing may be
+/// printed to stdout in here, as that's the protocol channel.
+pub fn start_lsp(base_dir: Option<PathBuf>, sample_folder: Option<PathBuf>) -> Result<()> {
+    let sample_folder = sample_folder.or_else(|| base_dir.as_ref().map(|b| b.join("samples")));
+    let init_file = base_dir.map(|b| b.join("sketchbook").join("init.megra3"));
+
+    let mut server = LanguageServer {
+        functions: define_standard_library(),
+        sample_sets: sample_folder
+            .as_deref()
+            .map(sample_set_names)
+            .unwrap_or_default(),
+        documents: HashMap::new(),
+        init_file: init_file.and_then(|p| {
+            let text = fs::read_to_string(&p).ok()?;
+            Some((format!("file://{}", p.display()), text))
+        }),
+    };
+
+    let stdin = io::stdin();
+    let mut reader = stdin.lock();
+    let mut out = io::stdout();
+
+    while let Some(msg) = read_message(&mut reader)? {
+        if !server.handle(&msg, &mut out)? {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostics() {
+        let functions = FunctionMap::new();
+        functions
+            .std_lib
+            .insert("sx".to_string(), |_, _, _, _, _| bail!("not needed"));
+
+        let src = ";; the beat
+(fun beat (a b) (sx 'a #t (bd)))
+
+(sx 'b #t (beat 1 2) (snare)";
+
+        let known = HashSet::from(["bd".to_string(), "beat".to_string()]);
+        let diags = diagnostics(src, &functions, &known);
+
+        assert_eq!(diags.len(), 2);
+        assert_eq!(&src[diags[0].start..diags[0].end], "(");
+        assert_eq!(diags[0].message, "missing closing paren");
+        assert_eq!(&src[diags[1].start..diags[1].end], "snare");
+        assert_eq!(diags[1].severity, SEVERITY_WARNING);
+
+        let defs = definitions(src);
+        assert_eq!(defs.len(), 1);
+        assert_eq!(defs[0].name, "beat");
+        assert_eq!(defs[0].args, vec!["a", "b"]);
+        assert_eq!(defs[0].doc, "the beat");
+    }
+
+    #[test]
+    fn test_positions() {
+        let src = "(sx 'ä\n  (bd))";
+        let pos = src.find("bd").unwrap();
+        assert_eq!(position(src, pos), json!({ "line": 1, "character": 3 }));
+        assert_eq!(offset(src, &position(src, pos)), pos);
+        assert_eq!(word_at(src, pos + 1), (pos, pos + 2));
+    }
+}
@@ -6,6 +6,7 @@
 
 // types to represent the AST
 pub mod ast_types;
+pub mod automation;
 // types to represent the evaluated megra language ...
 pub mod builtin_types;
 pub mod commands;
@@ -13,26 +14,43 @@ pub mod cyc_parser;
 pub mod duration_tree;
 pub mod editor;
 pub mod eval;
+pub mod eval_server;
 pub mod event;
 pub mod event_helpers;
 pub mod file_interpreter;
 pub mod generator;
+pub mod generator_file;
 pub mod generator_processor;
 pub mod interpreter;
+pub mod live_buffers;
 pub mod load_audio_file;
+pub mod lsp;
 pub mod markov_sequence_generator;
+pub mod megra_error;
+pub mod midi_clock;
+pub mod midi_file;
 pub mod midi_input;
+pub mod midi_output;
 pub mod music_theory;
+pub mod offline_render;
 pub mod osc_client;
 pub mod parameter;
 pub mod parser;
+pub mod peer_sync;
 pub mod pfa_growth;
 pub mod pfa_reverse;
+pub mod random;
 pub mod real_time_streaming;
 pub mod repl;
+pub mod sample_analysis;
 pub mod sample_set;
 pub mod scheduler;
+pub mod score_export;
 pub mod session;
+pub mod session_snapshot;
+pub mod session_view;
+pub mod speaker_layout;
+pub mod stems;
 pub mod synth_parameter_value_arithmetic;
 
 #[rustfmt::skip]
@@ -43,20 +61,28 @@ mod osc_sender;
 mod visualizer_client;
 
 use crate::builtin_types::*;
+use crate::eval_server::EvalServer;
+use crate::live_buffers::LiveBuffers;
 use crate::osc_client::OscClient;
 use crate::sample_set::SampleAndWavematrixSet;
+use crate::scheduler::BeatClock;
 use crate::session::{OutputMode, Session};
+use crate::session_snapshot::Journal;
+use crate::speaker_layout::SpeakerLayout;
+use crate::stems::{StemBuses, StemOutput};
 use anyhow::anyhow;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{Stream, StreamConfig};
 use dashmap::DashMap;
 use directories_next::ProjectDirs;
 use getopts::Options;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use real_time_streaming::Throw;
 use ruffbox_synth::ruffbox::{init_ruffbox, ReverbMode, RuffboxPlayhead};
+use session_view::RunningSession;
 use standard_library::define_standard_library;
 
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::{env, sync, thread};
 
@@ -95,6 +121,8 @@ const BLOCKSIZE_FLOAT: f32 = 512.0;
 
 struct RunOptions {
     mode: OutputMode,
+    speaker_layout: Option<sync::Arc<SpeakerLayout>>,
+    stem_buses: usize,
     num_live_buffers: usize,
     live_buffer_time: f32,
     max_sample_buffers: usize,
@@ -109,6 +137,14 @@ struct RunOptions {
     downmix_stereo: bool,
     ambisonic_binaural: bool,
     karl_yerkes_mode: bool,
+    eval_server_port: Option<u16>,
+}
+
+struct RenderOptions {
+    script: String,
+    duration: f64,
+    out: String,
+    samplerate: u32,
 }
 
 fn main() -> Result<(), anyhow::Error> {
@@ -137,9 +173,21 @@ fn main() -> Result<(), anyhow::Error> {
         "don't downmix stereo samples to mono (which is the default behaviour)",
     );
 
+    opts.optflag(
+        "",
+        "lsp",
+        "run as language server on stdin/stdout (no audio)",
+    );
+
     opts.optflag("h", "help", "Print this help");
     opts.optflag("n", "no-samples", "don't load default samples");
     opts.optopt("o", "output-mode", "output mode (stereo, 8ch)", "stereo");
+    opts.optopt(
+        "",
+        "speakers",
+        "speaker layout file (channel, azimuth, elevation per line) or list of azimuths, sets the output mode",
+        "30,-30,110,-110",
+    );
     opts.optflag("l", "list-devices", "list available audio devices");
     opts.optopt("d", "device", "choose device", "default");
     opts.optopt(
@@ -156,6 +204,13 @@ fn main() -> Result<(), anyhow::Error> {
     );
     opts.optopt("", "reverb-ir", "reverb impulse response (file)", "");
 
+    opts.optopt(
+        "",
+        "eval-server",
+        "evaluate code sent by other programs on this (local) port",
+        "57200",
+    );
+
     opts.optopt("", "sample-folder", "folder to a collection of samples", "");
     opts.optopt(
         "",
@@ -164,6 +219,13 @@ fn main() -> Result<(), anyhow::Error> {
         "",
     );
 
+    opts.optopt(
+        "",
+        "stem-buses",
+        "number of extra buses to record generators or tags as separate stems",
+        "0",
+    );
+
     opts.optopt(
         "",
         "live-buffers",
@@ -187,6 +249,26 @@ fn main() -> Result<(), anyhow::Error> {
 
     opts.optopt("", "font-size", "editor font size", "15.0");
 
+    opts.optopt(
+        "",
+        "render",
+        "render a script offline (faster than realtime) to a wav file, no audio device needed",
+        "script.megra3",
+    );
+    opts.optopt(
+        "",
+        "duration",
+        "duration of the offline render (i.e. 60s, 500ms, 2m)",
+        "60s",
+    );
+    opts.optopt("", "out", "output file of the offline render", "render.wav");
+    opts.optopt(
+        "",
+        "samplerate",
+        "samplerate of the offline render",
+        "44100",
+    );
+
     let matches = match opts.parse(argv) {
         Ok(m) => m,
         Err(e) => {
@@ -212,14 +294,44 @@ fn main() -> Result<(), anyhow::Error> {
         return Ok(());
     }
 
-    let out_mode = match matches.opt_str("o").as_deref() {
-        Some("16ch") => OutputMode::SixteenChannel,
-        Some("8ch") => OutputMode::EightChannel,
-        Some("4ch") => OutputMode::FourChannel,
-        Some("stereo") => OutputMode::Stereo,
-        _ => {
-            println!("invalid output mode, assume stereo");
-            OutputMode::Stereo
+    // before anything is printed, as stdout is the protocol channel
+    if matches.opt_present("lsp") {
+        let base_dir = matches
+            .opt_str("base")
+            .map(std::path::PathBuf::from)
+            .or_else(|| {
+                ProjectDirs::from("de", "parkellipsen", "megra")
+                    .map(|p| p.config_dir().to_path_buf())
+            });
+        let sample_folder = matches
+            .opt_str("sample-folder")
+            .map(std::path::PathBuf::from);
+        return lsp::start_lsp(base_dir, sample_folder);
+    }
+
+    let speaker_layout = matches
+        .opt_str("speakers")
+        .map(|s| SpeakerLayout::from_option(&s).map(sync::Arc::new))
+        .transpose()?;
+
+    // the speaker layout needs a certain number of channels
+    let out_mode = if let Some(layout) = speaker_layout.as_ref() {
+        println!(
+            "using a layout of {} speakers, output mode is {:?}",
+            layout.num_speakers(),
+            layout.output_mode()
+        );
+        layout.output_mode()
+    } else {
+        match matches.opt_str("o").as_deref() {
+            Some("16ch") => OutputMode::SixteenChannel,
+            Some("8ch") => OutputMode::EightChannel,
+            Some("4ch") => OutputMode::FourChannel,
+            Some("stereo") => OutputMode::Stereo,
+            _ => {
+                println!("invalid output mode, assume stereo");
+                OutputMode::Stereo
+            }
         }
     };
 
@@ -262,6 +374,12 @@ fn main() -> Result<(), anyhow::Error> {
         1
     };
 
+    let stem_buses: usize = if let Some(s) = matches.opt_str("stem-buses") {
+        s.parse().unwrap_or(0)
+    } else {
+        0
+    };
+
     let max_sample_buffers: usize = if let Some(s) = matches.opt_str("max-sample-buffers") {
         s.parse().unwrap_or(3000)
     } else {
@@ -282,6 +400,58 @@ fn main() -> Result<(), anyhow::Error> {
 
     println!("using a live buffer time of: {live_buffer_time}");
 
+    let run_opts = RunOptions {
+        mode: out_mode,
+        speaker_layout,
+        stem_buses,
+        num_live_buffers: num_live_buffers as usize,
+        live_buffer_time,
+        max_sample_buffers,
+        editor,
+        create_sketch,
+        load_samples,
+        sample_folder: matches.opt_str("sample-folder"),
+        base_folder: matches.opt_str("base"),
+        reverb_mode,
+        font: matches.opt_str("font"),
+        font_size,
+        downmix_stereo,
+        ambisonic_binaural,
+        karl_yerkes_mode,
+        eval_server_port: matches.opt_str("eval-server").and_then(|s| s.parse().ok()),
+    };
+
+    // offline rendering doesn't need any audio device
+    if let Some(script) = matches.opt_str("render") {
+        let render_opts = RenderOptions {
+            script,
+            duration: matches
+                .opt_str("duration")
+                .map(|d| {
+                    offline_render::parse_duration_secs(&d)
+                        .ok_or(anyhow!("invalid render duration: {d}"))
+                })
+                .transpose()?
+                .unwrap_or(60.0),
+            out: matches
+                .opt_str("out")
+                .unwrap_or_else(|| "render.wav".to_string()),
+            samplerate: if let Some(s) = matches.opt_str("samplerate") {
+                s.parse().unwrap_or(44100)
+            } else {
+                44100
+            },
+        };
+
+        // no ui, so the whole render can happen on an evaluating thread
+        return eval::on_evaluating_thread(move || match out_mode {
+            OutputMode::Stereo => render::<2>(run_opts, render_opts),
+            OutputMode::FourChannel => render::<4>(run_opts, render_opts),
+            OutputMode::EightChannel => render::<8>(run_opts, render_opts),
+            OutputMode::SixteenChannel => render::<16>(run_opts, render_opts),
+        });
+    }
+
     #[cfg(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd"))]
     let host = cpal::host_from_id(cpal::available_hosts()
 				  .into_iter()
@@ -323,24 +493,6 @@ fn main() -> Result<(), anyhow::Error> {
             .find(|x| x.name().map(|y| y == out_device).unwrap_or(false))
     };
 
-    let run_opts = RunOptions {
-        mode: out_mode,
-        num_live_buffers: num_live_buffers as usize,
-        live_buffer_time,
-        max_sample_buffers,
-        editor,
-        create_sketch,
-        load_samples,
-        sample_folder: matches.opt_str("sample-folder"),
-        base_folder: matches.opt_str("base"),
-        reverb_mode,
-        font: matches.opt_str("font"),
-        font_size,
-        downmix_stereo,
-        ambisonic_binaural,
-        karl_yerkes_mode,
-    };
-
     match out_mode {
         OutputMode::Stereo => run::<2>(input_device, output_device, run_opts)?,
         OutputMode::FourChannel => run::<4>(input_device, output_device, run_opts)?,
@@ -354,6 +506,7 @@ fn main() -> Result<(), anyhow::Error> {
 fn run_input<const NCHAN: usize>(
     input_device: &cpal::Device,
     playhead_in: sync::Arc<Mutex<RuffboxPlayhead<BLOCKSIZE, NCHAN>>>,
+    live_buffers: sync::Arc<LiveBuffers>,
     is_recording_input: sync::Arc<AtomicBool>,
     throw_in: Throw<BLOCKSIZE, NCHAN>,
     options: &RunOptions,
@@ -382,6 +535,8 @@ fn run_input<const NCHAN: usize>(
             // Unless I run into trouble, this might just stay the way it is for now.
             let mut ruff = playhead_in.lock();
 
+            live_buffers.write(data, in_channels);
+
             if is_recording_input.load(Ordering::SeqCst) {
                 let mut stream_item = throw_in.prep_next().unwrap();
                 // there might be a faster way to de-interleave here ...
@@ -464,6 +619,7 @@ fn run_input<const NCHAN: usize>(
                     ruff.write_sample_to_live_buffer(ch, *s);
                 }
             }
+            live_buffers.write(data, in_channels);
         },
         err_fn,
         None,
@@ -479,6 +635,8 @@ fn run_output<const NCHAN: usize>(
     playhead_out: sync::Arc<Mutex<RuffboxPlayhead<BLOCKSIZE, NCHAN>>>,
     is_recording_output: sync::Arc<AtomicBool>,
     throw_out: Throw<BLOCKSIZE, NCHAN>,
+    mut stem_output: StemOutput<BLOCKSIZE, NCHAN>,
+    speaker_layout: Option<sync::Arc<SpeakerLayout>>,
 ) -> Result<Stream, anyhow::Error> {
     let mut out_config: StreamConfig = output_device.default_output_config()?.into();
     out_config.channels = NCHAN as u16;
@@ -502,8 +660,21 @@ fn run_output<const NCHAN: usize>(
             // ruffbox handles it's own logical time ...
             let ruff_out = ruff.process(0.0, true);
 
+            // add the stem buses
+            let mixed = stem_output.mix(&ruff_out, speaker_layout.as_deref());
+
+            // route the ruffbox channels to the speakers
+            let routed;
+            let ruff_out: &[[f32; BLOCKSIZE]; NCHAN] = match speaker_layout.as_ref() {
+                Some(layout) => {
+                    routed = layout.route(&mixed);
+                    &routed
+                }
+                None => &mixed,
+            };
+
             if is_recording_output.load(Ordering::SeqCst) {
-                throw_out.write_samples(&ruff_out, BLOCKSIZE);
+                throw_out.write_samples(ruff_out, BLOCKSIZE);
             }
 
             // there might be a faster way to de-interleave here ...
@@ -560,8 +731,21 @@ fn run_output<const NCHAN: usize>(
                 while samples_actually_needed > 0 {
                     let ruff_out = ruff.process(0.0, true);
 
+                    // add the stem buses
+                    let mixed = stem_output.mix(&ruff_out, speaker_layout.as_deref());
+
+                    // route the ruffbox channels to the speakers
+                    let routed;
+                    let ruff_out: &[[f32; BLOCKSIZE]; NCHAN] = match speaker_layout.as_ref() {
+                        Some(layout) => {
+                            routed = layout.route(&mixed);
+                            &routed
+                        }
+                        None => &mixed,
+                    };
+
                     if is_recording_output.load(Ordering::SeqCst) {
-                        throw_out.write_samples(&ruff_out, BLOCKSIZE);
+                        throw_out.write_samples(ruff_out, BLOCKSIZE);
                     }
 
                     //produced += BLOCKSIZE;
@@ -621,6 +805,38 @@ fn run_output<const NCHAN: usize>(
     Ok(out_stream)
 }
 
+/// the base dir contains the samples, the sketchbook and the recordings,
+/// create it if it doesn't exist
+fn resolve_base_dir(base_folder: Option<String>) -> Result<std::path::PathBuf, anyhow::Error> {
+    let base_dir = if let Some(p) = base_folder {
+        let bd = std::path::PathBuf::from(p);
+        if !bd.exists() {
+            println!("create custom megra resource directory {bd:?}");
+            std::fs::create_dir_all(bd.to_str().unwrap())?;
+        }
+        bd
+    } else if let Some(proj_dirs) = ProjectDirs::from("de", "parkellipsen", "megra") {
+        if !proj_dirs.config_dir().exists() {
+            println!(
+                "create default megra resource directory {:?}",
+                proj_dirs.config_dir()
+            );
+            std::fs::create_dir_all(proj_dirs.config_dir().to_str().unwrap())?;
+        }
+        proj_dirs.config_dir().to_path_buf()
+    } else {
+        // not the most elegant solution, hope this doesn't happen
+        let bd = std::path::PathBuf::from("~/MEGRA_FALLBACK");
+        if !bd.exists() {
+            println!("create custom megra resource directory {bd:?}");
+            std::fs::create_dir_all(bd.to_str().unwrap())?;
+        }
+        bd
+    };
+
+    Ok(base_dir)
+}
+
 fn run<const NCHAN: usize>(
     input_device: Option<cpal::Device>,
     output_device: Option<cpal::Device>,
@@ -650,6 +866,14 @@ fn run<const NCHAN: usize>(
         options.ambisonic_binaural,
     );
 
+    // megra's own copy of the live (and freeze) buffers, so they can be analyzed
+    let live_buffers = sync::Arc::new(LiveBuffers::new(
+        options.num_live_buffers,
+        10,
+        options.live_buffer_time.into(),
+        sample_rate.into(),
+    ));
+
     // OUTPUT RECORDING
     let (throw_out, catch_out) = real_time_streaming::init_real_time_stream::<BLOCKSIZE, NCHAN>(
         (BLOCKSIZE_FLOAT / sample_rate) as f64,
@@ -664,6 +888,41 @@ fn run<const NCHAN: usize>(
 
     let is_recording_output = sync::Arc::new(AtomicBool::new(false));
     let is_recording_input = sync::Arc::new(AtomicBool::new(false));
+    let is_recording_stems = sync::Arc::new(AtomicBool::new(false));
+
+    // STEM BUSES (extra ruffbox instances, summed in the output callback)
+    let mut stem_controls = Vec::new();
+    let mut stem_output = StemOutput {
+        playheads: Vec::new(),
+        throws: Vec::new(),
+        is_recording: sync::Arc::clone(&is_recording_stems),
+    };
+    let mut catch_stems = Vec::new();
+    if options.stem_buses > 0 {
+        // the mix and the main instance are recorded as stems, too
+        for i in 0..options.stem_buses + 2 {
+            if i > 1 {
+                let (stem_ctrl, stem_playhead) = init_ruffbox::<BLOCKSIZE, NCHAN>(
+                    options.num_live_buffers,
+                    options.live_buffer_time.into(),
+                    &options.reverb_mode,
+                    sample_rate.into(),
+                    options.max_sample_buffers,
+                    10,
+                    options.ambisonic_binaural,
+                );
+                stem_controls.push(stem_ctrl);
+                stem_output.playheads.push(stem_playhead);
+            }
+            let (throw_stem, catch_stem) =
+                real_time_streaming::init_real_time_stream::<BLOCKSIZE, NCHAN>(
+                    (BLOCKSIZE_FLOAT / sample_rate) as f64,
+                    0.25,
+                );
+            stem_output.throws.push(throw_stem);
+            catch_stems.push(Some(catch_stem));
+        }
+    }
 
     let rec_control = real_time_streaming::RecordingControl {
         is_recording_output: sync::Arc::clone(&is_recording_output),
@@ -672,6 +931,9 @@ fn run<const NCHAN: usize>(
         catch_out_handle: None,
         catch_in: Some(catch_in),
         catch_in_handle: None,
+        is_recording_stems,
+        catch_stems_handles: catch_stems.iter().map(|_| None).collect(),
+        catch_stems,
         samplerate: sample_rate as u32,
     };
 
@@ -681,7 +943,14 @@ fn run<const NCHAN: usize>(
     // keeping them in scope
     let in_stream = if let Some(in_dev) = input_device {
         let playhead_in = sync::Arc::clone(&playhead_out); // the one for the audio thread (in stream)...
-        run_input(&in_dev, playhead_in, is_recording_input, throw_in, &options)
+        run_input(
+            &in_dev,
+            playhead_in,
+            sync::Arc::clone(&live_buffers),
+            is_recording_input,
+            throw_in,
+            &options,
+        )
     } else {
         Err(anyhow!("can't start input stream"))
     };
@@ -693,7 +962,14 @@ fn run<const NCHAN: usize>(
     }
 
     let out_stream = if let Some(out_dev) = output_device {
-        run_output(&out_dev, playhead_out, is_recording_output, throw_out)
+        run_output(
+            &out_dev,
+            playhead_out,
+            is_recording_output,
+            throw_out,
+            stem_output,
+            options.speaker_layout.clone(),
+        )
     } else {
         Err(anyhow!("can't start output stream"))
     };
@@ -709,41 +985,41 @@ fn run<const NCHAN: usize>(
         schedulers: sync::Arc::new(DashMap::new()),
         contexts: sync::Arc::new(DashMap::new()),
         osc_client: OscClient::new(),
+        midi_out: sync::Arc::new(RwLock::new(None)),
+        midi_clock: sync::Arc::new(Mutex::new(None)),
+        midi_clock_follower: sync::Arc::new(Mutex::new(None)),
+        peer_sync: sync::Arc::new(RwLock::new(None)),
+        eval_server: sync::Arc::new(Mutex::new(None)),
         rec_control: sync::Arc::new(Mutex::new(Some(rec_control))),
+        scheduler_queue: sync::Arc::new(Mutex::new(Vec::new())),
+        journal: sync::Arc::new(Mutex::new(Journal::default())),
+        imports: sync::Arc::new(Mutex::new(HashSet::new())),
+        ramps: sync::Arc::new(Mutex::new(Vec::new())),
+        pending_contexts: sync::Arc::new(Mutex::new(HashMap::new())),
+        transport_stopped: sync::Arc::new(Mutex::new(None)),
+        midi_recording: sync::Arc::new(Mutex::new(None)),
+        beat_clock: sync::Arc::new(Mutex::new(BeatClock::new(0.2))),
         globals: sync::Arc::new(GlobalVariables::new()),
-        sample_set: SampleAndWavematrixSet::new(),
+        sample_set: SampleAndWavematrixSet::with_live_buffers(live_buffers),
         ruffbox: sync::Arc::new(controls),
         output_mode: options.mode,
+        speaker_layout: options.speaker_layout.clone(),
+        stems: sync::Arc::new(StemBuses::new(stem_controls)),
         sync_mode: session::SyncMode::NotOnSilence,
         // define the "standard library"
         functions: sync::Arc::new(define_standard_library()),
     };
+    *session.functions.session_view.write() = Some(Box::new(RunningSession::new(&session)));
+    if let Some(layout) = session.speaker_layout.as_ref() {
+        commands::set_speaker_count(&session.globals, layout.num_speakers());
+    }
 
-    let base_dir = if let Some(p) = options.base_folder {
-        let bd = std::path::PathBuf::from(p);
-        if !bd.exists() {
-            println!("create custom megra resource directory {bd:?}");
-            std::fs::create_dir_all(bd.to_str().unwrap())?;
-        }
-        bd
-    } else if let Some(proj_dirs) = ProjectDirs::from("de", "parkellipsen", "megra") {
-        if !proj_dirs.config_dir().exists() {
-            println!(
-                "create default megra resource directory {:?}",
-                proj_dirs.config_dir()
-            );
-            std::fs::create_dir_all(proj_dirs.config_dir().to_str().unwrap())?;
-        }
-        proj_dirs.config_dir().to_path_buf()
-    } else {
-        // not the most elegant solution, hope this doesn't happen
-        let bd = std::path::PathBuf::from("~/MEGRA_FALLBACK");
-        if !bd.exists() {
-            println!("create custom megra resource directory {bd:?}");
-            std::fs::create_dir_all(bd.to_str().unwrap())?;
-        }
-        bd
-    };
+    // keeps the generators running
+    scheduler::start_timing_thread(session.clone());
+    // runs the parameter ramps
+    automation::start_automation_thread(session.clone());
+
+    let base_dir = resolve_base_dir(options.base_folder)?;
 
     println!("base dir is: {base_dir:?}");
 
@@ -776,23 +1052,36 @@ fn run<const NCHAN: usize>(
     } else {
         let init_path_string = init_file_path.to_str().unwrap().to_string();
         println!("loading init file {init_path_string}");
-        file_interpreter::parse_file(
-            init_path_string,
-            &session,
-            base_dir.to_str().unwrap().to_string(),
-        );
+        let init_session = session.clone();
+        let init_base_dir = base_dir.to_str().unwrap().to_string();
+        eval::on_evaluating_thread(move || {
+            file_interpreter::parse_file(init_path_string, &init_session, init_base_dir)
+        });
     };
 
+    if let Some(port) = options.eval_server_port {
+        match EvalServer::start(session.clone(), base_dir.display().to_string(), port) {
+            Ok(es) => {
+                *session.eval_server.lock() = Some(es);
+            }
+            Err(e) => {
+                println!("can't start eval server - {e}");
+            }
+        }
+    }
+
     // load the default sample set ...
     if options.load_samples {
         println!("load samples from path: {samples_path:?}");
         let controls_arc2 = sync::Arc::clone(&session.ruffbox);
+        let stems2 = sync::Arc::clone(&session.stems);
         let stdlib2 = sync::Arc::clone(&session.functions);
         let sample_set2 = session.sample_set.clone();
         thread::spawn(move || {
             commands::load_sample_sets_path(
                 &stdlib2,
                 &controls_arc2,
+                &stems2,
                 sample_set2,
                 &samples_path,
                 options.downmix_stereo,
@@ -817,3 +1106,106 @@ fn run<const NCHAN: usize>(
         repl::start_repl(session, base_dir.display().to_string())
     }
 }
+
+/// render a script offline, with no audio device, faster than realtime
+fn render<const NCHAN: usize>(
+    options: RunOptions,
+    render_options: RenderOptions,
+) -> Result<(), anyhow::Error> {
+    let (controls, mut playhead) = init_ruffbox::<BLOCKSIZE, NCHAN>(
+        options.num_live_buffers,
+        options.live_buffer_time.into(),
+        &options.reverb_mode,
+        render_options.samplerate.into(),
+        options.max_sample_buffers,
+        10,
+        options.ambisonic_binaural,
+    );
+
+    let session = Session {
+        schedulers: sync::Arc::new(DashMap::new()),
+        contexts: sync::Arc::new(DashMap::new()),
+        osc_client: OscClient::new(),
+        midi_out: sync::Arc::new(RwLock::new(None)),
+        midi_clock: sync::Arc::new(Mutex::new(None)),
+        midi_clock_follower: sync::Arc::new(Mutex::new(None)),
+        peer_sync: sync::Arc::new(RwLock::new(None)),
+        eval_server: sync::Arc::new(Mutex::new(None)),
+        // no recording while rendering
+        rec_control: sync::Arc::new(Mutex::new(None)),
+        // no timing thread here, the renderer steps the schedulers
+        scheduler_queue: sync::Arc::new(Mutex::new(Vec::new())),
+        journal: sync::Arc::new(Mutex::new(Journal::default())),
+        imports: sync::Arc::new(Mutex::new(HashSet::new())),
+        ramps: sync::Arc::new(Mutex::new(Vec::new())),
+        pending_contexts: sync::Arc::new(Mutex::new(HashMap::new())),
+        transport_stopped: sync::Arc::new(Mutex::new(None)),
+        midi_recording: sync::Arc::new(Mutex::new(None)),
+        beat_clock: sync::Arc::new(Mutex::new(BeatClock::new(0.2))),
+        globals: sync::Arc::new(GlobalVariables::new()),
+        sample_set: SampleAndWavematrixSet::new(),
+        ruffbox: sync::Arc::new(controls),
+        output_mode: options.mode,
+        speaker_layout: options.speaker_layout.clone(),
+        // no stems while rendering
+        stems: sync::Arc::new(StemBuses::new(Vec::new())),
+        sync_mode: session::SyncMode::NotOnSilence,
+        functions: sync::Arc::new(define_standard_library()),
+    };
+    *session.functions.session_view.write() = Some(Box::new(RunningSession::new(&session)));
+    if let Some(layout) = session.speaker_layout.as_ref() {
+        commands::set_speaker_count(&session.globals, layout.num_speakers());
+    }
+
+    let base_dir = resolve_base_dir(options.base_folder)?;
+
+    // samples need to be there before the script starts, so no
+    // loading in the background here ...
+    if options.load_samples {
+        let samples_path = if let Some(folder) = options.sample_folder {
+            std::path::PathBuf::from(folder)
+        } else {
+            base_dir.join("samples")
+        };
+        if samples_path.exists() {
+            println!("load samples from path: {samples_path:?}");
+            commands::load_sample_sets_path(
+                &session.functions,
+                &session.ruffbox,
+                &session.stems,
+                session.sample_set.clone(),
+                &samples_path,
+                options.downmix_stereo,
+            );
+        }
+    }
+
+    let init_file_path = base_dir.join("sketchbook").join("init.megra3");
+    if init_file_path.exists() {
+        let init_path_string = init_file_path.to_str().unwrap().to_string();
+        println!("loading init file {init_path_string}");
+        file_interpreter::parse_file(
+            init_path_string,
+            &session,
+            base_dir.to_str().unwrap().to_string(),
+        );
+    }
+
+    if !std::path::Path::new(&render_options.script).exists() {
+        return Err(anyhow!("can't find script {}", render_options.script));
+    }
+
+    file_interpreter::parse_file(
+        render_options.script,
+        &session,
+        base_dir.to_str().unwrap().to_string(),
+    );
+
+    offline_render::render_to_file(
+        &session,
+        &mut playhead,
+        render_options.samplerate,
+        render_options.duration,
+        &render_options.out,
+    )
+}
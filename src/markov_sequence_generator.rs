@@ -1,7 +1,9 @@
 use crate::duration_tree::{find_longest_suffix_duration_with_symbol, DurationTreeNode};
 use crate::event::{Event, InterpretableEvent, SourceEvent, StaticEvent};
 use crate::parameter::DynVal;
+use crate::random::CurrentRng;
 use crate::GlobalVariables;
+use rand::seq::SliceRandom;
 use std::collections::{BTreeMap, HashMap};
 use vom_rs::pfa::{self};
 
@@ -43,6 +45,53 @@ aaaa --- 400ms ---> b
 
 */
 
+/// Advance the pfa, like `Pfa::next_transition`, but choose the next state
+/// with the current rng, as the pfa itself always uses the thread rng,
+/// which can't be seeded.
+pub fn next_transition(pfa: &mut pfa::Pfa<char>) -> Option<pfa::PfaQueryResult<char>> {
+    // find the next state first, so nothing changes if there isn't any
+    let mut cur_state = pfa.current_state?;
+    let last_symbol = pfa.current_symbol?;
+
+    if pfa.state_childfree_hash(cur_state) && pfa.restart_when_stuck {
+        cur_state = pfa.init_state?;
+    }
+
+    let mut choice_list = Vec::new();
+    if let Some(children) = pfa.children.get(&cur_state) {
+        for c in children.iter().filter(|c| pfa.has_state_hash(c.child_hash)) {
+            let prob = (100.0 * c.prob) as i32;
+            for _ in 0..prob {
+                choice_list.push(c.child_hash);
+            }
+        }
+    }
+
+    let res = *choice_list.choose(&mut CurrentRng)?;
+    let sym = *pfa.labels.get(&res)?.last()?;
+
+    // push before updating
+    pfa.state_history.push(cur_state);
+    pfa.history.push(last_symbol);
+    pfa.current_state = Some(res);
+    pfa.current_symbol = Some(sym);
+
+    // truncate history
+    if pfa.history.len() > pfa.history_length {
+        pfa.history.drain(0..1);
+    }
+    if pfa.state_history.len() > pfa.history_length {
+        pfa.state_history.drain(0..1);
+    }
+
+    Some(pfa::PfaQueryResult {
+        last_state: pfa.labels[&cur_state].clone(),
+        current_state: pfa.labels[&res].clone(),
+        last_symbol,
+        next_symbol: sym,
+    })
+}
+
 #[derive(Clone)]
 pub struct MarkovSequenceGenerator {
     // the name of this generator
@@ -132,7 +181,7 @@ impl MarkovSequenceGenerator {
         };
 
         // advance pfa ...
-        self.last_transition = self.generator.next_transition();
+        self.last_transition = next_transition(&mut self.generator);
 
         let mut dur_ev = None;
 
@@ -185,3 +234,65 @@ impl MarkovSequenceGenerator {
         self.modified = false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::{GeneratorRngGuard, SeededRng};
+    use vom_rs::pfa::Pfa;
+
+    #[test]
+    fn test_seeded_transitions_are_reproducible() {
+        let mut rules = Vec::new();
+        for (source, symbol) in [
+            ('a', 'a'),
+            ('a', 'b'),
+            ('b', 'a'),
+            ('b', 'c'),
+            ('c', 'a'),
+            ('c', 'b'),
+        ] {
+            rules.push(pfa::Rule {
+                source: vec![source],
+                symbol,
+                probability: 0.5,
+            });
+        }
+        let pfa = Pfa::<char>::infer_from_rules(&mut rules, true);
+
+        let run = || {
+            let _guard = GeneratorRngGuard::set(Some(SeededRng::new(23)));
+            let mut pfa = pfa.clone();
+            (0..50)
+                .filter_map(|_| next_transition(&mut pfa).map(|t| t.next_symbol))
+                .collect::<Vec<char>>()
+        };
+
+        let first = run();
+        assert_eq!(first.len(), 50);
+        assert_eq!(first, run());
+    }
+
+    #[test]
+    fn test_stuck_transition_keeps_state() {
+        let mut rules = vec![pfa::Rule {
+            source: vec!['a'],
+            symbol: 'b',
+            probability: 1.0,
+        }];
+        let mut pfa = Pfa::<char>::infer_from_rules(&mut rules, false);
+        pfa.restart_when_stuck = false;
+
+        assert_eq!(next_transition(&mut pfa).unwrap().next_symbol, 'b');
+
+        // 'b' is a dead end, nothing changes
+        let state = pfa.current_state;
+        let history = pfa.history.clone();
+        let state_history = pfa.state_history.clone();
+        assert!(next_transition(&mut pfa).is_none());
+        assert_eq!(pfa.current_state, state);
+        assert_eq!(pfa.current_symbol, Some('b'));
+        assert_eq!(pfa.history, history);
+        assert_eq!(pfa.state_history, state_history);
+    }
+}
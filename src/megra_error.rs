@@ -0,0 +1,270 @@
+//! Errors from parsing and evaluating source text, with the position
+//! in the source they refer to.
+
+use nom::error::{VerboseError, VerboseErrorKind};
+use std::fmt;
+
+use crate::parser::{tokenize, Token, TokenKind};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Parse,
+    // the input ended before the expression was complete
+    Incomplete,
+    Eval,
+}
+
+/// A region of the source text. The offsets are in bytes, line
+/// and column (in chars) start at one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(src: &str, start: usize, end: usize) -> Self {
+        let start = start.min(src.len());
+        let line_start = src[..start].rfind('\n').map(|p| p + 1).unwrap_or(0);
+        Span {
+            start,
+            end: end.clamp(start, src.len()),
+            line: src[..start].matches('\n').count() + 1,
+            column: src[line_start..start].chars().count() + 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MegraError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+/// An evaluation error, along with the position of the failing
+/// expression, as indices into the tails of the enclosing expressions.
+#[derive(Debug)]
+pub struct EvalError {
+    pub message: String,
+    // innermost first
+    path: Vec<usize>,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// Mark an error as coming from the expression at the given index of the tail.
+pub fn at_tail_index(err: anyhow::Error, idx: usize) -> anyhow::Error {
+    match err.downcast::<EvalError>() {
+        Ok(mut e) => {
+            e.path.push(idx);
+            e.into()
+        }
+        Err(e) => EvalError {
+            message: e.to_string(),
+            path: vec![idx],
+        }
+        .into(),
+    }
+}
+
+/// Forget the position of an error, i.e. if it comes from
+/// a function body, which isn't part of the evaluated source.
+pub fn without_position(err: anyhow::Error) -> anyhow::Error {
+    match err.downcast::<EvalError>() {
+        Ok(e) => anyhow::anyhow!(e.message),
+        Err(e) => e,
+    }
+}
+
+// an expression in the source, atoms have no children
+struct Node {
+    start: usize,
+    end: usize,
+    children: Vec<Node>,
+}
+
+fn node(tokens: &[Token], idx: &mut usize) -> Option<Node> {
+    let t = tokens.get(*idx)?;
+    *idx += 1;
+
+    if t.kind != TokenKind::Open {
+        return Some(Node {
+            start: t.start,
+            end: t.end,
+            children: Vec::new(),
+        });
+    }
+
+    let mut children = Vec::new();
+    loop {
+        let next = tokens.get(*idx)?;
+        if next.kind == TokenKind::Close {
+            *idx += 1;
+            return Some(Node {
+                start: t.start,
+                end: next.end,
+                children,
+            });
+        }
+        children.push(node(tokens, idx)?);
+    }
+}
+
+// the position of the (sub-)expression given by the tail indices
+fn expression_span(src: &str, path: &[usize]) -> Option<Span> {
+    let tokens = tokenize(src).ok()?;
+    let mut idx = tokens.iter().position(|t| t.kind == TokenKind::Open)?;
+    let mut current = node(&tokens, &mut idx)?;
+
+    // path is innermost first, the head is the first child
+    for idx in path.iter().rev() {
+        if idx + 1 >= current.children.len() {
+            break;
+        }
+        current = current.children.swap_remove(idx + 1);
+    }
+
+    Some(Span::new(src, current.start, current.end))
+}
+
+impl MegraError {
+    pub fn parse(src: &str, err: nom::Err<VerboseError<&str>>) -> Self {
+        let e = match err {
+            nom::Err::Error(e) | nom::Err::Failure(e) => e,
+            nom::Err::Incomplete(_) => {
+                return MegraError {
+                    kind: ErrorKind::Incomplete,
+                    message: "incomplete expression".to_string(),
+                    span: None,
+                }
+            }
+        };
+
+        // the innermost error comes first
+        let rest = e.errors.first().map(|(r, _)| r.trim_start()).unwrap_or("");
+        let start = src.len() - rest.len().min(src.len());
+        let context = e.errors.iter().find_map(|(_, k)| match k {
+            VerboseErrorKind::Context(c) => Some(*c),
+            _ => None,
+        });
+
+        MegraError {
+            kind: if context == Some("closing paren") && rest.is_empty() {
+                ErrorKind::Incomplete
+            } else {
+                ErrorKind::Parse
+            },
+            message: if let Some(c) = context {
+                format!("expected {c}")
+            } else {
+                "can't parse this".to_string()
+            },
+            span: Some(Span::new(src, start, start + 1)),
+        }
+    }
+
+    pub fn eval(src: &str, err: anyhow::Error) -> Self {
+        let (message, span) = match err.downcast::<EvalError>() {
+            Ok(e) => (e.message, expression_span(src, &e.path)),
+            Err(e) => (e.to_string(), expression_span(src, &[])),
+        };
+
+        MegraError {
+            kind: ErrorKind::Eval,
+            message,
+            span,
+        }
+    }
+
+    /// whether more input might make this valid
+    pub fn is_incomplete(&self) -> bool {
+        self.kind == ErrorKind::Incomplete
+    }
+
+    /// The source line of the error, with carets pointing to the
+    /// erroneous part, compiler-style.
+    pub fn caret_diagnostic(&self, src: &str) -> String {
+        let Some(span) = self.span else {
+            return format!("{self}");
+        };
+
+        let line_start = src[..span.start].rfind('\n').map(|p| p + 1).unwrap_or(0);
+        let line_end = src[span.start..]
+            .find('\n')
+            .map(|p| span.start + p)
+            .unwrap_or(src.len());
+        let width = src[span.start..span.end.min(line_end)]
+            .chars()
+            .count()
+            .max(1);
+
+        format!(
+            "{self}\n{}\n{}{}",
+            &src[line_start..line_end],
+            " ".repeat(span.column - 1),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl fmt::Display for MegraError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            ErrorKind::Parse => "parser error",
+            ErrorKind::Incomplete => "incomplete input",
+            ErrorKind::Eval => "evaluation error",
+        };
+        if let Some(span) = self.span {
+            write!(
+                f,
+                "{kind} at line {}, column {} - {}",
+                span.line, span.column, self.message
+            )
+        } else {
+            write!(f, "{kind} - {}", self.message)
+        }
+    }
+}
+
+impl std::error::Error for MegraError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_error_span() {
+        let src = "(sx 'a #t\n  (nuc 'b %))";
+        let err = MegraError::parse(src, crate::parser::parse_expr(src).unwrap_err());
+        assert_eq!(err.kind, ErrorKind::Parse);
+        let span = err.span.unwrap();
+        assert_eq!((span.line, span.column), (2, 11));
+        assert_eq!(
+            err.caret_diagnostic(src),
+            "parser error at line 2, column 11 - expected closing paren\n  (nuc 'b %))\n          ^"
+        );
+
+        let src = "(sx 'a #t\n  (nuc 'b (bd))";
+        let err = MegraError::parse(src, crate::parser::parse_expr(src).unwrap_err());
+        assert!(err.is_incomplete());
+    }
+
+    #[test]
+    fn test_eval_error_span() {
+        let src = "(sx 'a #t (nuc 'b (bd)) (learn))";
+        let err = at_tail_index(anyhow::anyhow!("learn - missing name"), 3);
+        let err = MegraError::eval(src, err);
+        let span = err.span.unwrap();
+        assert_eq!(&src[span.start..span.end], "(learn)");
+        assert_eq!(err.message, "learn - missing name");
+    }
+}
@@ -0,0 +1,254 @@
+use anyhow::{anyhow, Result};
+use midir::{Ignore, MidiInput};
+use parking_lot::RwLock;
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use std::{sync, thread};
+
+use crate::builtin_types::{ConfigParameter, GlobalVariables, TypedEntity, VariableId};
+use crate::commands;
+use crate::midi_output::MidiOutSender;
+use crate::parameter::DynVal;
+use crate::scheduler;
+use crate::session::Session;
+
+/// midi clock runs at 24 pulses per quarter note
+pub const PPQ: f64 = 24.0;
+
+const CLOCK_TICK: u8 = 0xF8;
+const CLOCK_START: u8 = 0xFA;
+const CLOCK_CONTINUE: u8 = 0xFB;
+const CLOCK_STOP: u8 = 0xFC;
+
+// number of ticks to average over when following an external clock
+const FOLLOW_WINDOW: usize = 24;
+// how often the follower checks whether it should stop
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The current beat duration in milliseconds, that is, the default duration
+/// scaled by the global time modifier.
+pub fn current_beat_duration(globals: &sync::Arc<GlobalVariables>) -> f64 {
+    let default_duration = commands::get_default_duration(globals);
+
+    // clone, so we don't advance any dynamic tmod that's
+    // evaluated by the schedulers ...
+    let tmod = if let Some(TypedEntity::ConfigParameter(ConfigParameter::Dynamic(mut t))) = globals
+        .get(&VariableId::GlobalTimeModifier)
+        .map(|t| t.value().clone())
+    {
+        t.evaluate_numerical() as f64
+    } else {
+        1.0
+    };
+
+    default_duration * tmod
+}
+
+/// the time modifier needed to make the default duration match
+/// an incoming clock with the given (average) tick interval
+pub fn tmod_from_tick_interval(tick_interval_ms: f64, default_duration_ms: f64) -> f64 {
+    (tick_interval_ms * PPQ) / default_duration_ms
+}
+
+/// Sends midi clock (and start/stop) to the current midi output port,
+/// with the tempo derived from the global default duration.
+pub struct MidiClockSender {
+    running: sync::Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl MidiClockSender {
+    pub fn start(
+        midi_out: sync::Arc<RwLock<Option<MidiOutSender>>>,
+        globals: sync::Arc<GlobalVariables>,
+    ) -> Result<Self> {
+        if midi_out.read().is_none() {
+            return Err(anyhow!("no midi output port open"));
+        }
+
+        let running = sync::Arc::new(AtomicBool::new(true));
+        let running2 = sync::Arc::clone(&running);
+
+        let handle = thread::Builder::new()
+            .name("midi clock out".to_string())
+            .spawn(move || {
+                let mut next_tick = Instant::now();
+
+                if let Some(out) = midi_out.read().as_ref() {
+                    out.send_message(vec![CLOCK_START], next_tick);
+                }
+
+                while running2.load(Ordering::SeqCst) {
+                    if let Some(out) = midi_out.read().as_ref() {
+                        out.send_message(vec![CLOCK_TICK], next_tick);
+                    }
+                    // re-read the tempo on every tick, so tempo changes
+                    // are followed immediately
+                    let tick_interval = current_beat_duration(&globals) / PPQ;
+                    next_tick += Duration::from_secs_f64((tick_interval * 0.001).max(0.0001));
+                    thread::sleep(next_tick.saturating_duration_since(Instant::now()));
+                }
+
+                if let Some(out) = midi_out.read().as_ref() {
+                    out.send_message(vec![CLOCK_STOP], Instant::now());
+                }
+            })?;
+
+        println!("started sending midi clock");
+
+        Ok(MidiClockSender {
+            running,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(h) = self.handle.take() {
+            if h.join().is_err() {
+                println!("could not join midi clock thread");
+            }
+        }
+        println!("stopped sending midi clock");
+    }
+}
+
+/// Follows an incoming midi clock by adjusting the global time modifier,
+/// so that the default duration matches one quarter note of the external
+/// clock. Start, stop and continue messages control the session's
+/// transport.
+pub struct MidiClockFollower {
+    running: sync::Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl MidiClockFollower {
+    pub fn start<const BUFSIZE: usize, const NCHAN: usize>(
+        in_port_num: usize,
+        session: Session<BUFSIZE, NCHAN>,
+    ) -> Result<Self> {
+        let mut midi_in = MidiInput::new("midir clock input")?;
+        // we want the clock messages here ...
+        midi_in.ignore(Ignore::SysexAndActiveSense);
+        let in_ports = midi_in.ports();
+        let in_port = in_ports
+            .get(in_port_num)
+            .ok_or(anyhow!("invalid midi clock input port selected"))?;
+
+        let in_port_name = midi_in.port_name(in_port)?;
+
+        // the callback runs on a thread of the midi backend, the messages
+        // are handled on the follower thread
+        let (tx, rx) = mpsc::channel::<(u64, u8)>();
+
+        let conn_in = midi_in
+            .connect(
+                in_port,
+                "megra-midi-clock-input",
+                move |stamp, message, _| {
+                    if let Some(status) = message.first() {
+                        let _ = tx.send((stamp, *status));
+                    }
+                },
+                (),
+            )
+            .map_err(|e| anyhow!("can't connect to midi clock input - {:?}", e.kind()))?;
+
+        let running = sync::Arc::new(AtomicBool::new(true));
+        let running2 = sync::Arc::clone(&running);
+
+        let handle = thread::Builder::new()
+            .name("midi clock in".to_string())
+            .spawn(move || {
+                // the connection needs to be kept alive as long as we follow
+                let _conn_in = conn_in;
+
+                let mut last_tick: Option<u64> = None;
+                let mut intervals: VecDeque<u64> = VecDeque::with_capacity(FOLLOW_WINDOW);
+
+                while running2.load(Ordering::SeqCst) {
+                    let Ok((stamp, status)) = rx.recv_timeout(FOLLOW_POLL_INTERVAL) else {
+                        continue;
+                    };
+
+                    match status {
+                        CLOCK_TICK => {
+                            if let Some(last) = last_tick {
+                                if intervals.len() == FOLLOW_WINDOW {
+                                    intervals.pop_front();
+                                }
+                                intervals.push_back(stamp.saturating_sub(last));
+                            }
+                            last_tick = Some(stamp);
+
+                            if intervals.is_empty() {
+                                continue;
+                            }
+
+                            // timestamps are microseconds
+                            let avg_interval_ms = intervals.iter().sum::<u64>() as f64
+                                / intervals.len() as f64
+                                * 0.001;
+
+                            commands::set_global_tmod(
+                                &session.globals,
+                                DynVal::with_value(tmod_from_tick_interval(
+                                    avg_interval_ms,
+                                    commands::get_default_duration(&session.globals),
+                                ) as f32),
+                            );
+                        }
+                        CLOCK_START | CLOCK_CONTINUE | CLOCK_STOP => {
+                            // start over, the clock might come back with a different tempo
+                            last_tick = None;
+                            intervals.clear();
+
+                            match status {
+                                CLOCK_START => scheduler::continue_transport(&session, true),
+                                CLOCK_CONTINUE => scheduler::continue_transport(&session, false),
+                                _ => scheduler::stop_transport(&session),
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+
+                // don't leave the session hanging
+                scheduler::continue_transport(&session, false);
+            })?;
+
+        println!("Connection open, following midi clock from '{in_port_name}' ...");
+
+        Ok(MidiClockFollower {
+            running,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(h) = self.handle.take() {
+            if h.join().is_err() {
+                println!("could not join midi clock follower thread");
+            }
+        }
+        println!("stopped following midi clock");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tmod_from_tick_interval() {
+        // 120 bpm -> 500ms per quarter, ~20.83ms per tick
+        let tick = 500.0 / PPQ;
+        assert!((tmod_from_tick_interval(tick, 500.0) - 1.0).abs() < 0.0001);
+        // external clock twice as fast as our default duration
+        assert!((tmod_from_tick_interval(tick, 1000.0) - 0.5).abs() < 0.0001);
+    }
+}
@@ -0,0 +1,465 @@
+use anyhow::Result;
+use ruffbox_synth::building_blocks::{SynthParameterLabel, SynthParameterValue};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::{fs, sync};
+
+use crate::builtin_types::GlobalVariables;
+use crate::eval::FunctionMap;
+use crate::event::{InterpretableEvent, StaticEvent};
+use crate::generator::Generator;
+use crate::midi_output::{self, MidiNote};
+use crate::random::GeneratorRngGuard;
+use crate::sample_set::SampleAndWavematrixSet;
+use crate::session::OutputMode;
+
+/// ticks per quarter note in the exported files
+pub const PPQ: u16 = 480;
+
+// the general midi drum channel (10, counted from 1)
+const DRUM_CHANNEL: u8 = 9;
+
+/// how much of the event stream to export
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportLength {
+    Steps(usize),
+    Seconds(f64),
+}
+
+/// the events recorded from a generator, with their time in seconds
+pub struct RecordedTrack {
+    pub name: String,
+    pub events: Vec<(f64, StaticEvent)>,
+    /// the start of each step, plus the end of the last one
+    pub steps: Vec<f64>,
+}
+
+/// Taps the event stream the schedulers emit for the running generators
+/// with the chosen tags, until each of them has run for the given length.
+pub struct MidiRecording {
+    pub filename: String,
+    pub beat_duration_ms: f64,
+    length: ExportLength,
+    // stream time at which the recording started
+    start: f64,
+    // the tracks of the running generators, and whether they're complete
+    running: BTreeMap<BTreeSet<String>, (RecordedTrack, bool)>,
+    // tracks recorded up front, i.e. from static generators
+    recorded: Vec<RecordedTrack>,
+}
+
+impl MidiRecording {
+    pub fn new(
+        filename: &str,
+        gens: &[BTreeSet<String>],
+        recorded: Vec<RecordedTrack>,
+        length: ExportLength,
+        beat_duration_ms: f64,
+        start: f64,
+    ) -> Self {
+        MidiRecording {
+            filename: filename.to_string(),
+            beat_duration_ms,
+            length,
+            start,
+            running: gens
+                .iter()
+                .map(|tags| {
+                    (
+                        tags.clone(),
+                        (
+                            RecordedTrack {
+                                name: track_name(tags),
+                                events: Vec::new(),
+                                steps: Vec::new(),
+                            },
+                            false,
+                        ),
+                    )
+                })
+                .collect(),
+            recorded,
+        }
+    }
+
+    /// whether the generator's events still need to be recorded
+    pub fn records(&self, tags: &BTreeSet<String>) -> bool {
+        matches!(self.running.get(tags), Some((_, false)))
+    }
+
+    /// Record the sound events of one step, which starts at the given
+    /// stream time and lasts `dur` seconds. The last step of a generator
+    /// completes its track.
+    pub fn record_step(
+        &mut self,
+        tags: &BTreeSet<String>,
+        time: f64,
+        dur: f64,
+        events: Vec<StaticEvent>,
+        last: bool,
+    ) {
+        let Some((track, complete)) = self.running.get_mut(tags) else {
+            return;
+        };
+
+        // steps computed before the recording started
+        let time = time - self.start;
+        if *complete || time < 0.0 {
+            return;
+        }
+
+        if let ExportLength::Seconds(s) = self.length {
+            if time >= s {
+                *complete = true;
+                return;
+            }
+        }
+
+        if track.steps.is_empty() {
+            track.steps.push(time);
+        }
+        track.steps.push(time + dur);
+        track.events.extend(
+            events
+                .into_iter()
+                .filter(|e| e.name != "silence")
+                .map(|e| (time, e)),
+        );
+
+        *complete = last
+            || match self.length {
+                ExportLength::Steps(n) => track.steps.len() > n,
+                ExportLength::Seconds(s) => time + dur >= s,
+            };
+    }
+
+    /// complete the track of a generator that was stopped
+    pub fn stop(&mut self, tags: &BTreeSet<String>) {
+        if let Some((_, complete)) = self.running.get_mut(tags) {
+            *complete = true;
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.running.values().all(|(_, complete)| *complete)
+    }
+
+    pub fn into_tracks(self) -> Vec<RecordedTrack> {
+        let mut tracks = self.recorded;
+        tracks.extend(self.running.into_values().map(|(track, _)| track));
+        tracks
+    }
+}
+
+/// Step a generator (which should be a copy, as its state is
+/// advanced) and record the sound events it emits. Used for
+/// generators that aren't running, which have no event stream
+/// to tap.
+pub fn record_generator(
+    gen: &mut Generator,
+    length: ExportLength,
+    globals: &sync::Arc<GlobalVariables>,
+    functions: &sync::Arc<FunctionMap>,
+    sample_set: SampleAndWavematrixSet,
+    out_mode: OutputMode,
+) -> RecordedTrack {
+    // don't advance the rng of the running generator
+    gen.rng = gen.rng.as_ref().map(|r| r.fork());
+    let _rng_guard = GeneratorRngGuard::set(gen.rng.clone());
+
+    let mut events = Vec::new();
+    let mut step_times = vec![0.0];
+    let mut time = 0.0;
+    let mut steps = 0;
+
+    loop {
+        match length {
+            ExportLength::Steps(n) if steps >= n => break,
+            ExportLength::Seconds(s) if time >= s => break,
+            _ => {}
+        }
+
+        // same order as in the scheduler, the transition is the
+        // time to the next step
+        let dur = if let Some(SynthParameterValue::ScalarF32(t)) = gen
+            .current_transition(globals, functions, sample_set.clone(), out_mode)
+            .params
+            .get(&SynthParameterLabel::Duration.into())
+        {
+            *t as f64 * 0.001
+        } else {
+            0.2
+        };
+
+        for ev in gen.current_events(globals, functions, sample_set.clone(), out_mode) {
+            if let InterpretableEvent::Sound(s) = ev {
+                if s.name != "silence" {
+                    events.push((time, s));
+                }
+            }
+        }
+
+        if dur <= 0.0 {
+            break;
+        }
+
+        time += dur;
+        steps += 1;
+        step_times.push(time);
+
+        if gen.reached_end_state() {
+            break;
+        }
+    }
+
+    RecordedTrack {
+        name: track_name(&gen.id_tags),
+        events,
+        steps: step_times,
+    }
+}
+
+/// general midi drum notes for some common sample set names,
+/// everything else is assigned a note starting from 60, in
+/// order of appearance
+fn drum_note(set: &str, other_sets: &mut HashMap<String, u8>) -> u8 {
+    match set {
+        "bd" | "kick" => 36,
+        "rim" => 37,
+        "sn" | "snare" => 38,
+        "cp" | "clap" => 39,
+        "hh" | "hat" | "ch" => 42,
+        "oh" => 46,
+        "tom" | "lt" => 45,
+        "ht" => 50,
+        "cy" | "crash" => 49,
+        "ride" => 51,
+        _ => {
+            let next = 60 + other_sets.len().min(67) as u8;
+            *other_sets.entry(set.to_string()).or_insert(next)
+        }
+    }
+}
+
+/// Map an event to a midi note, if possible. Note events keep their
+/// pitch and channel, synth events are mapped by their frequency, and
+/// sample events become drum notes.
+pub fn midi_note_from_event(
+    ev: &StaticEvent,
+    channel: u8,
+    beat_duration_ms: f64,
+    other_sets: &mut HashMap<String, u8>,
+) -> Option<MidiNote> {
+    if ev.name == "note" {
+        return midi_output::note_from_event(ev, beat_duration_ms);
+    }
+
+    if let Some(lookup) = ev.sample_lookup.as_ref() {
+        let mut note = midi_output::note_with_event_params(ev, drum_note(lookup.set(), other_sets));
+        note.channel = DRUM_CHANNEL;
+        return Some(note);
+    }
+
+    if let Some(SynthParameterValue::ScalarF32(freq)) =
+        ev.params.get(&SynthParameterLabel::PitchFrequency.into())
+    {
+        if *freq <= 0.0 {
+            return None;
+        }
+        let nr = (69.0 + 12.0 * (freq / 440.0).log2())
+            .round()
+            .clamp(0.0, 127.0) as u8;
+        let mut note = midi_output::note_with_event_params(ev, nr);
+        note.channel = channel;
+        return Some(note);
+    }
+
+    None
+}
+
+fn write_var_len(buf: &mut Vec<u8>, mut val: u32) {
+    let mut bytes = vec![(val & 0x7F) as u8];
+    val >>= 7;
+    while val > 0 {
+        bytes.push(((val & 0x7F) as u8) | 0x80);
+        val >>= 7;
+    }
+    bytes.reverse();
+    buf.extend_from_slice(&bytes);
+}
+
+fn write_chunk(buf: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    buf.extend_from_slice(id);
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buf.extend_from_slice(data);
+}
+
+// track data from absolute-time (in ticks) messages, adds end of track
+fn track_data(mut messages: Vec<(u64, Vec<u8>)>) -> Vec<u8> {
+    // meta events first, then note-offs before note-ons at the same time
+    let rank = |msg: &[u8]| if msg[0] == 0xFF { 0 } else { msg[0] & 0xF0 };
+    messages.sort_by(|a, b| a.0.cmp(&b.0).then(rank(&a.1).cmp(&rank(&b.1))));
+
+    let mut data = Vec::new();
+    let mut last = 0;
+    for (t, msg) in messages {
+        write_var_len(&mut data, (t - last) as u32);
+        data.extend_from_slice(&msg);
+        last = t;
+    }
+    // end of track
+    data.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+    data
+}
+
+/// Turn the recorded tracks into a multitrack (format 1) standard midi file,
+/// with the given beat (quarter note) duration in milliseconds.
+pub fn to_smf(tracks: &[RecordedTrack], beat_duration_ms: f64) -> Vec<u8> {
+    let ticks_per_sec = PPQ as f64 * 1000.0 / beat_duration_ms;
+    let to_ticks = |secs: f64| (secs * ticks_per_sec).round().max(0.0) as u64;
+
+    let mut buf = Vec::new();
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&1u16.to_be_bytes()); // format 1
+    header.extend_from_slice(&(tracks.len() as u16 + 1).to_be_bytes());
+    header.extend_from_slice(&PPQ.to_be_bytes());
+    write_chunk(&mut buf, b"MThd", &header);
+
+    // tempo track
+    let tempo = ((beat_duration_ms * 1000.0) as u32).min(0xFFFFFF);
+    let mut tempo_msg = vec![0xFF, 0x51, 0x03];
+    tempo_msg.extend_from_slice(&tempo.to_be_bytes()[1..]);
+    write_chunk(&mut buf, b"MTrk", &track_data(vec![(0, tempo_msg)]));
+
+    let mut other_sets = HashMap::new();
+
+    for (idx, track) in tracks.iter().enumerate() {
+        // one channel per track, leaving out the drum channel
+        let channel = [0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 12, 13, 14, 15][idx % 15];
+
+        let mut name_msg = vec![0xFF, 0x03];
+        write_var_len(&mut name_msg, track.name.len() as u32);
+        name_msg.extend_from_slice(track.name.as_bytes());
+
+        let mut messages = vec![(0, name_msg)];
+
+        for (time, ev) in track.events.iter() {
+            if let Some(n) = midi_note_from_event(ev, channel, beat_duration_ms, &mut other_sets) {
+                let start = to_ticks(*time);
+                let end = to_ticks(*time + n.duration.as_secs_f64()).max(start + 1);
+                messages.push((start, vec![0x90 | n.channel, n.note, n.velocity.max(1)]));
+                messages.push((end, vec![0x80 | n.channel, n.note, 0]));
+            }
+        }
+
+        write_chunk(&mut buf, b"MTrk", &track_data(messages));
+    }
+
+    buf
+}
+
+pub fn write_smf(filename: &str, tracks: &[RecordedTrack], beat_duration_ms: f64) -> Result<()> {
+    fs::write(filename, to_smf(tracks, beat_duration_ms))?;
+    Ok(())
+}
+
+/// the tags of a generator as a single string
+pub fn track_name(tags: &BTreeSet<String>) -> String {
+    tags.iter().cloned().collect::<Vec<String>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::EventOperation;
+    use crate::parameter::NoteParameterLabel;
+
+    #[test]
+    fn test_var_len() {
+        let mut buf = Vec::new();
+        write_var_len(&mut buf, 0);
+        write_var_len(&mut buf, 0x7F);
+        write_var_len(&mut buf, 0x80);
+        write_var_len(&mut buf, 0x3FFF);
+        assert_eq!(buf, vec![0x00, 0x7F, 0x81, 0x00, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn test_to_smf() {
+        let mut params = HashMap::new();
+        params.insert(
+            NoteParameterLabel::Pitch.into(),
+            SynthParameterValue::ScalarF32(60.0),
+        );
+        params.insert(
+            SynthParameterLabel::Sustain.into(),
+            SynthParameterValue::ScalarF32(250.0),
+        );
+
+        let ev = StaticEvent {
+            name: "note".to_string(),
+            params,
+            tags: BTreeSet::new(),
+            op: EventOperation::Replace,
+            sample_lookup: None,
+        };
+
+        let tracks = vec![RecordedTrack {
+            name: "a".to_string(),
+            events: vec![(0.0, ev.clone()), (0.5, ev)],
+            steps: vec![0.0, 0.5, 1.0],
+        }];
+
+        let smf = to_smf(&tracks, 500.0);
+        assert_eq!(&smf[0..4], b"MThd");
+        // format 1, two tracks, 480 ppq
+        assert_eq!(&smf[8..14], &[0, 1, 0, 2, 0x01, 0xE0]);
+
+        // second note starts on the second beat (480 ticks), a quarter
+        // beat (sustain 250ms at 120bpm = 240 ticks) after the first note-off
+        let expected = [0x81, 0x70, 0x80, 60, 0, 0x81, 0x70, 0x90, 60, 100];
+        assert!(smf.windows(expected.len()).any(|w| w == expected));
+    }
+
+    #[test]
+    fn test_midi_recording() {
+        let a = BTreeSet::from(["a".to_string()]);
+        let b = BTreeSet::from(["b".to_string()]);
+        let mut rec = MidiRecording::new(
+            "a.mid",
+            &[a.clone(), b.clone()],
+            Vec::new(),
+            ExportLength::Steps(2),
+            500.0,
+            10.0,
+        );
+
+        let ev = StaticEvent {
+            name: "saw".to_string(),
+            params: HashMap::new(),
+            tags: BTreeSet::new(),
+            op: EventOperation::Replace,
+            sample_lookup: None,
+        };
+
+        // computed before the recording started
+        rec.record_step(&a, 9.8, 0.25, vec![ev.clone()], false);
+        assert!(rec.records(&a));
+
+        rec.record_step(&a, 10.05, 0.25, vec![ev.clone()], false);
+        rec.record_step(&a, 10.3, 0.25, vec![ev.clone()], false);
+        assert!(!rec.records(&a));
+        assert!(!rec.is_complete());
+
+        rec.stop(&b);
+        assert!(rec.is_complete());
+
+        let tracks = rec.into_tracks();
+        assert_eq!(tracks[0].name, "a");
+        assert_eq!(tracks[0].events.len(), 2);
+        assert!((tracks[0].events[1].0 - 0.3).abs() < 1e-9);
+        assert_eq!(tracks[0].steps.len(), 3);
+        assert!(tracks[1].events.is_empty());
+    }
+}
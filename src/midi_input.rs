@@ -4,6 +4,7 @@ use midir::{Ignore, MidiInput};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::sync::mpsc;
 
 use crate::builtin_types::Comparable;
 use crate::eval::{eval_expression, EvaluatedExpr, LocalVariables};
@@ -36,60 +37,17 @@ pub fn open_midi_input_port<const BUFSIZE: usize, const NCHAN: usize>(
     println!("\nOpening connection");
     let in_port_name = midi_in.port_name(in_port).unwrap();
 
+    // the callback runs on a thread of the midi backend, so the messages
+    // are handed over to be evaluated on this (evaluating) thread
+    let (tx, rx) = mpsc::channel::<Vec<u8>>();
+
     // _conn_in needs to be a named parameter, because it needs to be kept alive until the end of the scope
     let _conn_in = midi_in
         .connect(
             in_port,
             "midir-read-input",
             move |_, message, _| {
-                if session.functions.usr_lib.contains_key("midi") {
-                    let (fun_arg_names, fun_expr) =
-                        session.functions.usr_lib.get("midi").unwrap().clone();
-
-                    // FIRST, eval local args,
-                    // manual zip
-                    let mut local_args = HashMap::new();
-                    for (i, val) in fun_arg_names.iter().enumerate() {
-                        if i < message.len() {
-                            local_args.insert(
-                                val.clone(),
-                                EvaluatedExpr::Typed(
-                                    crate::builtin_types::TypedEntity::Comparable(
-                                        Comparable::Float(message[i] as f32),
-                                    ),
-                                ),
-                            );
-                        }
-                        //else {
-                        //  println!("no midi arg available for pos arg {val}");
-                        // }
-                    }
-                    let locals = Rc::new(RefCell::new(LocalVariables {
-                        pos_args: local_args,
-                        rest: vec![],
-                    }));
-
-                    // THIRD
-                    if let Ok(fun_tail) = fun_expr
-                        .iter()
-                        .map(|expr| {
-                            eval_expression(
-                                expr,
-                                &session.functions,
-                                &session.globals,
-                                Some(Rc::clone(&locals)),
-                                session.sample_set.clone(),
-                                session.output_mode,
-                            )
-                        })
-                        .collect::<Result<Vec<EvaluatedExpr>>>()
-                    {
-                        // return last form result, cl-style
-                        for eval_expr in fun_tail {
-                            interpreter::interpret(eval_expr, session.clone(), base_dir.clone());
-                        }
-                    }
-                }
+                let _ = tx.send(message.to_vec());
             },
             (),
         )
@@ -98,5 +56,51 @@ pub fn open_midi_input_port<const BUFSIZE: usize, const NCHAN: usize>(
     println!("Connection open, reading input from '{in_port_name}' ...");
 
     // keep midi thread running until we quit the program ...
-    std::thread::park();
+    for message in rx {
+        if session.functions.usr_lib.contains_key("midi") {
+            let (fun_arg_names, fun_expr) = session.functions.usr_lib.get("midi").unwrap().clone();
+
+            // FIRST, eval local args,
+            // manual zip
+            let mut local_args = HashMap::new();
+            for (i, val) in fun_arg_names.iter().enumerate() {
+                if i < message.len() {
+                    local_args.insert(
+                        val.clone(),
+                        EvaluatedExpr::Typed(crate::builtin_types::TypedEntity::Comparable(
+                            Comparable::Float(message[i] as f32),
+                        )),
+                    );
+                }
+                //else {
+                //  println!("no midi arg available for pos arg {val}");
+                // }
+            }
+            let locals = Rc::new(RefCell::new(LocalVariables {
+                pos_args: local_args,
+                rest: vec![],
+            }));
+
+            // THIRD
+            if let Ok(fun_tail) = fun_expr
+                .iter()
+                .map(|expr| {
+                    eval_expression(
+                        expr,
+                        &session.functions,
+                        &session.globals,
+                        Some(Rc::clone(&locals)),
+                        session.sample_set.clone(),
+                        session.output_mode,
+                    )
+                })
+                .collect::<Result<Vec<EvaluatedExpr>>>()
+            {
+                // return last form result, cl-style
+                for eval_expr in fun_tail {
+                    interpreter::interpret(eval_expr, session.clone(), base_dir.clone());
+                }
+            }
+        }
+    }
 }
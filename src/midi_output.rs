@@ -0,0 +1,296 @@
+use anyhow::{anyhow, Result};
+use crossbeam::channel::{unbounded, RecvTimeoutError, Sender};
+use midir::{MidiOutput, MidiOutputConnection};
+
+use ruffbox_synth::building_blocks::{SynthParameterLabel, SynthParameterValue};
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::event::StaticEvent;
+use crate::music_theory;
+use crate::parameter::NoteParameterLabel;
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+
+/// A note, extracted from a `note` event, ready to be
+/// sent out as a note-on/note-off pair.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MidiNote {
+    pub channel: u8,
+    pub note: u8,
+    pub velocity: u8,
+    pub duration: Duration,
+}
+
+pub fn list_midi_output_ports() {
+    if let Ok(midi_out) = MidiOutput::new("midir output") {
+        println!("\nAvailable output ports:");
+        let out_ports = midi_out.ports();
+        for (i, p) in out_ports.iter().enumerate() {
+            println!("{}: {}", i, midi_out.port_name(p).unwrap());
+        }
+    }
+}
+
+/// Turn a (static) note event into a midi note.
+/// Pitch can either be a midi note number or a note symbol (i.e. 'a4),
+/// velocity is derived from the level (0.0 - 1.0), the duration from the
+/// sustain time in milliseconds (if present) or the note value
+/// (4 = quarter note, "4." = dotted quarter), relative to the
+/// given beat (quarter note) duration in milliseconds.
+pub fn note_from_event(ev: &StaticEvent, beat_duration_ms: f64) -> Option<MidiNote> {
+    let note = match ev.params.get(&NoteParameterLabel::Pitch.into()) {
+        Some(SynthParameterValue::ScalarF32(n)) => n.round().clamp(0.0, 127.0) as u8,
+        Some(SynthParameterValue::Symbolic(s)) => {
+            // the internal note numbers start an octave lower than midi (a4 = 57)
+            (music_theory::to_note_nr(music_theory::from_string(s).ok()?) + 12).min(127)
+        }
+        _ => return None,
+    };
+
+    let mut n = note_with_event_params(ev, note);
+
+    if !ev.params.contains_key(&SynthParameterLabel::Sustain.into()) {
+        let note_value = match ev.params.get(&SynthParameterLabel::Duration.into()) {
+            Some(SynthParameterValue::ScalarF32(d)) => Some((*d as f64, false)),
+            Some(SynthParameterValue::Symbolic(d)) => {
+                let d = d.trim();
+                let dotted = d.ends_with('.');
+                d.trim_end_matches('.')
+                    .parse::<f64>()
+                    .ok()
+                    .map(|d| (d, dotted))
+            }
+            _ => None,
+        };
+
+        // unknown note values last a beat
+        let duration_ms = match note_value {
+            Some((d, dotted)) if d > 0.0 => {
+                beat_duration_ms * 4.0 / d * if dotted { 1.5 } else { 1.0 }
+            }
+            _ => beat_duration_ms,
+        };
+
+        n.duration = Duration::from_micros((duration_ms * 1000.0) as u64);
+    }
+
+    Some(n)
+}
+
+/// A midi note with the given note number and velocity, duration and
+/// channel taken from the event (the duration in milliseconds, from the
+/// sustain time or the event duration).
+pub fn note_with_event_params(ev: &StaticEvent, note: u8) -> MidiNote {
+    let velocity = if let Some(SynthParameterValue::ScalarF32(l)) =
+        ev.params.get(&SynthParameterLabel::EnvelopeLevel.into())
+    {
+        (l.clamp(0.0, 1.0) * 127.0).round() as u8
+    } else {
+        100
+    };
+
+    let duration_ms = if let Some(SynthParameterValue::ScalarF32(s)) =
+        ev.params.get(&SynthParameterLabel::Sustain.into())
+    {
+        *s
+    } else if let Some(SynthParameterValue::ScalarF32(d)) =
+        ev.params.get(&SynthParameterLabel::Duration.into())
+    {
+        *d
+    } else {
+        200.0
+    };
+
+    let channel = if let Some(SynthParameterValue::ScalarF32(c)) =
+        ev.params.get(&NoteParameterLabel::Channel.into())
+    {
+        // midi channels are counted from 1 in the language ...
+        (c.round() as i32 - 1).clamp(0, 15) as u8
+    } else {
+        0
+    };
+
+    MidiNote {
+        channel,
+        note,
+        velocity,
+        duration: Duration::from_micros((duration_ms.max(0.0) * 1000.0) as u64),
+    }
+}
+
+/// The handle to a midi output port. The connection itself lives in
+/// a dedicated thread that sends the timestamped messages
+/// once their time has come, so that the scheduler threads
+/// never have to wait for the note-offs.
+pub struct MidiOutSender {
+    pub port_name: String,
+    sender: Sender<(Instant, Vec<u8>)>,
+}
+
+impl MidiOutSender {
+    pub fn open(out_port_num: usize) -> Result<Self> {
+        let midi_out = MidiOutput::new("midir output")?;
+        let out_ports = midi_out.ports();
+        let out_port = out_ports
+            .get(out_port_num)
+            .ok_or(anyhow!("invalid output port selected"))?;
+
+        let port_name = midi_out.port_name(out_port)?;
+
+        let conn = midi_out
+            .connect(out_port, "megra-midi-output")
+            .map_err(|e| anyhow!("can't connect to midi output port - {:?}", e.kind()))?;
+
+        let (sender, receiver) = unbounded();
+
+        thread::Builder::new()
+            .name(format!("midi out {port_name}"))
+            .spawn(move || {
+                run_sender_loop(conn, receiver);
+            })?;
+
+        println!("Connection open, sending midi to '{port_name}' ...");
+
+        Ok(MidiOutSender { port_name, sender })
+    }
+
+    /// schedule note-on and note-off for a note event
+    pub fn send_note_event(&self, ev: &StaticEvent, beat_duration_ms: f64, at: Instant) {
+        if let Some(n) = note_from_event(ev, beat_duration_ms) {
+            self.send_note(&n, at);
+        }
+    }
+
+    pub fn send_note(&self, n: &MidiNote, at: Instant) {
+        self.send_message(vec![NOTE_ON | n.channel, n.note, n.velocity], at);
+        self.send_message(vec![NOTE_OFF | n.channel, n.note, 0], at + n.duration);
+    }
+
+    /// schedule any raw midi message (i.e. clock or transport messages)
+    pub fn send_message(&self, msg: Vec<u8>, at: Instant) {
+        let _ = self.sender.send((at, msg));
+    }
+}
+
+fn run_sender_loop(
+    mut conn: MidiOutputConnection,
+    receiver: crossbeam::channel::Receiver<(Instant, Vec<u8>)>,
+) {
+    // ordered by time first, then by message, so that a note-off
+    // (0x8n) goes out before a note-on (0x9n) at the same instant
+    let mut queue: BinaryHeap<Reverse<(Instant, Vec<u8>)>> = BinaryHeap::new();
+
+    loop {
+        let now = Instant::now();
+        while let Some(Reverse((t, msg))) = queue.peek() {
+            if *t > now {
+                break;
+            }
+            if let Err(e) = conn.send(msg) {
+                println!("midi out - can't send message: {e}");
+            }
+            queue.pop();
+        }
+
+        let incoming = if let Some(Reverse((t, _))) = queue.peek() {
+            receiver.recv_timeout(t.saturating_duration_since(now))
+        } else {
+            receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+
+        match incoming {
+            Ok(item) => queue.push(Reverse(item)),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                // don't leave hanging notes behind ...
+                while let Some(Reverse((_, msg))) = queue.pop() {
+                    if msg[0] & 0xF0 == NOTE_OFF {
+                        let _ = conn.send(&msg);
+                    }
+                }
+                conn.close();
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::EventOperation;
+    use std::collections::{BTreeSet, HashMap};
+
+    #[test]
+    fn test_note_from_event() {
+        let mut params = HashMap::new();
+        params.insert(
+            NoteParameterLabel::Pitch.into(),
+            SynthParameterValue::Symbolic("a4".to_string()),
+        );
+        params.insert(
+            SynthParameterLabel::Sustain.into(),
+            SynthParameterValue::ScalarF32(400.0),
+        );
+        params.insert(
+            SynthParameterLabel::EnvelopeLevel.into(),
+            SynthParameterValue::ScalarF32(0.5),
+        );
+        params.insert(
+            NoteParameterLabel::Channel.into(),
+            SynthParameterValue::ScalarF32(10.0),
+        );
+
+        let ev = StaticEvent {
+            name: "note".to_string(),
+            params,
+            tags: BTreeSet::new(),
+            op: EventOperation::Replace,
+            sample_lookup: None,
+        };
+
+        let n = note_from_event(&ev, 500.0).unwrap();
+        assert_eq!(n.note, 69);
+        assert_eq!(n.velocity, 64);
+        assert_eq!(n.channel, 9);
+        assert_eq!(n.duration, Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_note_value_duration() {
+        let functions = crate::standard_library::define_standard_library();
+        let globals = std::sync::Arc::new(crate::GlobalVariables::new());
+        let sample_set = crate::sample_set::SampleAndWavematrixSet::new();
+
+        let to_note = |expr: &str| {
+            let Ok(crate::eval::EvaluatedExpr::Typed(crate::TypedEntity::SoundEvent(mut ev))) =
+                crate::eval::parse_and_eval_from_str(
+                    expr,
+                    &functions,
+                    &globals,
+                    sample_set.clone(),
+                    crate::session::OutputMode::Stereo,
+                )
+            else {
+                panic!("{expr} is not an event");
+            };
+            note_from_event(&ev.get_static(&globals), 500.0).unwrap()
+        };
+
+        // a quarter note lasts a beat ...
+        let n = to_note("(note 'a4 4)");
+        assert_eq!(n.note, 69);
+        assert_eq!(n.duration, Duration::from_millis(500));
+
+        assert_eq!(to_note("(note 60 8)").duration, Duration::from_millis(250));
+        assert_eq!(
+            to_note("(note 60 \"4.\")").duration,
+            Duration::from_millis(750)
+        );
+    }
+}
@@ -27,7 +27,6 @@ pub fn from_string(string: &str) -> Result<Note> {
 }
 
 pub fn from_note_nr(nr: u8) -> Note {
-    println!("nr: {nr}");
     let pitch_class = PitchClass::from_u8(nr % 12);
     let octave = nr / 12;
     Note::new(pitch_class, octave)
@@ -37,11 +36,18 @@ pub fn from_freq(freq: f32, tuning: Tuning) -> Note {
     match tuning {
         Tuning::EqualTemperament => {
             let a440 = to_note_nr(Note::new(PitchClass::from_str("A").unwrap(), 4));
-            from_note_nr(((12.0 * (freq / 440.0).log2()) as i16 + a440 as i16) as u8)
+            from_note_nr(((12.0 * (freq / 440.0).log2()).round() as i16 + a440 as i16).max(0) as u8)
         }
     }
 }
 
+/// The megra symbol of a pitch class (0 - 11), i.e. "c", "cs", "d" ...
+pub fn pitch_class_symbol(class: u8) -> &'static str {
+    [
+        "c", "cs", "d", "ds", "e", "f", "fs", "g", "gs", "a", "as", "b",
+    ][class as usize % 12]
+}
+
 pub fn to_note_nr(note: Note) -> u8 {
     note.pitch_class.into_u8() + 12 * note.octave
 }
@@ -0,0 +1,95 @@
+use anyhow::Result;
+use ruffbox_synth::ruffbox::RuffboxPlayhead;
+
+use crate::automation;
+use crate::scheduler;
+use crate::session::Session;
+
+/// Parse a render duration like "60s", "500ms", "2m" or just "60" (seconds)
+/// into seconds.
+pub fn parse_duration_secs(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let (num, factor) = if let Some(n) = s.strip_suffix("ms") {
+        (n, 0.001)
+    } else if let Some(n) = s.strip_suffix('s') {
+        (n, 1.0)
+    } else if let Some(n) = s.strip_suffix('m') {
+        (n, 60.0)
+    } else {
+        (s, 1.0)
+    };
+    let secs = num.trim().parse::<f64>().ok()? * factor;
+    if secs > 0.0 && secs.is_finite() {
+        Some(secs)
+    } else {
+        None
+    }
+}
+
+/// Render the given session to a wav file, faster than realtime.
+/// The script needs to be interpreted before, so that the generators
+/// are queued, and there mustn't be a timing thread for this session.
+pub fn render_to_file<const BUFSIZE: usize, const NCHAN: usize>(
+    session: &Session<BUFSIZE, NCHAN>,
+    playhead: &mut RuffboxPlayhead<BUFSIZE, NCHAN>,
+    samplerate: u32,
+    duration: f64,
+    out_path: &str,
+) -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: NCHAN as u16,
+        sample_rate: samplerate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+
+    let mut writer = hound::WavWriter::create(out_path, spec)?;
+
+    let block_time = BUFSIZE as f64 / samplerate as f64;
+    let num_blocks = (duration / block_time).ceil() as usize;
+
+    println!("render {duration}s to {out_path} ...");
+
+    for block in 0..num_blocks {
+        // all events of this block need to be scheduled before it's processed
+        scheduler::run_schedulers_until(session, (block + 1) as f64 * block_time);
+        automation::run_ramps_until(session, block as f64 * block_time);
+
+        let out = playhead.process(0.0, true);
+        // route the ruffbox channels to the speakers
+        let routed;
+        let out: &[[f32; BUFSIZE]; NCHAN] = match session.speaker_layout.as_ref() {
+            Some(layout) => {
+                routed = layout.route(&out);
+                &routed
+            }
+            None => &out,
+        };
+        for f in 0..BUFSIZE {
+            for ch in out.iter() {
+                writer.write_sample(ch[f])?;
+            }
+        }
+    }
+
+    writer.finalize()?;
+
+    println!("rendered {out_path}");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration_secs() {
+        assert_eq!(parse_duration_secs("60s"), Some(60.0));
+        assert_eq!(parse_duration_secs("60"), Some(60.0));
+        assert_eq!(parse_duration_secs("2m"), Some(120.0));
+        assert_eq!(parse_duration_secs("500ms"), Some(0.5));
+        assert_eq!(parse_duration_secs("-1s"), None);
+        assert_eq!(parse_duration_secs("abc"), None);
+    }
+}
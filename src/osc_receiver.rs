@@ -8,7 +8,7 @@ use std::rc::Rc;
 use std::str::FromStr;
 
 use crate::builtin_types::{Comparable, TypedEntity};
-use crate::eval::{eval_expression, EvaluatedExpr, LocalVariables};
+use crate::eval::{self, eval_expression, EvaluatedExpr, LocalVariables};
 use crate::interpreter;
 
 use crate::session::Session;
@@ -32,7 +32,7 @@ impl OscReceiver {
 
         let mut buf = [0u8; rosc::decoder::MTU];
 
-        std::thread::spawn(move || loop {
+        let spawned = eval::spawn_evaluating("osc receiver", move || loop {
             match sock.recv_from(&mut buf) {
                 Ok((size, addr)) => {
                     println!("Received packet with size {} from: {}", size, addr);
@@ -130,5 +130,8 @@ impl OscReceiver {
                 }
             }
         });
+        if let Err(e) = spawned {
+            println!("can't start osc receiver - {e}");
+        }
     }
 }
@@ -13,6 +13,7 @@ use ruffbox_synth::building_blocks::{
 
 use crate::builtin_types::{Comparable, LazyArithmetic};
 use crate::eval::resolver::resolve_lazy;
+use crate::random;
 use crate::{GlobalVariables, TypedEntity, VariableId};
 
 #[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
@@ -20,6 +21,7 @@ pub enum NoteParameterLabel {
     Pitch,
     Syllable,
     Articulation,
+    Channel,
 }
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug)]
@@ -566,7 +568,7 @@ impl DynVal {
 
     pub fn shake(&mut self, mut factor: f32) {
         factor = factor.clamp(0.0, 1.0);
-        let mut rng = rand::thread_rng();
+        let mut rng = random::CurrentRng;
         // heuristic ... from old megra ... not sure what i thought back then, let's see ...
         let rand = (factor * (1000.0 - rng.gen_range(0.0..2000.0))) * (self.val / 1000.0);
         self.val += rand;
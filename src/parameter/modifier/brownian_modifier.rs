@@ -1,5 +1,6 @@
 use crate::parameter::modifier::Modifier;
 use crate::parameter::DynVal;
+use crate::random;
 use rand::Rng;
 
 #[derive(Clone)]
@@ -14,7 +15,7 @@ pub struct BrownianModifier {
 impl Modifier for BrownianModifier {
     fn evaluate(&mut self, _: f32) -> f32 {
         // why doesn't rust has a hashable float ?????
-        let mut rng = rand::thread_rng();
+        let mut rng = random::CurrentRng;
         // heuristic ... from old megra ... not sure what i thought back then, let's see ...
         let rand = rng.gen_range(0..2000);
         let step_size = self.step_size.evaluate_numerical();
@@ -1,5 +1,6 @@
 use crate::parameter::modifier::Modifier;
 use crate::parameter::DynVal;
+use crate::random;
 use rand::Rng;
 
 #[derive(Clone)]
@@ -18,7 +19,7 @@ impl Modifier for RandRangeModifier {
     fn evaluate(&mut self, _: f32) -> f32 {
         let min = self.min.evaluate_numerical();
         let max = self.max.evaluate_numerical();
-        let mut rng = rand::thread_rng();
+        let mut rng = random::CurrentRng;
         if (min - max).abs() < f32::EPSILON {
             // min == max
             max
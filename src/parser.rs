@@ -44,6 +44,11 @@ pub fn valid_identifier_name_char(chr: char) -> bool {
         || is_alphanumeric(chr as u8)
 }
 
+/// identifiers can additionally be namespaced, like `drums/kick`
+pub fn valid_namespaced_identifier_char(chr: char) -> bool {
+    chr == '/' || valid_identifier_name_char(chr)
+}
+
 /// parse a string, which is enclosed in double quotes
 fn parse_string(i: &str) -> IResult<&str, Atom, VerboseError<&str>> {
     map(
@@ -77,6 +82,8 @@ fn parse_definition(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
     alt((
         map(tag("fun"), |_| Expr::FunctionDefinition),
         map(tag("callback"), |_| Expr::FunctionDefinition),
+        map(tag("lambda"), |_| Expr::LambdaDefinition),
+        map(tag("defmacro"), |_| Expr::MacroDefinition),
         map(tag("let"), |_| Expr::VariableDefinition),
         map(tag("defpart"), |_| Expr::PersistantStateDefinition),
         map(tag("keep-state"), |_| Expr::PersistantStateDefinition),
@@ -108,7 +115,7 @@ pub fn parse_symbol(i: &str) -> IResult<&str, Atom, VerboseError<&str>> {
 /// function names are language constructs that contain allowed function name chars
 fn parse_identifier(i: &str) -> IResult<&str, Atom, VerboseError<&str>> {
     map(
-        context("identifer", take_while1(valid_identifier_name_char)),
+        context("identifer", take_while1(valid_namespaced_identifier_char)),
         |sym_str: &str| Atom::Identifier(sym_str.to_string()),
     )(i)
 }
@@ -147,6 +154,21 @@ fn parse_constant(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
     )(i)
 }
 
+fn quoted(name: &str, e: Expr) -> Expr {
+    Expr::Application(
+        Box::new(Expr::Constant(Atom::Identifier(name.to_string()))),
+        vec![e],
+    )
+}
+
+/// `` `expr `` and `,expr` are short for `(quasiquote expr)` and `(unquote expr)`
+fn parse_quasiquote(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    alt((
+        map(preceded(char('`'), parse_expr), |e| quoted("quasiquote", e)),
+        map(preceded(char(','), parse_expr), |e| quoted("unquote", e)),
+    ))(i)
+}
+
 /// Unlike the previous functions, this function doesn't take or consume input, instead it
 /// takes a parsing function and returns a new parsing function.
 fn s_exp<'a, O1, F>(inner: F) -> impl FnMut(&'a str) -> IResult<&'a str, O1, VerboseError<&'a str>>
@@ -175,11 +197,14 @@ fn parse_application(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
             parse_expr,
             many0(alt((
                 preceded(multispace0, parse_application), // applications can follow one another without whitespace
+                preceded(multispace0, parse_quasiquote),
                 preceded(multispace1, parse_constant), // constants are delimited by at least one whitespace
             ))),
         )),
         |(head, tail)| match head {
             Expr::FunctionDefinition => Expr::Definition(Box::new(head), tail),
+            Expr::LambdaDefinition => Expr::Definition(Box::new(head), tail),
+            Expr::MacroDefinition => Expr::Definition(Box::new(head), tail),
             Expr::VariableDefinition => Expr::Definition(Box::new(head), tail),
             Expr::PersistantStateDefinition => Expr::Definition(Box::new(head), tail),
             _ => Expr::Application(Box::new(head), tail),
@@ -192,7 +217,77 @@ fn parse_application(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
 /// We tie them all together again, making a top-level expression parser!
 /// This one generates the abstract syntax tree
 pub fn parse_expr(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    alt((parse_definition, parse_application, parse_constant))(i)
+    alt((
+        parse_definition,
+        parse_application,
+        parse_quasiquote,
+        parse_constant,
+    ))(i)
+}
+
+/// the lexical tokens, used to find things in the source text
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Open,
+    Close,
+    Atom,
+    String,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Split the text into parens, atoms and strings with their (byte) positions,
+/// skipping comments. An unterminated string is returned as error.
+pub fn tokenize(text: &str) -> std::result::Result<Vec<Token>, usize> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '(' => tokens.push(Token {
+                kind: TokenKind::Open,
+                start: pos,
+                end: pos + 1,
+            }),
+            ')' => tokens.push(Token {
+                kind: TokenKind::Close,
+                start: pos,
+                end: pos + 1,
+            }),
+            ';' => while chars.next_if(|(_, c)| *c != '\n').is_some() {},
+            '"' => {
+                let Some((end, _)) = chars.find(|(_, c)| *c == '"') else {
+                    return Err(pos);
+                };
+                tokens.push(Token {
+                    kind: TokenKind::String,
+                    start: pos,
+                    end: end + 1,
+                });
+            }
+            c if c.is_whitespace() => {}
+            _ => {
+                let mut end = pos + c.len_utf8();
+                while let Some((p, c)) =
+                    chars.next_if(|(_, c)| !c.is_whitespace() && !"();\"".contains(*c))
+                {
+                    end = p + c.len_utf8();
+                }
+                tokens.push(Token {
+                    kind: TokenKind::Atom,
+                    start: pos,
+                    end,
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
 }
 
 #[cfg(test)]
@@ -220,6 +315,21 @@ mod tests {
         assert!(!matches!(parse_symbol(":test"), Ok(("", Atom::Symbol(_)))));
     }
 
+    #[test]
+    fn test_parse_quasiquote() {
+        let Ok(("", Expr::Application(head, tail))) = parse_expr("`(sx 'a #t ,gen)") else {
+            panic!("can't parse quasiquote");
+        };
+        assert!(matches!(*head, Expr::Constant(Atom::Identifier(ref q)) if q == "quasiquote"));
+        let Expr::Application(_, ref sx_tail) = tail[0] else {
+            panic!("quasiquoted expression isn't an application");
+        };
+        assert!(matches!(
+            sx_tail[2],
+            Expr::Application(ref h, _) if matches!(**h, Expr::Constant(Atom::Identifier(ref u)) if u == "unquote")
+        ));
+    }
+
     #[test]
     fn test_parse_keyword() {
         assert!(matches!(parse_keyword(":test"), Ok(("", Atom::Keyword(_)))));
@@ -0,0 +1,343 @@
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{sync, thread};
+
+use crate::builtin_types::GlobalVariables;
+use crate::commands;
+
+/// the first port of the range peers look for each other on
+pub const DEFAULT_BASE_PORT: u16 = 57300;
+/// the number of ports (and thus instances per machine) in the range
+pub const PORT_RANGE: u16 = 8;
+
+const MESSAGE_PREFIX: &str = "megra-peer-sync";
+const ANNOUNCE_INTERVAL: Duration = Duration::from_millis(500);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// time since unix epoch in seconds, which serves as the common
+/// time base for all peers (so their system clocks should be in sync,
+/// which is trivially the case on the same machine)
+pub fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// A shared beat grid, defined by a beat duration and the
+/// (unix) time of some beat in the past.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BeatGrid {
+    /// beat duration in seconds
+    pub beat_duration: f64,
+    /// unix time in seconds of a beat
+    pub origin: f64,
+    /// unix time of the last tempo change, 0.0 if there never was any
+    pub changed_at: f64,
+}
+
+impl BeatGrid {
+    /// the phase of the beat at the given time, between 0.0 and 1.0
+    pub fn phase(&self, time: f64) -> f64 {
+        ((time - self.origin) / self.beat_duration).rem_euclid(1.0)
+    }
+
+    /// time from the given point in time to the next beat boundary
+    pub fn time_to_next_beat(&self, time: f64) -> f64 {
+        let phase = self.phase(time);
+        if phase == 0.0 {
+            0.0
+        } else {
+            (1.0 - phase) * self.beat_duration
+        }
+    }
+
+    /// the time of the last beat at (or before) the given time
+    pub fn last_beat(&self, time: f64) -> f64 {
+        time - self.phase(time) * self.beat_duration
+    }
+
+    fn to_message(self, peer_id: u64) -> String {
+        format!(
+            "{MESSAGE_PREFIX} {peer_id} {} {} {}",
+            self.beat_duration, self.origin, self.changed_at
+        )
+    }
+
+    fn from_message(msg: &str) -> Option<(u64, BeatGrid)> {
+        let mut parts = msg.split_whitespace();
+        if parts.next()? != MESSAGE_PREFIX {
+            return None;
+        }
+        let peer_id = parts.next()?.parse().ok()?;
+        let beat_duration: f64 = parts.next()?.parse().ok()?;
+        let origin = parts.next()?.parse().ok()?;
+        let changed_at = parts.next()?.parse().ok()?;
+        if beat_duration <= 0.0 {
+            return None;
+        }
+        Some((
+            peer_id,
+            BeatGrid {
+                beat_duration,
+                origin,
+                changed_at,
+            },
+        ))
+    }
+
+    /// whether the other grid should replace this one ... the most recent
+    /// tempo change wins, if there's a tie, the older grid (i.e. the one of
+    /// the peers that are already playing) stays, and only if that's a tie
+    /// as well, the lower peer id wins
+    fn yields_to(&self, my_id: u64, other: &BeatGrid, other_id: u64) -> bool {
+        if other.changed_at != self.changed_at {
+            return other.changed_at > self.changed_at;
+        }
+        if other.origin != self.origin {
+            return other.origin < self.origin;
+        }
+        other_id < my_id
+    }
+}
+
+/// Shares the session tempo (the default duration) and beat phase with
+/// other instances on the local network, so that generators can start
+/// aligned to a common beat grid.
+///
+/// Peers find each other by sending small UDP messages to every port in
+/// a fixed range, both on the loopback interface and as broadcast.
+pub struct PeerSync {
+    pub peer_id: u64,
+    pub port: u16,
+    grid: sync::Arc<Mutex<BeatGrid>>,
+    running: sync::Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl PeerSync {
+    pub fn start(globals: sync::Arc<GlobalVariables>, base_port: u16) -> Result<Self> {
+        // take the first free port in the range
+        let (socket, port) = (base_port..base_port.saturating_add(PORT_RANGE))
+            .find_map(|p| {
+                UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, p))
+                    .ok()
+                    .map(|s| (s, p))
+            })
+            .ok_or(anyhow!("no free port for peer sync"))?;
+
+        socket.set_broadcast(true)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        let peer_id: u64 = rand::random();
+        let now = unix_now();
+        let grid = sync::Arc::new(Mutex::new(BeatGrid {
+            beat_duration: commands::get_default_duration(&globals) * 0.001,
+            origin: now,
+            changed_at: 0.0,
+        }));
+
+        let running = sync::Arc::new(AtomicBool::new(true));
+
+        let grid2 = sync::Arc::clone(&grid);
+        let running2 = sync::Arc::clone(&running);
+
+        let handle = thread::Builder::new()
+            .name("peer sync".to_string())
+            .spawn(move || {
+                run_peer_sync_loop(socket, base_port, peer_id, globals, grid2, running2);
+            })?;
+
+        println!("peer sync started on port {port} (peer id {peer_id})");
+
+        Ok(PeerSync {
+            peer_id,
+            port,
+            grid,
+            running,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn grid(&self) -> BeatGrid {
+        *self.grid.lock()
+    }
+
+    /// Time (in seconds) from now until the next beat on the shared grid
+    /// will be audible, given the output latency.
+    pub fn time_to_next_beat(&self, latency: f64) -> f64 {
+        let grid = self.grid();
+        let t = grid.time_to_next_beat(unix_now() + latency);
+        // no need to wait a full beat if we're just a tiny bit late
+        if grid.beat_duration - t < 0.001 {
+            0.0
+        } else {
+            t
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(h) = self.handle.take() {
+            if h.join().is_err() {
+                println!("could not join peer sync thread");
+            }
+        }
+        println!("peer sync stopped");
+    }
+}
+
+fn announce(socket: &UdpSocket, base_port: u16, msg: &str) {
+    for p in base_port..base_port.saturating_add(PORT_RANGE) {
+        let _ = socket.send_to(msg.as_bytes(), SocketAddrV4::new(Ipv4Addr::LOCALHOST, p));
+        let _ = socket.send_to(msg.as_bytes(), SocketAddrV4::new(Ipv4Addr::BROADCAST, p));
+    }
+}
+
+fn run_peer_sync_loop(
+    socket: UdpSocket,
+    base_port: u16,
+    peer_id: u64,
+    globals: sync::Arc<GlobalVariables>,
+    grid: sync::Arc<Mutex<BeatGrid>>,
+    running: sync::Arc<AtomicBool>,
+) {
+    let mut buf = [0; 256];
+    let mut last_announce: Option<Instant> = None;
+
+    while running.load(Ordering::SeqCst) {
+        // check for local tempo changes
+        let local_beat_duration = commands::get_default_duration(&globals) * 0.001;
+        let mut changed = false;
+        {
+            let mut g = grid.lock();
+            if (g.beat_duration - local_beat_duration).abs() > 0.00001 {
+                let now = unix_now();
+                // keep the phase, start the new tempo from the last beat
+                g.origin = g.last_beat(now);
+                g.beat_duration = local_beat_duration;
+                g.changed_at = now;
+                changed = true;
+            }
+        }
+
+        if changed || last_announce.is_none_or(|t| t.elapsed() >= ANNOUNCE_INTERVAL) {
+            let msg = grid.lock().to_message(peer_id);
+            announce(&socket, base_port, &msg);
+            last_announce = Some(Instant::now());
+        }
+
+        // this will time out after the poll interval
+        if let Ok((len, _)) = socket.recv_from(&mut buf) {
+            let Ok(msg) = std::str::from_utf8(&buf[..len]) else {
+                continue;
+            };
+            let Some((other_id, other)) = BeatGrid::from_message(msg) else {
+                continue;
+            };
+            if other_id == peer_id {
+                continue;
+            }
+            let mut g = grid.lock();
+            if g.yields_to(peer_id, &other, other_id) && *g != other {
+                println!(
+                    "peer sync - adopt beat grid of peer {other_id} ({} bpm)",
+                    60.0 / other.beat_duration
+                );
+                *g = other;
+                // the default duration follows the shared tempo
+                commands::set_default_duration(&globals, (other.beat_duration * 1000.0) as f32);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_beat_grid() {
+        let grid = BeatGrid {
+            beat_duration: 0.5,
+            origin: 100.0,
+            changed_at: 0.0,
+        };
+
+        assert!((grid.phase(100.25) - 0.5).abs() < 0.00001);
+        assert!((grid.time_to_next_beat(100.25) - 0.25).abs() < 0.00001);
+        assert!((grid.time_to_next_beat(101.0)).abs() < 0.00001);
+        assert!((grid.last_beat(101.3) - 101.0).abs() < 0.00001);
+        // works before the origin as well
+        assert!((grid.time_to_next_beat(99.9) - 0.1).abs() < 0.00001);
+    }
+
+    #[test]
+    fn test_newcomer_yields() {
+        let playing = BeatGrid {
+            beat_duration: 0.5,
+            origin: 100.0,
+            changed_at: 0.0,
+        };
+        let newcomer = BeatGrid {
+            origin: 130.2,
+            ..playing
+        };
+
+        // no tempo changes on either side, the newcomer's lower id doesn't matter
+        assert!(newcomer.yields_to(2, &playing, 1));
+        assert!(!playing.yields_to(1, &newcomer, 0));
+
+        // a tempo change always wins
+        let changed = BeatGrid {
+            changed_at: 131.0,
+            ..newcomer
+        };
+        assert!(playing.yields_to(1, &changed, 2));
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        let grid = BeatGrid {
+            beat_duration: 0.5,
+            origin: 1700000000.125,
+            changed_at: 1700000001.5,
+        };
+
+        let (id, parsed) = BeatGrid::from_message(&grid.to_message(42)).unwrap();
+        assert_eq!(id, 42);
+        assert_eq!(parsed, grid);
+    }
+
+    #[test]
+    fn test_two_peers_on_loopback() {
+        // use a port range of its own so we don't run into any running instance
+        let base_port = DEFAULT_BASE_PORT + 100;
+
+        let globals_a = sync::Arc::new(GlobalVariables::new());
+        let globals_b = sync::Arc::new(GlobalVariables::new());
+
+        let mut a = PeerSync::start(sync::Arc::clone(&globals_a), base_port).unwrap();
+        let mut b = PeerSync::start(sync::Arc::clone(&globals_b), base_port).unwrap();
+
+        // change the tempo on a, b should follow
+        commands::set_default_duration(&globals_a, 500.0);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline
+            && (commands::get_default_duration(&globals_b) - 500.0).abs() > 0.01
+        {
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        assert!((commands::get_default_duration(&globals_b) - 500.0).abs() < 0.01);
+        assert_eq!(a.grid(), b.grid());
+
+        a.stop();
+        b.stop();
+    }
+}
@@ -6,6 +6,8 @@ use rand::{seq::SliceRandom, Rng};
 use vom_rs::pfa::*;
 use vom_rs::pst;
 
+use crate::random;
+
 /// This is the "old" method because it's the first one I devised,
 /// guided by intuition ...
 pub fn grow_old(pfa: &mut Pfa<char>) -> Option<PfaOperationResult<char>> {
@@ -16,7 +18,7 @@ pub fn grow_old(pfa: &mut Pfa<char>) -> Option<PfaOperationResult<char>> {
 
     let source_id = vec![*pfa.history.first().unwrap()];
     let dest_id = vec![*pfa.history.last().unwrap()];
-    let node_id = *pfa.history.choose(&mut rand::thread_rng()).unwrap();
+    let node_id = *pfa.history.choose(&mut random::CurrentRng).unwrap();
 
     // make sure states exists, and isn't the (empty) origin
     if !pfa.has_state(&source_id)
@@ -29,7 +31,7 @@ pub fn grow_old(pfa: &mut Pfa<char>) -> Option<PfaOperationResult<char>> {
 
     let mut rand_state = Vec::new();
 
-    let mut rng = rand::thread_rng();
+    let mut rng = random::CurrentRng;
 
     for _ in 0..10 {
         let c: char = rng.gen();
@@ -124,7 +126,7 @@ pub fn grow_flower(pfa: &mut Pfa<char>) -> Option<PfaOperationResult<char>> {
 
     let mut rand_state = Vec::new();
 
-    let mut rng = rand::thread_rng();
+    let mut rng = random::CurrentRng;
 
     for _ in 0..10 {
         let c: char = rng.gen(); // this is a bit critical because it causes unprintable chars ...
@@ -205,7 +207,7 @@ pub fn grow_triloop(pfa: &mut Pfa<char>) -> Option<PfaOperationResult<char>> {
 
     let mut rand_state = Vec::new();
 
-    let mut rng = rand::thread_rng();
+    let mut rng = random::CurrentRng;
 
     for _ in 0..10 {
         let c: char = rng.gen();
@@ -293,7 +295,7 @@ pub fn grow_loop(pfa: &mut Pfa<char>) -> Option<PfaOperationResult<char>> {
 
     let mut rand_state = Vec::new();
 
-    let mut rng = rand::thread_rng();
+    let mut rng = random::CurrentRng;
 
     for _ in 0..10 {
         let c: char = rng.gen();
@@ -386,7 +388,7 @@ pub fn grow_quadloop(pfa: &mut Pfa<char>) -> Option<PfaOperationResult<char>> {
 
     let mut rand_state = Vec::new();
 
-    let mut rng = rand::thread_rng();
+    let mut rng = random::CurrentRng;
 
     for _ in 0..10 {
         let c: char = rng.gen();
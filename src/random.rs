@@ -0,0 +1,135 @@
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+
+use std::cell::RefCell;
+use std::sync;
+
+// the session-wide rng, only present if the session has been seeded,
+// otherwise the thread rng is used, like it's always been
+static SESSION_RNG: Mutex<Option<StdRng>> = parking_lot::const_mutex(None);
+
+thread_local! {
+    // the rng of the generator that's currently evaluated on this thread
+    static GENERATOR_RNG: RefCell<Option<SeededRng>> = const { RefCell::new(None) };
+}
+
+/// A seeded random number generator, shared between the
+/// copies of a generator.
+#[derive(Clone)]
+pub struct SeededRng {
+    pub seed: u64,
+    rng: sync::Arc<Mutex<StdRng>>,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        SeededRng {
+            seed,
+            rng: sync::Arc::new(Mutex::new(StdRng::seed_from_u64(seed))),
+        }
+    }
+
+    /// an independent copy, continuing from the current state
+    pub fn fork(&self) -> Self {
+        SeededRng {
+            seed: self.seed,
+            rng: sync::Arc::new(Mutex::new(self.rng.lock().clone())),
+        }
+    }
+}
+
+/// Seed the session-wide rng. Generators without a seed of their own
+/// derive their seed from this one on their first evaluation.
+pub fn seed_session(seed: u64) {
+    *SESSION_RNG.lock() = Some(StdRng::seed_from_u64(seed));
+}
+
+/// go back to non-reproducible randomness
+pub fn unseed_session() {
+    *SESSION_RNG.lock() = None;
+}
+
+/// a new generator rng derived from the session rng, if the session is seeded
+pub fn derive_from_session() -> Option<SeededRng> {
+    SESSION_RNG
+        .lock()
+        .as_mut()
+        .map(|rng| SeededRng::new(rng.gen()))
+}
+
+/// Sets the rng for the generator evaluated on the current thread,
+/// until the guard is dropped.
+pub struct GeneratorRngGuard {
+    previous: Option<SeededRng>,
+}
+
+impl GeneratorRngGuard {
+    pub fn set(rng: Option<SeededRng>) -> Self {
+        let previous = GENERATOR_RNG.with(|g| g.replace(rng));
+        GeneratorRngGuard { previous }
+    }
+}
+
+impl Drop for GeneratorRngGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        GENERATOR_RNG.with(|g| *g.borrow_mut() = previous);
+    }
+}
+
+// Run the given function with the current rng, that is the rng
+// of the generator evaluated on this thread, or the session rng
+// if the session has been seeded, or the thread rng otherwise.
+fn with_rng<R>(fun: impl FnOnce(&mut dyn RngCore) -> R) -> R {
+    if let Some(gen_rng) = GENERATOR_RNG.with(|g| g.borrow().clone()) {
+        return fun(&mut *gen_rng.rng.lock());
+    }
+
+    let mut session_rng = SESSION_RNG.lock();
+    if let Some(rng) = session_rng.as_mut() {
+        fun(rng)
+    } else {
+        drop(session_rng);
+        fun(&mut rand::thread_rng())
+    }
+}
+
+/// Use this instead of `rand::thread_rng()` for anything musically
+/// relevant, so it can be made reproducible by seeding.
+pub struct CurrentRng;
+
+impl RngCore for CurrentRng {
+    fn next_u32(&mut self) -> u32 {
+        with_rng(|rng| rng.next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        with_rng(|rng| rng.next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        with_rng(|rng| rng.fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        with_rng(|rng| rng.try_fill_bytes(dest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generator_rng_is_reproducible() {
+        let draw = || {
+            let _guard = GeneratorRngGuard::set(Some(SeededRng::new(42)));
+            (0..10)
+                .map(|_| CurrentRng.gen_range(0..1000))
+                .collect::<Vec<u32>>()
+        };
+
+        assert_eq!(draw(), draw());
+    }
+}
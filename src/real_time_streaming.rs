@@ -58,6 +58,16 @@ pub struct Catch<const MAX: usize, const NCHAN: usize> {
     write_interval_ms: f64,
 }
 
+impl<const MAX: usize, const NCHAN: usize> Catch<MAX, NCHAN> {
+    /// drop whatever was thrown while nobody was writing
+    pub fn clear(&self) {
+        for mut stream_item in self.catch_q.try_iter() {
+            stream_item.size = 0;
+            self.return_q.send(stream_item).unwrap();
+        }
+    }
+}
+
 pub struct CatchHandle<const MAX: usize, const NCHAN: usize> {
     pub handle: Option<thread::JoinHandle<Catch<MAX, NCHAN>>>,
     pub running: sync::Arc<AtomicBool>,
@@ -70,6 +80,10 @@ pub struct RecordingControl<const MAX: usize, const NCHAN: usize> {
     pub catch_out_handle: Option<CatchHandle<MAX, NCHAN>>,
     pub catch_in: Option<Catch<MAX, NCHAN>>,
     pub catch_in_handle: Option<CatchHandle<MAX, NCHAN>>,
+    pub is_recording_stems: sync::Arc<AtomicBool>,
+    // the mix, the main ruffbox instance, then the stem buses
+    pub catch_stems: Vec<Option<Catch<MAX, NCHAN>>>,
+    pub catch_stems_handles: Vec<Option<CatchHandle<MAX, NCHAN>>>,
     pub samplerate: u32, // assume output and input have the same samplerate
 }
 
@@ -1,17 +1,156 @@
+use std::borrow::Cow;
+use std::path::Path;
+use std::sync;
+
+use rustyline::completion::{Completer, Pair};
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
+use rustyline::highlight::{Highlighter, MatchingBracketHighlighter};
+use rustyline::hint::Hinter;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Editor, Helper};
 
+use crate::eval::events::sound::SAMPLE_QUERIES;
+use crate::eval::{self, FunctionMap};
+use crate::event_helpers::PARAMETER_NAMES;
 use crate::interpreter;
-
+use crate::parser::valid_namespaced_identifier_char;
 use crate::session::Session;
+use crate::SampleAndWavematrixSet;
+
+/// completion, hints, bracket matching and validation for the repl
+struct MegraHelper {
+    functions: sync::Arc<FunctionMap>,
+    sample_set: SampleAndWavematrixSet,
+    brackets: MatchingBracketHighlighter,
+}
+
+impl MegraHelper {
+    // the start of the word before the cursor
+    fn word_start(line: &str, pos: usize) -> usize {
+        line[..pos]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| valid_namespaced_identifier_char(*c) || *c == ':')
+            .last()
+            .map(|(p, _)| p)
+            .unwrap_or(pos)
+    }
+
+    // keywords after a colon, otherwise functions and sample sets
+    fn candidates(&self, word: &str) -> Vec<String> {
+        let mut candidates: Vec<String> = if let Some(prefix) = word.strip_prefix(':') {
+            PARAMETER_NAMES
+                .iter()
+                .chain(SAMPLE_QUERIES.iter())
+                .filter(|k| k.starts_with(prefix))
+                .map(|k| format!(":{k}"))
+                .collect()
+        } else {
+            self.functions
+                .std_lib
+                .iter()
+                .map(|f| f.key().clone())
+                .chain(self.functions.usr_lib.iter().map(|f| f.key().clone()))
+                .chain(self.functions.macros.iter().map(|f| f.key().clone()))
+                .chain(self.sample_set.names())
+                .filter(|f| f.starts_with(word))
+                .collect()
+        };
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+}
+
+impl Completer for MegraHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = MegraHelper::word_start(line, pos);
+        if start == pos {
+            return Ok((pos, Vec::new()));
+        }
+
+        let candidates = self
+            .candidates(&line[start..pos])
+            .into_iter()
+            .map(|c| Pair {
+                display: c.clone(),
+                replacement: c,
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for MegraHelper {
+    type Hint = String;
+
+    // hint the rest of the word if there's only one way to complete it
+    fn hint(&self, line: &str, pos: usize, _: &Context<'_>) -> Option<String> {
+        if pos < line.len() {
+            return None;
+        }
+        let start = MegraHelper::word_start(line, pos);
+        let word = &line[start..pos];
+        if word.len() < 2 {
+            return None;
+        }
+        match self.candidates(word).as_slice() {
+            [c] if c.len() > word.len() => Some(c[word.len()..].to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl Highlighter for MegraHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        self.brackets.highlight(line, pos)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        // dimmed
+        Cow::Owned(format!("\x1b[2m{hint}\x1b[m"))
+    }
+
+    fn highlight_char(&self, line: &str, pos: usize) -> bool {
+        self.brackets.highlight_char(line, pos)
+    }
+}
+
+impl Validator for MegraHelper {
+    // if a closing paren is missing, wait for more lines,
+    // other errors are reported when evaluating
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        match eval::parse_from_str(ctx.input()) {
+            Err(e) if e.is_incomplete() => Ok(ValidationResult::Incomplete),
+            _ => Ok(ValidationResult::Valid(None)),
+        }
+    }
+}
+
+impl Helper for MegraHelper {}
 
 pub fn start_repl<const BUFSIZE: usize, const NCHAN: usize>(
     session: Session<BUFSIZE, NCHAN>,
     base_dir: String,
 ) -> Result<(), anyhow::Error> {
-    // `()` can be used when no completer is required
-    let mut rl = Editor::<()>::new();
-    if rl.load_history("history.txt").is_err() {
+    let mut rl = Editor::<MegraHelper>::new();
+    rl.set_helper(Some(MegraHelper {
+        functions: session.functions.clone(),
+        sample_set: session.sample_set.clone(),
+        brackets: MatchingBracketHighlighter::new(),
+    }));
+
+    // keep the history in the base folder, so it's the same
+    // no matter where megra is started
+    let history = Path::new(&base_dir).join("history.txt");
+    if rl.load_history(&history).is_err() {
         println!("No previous history.");
     }
 
@@ -20,71 +159,35 @@ pub fn start_repl<const BUFSIZE: usize, const NCHAN: usize>(
         match readline {
             Ok(line) => {
                 // ignore empty lines ...
-                if line.is_empty() {
+                if line.trim().is_empty() {
                     continue;
                 }
 
-                let pfa_in = crate::eval::parse_and_eval_from_str(
-                    line.as_str(),
-                    &session.functions,
-                    &session.globals,
-                    session.sample_set.clone(),
-                    session.output_mode,
-                );
-
-                match pfa_in {
-                    Err(e) => {
-                        // this needs a more elegant way ... like,
-                        // retrieve the actual error from the parser, instead
-                        // of looking for the string ...
-                        // if the error is that a closing paren is missing,
-                        // assume we're waiting for more lines.
-                        // once a complete input is found,
-                        if e.to_string().contains("closing paren") {
-                            let mut line_buffer: String = "".to_string();
-                            line_buffer.push_str(line.as_str());
-                            loop {
-                                let readline_inner = rl.readline(".. ");
-                                match readline_inner {
-                                    Ok(line) => {
-                                        line_buffer.push_str(line.as_str());
-                                        let inner_pfa_in = crate::eval::parse_and_eval_from_str(
-                                            line_buffer.as_str(),
-                                            &session.functions,
-                                            &session.globals,
-                                            session.sample_set.clone(),
-                                            session.output_mode,
-                                        );
-                                        match inner_pfa_in {
-                                            Ok(pfa) => {
-                                                interpreter::interpret(
-                                                    pfa,
-                                                    session.clone(),
-                                                    base_dir.clone(),
-                                                );
-                                                rl.add_history_entry(line_buffer.as_str());
-                                                break;
-                                            }
-                                            Err(_) => {
-                                                // wait for more input ...
-                                                continue;
-                                            }
-                                        }
-                                    }
-                                    Err(_) => {
-                                        break;
-                                    }
-                                }
-                            }
-                        } else {
-                            println!("parser error {e}");
+                rl.add_history_entry(line.as_str());
+
+                let session = session.clone();
+                let base_dir = base_dir.clone();
+                eval::on_evaluating_thread(move || {
+                    match eval::parse_and_eval_from_str(
+                        line.as_str(),
+                        &session.functions,
+                        &session.globals,
+                        session.sample_set.clone(),
+                        session.output_mode,
+                    ) {
+                        Ok(pfa) => {
+                            interpreter::interpret_source(
+                                line.as_str(),
+                                pfa,
+                                session.clone(),
+                                base_dir.clone(),
+                            );
+                        }
+                        Err(e) => {
+                            println!("{}", e.caret_diagnostic(&line));
                         }
                     }
-                    Ok(pfa) => {
-                        interpreter::interpret(pfa, session.clone(), base_dir.clone());
-                        rl.add_history_entry(line.as_str());
-                    }
-                }
+                });
             }
             Err(ReadlineError::Interrupted) => {
                 println!("CTRL-C");
@@ -101,6 +204,22 @@ pub fn start_repl<const BUFSIZE: usize, const NCHAN: usize>(
         }
     }
 
-    rl.save_history("history.txt").unwrap();
+    if let Err(e) = rl.save_history(&history) {
+        println!("couldn't save history to {} - {e}", history.display());
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_start() {
+        let line = "(sx 'a #t (nuc 'b (bd :lv";
+        assert_eq!(&line[MegraHelper::word_start(line, line.len())..], ":lv");
+        let line = "(d/ki";
+        assert_eq!(&line[MegraHelper::word_start(line, line.len())..], "d/ki");
+        assert_eq!(MegraHelper::word_start("(bd ", 4), 4);
+    }
+}
@@ -0,0 +1,227 @@
+//! Descriptors of loaded samples, so that sample sets can be addressed
+//! by the character of the sounds instead of by filename keywords.
+//!
+//! Everything is computed once, when a sample is loaded, on the mono
+//! (or downmixed) signal: the RMS loudness, the spectral centroid, an
+//! estimated pitch (if there is one) and the onset positions.
+
+use realfft::{RealFftPlanner, RealToComplex};
+use std::f32::consts::PI;
+
+// analysis frame size and hop size for the spectral descriptors
+const FRAME: usize = 1024;
+const HOP: usize = 512;
+
+// pitch range for the estimation, in Hz
+const MIN_PITCH: f32 = 40.0;
+const MAX_PITCH: f32 = 2000.0;
+
+#[derive(Debug, Clone, Default)]
+pub struct SampleDescriptors {
+    pub rms: f32,           // loudness in dB
+    pub centroid: f32,      // spectral centroid in Hz
+    pub pitch: Option<f32>, // estimated fundamental in Hz, None if unpitched
+    pub onsets: Vec<f32>,   // onset positions, relative to the sample length (0.0 - 1.0)
+    pub length: f32,        // the full length in ms
+}
+
+pub fn analyze(samples: &[f32], samplerate: f32) -> SampleDescriptors {
+    if samples.is_empty() {
+        return SampleDescriptors::default();
+    }
+
+    let rms = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
+
+    // magnitude spectra of all complete frames (a short sample is zero-padded,
+    // as the end of a longer one would show up as an onset otherwise)
+    let window: Vec<f32> = (0..FRAME)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FRAME as f32).cos())
+        .collect();
+    let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FRAME);
+    let mut spectra = Vec::new();
+    let mut pos = 0;
+    while pos == 0 || pos + FRAME <= samples.len() {
+        let mut frame: Vec<f32> = (0..FRAME)
+            .map(|i| samples.get(pos + i).copied().unwrap_or(0.0) * window[i])
+            .collect();
+        spectra.push(magnitudes(fft.as_ref(), &mut frame));
+        pos += HOP;
+    }
+
+    SampleDescriptors {
+        rms: 20.0 * rms.max(1e-9).log10(),
+        centroid: centroid(&spectra, samplerate),
+        pitch: pitch(samples, samplerate),
+        onsets: onsets(&spectra)
+            .into_iter()
+            .map(|frame| (frame * HOP) as f32 / samples.len() as f32)
+            .collect(),
+        length: samples.len() as f32 / samplerate * 1000.0,
+    }
+}
+
+/// the magnitudes of the positive frequencies (the frame is used as scratch space)
+fn magnitudes(fft: &dyn RealToComplex<f32>, frame: &mut [f32]) -> Vec<f32> {
+    let mut spectrum = fft.make_output_vec();
+    // the length always matches the plan
+    fft.process(frame, &mut spectrum).unwrap();
+    spectrum.iter().map(|c| c.norm()).collect()
+}
+
+/// the centroid of the summed spectrum
+fn centroid(spectra: &[Vec<f32>], samplerate: f32) -> f32 {
+    let mut weighted = 0.0;
+    let mut total = 0.0;
+    for spectrum in spectra.iter() {
+        for (k, mag) in spectrum.iter().enumerate() {
+            weighted += k as f32 * samplerate / FRAME as f32 * mag;
+            total += mag;
+        }
+    }
+    if total > 0.0 {
+        weighted / total
+    } else {
+        0.0
+    }
+}
+
+/// Onset frames by peak picking on the spectral flux, with a threshold
+/// that follows the local median.
+fn onsets(spectra: &[Vec<f32>]) -> Vec<usize> {
+    // the first frame is compared to silence, so a sound
+    // that starts right away has an onset at the beginning
+    let mut flux = Vec::with_capacity(spectra.len());
+    let silence = vec![0.0f32; FRAME / 2 + 1];
+    let mut prev = &silence;
+    for spectrum in spectra.iter() {
+        flux.push(
+            spectrum
+                .iter()
+                .zip(prev.iter())
+                .map(|(cur, prev)| ((1.0 + cur).ln() - (1.0 + prev).ln()).max(0.0))
+                .sum::<f32>(),
+        );
+        prev = spectrum;
+    }
+
+    let max = flux.iter().cloned().fold(0.0, f32::max);
+    if max <= 0.0 {
+        return Vec::new();
+    }
+    let mean = flux.iter().sum::<f32>() / flux.len() as f32;
+
+    let mut onsets: Vec<usize> = Vec::new();
+    for i in 0..flux.len() {
+        let lo = i.saturating_sub(8);
+        let hi = (i + 9).min(flux.len());
+        let mut local = flux[lo..hi].to_vec();
+        local.sort_by(|a, b| a.total_cmp(b));
+        let threshold = local[local.len() / 2] + 0.5 * mean;
+
+        let is_peak = flux[i.saturating_sub(2)..(i + 3).min(flux.len())]
+            .iter()
+            .all(|f| *f <= flux[i]);
+
+        // at least ~50ms between onsets
+        let spaced = onsets.last().map(|last| i - last > 4).unwrap_or(true);
+
+        if is_peak && spaced && flux[i] > threshold && flux[i] > 0.1 * max {
+            onsets.push(i);
+        }
+    }
+    onsets
+}
+
+/// Estimate the pitch around the loudest part of the sample, using
+/// the cumulative mean normalized difference (as in YIN).
+fn pitch(samples: &[f32], samplerate: f32) -> Option<f32> {
+    let tau_min = (samplerate / MAX_PITCH) as usize;
+    let tau_max = (samplerate / MIN_PITCH) as usize;
+    let window = 2048;
+    if samples.len() < window + tau_max {
+        return None;
+    }
+
+    // the loudest block that leaves enough room for the lags
+    let start = (0..samples.len() - window - tau_max)
+        .step_by(HOP)
+        .max_by(|a, b| {
+            let energy = |p: &usize| samples[*p..*p + window].iter().map(|s| s * s).sum::<f32>();
+            energy(a).total_cmp(&energy(b))
+        })?;
+
+    let block = &samples[start..start + window + tau_max];
+    let mut diff = vec![0.0; tau_max + 1];
+    for (tau, d) in diff.iter_mut().enumerate().skip(1) {
+        *d = (0..window)
+            .map(|i| {
+                let delta = block[i] - block[i + tau];
+                delta * delta
+            })
+            .sum();
+    }
+
+    let mut running = 0.0;
+    let mut cmnd = vec![1.0; tau_max + 1];
+    for tau in 1..=tau_max {
+        running += diff[tau];
+        if running > 0.0 {
+            cmnd[tau] = diff[tau] * tau as f32 / running;
+        }
+    }
+
+    // the first dip below the threshold, followed down to its minimum
+    let mut tau = tau_min.max(2);
+    while tau < tau_max {
+        if cmnd[tau] < 0.15 {
+            while tau + 1 < tau_max && cmnd[tau + 1] < cmnd[tau] {
+                tau += 1;
+            }
+            // parabolic interpolation between the neighbours
+            let (a, b, c) = (cmnd[tau - 1], cmnd[tau], cmnd[tau + 1]);
+            let denom = a - 2.0 * b + c;
+            let offset = if denom.abs() > f32::EPSILON {
+                0.5 * (a - c) / denom
+            } else {
+                0.0
+            };
+            return Some(samplerate / (tau as f32 + offset));
+        }
+        tau += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_analyze() {
+        let sr = 44100.0;
+
+        // a sine with a second note starting in the middle
+        let mut samples: Vec<f32> = (0..sr as usize)
+            .map(|i| (2.0 * PI * 440.0 * i as f32 / sr).sin() * 0.5)
+            .collect();
+        for (i, s) in samples.iter_mut().enumerate().skip(sr as usize / 2) {
+            *s = (2.0 * PI * 880.0 * i as f32 / sr).sin();
+        }
+
+        let desc = analyze(&samples, sr);
+        assert_approx_eq!(desc.length, 1000.0, 0.1);
+        assert!(desc.rms < 0.0 && desc.rms > -6.0);
+        assert!(desc.centroid > 440.0 && desc.centroid < 880.0);
+        // the loudest part is the second note
+        assert_approx_eq!(desc.pitch.unwrap(), 880.0, 2.0);
+        assert_eq!(desc.onsets.len(), 2);
+        assert_approx_eq!(desc.onsets[0], 0.0, 0.02);
+        assert_approx_eq!(desc.onsets[1], 0.5, 0.02);
+
+        // silence has neither pitch nor onsets
+        let silence = vec![0.0; 4096];
+        assert!(analyze(&silence, sr).pitch.is_none());
+        assert!(analyze(&silence, sr).onsets.is_empty());
+    }
+}
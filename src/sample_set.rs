@@ -1,6 +1,11 @@
-use crate::parameter::DynVal;
+use crate::event::StaticEvent;
+use crate::live_buffers::LiveBuffers;
+use crate::parameter::{DynVal, ParameterAddress};
+use crate::random;
+use crate::sample_analysis::SampleDescriptors;
 use dashmap::DashMap;
 use rand::seq::SliceRandom;
+use ruffbox_synth::building_blocks::{SynthParameterLabel, SynthParameterValue};
 use std::collections::HashSet;
 use std::sync::Arc;
 
@@ -11,6 +16,45 @@ pub enum SampleLookup {
     N(String, usize),                    // lookup by position
     Random(String),                      // final random (different sample every time)
     FixedRandom(String, (usize, usize)), // parse-time random (random sample will be chosen at parsing time)
+    // lookup by key, then by sound character
+    Descriptor(String, HashSet<String>, SampleQuery),
+}
+
+/// choose among the matching samples by their descriptors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleQuery {
+    Loudest,
+    Quietest,
+    Brightest,
+    Darkest,
+    NearPitch(f32), // in Hz
+}
+
+impl SampleLookup {
+    /// the sample set this looks into
+    pub fn set(&self) -> &str {
+        match self {
+            SampleLookup::Key(set, _)
+            | SampleLookup::N(set, _)
+            | SampleLookup::Random(set)
+            | SampleLookup::FixedRandom(set, _)
+            | SampleLookup::Descriptor(set, _, _) => set,
+        }
+    }
+
+    /// keep the keys (if there are any), but choose by sound
+    pub fn with_query(self, query: SampleQuery) -> SampleLookup {
+        match self {
+            SampleLookup::Key(set, keys) | SampleLookup::Descriptor(set, keys, _) => {
+                SampleLookup::Descriptor(set, keys, query)
+            }
+            SampleLookup::N(set, _)
+            | SampleLookup::Random(set)
+            | SampleLookup::FixedRandom(set, _) => {
+                SampleLookup::Descriptor(set, HashSet::new(), query)
+            }
+        }
+    }
 }
 
 /// the resolved sample info
@@ -19,6 +63,7 @@ pub struct SampleInfo {
     pub key: HashSet<String>, // the key this was stored with
     pub bufnum: usize,
     pub duration: usize, // duration in ms ..
+    pub descriptors: Option<Arc<SampleDescriptors>>,
 }
 
 impl SampleInfo {
@@ -39,6 +84,7 @@ impl SampleInfo {
 pub struct SampleAndWavematrixSet {
     subsets: Arc<DashMap<String, Vec<SampleInfo>>>,
     wavematrices: Arc<DashMap<String, Vec<Vec<DynVal>>>>,
+    live_buffers: Arc<LiveBuffers>,
 }
 
 impl Default for SampleAndWavematrixSet {
@@ -49,12 +95,23 @@ impl Default for SampleAndWavematrixSet {
 
 impl SampleAndWavematrixSet {
     pub fn new() -> Self {
+        Self::with_live_buffers(Arc::new(LiveBuffers::default()))
+    }
+
+    /// a sample set that knows the content of the live and freeze buffers
+    pub fn with_live_buffers(live_buffers: Arc<LiveBuffers>) -> Self {
         SampleAndWavematrixSet {
             subsets: Arc::new(DashMap::new()),
             wavematrices: Arc::new(DashMap::new()),
+            live_buffers,
         }
     }
 
+    /// the copies of the live and freeze buffers
+    pub fn live_buffers(&self) -> &Arc<LiveBuffers> {
+        &self.live_buffers
+    }
+
     pub fn insert_wavematrix(&mut self, key: String, table: Vec<Vec<DynVal>>) {
         self.wavematrices.insert(key, table);
     }
@@ -63,14 +120,27 @@ impl SampleAndWavematrixSet {
         self.wavematrices.get(key).map(|wm| wm.clone())
     }
 
-    pub fn insert(&mut self, set: String, keyword_set: HashSet<String>, bufnum: usize, dur: usize) {
+    pub fn insert(
+        &mut self,
+        set: String,
+        keyword_set: HashSet<String>,
+        bufnum: usize,
+        dur: usize,
+        descriptors: Option<SampleDescriptors>,
+    ) {
         self.subsets.entry(set).or_default().push(SampleInfo {
             key: keyword_set,
             bufnum,
             duration: dur,
+            descriptors: descriptors.map(Arc::new),
         });
     }
 
+    /// the names of all loaded sample sets
+    pub fn names(&self) -> Vec<String> {
+        self.subsets.iter().map(|s| s.key().clone()).collect()
+    }
+
     pub fn exists_not_empty(&self, set: &str) -> bool {
         self.subsets.contains_key(set) && !self.subsets.get(set).unwrap().is_empty()
     }
@@ -80,7 +150,7 @@ impl SampleAndWavematrixSet {
         if let Some(subset) = self.subsets.get(set) {
             let choice: Vec<&SampleInfo> = subset.iter().filter(|i| i.matches(keywords)).collect();
             if !choice.is_empty() {
-                let res = choice.choose(&mut rand::thread_rng()).unwrap();
+                let res = choice.choose(&mut random::CurrentRng).unwrap();
                 Some((res.bufnum, res.duration))
             } else {
                 // there's always one ...
@@ -108,11 +178,66 @@ impl SampleAndWavematrixSet {
 
     pub fn random(&self, set: &str) -> Option<(usize, usize)> {
         self.subsets.get(set).map(|subset| {
-            let res = subset.choose(&mut rand::thread_rng()).unwrap();
+            let res = subset.choose(&mut random::CurrentRng).unwrap();
             (res.bufnum, res.duration)
         })
     }
 
+    /// Get the sample that fits the query best among the ones matching the
+    /// keys (or among all, if none match), samples that haven't been analyzed
+    /// (or don't have a pitch, when looking for one) aren't considered.
+    pub fn query(
+        &self,
+        set: &str,
+        keywords: &HashSet<String>,
+        query: SampleQuery,
+    ) -> Option<(usize, usize)> {
+        // the smaller the better
+        let score = |d: &SampleDescriptors| match query {
+            SampleQuery::Loudest => Some(-d.rms),
+            SampleQuery::Quietest => Some(d.rms),
+            SampleQuery::Brightest => Some(-d.centroid),
+            SampleQuery::Darkest => Some(d.centroid),
+            SampleQuery::NearPitch(freq) => d.pitch.map(|p| (p / freq).log2().abs()),
+        };
+
+        let best = {
+            let subset = self.subsets.get(set)?;
+            let mut choice: Vec<&SampleInfo> =
+                subset.iter().filter(|i| i.matches(keywords)).collect();
+            if choice.is_empty() {
+                choice = subset.iter().collect();
+            }
+            choice
+                .iter()
+                .filter_map(|i| Some((i, score(i.descriptors.as_ref()?)?)))
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(res, _)| (res.bufnum, res.duration))
+        };
+
+        // nothing analyzed, fall back to the keys
+        best.or_else(|| self.keys(set, keywords))
+    }
+
+    /// the descriptors of a loaded sample
+    pub fn descriptors(&self, set: &str, bufnum: usize) -> Option<Arc<SampleDescriptors>> {
+        self.subsets
+            .get(set)?
+            .iter()
+            .find(|i| i.bufnum == bufnum)
+            .and_then(|i| i.descriptors.clone())
+    }
+
+    /// the position of the nth onset (wrapping around) of a loaded sample
+    pub fn onset(&self, set: &str, bufnum: usize, n: usize) -> Option<f32> {
+        let descriptors = self.descriptors(set, bufnum)?;
+        if descriptors.onsets.is_empty() {
+            None
+        } else {
+            Some(descriptors.onsets[n % descriptors.onsets.len()])
+        }
+    }
+
     // needs lifetimes for the temp return of the info ...
     pub fn resolve_lookup<'a>(&'a self, lookup: &'a SampleLookup) -> Option<(usize, usize)> {
         match lookup {
@@ -120,6 +245,54 @@ impl SampleAndWavematrixSet {
             SampleLookup::N(fname, pos) => self.pos(fname, *pos),
             SampleLookup::Random(fname) => self.random(fname),
             SampleLookup::FixedRandom(_, info) => Some(*info),
+            SampleLookup::Descriptor(fname, keywords, query) => self.query(fname, keywords, *query),
+        }
+    }
+
+    /// Resolve the sample of a sampler event right before it's played,
+    /// along with the playback start of the requested onset, if any.
+    /// Returns the buffer number to play from.
+    pub fn resolve_sample(&self, s: &mut StaticEvent) -> usize {
+        let mut bufnum: usize = 0;
+
+        if s.name == "frozensampler" {
+            if let Some(SynthParameterValue::ScalarUsize(b)) = s
+                .params
+                .get(&SynthParameterLabel::SampleBufferNumber.into())
+            {
+                bufnum = *b;
+            }
+        }
+
+        // the onset can only be resolved along with the sample
+        let onset = s
+            .params
+            .remove(&ParameterAddress::Custom("onset".to_string()));
+
+        if let Some(lookup) = s.sample_lookup.as_ref() {
+            if let Some((res_bufnum, duration)) = self.resolve_lookup(lookup) {
+                bufnum = res_bufnum;
+                // is this really needed ??
+                s.params.insert(
+                    SynthParameterLabel::SampleBufferNumber.into(),
+                    SynthParameterValue::ScalarUsize(bufnum),
+                );
+
+                if let Some(SynthParameterValue::ScalarF32(n)) = onset {
+                    if let Some(start) = self.onset(lookup.set(), bufnum, n.max(0.0) as usize) {
+                        s.params.insert(
+                            SynthParameterLabel::PlaybackStart.into(),
+                            SynthParameterValue::ScalarF32(start),
+                        );
+                    }
+                }
+
+                s.params
+                    .entry(SynthParameterLabel::Sustain.into())
+                    .or_insert_with(|| SynthParameterValue::ScalarF32((duration - 2) as f32));
+            }
         }
+
+        bufnum
     }
 }
@@ -1,4 +1,6 @@
+use crate::eval;
 use crate::generator::Generator;
+use crate::midi_clock;
 use crate::session::Session;
 use crossbeam::atomic::AtomicCell;
 use dashmap::DashSet;
@@ -9,12 +11,34 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 use std::{sync, thread};
 
-/// A simple time-recursion event scheduler running at a fixed time interval.
+// how far ahead of the audio stream the events are computed
+const LOOKAHEAD: f64 = 0.05;
+// how often the timing thread checks for due generators
+const TIMING_INTERVAL: Duration = Duration::from_millis(5);
+// how far a generator that doesn't advance in time is moved ahead
+const MIN_STEP: f64 = 0.001;
+
+/// The handle to a time-recursion event scheduler. The schedulers don't run
+/// in threads of their own, but are all stepped ahead of time by a single
+/// timing thread (or the offline renderer), while ruffbox takes care of
+/// the precise timing via the stream timestamps.
 pub struct Scheduler<const BUFSIZE: usize, const NCHAN: usize> {
-    pub handle: Option<thread::JoinHandle<()>>,
     pub running: sync::Arc<AtomicBool>,
 }
 
+/// the evaluation function called on every step of the time recursion,
+/// returning the time to the next step and the sync and end flags
+pub type SchedulerFunction<const BUFSIZE: usize, const NCHAN: usize> =
+    fn(&mut SchedulerData<BUFSIZE, NCHAN>, &Session<BUFSIZE, NCHAN>) -> (f64, bool, bool);
+
+/// A running scheduler, waiting in the queue to be stepped.
+#[derive(Clone)]
+pub struct QueuedScheduler<const BUFSIZE: usize, const NCHAN: usize> {
+    pub running: sync::Arc<AtomicBool>,
+    pub fun: SchedulerFunction<BUFSIZE, NCHAN>,
+    pub data: SchedulerData<BUFSIZE, NCHAN>,
+}
+
 impl<const BUFSIZE: usize, const NCHAN: usize> Default for Scheduler<BUFSIZE, NCHAN> {
     fn default() -> Self {
         Self::new()
@@ -106,7 +130,10 @@ impl<const BUFSIZE: usize, const NCHAN: usize> SchedulerData<BUFSIZE, NCHAN> {
         solo_tags: BTreeSet<String>,
     ) {
         let shift_diff = shift - old.shift.load();
-        self.start_time = old.start_time.clone();
+        // the queued copies of this scheduler share the mutex,
+        // so update the value instead of replacing it
+        let start_time = *old.start_time.lock();
+        *self.start_time.lock() = start_time;
         self.stream_time.store(old.stream_time.load() + shift_diff);
         self.logical_time
             .store(old.logical_time.load() + shift_diff);
@@ -162,102 +189,296 @@ impl<const BUFSIZE: usize, const NCHAN: usize> SchedulerData<BUFSIZE, NCHAN> {
 impl<const BUFSIZE: usize, const NCHAN: usize> Scheduler<BUFSIZE, NCHAN> {
     pub fn new() -> Self {
         Scheduler {
-            handle: None,
             running: sync::Arc::new(AtomicBool::new(false)),
         }
     }
 
-    /// Start this scheduler.
+    /// Run one step of the time recursion: evaluate, start synced
+    /// generators if requested, and return the time to the next step,
+    /// or `None` if the generator has ended.
+    pub fn step(
+        fun: SchedulerFunction<BUFSIZE, NCHAN>,
+        sched_data: &mut SchedulerData<BUFSIZE, NCHAN>,
+        session: &Session<BUFSIZE, NCHAN>,
+    ) -> Option<f64> {
+        // call event processing function that'll return
+        // the sync flag and
+        let (next, sync, end) = (fun)(sched_data, session);
+        if sync {
+            let mut syncs = sched_data.synced_generators.lock();
+            for (g, s) in syncs.drain(..) {
+                let gen_shift = g.time_shift;
+                Session::start_generator_data_sync(
+                    g,
+                    session,
+                    sched_data,
+                    s + (gen_shift as f64 * 0.001),
+                    &sched_data.block_tags,
+                    &sched_data.solo_tags,
+                );
+            }
+        }
+        if end {
+            sched_data.finished.store(true, Ordering::SeqCst);
+            None
+        } else {
+            Some(next)
+        }
+    }
+
+    /// Start this scheduler, that is, put it in the queue.
     pub fn start(
         &mut self,
-        name: &str,
-        fun: fn(&mut SchedulerData<BUFSIZE, NCHAN>, &Session<BUFSIZE, NCHAN>) -> (f64, bool, bool),
-        mut sched_data: SchedulerData<BUFSIZE, NCHAN>,
-        session: Session<BUFSIZE, NCHAN>,
+        fun: SchedulerFunction<BUFSIZE, NCHAN>,
+        sched_data: SchedulerData<BUFSIZE, NCHAN>,
+        session: &Session<BUFSIZE, NCHAN>,
     ) {
         self.running.store(true, Ordering::SeqCst);
-        let running = self.running.clone();
-
-        let builder = thread::Builder::new().name(name.into());
-
-        self.handle = Some(
-            builder
-                .spawn(move || {
-                    while running.load(Ordering::SeqCst) {
-                        let next: f64;
-                        let ldif: f64;
-                        let cur: f64;
-                        {
-                            // call event processing function that'll return
-                            // the sync flag and
-                            let sched_result = (fun)(&mut sched_data, &session);
-                            let sync = sched_result.1;
-			    let end = sched_result.2;
-                            if sync {
-                                let mut syncs = sched_data.synced_generators.lock();
-                                for (g, s) in syncs.drain(..) {
-				    let gen_shift = g.time_shift;
-                                    Session::start_generator_data_sync(
-                                        g,
-					&session,
-                                        &sched_data,
-                                        s + (gen_shift as f64 * 0.001),
-					&sched_data.block_tags,
-					&sched_data.solo_tags,
-                                    );
-				}
-			    }
-			    if end {
-				sched_data.finished.store(true, Ordering::SeqCst);
-				running.store(false, Ordering::SeqCst);
-				return;
-			    }
-			    cur = sched_data.start_time.lock().elapsed().as_secs_f64();
-                            sched_data.last_diff.store(cur - sched_data.logical_time.load());
-                            next = sched_result.0;
-                            ldif = sched_data.last_diff.load();
-                            // compensate for eventual lateness ...
-                            if (next - ldif) < 0.0 {
-                                let handle = thread::current();
-				println!(
-                                    "{} negative duration found: cur before {} cur after {} {} {} {}",
-                                    handle.name().unwrap(),
-                                    cur,
-				    sched_data.start_time.lock().elapsed().as_secs_f64(),
-                                    sched_data.logical_time.load(),
-                                    next,
-                                    ldif
-                                );
-				sched_data.finished.store(true, Ordering::SeqCst);
-				running.store(false, Ordering::SeqCst);
-				return;
-                            }
-                            sched_data.logical_time.store(sched_data.logical_time.load() + next);
-                            sched_data.stream_time.store(sched_data.stream_time.load() + next);
-                        }
-
-                        thread::sleep(Duration::from_secs_f64(next - ldif));
-                    }
-                })
-                .unwrap(),
-        );
+        session.scheduler_queue.lock().push(QueuedScheduler {
+            running: self.running.clone(),
+            fun,
+            data: sched_data,
+        });
     }
 
-    /// Stop this scheduler.
+    /// Stop this scheduler. It'll be removed from the queue
+    /// on the next occasion.
     pub fn stop(&mut self) {
         self.running.store(false, Ordering::SeqCst);
     }
+}
+
+/// The session-wide beat grid that quantized contexts start on, in
+/// stream time. It counts beats from the start of the stream, and a
+/// tempo change continues the count from the current position, so the
+/// beats that already passed stay where they are.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BeatClock {
+    anchor_time: f64,
+    anchor_beat: f64,
+    // in seconds
+    beat_duration: f64,
+}
+
+impl BeatClock {
+    pub fn new(beat_duration: f64) -> Self {
+        BeatClock {
+            anchor_time: 0.0,
+            anchor_beat: 0.0,
+            beat_duration,
+        }
+    }
+
+    /// start counting beats over at the given stream time
+    pub fn restart(&mut self, time: f64) {
+        self.anchor_time = time;
+        self.anchor_beat = 0.0;
+    }
+
+    /// the (fractional) number of beats at the given stream time
+    pub fn beat_at(&self, time: f64) -> f64 {
+        self.anchor_beat + (time - self.anchor_time) / self.beat_duration
+    }
+
+    /// follow a tempo change at the given stream time
+    pub fn set_beat_duration(&mut self, time: f64, beat_duration: f64) {
+        if beat_duration > 0.0 && beat_duration != self.beat_duration {
+            self.anchor_beat = self.beat_at(time);
+            self.anchor_time = time;
+            self.beat_duration = beat_duration;
+        }
+    }
+
+    /// The stream time of the next boundary of a grid with the given
+    /// number of beats, or `None` if the time is (just about) on one.
+    pub fn next_boundary(&self, time: f64, beats: f64) -> Option<f64> {
+        let pos = self.beat_at(time) / beats;
+        // no need to wait a full period if we're just a tiny bit late
+        if (pos - pos.floor()) * beats * self.beat_duration < 0.001 {
+            return None;
+        }
+        Some(self.anchor_time + (pos.ceil() * beats - self.anchor_beat) * self.beat_duration)
+    }
+}
+
+/// Step all queued schedulers that are due before the given point
+/// in stream time, earliest first. Quantized contexts are started
+/// on their boundary, in between.
+pub fn run_schedulers_until<const BUFSIZE: usize, const NCHAN: usize>(
+    session: &Session<BUFSIZE, NCHAN>,
+    until: f64,
+) {
+    if session.transport_stopped.lock().is_some() {
+        return;
+    }
+
+    // the grid of the quantized contexts follows the tempo
+    session.beat_clock.lock().set_beat_duration(
+        session.ruffbox.get_now(),
+        midi_clock::current_beat_duration(&session.globals) / 1000.0,
+    );
 
-    /// Join this scheduler thread.
-    pub fn join(&mut self) {
-        if let Some(h) = self.handle.take() {
-            if h.join().is_ok() {
-                println!("joined!");
-            } else {
-                println!("Could not join spawned thread");
+    loop {
+        // take a copy of the next due scheduler, as stepping it might
+        // start new schedulers, which need the lock ...
+        let due = {
+            let mut scheds = session.scheduler_queue.lock();
+            scheds.retain(|s| s.running.load(Ordering::SeqCst));
+            scheds
+                .iter()
+                .filter(|s| s.data.stream_time.load() < until)
+                .min_by(|a, b| {
+                    a.data
+                        .stream_time
+                        .load()
+                        .total_cmp(&b.data.stream_time.load())
+                })
+                .cloned()
+        };
+
+        // a context starts before the schedulers due at the same time,
+        // so that the generators it replaces switch right on the boundary
+        let context = {
+            let mut pending = session.pending_contexts.lock();
+            let next = pending
+                .iter()
+                .map(|(name, (start, _))| (name, *start))
+                .filter(|(_, start)| {
+                    *start < until
+                        && due
+                            .as_ref()
+                            .map(|s| *start <= s.data.stream_time.load())
+                            .unwrap_or(true)
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(name, _)| name.clone());
+            next.and_then(|name| pending.remove(&name))
+        };
+
+        if let Some((start, mut ctx)) = context {
+            Session::handle_context(&mut ctx, session, Some(start));
+            continue;
+        }
+
+        let Some(mut sched) = due else {
+            return;
+        };
+
+        match Scheduler::step(sched.fun, &mut sched.data, session) {
+            Some(mut next) => {
+                // a generator that doesn't advance in time would stall everything else
+                if next <= 0.0 {
+                    println!("non-positive duration {next} found, advancing by {MIN_STEP}");
+                    next = MIN_STEP;
+                }
+                sched
+                    .data
+                    .logical_time
+                    .store(sched.data.logical_time.load() + next);
+                sched
+                    .data
+                    .stream_time
+                    .store(sched.data.stream_time.load() + next);
             }
-        } else {
-            println!("Called stop on non-running thread");
+            None => {
+                sched.running.store(false, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+/// Stop the transport (i.e. when an external midi clock stops), the
+/// schedulers aren't stepped until it continues.
+pub fn stop_transport<const BUFSIZE: usize, const NCHAN: usize>(session: &Session<BUFSIZE, NCHAN>) {
+    let mut stopped = session.transport_stopped.lock();
+    if stopped.is_none() {
+        *stopped = Some(session.ruffbox.get_now());
+        println!("transport stopped");
+    }
+}
+
+/// Continue the transport, moving the schedulers and the pending contexts
+/// ahead by the time it was stopped. A restart also starts the beat grid
+/// over, so that quantized contexts line up with the external downbeat.
+pub fn continue_transport<const BUFSIZE: usize, const NCHAN: usize>(
+    session: &Session<BUFSIZE, NCHAN>,
+    restart: bool,
+) {
+    let now = session.ruffbox.get_now();
+    let stopped = session.transport_stopped.lock().take();
+
+    if let Some(stopped_at) = stopped {
+        let pause = now - stopped_at;
+        for sched in session.scheduler_queue.lock().iter() {
+            sched
+                .data
+                .stream_time
+                .store(sched.data.stream_time.load() + pause);
+            sched
+                .data
+                .logical_time
+                .store(sched.data.logical_time.load() + pause);
+        }
+        for (start, _) in session.pending_contexts.lock().values_mut() {
+            *start += pause;
         }
+        println!("transport continued");
+    }
+
+    if restart {
+        session.beat_clock.lock().restart(now);
+    }
+}
+
+/// Start the thread that steps all schedulers a little ahead
+/// of the audio stream.
+pub fn start_timing_thread<const BUFSIZE: usize, const NCHAN: usize>(
+    session: Session<BUFSIZE, NCHAN>,
+) -> thread::JoinHandle<()> {
+    // control events are interpreted here, which might evaluate code
+    eval::spawn_evaluating("scheduler timing", move || loop {
+        run_schedulers_until(&session, session.ruffbox.get_now() + LOOKAHEAD);
+        thread::sleep(TIMING_INTERVAL);
+    })
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_beat_clock_boundaries() {
+        // 120 bpm
+        let clock = BeatClock::new(0.5);
+
+        assert!((clock.next_boundary(0.3, 1.0).unwrap() - 0.5).abs() < 0.00001);
+        // bars of four beats
+        assert!((clock.next_boundary(2.1, 4.0).unwrap() - 4.0).abs() < 0.00001);
+        // right on (or just past) the boundary starts right away
+        assert!(clock.next_boundary(4.0, 4.0).is_none());
+        assert!(clock.next_boundary(4.0005, 4.0).is_none());
+    }
+
+    #[test]
+    fn test_beat_clock_follows_tempo() {
+        let mut clock = BeatClock::new(0.5);
+
+        // half way through the third beat, the tempo halves
+        clock.set_beat_duration(1.25, 1.0);
+        assert!((clock.beat_at(1.25) - 2.5).abs() < 0.00001);
+        // the rest of the beat takes twice as long
+        assert!((clock.next_boundary(1.25, 1.0).unwrap() - 1.75).abs() < 0.00001);
+        // the bar ends after the remaining 1.5 beats
+        assert!((clock.next_boundary(1.25, 4.0).unwrap() - 2.75).abs() < 0.00001);
+
+        // no change, no new anchor
+        let before = clock;
+        clock.set_beat_duration(3.0, 1.0);
+        assert_eq!(clock, before);
+
+        // start over from the external downbeat
+        clock.restart(3.2);
+        assert!((clock.next_boundary(3.3, 4.0).unwrap() - 7.2).abs() < 0.00001);
     }
 }
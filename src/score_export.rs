@@ -0,0 +1,509 @@
+use anyhow::{bail, Result};
+use ruffbox_synth::building_blocks::{SynthParameterLabel, SynthParameterValue};
+use rust_music_theory::note::Note;
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::event::StaticEvent;
+use crate::midi_file::RecordedTrack;
+use crate::music_theory::{self, Tuning};
+use crate::parameter::NoteParameterLabel;
+
+// everything is quantized to sixteenth notes and written in 4/4
+const TICKS_PER_BEAT: u64 = 4;
+const TICKS_PER_BAR: u64 = 16;
+
+// the note values that can be written without ties, in sixteenths,
+// longest first (dotted ones included)
+const NOTE_VALUES: [u64; 8] = [16, 12, 8, 6, 4, 3, 2, 1];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScoreFormat {
+    LilyPond,
+    MusicXml,
+}
+
+impl ScoreFormat {
+    /// guess the format from the file extension
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        let ext = filename.rsplit('.').next()?.to_lowercase();
+        match ext.as_str() {
+            "ly" => Some(ScoreFormat::LilyPond),
+            "musicxml" | "xml" => Some(ScoreFormat::MusicXml),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pitch {
+    pub octave: u8,
+    pub class: u8,
+}
+
+impl Pitch {
+    fn from_note(note: Note) -> Self {
+        Pitch {
+            octave: note.octave,
+            class: note.pitch_class.into_u8(),
+        }
+    }
+
+    fn lilypond(&self) -> String {
+        let symbol = music_theory::pitch_class_symbol(self.class);
+        let name = if let Some(base) = symbol.strip_suffix('s') {
+            format!("{base}is")
+        } else {
+            symbol.to_string()
+        };
+        // c' is the middle c (c4)
+        let marks = if self.octave >= 3 {
+            "'".repeat(self.octave as usize - 3)
+        } else {
+            ",".repeat(3 - self.octave as usize)
+        };
+        format!("{name}{marks}")
+    }
+}
+
+enum EventPitch {
+    Pitched(Pitch),
+    Unpitched,
+}
+
+// Note events keep their pitch, synth events are spelled from their
+// frequency, and everything else (samples, noise) is unpitched.
+fn event_pitch(ev: &StaticEvent) -> Option<EventPitch> {
+    if ev.name == "note" {
+        return match ev.params.get(&NoteParameterLabel::Pitch.into()) {
+            // midi note numbers are an octave above the internal ones
+            Some(SynthParameterValue::ScalarF32(n)) => Some(EventPitch::Pitched(Pitch::from_note(
+                music_theory::from_note_nr(n.round().clamp(12.0, 127.0) as u8 - 12),
+            ))),
+            Some(SynthParameterValue::Symbolic(s)) => music_theory::from_string(s)
+                .ok()
+                .map(|n| EventPitch::Pitched(Pitch::from_note(n))),
+            _ => None,
+        };
+    }
+
+    if ev.sample_lookup.is_none() {
+        if let Some(SynthParameterValue::ScalarF32(freq)) =
+            ev.params.get(&SynthParameterLabel::PitchFrequency.into())
+        {
+            if *freq > 0.0 {
+                return Some(EventPitch::Pitched(Pitch::from_note(
+                    music_theory::from_freq(*freq, Tuning::EqualTemperament),
+                )));
+            }
+        }
+    }
+
+    Some(EventPitch::Unpitched)
+}
+
+/// A chord (or a single note, or an unpitched hit if there are no
+/// pitches) or a rest, with its length in sixteenths.
+#[derive(Clone, Debug, PartialEq)]
+enum Item {
+    Chord(Vec<Pitch>, u64),
+    Rest(u64),
+}
+
+/// a notatable piece of an item, tied to the next one if the item continues
+#[derive(Clone, Debug, PartialEq)]
+pub struct Piece {
+    pub pitches: Option<Vec<Pitch>>,
+    pub len: u64,
+    pub tie: bool,
+}
+
+pub struct ScorePart {
+    pub name: String,
+    pub percussive: bool,
+    pub measures: Vec<Vec<Piece>>,
+}
+
+impl ScorePart {
+    fn bass_clef(&self) -> bool {
+        let pitches: Vec<u32> = self
+            .measures
+            .iter()
+            .flatten()
+            .filter_map(|p| p.pitches.as_ref())
+            .flatten()
+            .map(|p| p.octave as u32 * 12 + p.class as u32)
+            .collect();
+        // below middle c on average
+        !pitches.is_empty() && pitches.iter().sum::<u32>() / (pitches.len() as u32) < 48
+    }
+}
+
+// Turn the recorded events into chords and rests. Each chord lasts until
+// the next step, so the rhythm follows the durations of the generator.
+// Parts that only contain unpitched events become percussion parts,
+// otherwise unpitched events are left out.
+fn items_from_track(track: &RecordedTrack, tick_secs: f64) -> (Vec<Item>, bool) {
+    let to_ticks = |secs: f64| (secs / tick_secs).round().max(0.0) as u64;
+
+    let pitched: Vec<(f64, Option<Pitch>)> = track
+        .events
+        .iter()
+        .filter_map(|(t, ev)| match event_pitch(ev)? {
+            EventPitch::Pitched(p) => Some((*t, Some(p))),
+            EventPitch::Unpitched => Some((*t, None)),
+        })
+        .collect();
+
+    let percussive = pitched.iter().all(|(_, p)| p.is_none());
+
+    let mut chords: BTreeMap<u64, Vec<Pitch>> = BTreeMap::new();
+    for (t, p) in pitched {
+        match p {
+            Some(p) => chords.entry(to_ticks(t)).or_default().push(p),
+            None if percussive => {
+                chords.entry(to_ticks(t)).or_default();
+            }
+            None => {}
+        }
+    }
+
+    let mut boundaries: Vec<u64> = track.steps.iter().map(|t| to_ticks(*t)).collect();
+    boundaries.dedup();
+
+    let mut items = Vec::new();
+    let mut pos = 0;
+
+    for (start, mut pitches) in chords {
+        if start < pos {
+            continue;
+        }
+        if start > pos {
+            items.push(Item::Rest(start - pos));
+        }
+        let end = boundaries
+            .iter()
+            .find(|b| **b > start)
+            .copied()
+            .unwrap_or(start + 1);
+        pitches.sort();
+        pitches.dedup();
+        items.push(Item::Chord(pitches, end - start));
+        pos = end;
+    }
+
+    if let Some(end) = boundaries.last() {
+        if *end > pos {
+            items.push(Item::Rest(end - pos));
+        }
+    }
+
+    (items, percussive)
+}
+
+// add an item to the bars, split into notatable pieces
+fn push_item(
+    measures: &mut Vec<Vec<Piece>>,
+    pos: &mut u64,
+    pitches: Option<Vec<Pitch>>,
+    mut remaining: u64,
+) {
+    while remaining > 0 {
+        let len = *NOTE_VALUES
+            .iter()
+            .find(|v| **v <= remaining.min(TICKS_PER_BAR - *pos))
+            .unwrap();
+        remaining -= len;
+        *pos += len;
+        measures.last_mut().unwrap().push(Piece {
+            pitches: pitches.clone(),
+            len,
+            tie: pitches.is_some() && remaining > 0,
+        });
+        if *pos == TICKS_PER_BAR {
+            measures.push(Vec::new());
+            *pos = 0;
+        }
+    }
+}
+
+// Split the items into bars of notatable pieces, tying notes
+// across bar lines, and fill up the last bar with rests.
+fn layout(items: &[Item]) -> Vec<Vec<Piece>> {
+    let mut measures = vec![Vec::new()];
+    let mut pos = 0;
+
+    for item in items {
+        match item {
+            Item::Chord(p, len) => push_item(&mut measures, &mut pos, Some(p.clone()), *len),
+            Item::Rest(len) => push_item(&mut measures, &mut pos, None, *len),
+        }
+    }
+
+    if pos > 0 {
+        let fill = TICKS_PER_BAR - pos;
+        push_item(&mut measures, &mut pos, None, fill);
+    }
+
+    // a full bar always opens a new (empty) one
+    measures.pop();
+    if measures.is_empty() {
+        measures.push(vec![Piece {
+            pitches: None,
+            len: TICKS_PER_BAR,
+            tie: false,
+        }]);
+    }
+
+    measures
+}
+
+/// Quantize the recorded tracks to sixteenth notes, with the given beat
+/// (quarter note) duration in milliseconds.
+pub fn score_parts(tracks: &[RecordedTrack], beat_duration_ms: f64) -> Vec<ScorePart> {
+    let tick_secs = beat_duration_ms * 0.001 / TICKS_PER_BEAT as f64;
+    tracks
+        .iter()
+        .map(|track| {
+            let (items, percussive) = items_from_track(track, tick_secs);
+            ScorePart {
+                name: track.name.clone(),
+                percussive,
+                measures: layout(&items),
+            }
+        })
+        .collect()
+}
+
+fn lilypond_duration(len: u64) -> &'static str {
+    match len {
+        16 => "1",
+        12 => "2.",
+        8 => "2",
+        6 => "4.",
+        4 => "4",
+        3 => "8.",
+        2 => "8",
+        _ => "16",
+    }
+}
+
+pub fn to_lilypond(parts: &[ScorePart], bpm: f64) -> String {
+    let mut ly = String::new();
+    ly.push_str("\\version \"2.24.0\"\n\n\\score {\n  <<\n");
+
+    for part in parts.iter() {
+        let staff = if part.percussive {
+            "RhythmicStaff"
+        } else {
+            "Staff"
+        };
+        ly.push_str(&format!(
+            "    \\new {staff} \\with {{ instrumentName = \"{}\" }} {{\n",
+            part.name.replace('"', "'")
+        ));
+        ly.push_str(&format!("      \\tempo 4 = {}\n", bpm.round()));
+        ly.push_str("      \\time 4/4\n");
+        if part.bass_clef() {
+            ly.push_str("      \\clef bass\n");
+        }
+        for measure in part.measures.iter() {
+            let mut bar = Vec::new();
+            for piece in measure.iter() {
+                let dur = lilypond_duration(piece.len);
+                let note = match piece.pitches.as_ref() {
+                    None => format!("r{dur}"),
+                    // rhythmic staves ignore the pitch
+                    Some(p) if p.is_empty() => format!("c{dur}"),
+                    Some(p) if p.len() == 1 => format!("{}{dur}", p[0].lilypond()),
+                    Some(p) => format!(
+                        "<{}>{dur}",
+                        p.iter()
+                            .map(|p| p.lilypond())
+                            .collect::<Vec<String>>()
+                            .join(" ")
+                    ),
+                };
+                bar.push(if piece.tie { format!("{note} ~") } else { note });
+            }
+            ly.push_str(&format!("      {} |\n", bar.join(" ")));
+        }
+        ly.push_str("    }\n");
+    }
+
+    ly.push_str("  >>\n  \\layout { }\n}\n");
+    ly
+}
+
+fn musicxml_type(len: u64) -> (&'static str, bool) {
+    match len {
+        16 => ("whole", false),
+        12 => ("half", true),
+        8 => ("half", false),
+        6 => ("quarter", true),
+        4 => ("quarter", false),
+        3 => ("eighth", true),
+        2 => ("eighth", false),
+        _ => ("16th", false),
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn to_musicxml(parts: &[ScorePart], bpm: f64) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n");
+    xml.push_str("<!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \"http://www.musicxml.org/dtds/partwise.dtd\">\n");
+    xml.push_str("<score-partwise version=\"4.0\">\n  <part-list>\n");
+    for (idx, part) in parts.iter().enumerate() {
+        xml.push_str(&format!(
+            "    <score-part id=\"P{}\"><part-name>{}</part-name></score-part>\n",
+            idx + 1,
+            xml_escape(&part.name)
+        ));
+    }
+    xml.push_str("  </part-list>\n");
+
+    for (idx, part) in parts.iter().enumerate() {
+        xml.push_str(&format!("  <part id=\"P{}\">\n", idx + 1));
+        let mut tied = false;
+        for (num, measure) in part.measures.iter().enumerate() {
+            xml.push_str(&format!("    <measure number=\"{}\">\n", num + 1));
+            if num == 0 {
+                let clef = if part.percussive {
+                    "<sign>percussion</sign>"
+                } else if part.bass_clef() {
+                    "<sign>F</sign><line>4</line>"
+                } else {
+                    "<sign>G</sign><line>2</line>"
+                };
+                xml.push_str(&format!(
+                    "      <attributes><divisions>{TICKS_PER_BEAT}</divisions><time><beats>4</beats><beat-type>4</beat-type></time><clef>{clef}</clef></attributes>\n"
+                ));
+                xml.push_str(&format!(
+                    "      <direction placement=\"above\"><direction-type><metronome><beat-unit>quarter</beat-unit><per-minute>{0}</per-minute></metronome></direction-type><sound tempo=\"{0}\"/></direction>\n",
+                    bpm.round()
+                ));
+            }
+            for piece in measure.iter() {
+                let (typ, dot) = musicxml_type(piece.len);
+                let mut ties = String::new();
+                let mut tied_notations = String::new();
+                if tied {
+                    ties.push_str("<tie type=\"stop\"/>");
+                    tied_notations.push_str("<tied type=\"stop\"/>");
+                }
+                if piece.tie {
+                    ties.push_str("<tie type=\"start\"/>");
+                    tied_notations.push_str("<tied type=\"start\"/>");
+                }
+                let dots = if dot { "<dot/>" } else { "" };
+                let notations = if tied_notations.is_empty() {
+                    String::new()
+                } else {
+                    format!("<notations>{tied_notations}</notations>")
+                };
+
+                let heads: Vec<String> = match piece.pitches.as_ref() {
+                    None => vec!["<rest/>".to_string()],
+                    Some(p) if p.is_empty() => vec![
+                        "<unpitched><display-step>C</display-step><display-octave>5</display-octave></unpitched>".to_string(),
+                    ],
+                    Some(p) => p
+                        .iter()
+                        .map(|p| {
+                            let symbol = music_theory::pitch_class_symbol(p.class);
+                            let alter = if symbol.ends_with('s') {
+                                "<alter>1</alter>"
+                            } else {
+                                ""
+                            };
+                            format!(
+                                "<pitch><step>{}</step>{alter}<octave>{}</octave></pitch>",
+                                symbol[..1].to_uppercase(),
+                                p.octave
+                            )
+                        })
+                        .collect(),
+                };
+
+                for (i, head) in heads.iter().enumerate() {
+                    let chord = if i > 0 { "<chord/>" } else { "" };
+                    xml.push_str(&format!(
+                        "      <note>{chord}{head}<duration>{}</duration>{ties}<type>{typ}</type>{dots}{notations}</note>\n",
+                        piece.len
+                    ));
+                }
+                tied = piece.tie;
+            }
+            xml.push_str("    </measure>\n");
+        }
+        xml.push_str("  </part>\n");
+    }
+
+    xml.push_str("</score-partwise>\n");
+    xml
+}
+
+pub fn write_score(
+    filename: &str,
+    format: ScoreFormat,
+    tracks: &[RecordedTrack],
+    beat_duration_ms: f64,
+) -> Result<()> {
+    if tracks.is_empty() {
+        bail!("nothing to export");
+    }
+    let parts = score_parts(tracks, beat_duration_ms);
+    let bpm = 60000.0 / beat_duration_ms;
+    let score = match format {
+        ScoreFormat::LilyPond => to_lilypond(&parts, bpm),
+        ScoreFormat::MusicXml => to_musicxml(&parts, bpm),
+    };
+    fs::write(filename, score)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_ties_across_bars() {
+        let c4 = Pitch {
+            octave: 4,
+            class: 0,
+        };
+        // a dotted half, then a half note that crosses the bar line
+        let measures = layout(&[Item::Chord(vec![c4], 12), Item::Chord(vec![c4], 8)]);
+
+        assert_eq!(measures.len(), 2);
+        assert_eq!(measures[0][1].len, 4);
+        assert!(measures[0][1].tie);
+        assert_eq!(measures[1][0].len, 4);
+        assert!(!measures[1][0].tie);
+        // the rest of the second bar is filled up with a dotted half rest
+        assert_eq!(measures[1][1].pitches, None);
+        assert_eq!(measures[1][1].len, 12);
+    }
+
+    #[test]
+    fn test_lilypond_pitches() {
+        let cs5 = Pitch {
+            octave: 5,
+            class: 1,
+        };
+        let a2 = Pitch {
+            octave: 2,
+            class: 9,
+        };
+        assert_eq!(cs5.lilypond(), "cis''");
+        assert_eq!(a2.lilypond(), "a,");
+    }
+}
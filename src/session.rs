@@ -1,26 +1,38 @@
 use dashmap::{DashMap, DashSet};
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use rosc::OscType;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::Duration;
 use std::{sync, thread};
 
 use ruffbox_synth::building_blocks::{SynthParameterLabel, SynthParameterValue};
 use ruffbox_synth::ruffbox::RuffboxControls;
 
+use crate::automation::{self, ActiveRamp};
 use crate::builtin_types::{Command, ConfigParameter, GlobalVariables, VariableId};
 use crate::eval::FunctionMap;
+use crate::eval_server::EvalServer;
 use crate::event::InterpretableEvent;
 use crate::event_helpers::*;
 use crate::generator::Generator;
+use crate::midi_clock::{self, MidiClockFollower, MidiClockSender};
+use crate::midi_file::MidiRecording;
+use crate::midi_output::MidiOutSender;
 use crate::osc_client::OscClient;
 use crate::parameter::*;
+use crate::peer_sync::PeerSync;
+use crate::random;
 use crate::real_time_streaming;
-use crate::scheduler::{Scheduler, SchedulerData};
+use crate::scheduler::{BeatClock, QueuedScheduler, Scheduler, SchedulerData};
+use crate::session_snapshot::Journal;
+use crate::speaker_layout::SpeakerLayout;
+use crate::stems::StemBuses;
 use crate::SampleAndWavematrixSet;
 use crate::TypedEntity;
 use crate::{commands, Comparable};
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum OutputMode {
     Stereo,
     // AmbisonicsBinaural,
@@ -50,6 +62,8 @@ pub struct SyncContext {
     pub block_tags: BTreeSet<String>,
     pub solo_tags: BTreeSet<String>,
     pub resync: bool,
+    // beats, overrides the global quantization (0 is off)
+    pub quantize: Option<f32>,
 }
 
 pub struct ContextGeneratorIds {
@@ -57,12 +71,20 @@ pub struct ContextGeneratorIds {
     pub root: BTreeSet<BTreeSet<String>>,
     /// supplemental (internal, composed) generators
     pub supplemental: BTreeSet<BTreeSet<String>>,
+    /// the context this one is synced to
+    pub sync_to: Option<String>,
+    /// time shift in milliseconds
+    pub shift: i32,
 }
 
 #[derive(Clone)]
 pub struct Session<const BUFSIZE: usize, const NCHAN: usize> {
     pub sample_set: SampleAndWavematrixSet,
     pub output_mode: OutputMode,
+    // maps the ruffbox channels to the speakers, if they're not a plain ring
+    pub speaker_layout: Option<sync::Arc<SpeakerLayout>>,
+    // extra ruffbox instances, to record stems
+    pub stems: sync::Arc<StemBuses<BUFSIZE, NCHAN>>,
     pub sync_mode: SyncMode,
     pub ruffbox: sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
     pub globals: sync::Arc<GlobalVariables>,
@@ -72,8 +94,30 @@ pub struct Session<const BUFSIZE: usize, const NCHAN: usize> {
     >,
     pub contexts: sync::Arc<DashMap<String, ContextGeneratorIds>>,
     pub osc_client: OscClient,
+    pub midi_out: sync::Arc<RwLock<Option<MidiOutSender>>>,
+    pub midi_clock: sync::Arc<Mutex<Option<MidiClockSender>>>,
+    pub midi_clock_follower: sync::Arc<Mutex<Option<MidiClockFollower>>>,
+    pub peer_sync: sync::Arc<RwLock<Option<PeerSync>>>,
+    pub eval_server: sync::Arc<Mutex<Option<EvalServer>>>,
     pub rec_control:
         sync::Arc<Mutex<Option<real_time_streaming::RecordingControl<BUFSIZE, NCHAN>>>>,
+    // the running schedulers, stepped by the timing thread
+    // (or the offline renderer)
+    pub scheduler_queue: sync::Arc<Mutex<Vec<QueuedScheduler<BUFSIZE, NCHAN>>>>,
+    // the source of everything that defines the session, to save it
+    pub journal: sync::Arc<Mutex<Journal>>,
+    // the imported files and the namespaces they were imported to
+    pub imports: sync::Arc<Mutex<HashSet<(PathBuf, String)>>>,
+    // the running parameter ramps
+    pub ramps: sync::Arc<Mutex<Vec<ActiveRamp>>>,
+    // quantized contexts, waiting for their start (in stream time)
+    pub pending_contexts: sync::Arc<Mutex<HashMap<String, (f64, SyncContext)>>>,
+    // the grid the quantized contexts start on
+    pub beat_clock: sync::Arc<Mutex<BeatClock>>,
+    // the stream time the transport was stopped at, if it is
+    pub transport_stopped: sync::Arc<Mutex<Option<f64>>>,
+    // taps the emitted events for the midi export
+    pub midi_recording: sync::Arc<Mutex<Option<MidiRecording>>>,
 }
 
 // naive disjoint test, assume unsorted
@@ -96,6 +140,16 @@ fn eval_loop<const BUFSIZE: usize, const NCHAN: usize>(
     data: &mut SchedulerData<BUFSIZE, NCHAN>,
     session: &Session<BUFSIZE, NCHAN>,
 ) -> (f64, bool, bool) {
+    // everything random that happens in this step uses the
+    // generator's rng, if it (or the session) has been seeded
+    let _rng_guard = {
+        let mut gen = data.generator.lock();
+        if gen.rng.is_none() {
+            gen.rng = random::derive_from_session();
+        }
+        random::GeneratorRngGuard::set(gen.rng.clone())
+    };
+
     // global tempo modifier, allows us to do weird stuff with the
     // global tempo ...
     let mut tmod: f64 = 1.0;
@@ -124,7 +178,7 @@ fn eval_loop<const BUFSIZE: usize, const NCHAN: usize>(
     }
 
     // GENERATOR LOCK !!!
-    let (time, mut events, end_state) = {
+    let (time, mut events, end_state, stem_bus, recording) = {
         // HERE IT IS ... LOCK, LOCK, LOCK
         let mut gen = data.generator.lock();
 
@@ -173,14 +227,37 @@ fn eval_loop<const BUFSIZE: usize, const NCHAN: usize>(
         //    println!("really no events");
         //}
         let end_state = gen.reached_end_state();
-        (time, events, end_state)
+        // the stem bus this generator is recorded to, if any
+        let stem_bus = session.stems.bus_for_generator(&gen.id_tags);
+        // the tags to record the events under, if they're exported
+        let recording = session
+            .midi_recording
+            .lock()
+            .as_ref()
+            .filter(|rec| rec.records(&gen.id_tags))
+            .map(|_| gen.id_tags.clone());
+        (time, events, end_state, stem_bus, recording)
     }; // END GENERATOR LOCK ...
 
+    // if the timing thread couldn't keep up, the events would end up in the
+    // past, so they are skipped, but the generator keeps running
+    let lateness = session.ruffbox.get_now() - (data.stream_time.load() + latency);
+    let late = lateness > 0.0;
+    if late {
+        println!(
+            "late by {:.1}ms, skipping events of generator {:?}",
+            lateness * 1000.0,
+            data.generator.lock().id_tags
+        );
+    }
+
     // the sync flag will be returned alongside the
     // time to let the scheduler know that it should
     // trigger the synced generators
     let mut sync = false;
 
+    let mut recorded = Vec::new();
+
     // start the generators ready to be synced ...
     if session.sync_mode == SyncMode::All {
         //println!("sync all");
@@ -191,10 +268,7 @@ fn eval_loop<const BUFSIZE: usize, const NCHAN: usize>(
         match ev {
             InterpretableEvent::Sound(s) => {
                 // no need to allocate a string everytime here, should be changed
-
-                // notes are currently not interpreted (there's no midi out currently),
-                // they are only here for mappers
-                if s.name == "silence" || s.name == "note" {
+                if s.name == "silence" {
                     // start the generators ready to be synced ...
                     if session.sync_mode == SyncMode::OnlyOnSilence {
                         //println!("sync silence");
@@ -222,42 +296,46 @@ fn eval_loop<const BUFSIZE: usize, const NCHAN: usize>(
                     continue;
                 }
 
-                // if this is a sampler event and contains a sample lookup,
-                // resolve it NOW ... at the very end, finally ...
-                let mut bufnum: usize = 0;
-
-                if s.name == "frozensampler" {
-                    if let Some(SynthParameterValue::ScalarUsize(b)) = s
-                        .params
-                        .get(&SynthParameterLabel::SampleBufferNumber.into())
-                    {
-                        bufnum = *b;
-                    }
+                // late events are recorded nonetheless
+                if recording.is_some() {
+                    recorded.push(s.clone());
                 }
 
-                if let Some(lookup) = s.sample_lookup.as_ref() {
-                    if let Some((res_bufnum, duration)) = session.sample_set.resolve_lookup(lookup)
-                    {
-                        bufnum = res_bufnum;
-                        // is this really needed ??
-                        s.params.insert(
-                            SynthParameterLabel::SampleBufferNumber.into(),
-                            SynthParameterValue::ScalarUsize(bufnum),
-                        );
+                if late {
+                    continue;
+                }
 
-                        s.params
-                            .entry(SynthParameterLabel::Sustain.into())
-                            .or_insert_with(|| {
-                                SynthParameterValue::ScalarF32((duration - 2) as f32)
-                            });
+                // notes go to the midi output, if there is one,
+                // otherwise they are only here for mappers
+                if s.name == "note" {
+                    if let Some(midi_out) = session.midi_out.read().as_ref() {
+                        let at = *data.start_time.lock()
+                            + Duration::from_secs_f64(
+                                (data.logical_time.load() + latency).max(0.0),
+                            );
+                        midi_out.send_note_event(
+                            s,
+                            midi_clock::current_beat_duration(&session.globals),
+                            at,
+                        );
                     }
+                    continue;
                 }
 
+                // if this is a sampler event and contains a sample lookup,
+                // resolve it NOW ... at the very end, finally ...
+                let bufnum = session.sample_set.resolve_sample(s);
+
                 // prepare a single, self-contained envelope from
                 // the available information ...
                 s.build_envelope();
 
-                if let Some(mut inst) = session.ruffbox.prepare_instance(
+                if let Some(layout) = session.speaker_layout.as_ref() {
+                    layout.pan(&mut s.params);
+                }
+
+                let ruffbox = session.stems.select(&session.ruffbox, stem_bus, s);
+                if let Some(mut inst) = ruffbox.prepare_instance(
                     map_synth_type(&s.name, &s.params),
                     data.stream_time.load() + latency,
                     bufnum,
@@ -292,7 +370,7 @@ fn eval_loop<const BUFSIZE: usize, const NCHAN: usize>(
                             _ => inst.set_instance_parameter(*addr, v),
                         }
                     }
-                    session.ruffbox.trigger(inst);
+                    ruffbox.trigger(inst);
                 } else {
                     println!("can't prepare instance !");
                 }
@@ -308,7 +386,7 @@ fn eval_loop<const BUFSIZE: usize, const NCHAN: usize>(
                 if let Some(mut contexts) = c.ctx.clone() {
                     // this is the worst clone ....
                     for mut sx in contexts.drain(..) {
-                        Session::handle_context(&mut sx, session);
+                        Session::handle_context(&mut sx, session, None);
                     }
                 }
                 if let Some(mut commands) = c.cmd.clone() {
@@ -316,7 +394,12 @@ fn eval_loop<const BUFSIZE: usize, const NCHAN: usize>(
                     for c in commands.drain(..) {
                         match c {
                             Command::FreezeBuffer(freezbuf, inbuf) => {
-                                commands::freeze_buffer(&session.ruffbox, freezbuf, inbuf);
+                                commands::freeze_buffer(
+                                    &session.ruffbox,
+                                    &session.sample_set,
+                                    freezbuf,
+                                    inbuf,
+                                );
                                 //println!("freeze buffer");
                             }
                             Command::Tmod(p) => {
@@ -334,6 +417,7 @@ fn eval_loop<const BUFSIZE: usize, const NCHAN: usize>(
                             Command::GlobalRuffboxParams(mut m) => {
                                 commands::set_global_ruffbox_parameters(
                                     &session.ruffbox,
+                                    &session.stems,
                                     &session.globals,
                                     &mut m,
                                 );
@@ -392,18 +476,83 @@ fn eval_loop<const BUFSIZE: usize, const NCHAN: usize>(
         }
     }
 
+    if let Some(tags) = recording {
+        commands::record_midi_step(
+            session,
+            &tags,
+            data.stream_time.load(),
+            time,
+            recorded,
+            end_state,
+        );
+    }
+
     (time, sync, end_state)
 }
 // END INNER MAIN SCHEDULER FUNCTION ...
 
 impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
-    pub fn handle_context(ctx: &mut SyncContext, session: &Session<BUFSIZE, NCHAN>) {
+    // The stream time of the next boundary of the session-wide beat grid,
+    // if the context is quantized.
+    fn quantization_boundary(ctx: &SyncContext, session: &Session<BUFSIZE, NCHAN>) -> Option<f64> {
+        // explicit sync has precedence
+        if ctx.sync_to.is_some() {
+            return None;
+        }
+
+        let beats = ctx
+            .quantize
+            .unwrap_or_else(|| commands::get_global_quantization(&session.globals));
+        if beats <= 0.0 {
+            return None;
+        }
+
+        let now = session.ruffbox.get_now();
+        let mut clock = session.beat_clock.lock();
+        clock.set_beat_duration(
+            now,
+            midi_clock::current_beat_duration(&session.globals) / 1000.0,
+        );
+        clock.next_boundary(now, beats as f64)
+    }
+
+    /// Handle the context right away or, if it's quantized, let the
+    /// timing thread (or the offline renderer) start it on the next grid
+    /// boundary. Evaluating the context again before that replaces
+    /// the pending one.
+    pub fn handle_context_quantized(mut ctx: SyncContext, session: &Session<BUFSIZE, NCHAN>) {
+        let Some(start) = Session::quantization_boundary(&ctx, session) else {
+            session.pending_contexts.lock().remove(&ctx.name);
+            Session::handle_context(&mut ctx, session, None);
+            return;
+        };
+
+        println!(
+            "context \'{}\' starts in {:.3}s",
+            ctx.name,
+            start - session.ruffbox.get_now()
+        );
+        session
+            .pending_contexts
+            .lock()
+            .insert(ctx.name.clone(), (start, ctx));
+    }
+
+    /// Handle the context, new generators start at the given stream time,
+    /// or right away.
+    pub fn handle_context(
+        ctx: &mut SyncContext,
+        session: &Session<BUFSIZE, NCHAN>,
+        start_time: Option<f64>,
+    ) {
         let name = ctx.name.clone(); // keep a copy for later
         if ctx.active {
             // otherwise, handle internal sync relations ...
             let mut new_gens = ContextGeneratorIds {
                 root: BTreeSet::new(),
                 supplemental: BTreeSet::new(),
+                sync_to: ctx.sync_to.clone(),
+                shift: ctx.shift,
             };
             let mut gen_map: HashMap<BTreeSet<String>, Generator> = HashMap::new();
             // collect id_tags and organize in map
@@ -516,6 +665,7 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
                         gen,
                         session,
                         (ctx.shift + gen_shift) as f64 * 0.001,
+                        start_time,
                         &ctx.block_tags,
                         &ctx.solo_tags,
                     );
@@ -559,6 +709,7 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
                         gen,
                         session,
                         (ctx.shift + gen_shift) as f64 * 0.001,
+                        start_time,
                         &ctx.block_tags,
                         &ctx.solo_tags,
                     );
@@ -570,6 +721,7 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
                             gen,
                             session,
                             (ctx.shift + gen_shift) as f64 * 0.001,
+                            start_time,
                             &ctx.block_tags,
                             &ctx.solo_tags,
                         );
@@ -583,6 +735,7 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
                         gen,
                         session,
                         (ctx.shift + gen_shift) as f64 * 0.001,
+                        start_time,
                         &ctx.block_tags,
                         &ctx.solo_tags,
                     );
@@ -626,6 +779,7 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
                             gen,
                             session,
                             (ctx.shift + gen_shift) as f64 * 0.001,
+                            start_time,
                             &ctx.block_tags,
                             &ctx.solo_tags,
                         );
@@ -660,6 +814,7 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
         gen: Generator,
         session: &Session<BUFSIZE, NCHAN>,
         shift: f64,
+        start_time: Option<f64>,
         block_tags: &BTreeSet<String>,
         solo_tags: &BTreeSet<String>,
     ) {
@@ -675,7 +830,9 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
 
         if finished {
             Session::stop_generator(session, &id_tags);
-            Session::start_generator_no_sync(gen, session, shift, block_tags, solo_tags);
+            Session::start_generator_no_sync(
+                gen, session, shift, start_time, block_tags, solo_tags,
+            );
             println!("restarted finished gen");
         } else if let Some(mut v) = session.schedulers.get_mut(&id_tags) {
             let (_, data) = v.value_mut();
@@ -798,11 +955,26 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
         gen: Generator,
         session: &Session<BUFSIZE, NCHAN>,
         shift: f64,
+        start_time: Option<f64>,
         block_tags: &BTreeSet<String>,
         solo_tags: &BTreeSet<String>,
     ) {
         let id_tags = gen.id_tags.clone();
 
+        let now = session.ruffbox.get_now();
+        let start_time = start_time.unwrap_or(now);
+        // if there's other peers, start on the next beat of the shared grid,
+        // the offset isn't part of the shift, so that re-evaluating the
+        // generator (or syncing to it) keeps it on the grid
+        let start_time = if let Some(ps) = session.peer_sync.read().as_ref() {
+            start_time
+                + ps.time_to_next_beat(
+                    commands::get_global_latency(&session.globals) + (start_time - now),
+                )
+        } else {
+            start_time
+        };
+
         print!("start generator (no sync) \'");
         for tag in id_tags.iter() {
             print!("{tag} ");
@@ -812,7 +984,7 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
         let sched_data = SchedulerData::<BUFSIZE, NCHAN>::new(
             gen,
             shift,
-            session.ruffbox.get_now(),
+            start_time,
             block_tags.clone(),
             solo_tags.clone(),
         );
@@ -823,7 +995,6 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
     // start scheduler and main time recursion //
     /////////////////////////////////////////////
     /// start a scheduler, create scheduler data, etc ...
-    #[allow(clippy::format_push_string)]
     fn start_scheduler(
         session: &Session<BUFSIZE, NCHAN>,
         sched_data: SchedulerData<BUFSIZE, NCHAN>,
@@ -832,18 +1003,7 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
         // otherwise, create new sched and data ...
         let mut sched = Scheduler::<BUFSIZE, NCHAN>::new();
 
-        // assemble name for thread ...
-        let mut thread_name: String = "".to_owned();
-        for tag in id_tags.iter() {
-            thread_name.push_str(&(format!("{tag} ")));
-        }
-
-        sched.start(
-            thread_name.trim(),
-            eval_loop,
-            sched_data.clone(),
-            session.clone(),
-        );
+        sched.start(eval_loop, sched_data.clone(), session);
 
         // get sched out of map, try to keep lock only shortly ...
         let sched_prox = if let Some((_, v)) = session.schedulers.remove(&id_tags) {
@@ -856,17 +1016,14 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
             .schedulers
             .insert(id_tags.clone(), (sched, sched_data));
 
-        // prepare for replacement
+        // replace
         if let Some((mut sched, _)) = sched_prox {
-            thread::spawn(move || {
-                sched.stop();
-                sched.join();
-                print!("replacing generator \'");
-                for tag in id_tags.iter() {
-                    print!("{tag} ");
-                }
-                println!("\'");
-            });
+            sched.stop();
+            print!("replacing generator \'");
+            for tag in id_tags.iter() {
+                print!("{tag} ");
+            }
+            println!("\'");
         }
     }
 
@@ -891,7 +1048,8 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
             };
 
             sched.stop();
-            sched.join();
+
+            commands::stop_midi_recording(session, gen_name);
 
             print!("stopped/removed generator \'");
             for tag in gen_name.iter() {
@@ -937,10 +1095,8 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
         }
 
         // stop
-        let mut sched_proxies2 = Vec::new(); // sometimes rust is really annoying ...
         for (mut sched, data) in sched_proxies.drain(..) {
             sched.stop();
-            sched_proxies2.push(sched);
 
             if session
                 .osc_client
@@ -956,22 +1112,12 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
                 }
             };
         }
-
-        // join
-        for mut sched in sched_proxies2.drain(..) {
-            sched.join();
-        }
     }
 
     pub fn clear_session(session: Session<BUFSIZE, NCHAN>) {
-        for mut sc in session.schedulers.iter_mut() {
-            let (sched, _) = sc.value_mut();
-            sched.stop();
-        }
-
         for mut sc in session.schedulers.iter_mut() {
             let (k, (sched, _)) = sc.pair_mut();
-            sched.join();
+            sched.stop();
             print!("stopped/removed generator \'");
             for tag in k.iter() {
                 print!("{tag} ");
@@ -999,5 +1145,97 @@ impl<const BUFSIZE: usize, const NCHAN: usize> Session<BUFSIZE, NCHAN> {
 
         session.schedulers.clear();
         session.contexts.clear();
+        session.pending_contexts.lock().clear();
+        automation::stop_ramps(&session);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{parse_and_eval_from_str, EvaluatedExpr};
+    use crate::peer_sync::{PeerSync, DEFAULT_BASE_PORT};
+    use crate::session_view::RunningSession;
+    use crate::standard_library::define_standard_library;
+    use ruffbox_synth::ruffbox::{init_ruffbox, ReverbMode};
+
+    fn test_session() -> Session<128, 2> {
+        let (controls, _) =
+            init_ruffbox::<128, 2>(1, 1.0, &ReverbMode::FreeVerb, 44100.0, 10, 1, false);
+        let session = Session {
+            schedulers: sync::Arc::new(DashMap::new()),
+            contexts: sync::Arc::new(DashMap::new()),
+            osc_client: OscClient::new(),
+            midi_out: sync::Arc::new(RwLock::new(None)),
+            midi_clock: sync::Arc::new(Mutex::new(None)),
+            midi_clock_follower: sync::Arc::new(Mutex::new(None)),
+            peer_sync: sync::Arc::new(RwLock::new(None)),
+            eval_server: sync::Arc::new(Mutex::new(None)),
+            rec_control: sync::Arc::new(Mutex::new(None)),
+            scheduler_queue: sync::Arc::new(Mutex::new(Vec::new())),
+            journal: sync::Arc::new(Mutex::new(Journal::default())),
+            imports: sync::Arc::new(Mutex::new(HashSet::new())),
+            ramps: sync::Arc::new(Mutex::new(Vec::new())),
+            pending_contexts: sync::Arc::new(Mutex::new(HashMap::new())),
+            transport_stopped: sync::Arc::new(Mutex::new(None)),
+            midi_recording: sync::Arc::new(Mutex::new(None)),
+            beat_clock: sync::Arc::new(Mutex::new(BeatClock::new(0.2))),
+            globals: sync::Arc::new(GlobalVariables::new()),
+            sample_set: SampleAndWavematrixSet::new(),
+            ruffbox: sync::Arc::new(controls),
+            output_mode: OutputMode::Stereo,
+            speaker_layout: None,
+            stems: sync::Arc::new(StemBuses::new(Vec::new())),
+            sync_mode: SyncMode::NotOnSilence,
+            functions: sync::Arc::new(define_standard_library()),
+        };
+        *session.functions.session_view.write() = Some(Box::new(RunningSession::new(&session)));
+        session
+    }
+
+    #[test]
+    fn test_reevaluate_on_peer_grid() {
+        let session = test_session();
+        // a port range of its own, so we don't run into any running instance
+        *session.peer_sync.write() = Some(
+            PeerSync::start(sync::Arc::clone(&session.globals), DEFAULT_BASE_PORT + 200).unwrap(),
+        );
+
+        let gen = || {
+            let Ok(EvaluatedExpr::Typed(TypedEntity::Generator(g))) = parse_and_eval_from_str(
+                "(nuc 'grid (saw 100))",
+                &session.functions,
+                &session.globals,
+                session.sample_set.clone(),
+                session.output_mode,
+            ) else {
+                panic!();
+            };
+            g
+        };
+
+        let no_tags = BTreeSet::new();
+        Session::start_generator_no_sync(gen(), &session, 0.0, None, &no_tags, &no_tags);
+
+        let (tags, data) = {
+            let entry = session.schedulers.iter().next().unwrap();
+            (entry.key().clone(), entry.value().1.clone())
+        };
+        let start = data.stream_time.load();
+        // the offset to the next beat is part of the start, not of the shift
+        assert_eq!(data.shift.load(), 0.0);
+        assert!((0.0..=0.2).contains(&start));
+
+        // re-evaluating keeps the generator where it is
+        Session::resume_generator(gen(), &session, 0.0, None, &no_tags, &no_tags);
+        assert_eq!(
+            session.schedulers.get(&tags).unwrap().1.stream_time.load(),
+            start
+        );
+
+        let ps = session.peer_sync.write().take();
+        if let Some(mut ps) = ps {
+            ps.stop();
+        }
     }
 }
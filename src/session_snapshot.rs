@@ -0,0 +1,427 @@
+//! Saving and restoring sessions.
+//!
+//! Generators, processors and modifiers can't be serialized as such, so a
+//! snapshot consists of the source of everything that defines the session
+//! (definitions, contexts and a few global settings), which is evaluated
+//! again on restore, and the state that the generators accumulated while
+//! running (the PFA, symbol ages, grown symbols, shaken parameters ...),
+//! which is applied to the freshly evaluated generators afterwards.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+use vom_rs::pfa::Pfa;
+
+use crate::builtin_types::Command;
+use crate::duration_tree::{add_leaf, DurationTreeNode};
+use crate::eval::EvaluatedExpr;
+use crate::event::{Event, SourceEvent};
+use crate::generator::Generator;
+use crate::parameter::ParameterValue;
+use crate::random::SeededRng;
+
+/// The source of everything that defines the session state, in order
+/// of evaluation. Entries with a key replace earlier ones with the same key,
+/// so re-evaluating a context over and over doesn't grow the journal.
+#[derive(Default)]
+pub struct Journal {
+    entries: Vec<(Option<String>, String)>,
+}
+
+impl Journal {
+    /// record the source of an evaluated expression, if it's relevant for the session state
+    pub fn record(&mut self, src: &str, expr: &EvaluatedExpr) {
+        if let EvaluatedExpr::Command(Command::Clear) = expr {
+            // contexts are gone, definitions stay
+            self.entries
+                .retain(|(k, _)| !k.as_ref().is_some_and(|k| k.starts_with("sx:")));
+            return;
+        }
+
+        if !defines_state(expr) {
+            return;
+        }
+
+        let key = journal_key(expr);
+        if key.is_some() {
+            self.entries.retain(|(k, _)| *k != key);
+        }
+        self.entries.push((key, src.trim().to_string()));
+    }
+
+    pub fn sources(&self) -> Vec<String> {
+        self.entries.iter().map(|(_, s)| s.clone()).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+fn defines_state(expr: &EvaluatedExpr) -> bool {
+    match expr {
+        EvaluatedExpr::FunctionDefinition(..)
+        | EvaluatedExpr::MacroDefinition(..)
+        | EvaluatedExpr::VariableDefinition(..)
+        | EvaluatedExpr::SyncContext(_) => true,
+        EvaluatedExpr::Progn(exprs) => exprs.iter().any(defines_state),
+        EvaluatedExpr::Command(c) => matches!(
+            c,
+            Command::Tmod(_)
+                | Command::Latency(_)
+                | Command::Bpm(_)
+                | Command::DefaultDuration(_)
+                | Command::GlobRes(_)
+                | Command::Quantize(_)
+                | Command::GlobalRuffboxParams(_)
+                | Command::LoadSampleAsWavematrix(..)
+                | Command::ImportSampleSet(_)
+                | Command::LoadSample(..)
+                | Command::LoadSampleSet(..)
+                | Command::LoadSampleSets(..)
+                | Command::OscDefineClient(..)
+                | Command::OscStartReceiver(_)
+                | Command::MidiStartReceiver(_)
+                | Command::MidiStartSender(_)
+                | Command::Seed(_)
+                | Command::Push(..)
+                | Command::Insert(..)
+                | Command::Import(..)
+        ),
+        _ => false,
+    }
+}
+
+// things where only the latest definition counts
+fn journal_key(expr: &EvaluatedExpr) -> Option<String> {
+    match expr {
+        EvaluatedExpr::SyncContext(s) => Some(format!("sx:{}", s.name)),
+        EvaluatedExpr::FunctionDefinition(name, _, _) => Some(format!("fun:{name}")),
+        EvaluatedExpr::MacroDefinition(name, _, _) => Some(format!("defmacro:{name}")),
+        EvaluatedExpr::VariableDefinition(id, _, _) => Some(format!("let:{id:?}")),
+        EvaluatedExpr::Command(Command::Tmod(_)) => Some("tmod".to_string()),
+        EvaluatedExpr::Command(Command::Latency(_)) => Some("latency".to_string()),
+        EvaluatedExpr::Command(Command::Bpm(_) | Command::DefaultDuration(_)) => {
+            Some("default-duration".to_string())
+        }
+        EvaluatedExpr::Command(Command::GlobRes(_)) => Some("global-resources".to_string()),
+        EvaluatedExpr::Command(Command::Quantize(_)) => Some("quantize".to_string()),
+        EvaluatedExpr::Command(Command::Seed(_)) => Some("seed".to_string()),
+        EvaluatedExpr::Command(Command::Import(file, namespace, _)) => Some(format!(
+            "import:{file}:{}",
+            namespace.as_deref().unwrap_or("")
+        )),
+        _ => None,
+    }
+}
+
+/// the structure and current state of a PFA
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PfaState {
+    pub states: Vec<Vec<char>>,
+    // source state, destination state, probability
+    pub transitions: Vec<(Vec<char>, Vec<char>, f32)>,
+    pub alphabet: Vec<char>,
+    pub current_state: Option<Vec<char>>,
+    pub current_symbol: Option<char>,
+    pub history: Vec<char>,
+}
+
+impl PfaState {
+    pub fn capture(pfa: &Pfa<char>) -> Self {
+        let mut states: Vec<Vec<char>> = pfa.labels.values().cloned().collect();
+        states.sort();
+
+        let mut transitions = Vec::new();
+        for (lh, chn) in pfa.children.iter() {
+            for ch in chn.iter() {
+                transitions.push((pfa.labels[lh].clone(), ch.child.clone(), ch.prob));
+            }
+        }
+        transitions.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+        PfaState {
+            states,
+            transitions,
+            alphabet: pfa.alphabet.clone(),
+            current_state: pfa.current_state.and_then(|h| pfa.labels.get(&h).cloned()),
+            current_symbol: pfa.current_symbol,
+            history: pfa.history.clone(),
+        }
+    }
+
+    pub fn restore(&self) -> Pfa<char> {
+        let mut pfa = Pfa::<char> {
+            pst_root: None,
+            ..Default::default()
+        };
+
+        for l in self.states.iter() {
+            pfa.add_state(l);
+        }
+
+        for (src, dest, prob) in self.transitions.iter() {
+            pfa.add_state_transition(src, dest, *prob, false);
+        }
+
+        pfa.rebuild_pst();
+
+        // adding states and transitions moves the current symbol,
+        // so the state is only restored once the pfa is complete
+        pfa.alphabet = self.alphabet.clone();
+        pfa.history = self.history.clone();
+        pfa.current_symbol = self.current_symbol;
+        pfa.current_state = self
+            .current_state
+            .as_ref()
+            .and_then(|cur| pfa.labels.iter().find(|(_, l)| *l == cur).map(|(h, _)| *h));
+
+        pfa
+    }
+}
+
+/// the current values of the scalar parameters of an event
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EventState {
+    pub name: String,
+    pub scalars: BTreeMap<String, f32>,
+}
+
+impl EventState {
+    fn capture(ev: &Event) -> Self {
+        EventState {
+            name: ev.name.clone(),
+            scalars: ev
+                .params
+                .iter()
+                .filter_map(|(k, v)| match v {
+                    ParameterValue::Scalar(d) => Some((format!("{k:?}"), d.val)),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+
+    fn apply(&self, ev: &mut Event) {
+        for (k, v) in ev.params.iter_mut() {
+            if let ParameterValue::Scalar(d) = v {
+                if let Some(val) = self.scalars.get(&format!("{k:?}")) {
+                    d.val = *val;
+                }
+            }
+        }
+    }
+}
+
+/// the events of a symbol, control events are `None`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SymbolState {
+    pub events: Vec<Option<EventState>>,
+    pub duration: EventState,
+}
+
+impl SymbolState {
+    // whether the events were (most likely) derived from the same source
+    fn same_structure(&self, events: &[SourceEvent]) -> bool {
+        self.events.len() == events.len()
+            && self
+                .events
+                .iter()
+                .zip(events.iter())
+                .all(|(s, e)| match (s, e) {
+                    (Some(s), SourceEvent::Sound(e)) => s.name == e.name,
+                    (None, SourceEvent::Control(_)) => true,
+                    _ => false,
+                })
+    }
+}
+
+/// everything a generator accumulates while running
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GeneratorState {
+    pub id_tags: BTreeSet<String>,
+    pub pfa: PfaState,
+    pub symbols: BTreeMap<char, SymbolState>,
+    pub symbol_ages: HashMap<char, u64>,
+    pub override_durations: Option<Vec<(Vec<char>, u64)>>,
+    pub seed: Option<u64>,
+}
+
+fn collect_durations(node: &DurationTreeNode<char, u64>, out: &mut Vec<(Vec<char>, u64)>) {
+    if let Some(d) = node.duration {
+        out.push((node.label.clone(), d));
+    }
+    for ch in node.children.values() {
+        collect_durations(ch, out);
+    }
+}
+
+/// the override durations as a list of labels and durations
+pub fn durations_to_list(tree: &DurationTreeNode<char, u64>) -> Vec<(Vec<char>, u64)> {
+    let mut durs = Vec::new();
+    collect_durations(tree, &mut durs);
+    durs
+}
+
+pub fn durations_from_list(durs: &[(Vec<char>, u64)]) -> DurationTreeNode<char, u64> {
+    let mut tree = DurationTreeNode::new(&[], None);
+    for (label, d) in durs.iter() {
+        if label.is_empty() {
+            tree.duration = Some(*d);
+        } else {
+            add_leaf(&mut tree, label, Some(*d));
+        }
+    }
+    tree
+}
+
+impl GeneratorState {
+    pub fn capture(gen: &Generator) -> Self {
+        let root = &gen.root_generator;
+
+        let symbols = root
+            .event_mapping
+            .iter()
+            .map(|(sym, (evs, dur))| {
+                (
+                    *sym,
+                    SymbolState {
+                        events: evs
+                            .iter()
+                            .map(|e| match e {
+                                SourceEvent::Sound(e) => Some(EventState::capture(e)),
+                                SourceEvent::Control(_) => None,
+                            })
+                            .collect(),
+                        duration: EventState::capture(dur),
+                    },
+                )
+            })
+            .collect();
+
+        GeneratorState {
+            id_tags: gen.id_tags.clone(),
+            pfa: PfaState::capture(&root.generator),
+            symbols,
+            symbol_ages: root.symbol_ages.clone(),
+            override_durations: root.override_durations.as_ref().map(durations_to_list),
+            seed: gen.rng.as_ref().map(|r| r.seed),
+        }
+    }
+
+    /// Apply the state to a generator evaluated from the same source. The
+    /// events of symbols that were grown at runtime are re-created from
+    /// symbols with the same kind of events, as growing copies them.
+    pub fn apply(&self, gen: &mut Generator) {
+        let root = &mut gen.root_generator;
+
+        let mut event_mapping = BTreeMap::new();
+        for (sym, state) in self.symbols.iter() {
+            let existing = root.event_mapping.get(sym);
+            let template = existing
+                .filter(|(evs, _)| state.same_structure(evs))
+                .or_else(|| {
+                    root.event_mapping
+                        .values()
+                        .find(|(evs, _)| state.same_structure(evs))
+                });
+
+            if let Some((evs, dur)) = template {
+                let mut evs = evs.clone();
+                let mut dur = dur.clone();
+                for (ev, ev_state) in evs.iter_mut().zip(state.events.iter()) {
+                    if let (SourceEvent::Sound(e), Some(s)) = (ev, ev_state) {
+                        s.apply(e);
+                    }
+                }
+                state.duration.apply(&mut dur);
+                event_mapping.insert(*sym, (evs, dur));
+            } else if let Some(mapping) = existing {
+                // the source has changed, keep the new events
+                event_mapping.insert(*sym, mapping.clone());
+            } else {
+                println!("can't restore the events of symbol {sym}");
+            }
+        }
+
+        root.event_mapping = event_mapping;
+        root.generator = self.pfa.restore();
+        root.symbol_ages = self.symbol_ages.clone();
+        root.override_durations = self
+            .override_durations
+            .as_ref()
+            .map(|d| durations_from_list(d));
+        // continue from the current state of the pfa
+        root.last_transition = None;
+        root.last_symbol = None;
+        root.set_modified();
+
+        if let Some(seed) = self.seed {
+            gen.rng = Some(SeededRng::new(seed));
+        }
+    }
+}
+
+/// A saved session, see the module description.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SessionSnapshot {
+    pub sources: Vec<String>,
+    /// the running generators
+    pub running: Vec<GeneratorState>,
+    /// generators stored in global variables, by variable
+    pub globals: Vec<(String, Vec<GeneratorState>)>,
+}
+
+impl SessionSnapshot {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vom_rs::pfa::Rule;
+
+    #[test]
+    fn test_pfa_state_roundtrip() {
+        let mut rules = vec![
+            Rule {
+                source: "a".chars().collect(),
+                symbol: 'b',
+                probability: 0.5,
+            },
+            Rule {
+                source: "a".chars().collect(),
+                symbol: 'a',
+                probability: 0.5,
+            },
+            Rule {
+                source: "b".chars().collect(),
+                symbol: 'a',
+                probability: 1.0,
+            },
+        ];
+
+        let mut pfa = Pfa::<char>::infer_from_rules(&mut rules, true);
+        for _ in 0..5 {
+            pfa.next_transition();
+        }
+
+        let state = PfaState::capture(&pfa);
+        let restored = state.restore();
+
+        assert_eq!(PfaState::capture(&restored), state);
+    }
+}
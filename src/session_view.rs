@@ -0,0 +1,215 @@
+//! What is running in a session. The evaluator can't see the session
+//! itself, so the inspection commands get their information from here.
+
+use dashmap::DashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::sync;
+
+use crate::builtin_types::{Comparable, TypedEntity, VariableId};
+use crate::scheduler::{Scheduler, SchedulerData};
+use crate::session::{ContextGeneratorIds, Session};
+
+/// a running generator and its current state
+pub struct GeneratorInfo {
+    pub id_tags: BTreeSet<String>,
+    pub context: Option<String>,
+    pub state: Option<String>,
+    pub last_symbol: Option<String>,
+    pub processors: Vec<String>,
+    // milliseconds
+    pub shift: f64,
+    pub block_tags: BTreeSet<String>,
+    pub solo_tags: BTreeSet<String>,
+}
+
+/// a sync context and the generators it started
+pub struct ContextInfo {
+    pub name: String,
+    pub generators: Vec<BTreeSet<String>>,
+    pub sync_to: Option<String>,
+    // milliseconds
+    pub shift: i32,
+}
+
+fn symbol(s: &str) -> TypedEntity {
+    TypedEntity::Comparable(Comparable::Symbol(s.to_string()))
+}
+
+fn symbols<'a>(s: impl Iterator<Item = &'a String>) -> TypedEntity {
+    TypedEntity::Vec(s.map(|s| Box::new(symbol(s))).collect())
+}
+
+fn symbol_or_false(s: &Option<String>) -> TypedEntity {
+    match s {
+        Some(s) => symbol(s),
+        None => TypedEntity::Comparable(Comparable::Boolean(false)),
+    }
+}
+
+impl GeneratorInfo {
+    /// a map with symbols as keys, to be used with `get`
+    pub fn to_map(&self) -> TypedEntity {
+        let mut map = HashMap::new();
+        map.insert(
+            VariableId::Symbol("id".to_string()),
+            symbols(self.id_tags.iter()),
+        );
+        map.insert(
+            VariableId::Symbol("context".to_string()),
+            symbol_or_false(&self.context),
+        );
+        map.insert(
+            VariableId::Symbol("state".to_string()),
+            symbol_or_false(&self.state),
+        );
+        map.insert(
+            VariableId::Symbol("symbol".to_string()),
+            symbol_or_false(&self.last_symbol),
+        );
+        map.insert(
+            VariableId::Symbol("processors".to_string()),
+            symbols(self.processors.iter()),
+        );
+        map.insert(
+            VariableId::Symbol("shift".to_string()),
+            TypedEntity::Comparable(Comparable::Float(self.shift as f32)),
+        );
+        map.insert(
+            VariableId::Symbol("block".to_string()),
+            symbols(self.block_tags.iter()),
+        );
+        map.insert(
+            VariableId::Symbol("solo".to_string()),
+            symbols(self.solo_tags.iter()),
+        );
+        TypedEntity::Map(map)
+    }
+}
+
+impl ContextInfo {
+    /// a map with symbols as keys, to be used with `get`
+    pub fn to_map(&self) -> TypedEntity {
+        let mut map = HashMap::new();
+        map.insert(VariableId::Symbol("name".to_string()), symbol(&self.name));
+        map.insert(
+            VariableId::Symbol("generators".to_string()),
+            TypedEntity::Vec(
+                self.generators
+                    .iter()
+                    .map(|tags| Box::new(symbols(tags.iter())))
+                    .collect(),
+            ),
+        );
+        map.insert(
+            VariableId::Symbol("sync".to_string()),
+            symbol_or_false(&self.sync_to),
+        );
+        map.insert(
+            VariableId::Symbol("shift".to_string()),
+            TypedEntity::Comparable(Comparable::Float(self.shift as f32)),
+        );
+        TypedEntity::Map(map)
+    }
+}
+
+pub trait SessionView: Send + Sync {
+    fn generators(&self) -> Vec<GeneratorInfo>;
+    fn contexts(&self) -> Vec<ContextInfo>;
+}
+
+/// Only holds the schedulers and contexts (not the whole session, which
+/// owns the function map this is stored in).
+pub struct RunningSession<const BUFSIZE: usize, const NCHAN: usize> {
+    schedulers: sync::Arc<
+        DashMap<BTreeSet<String>, (Scheduler<BUFSIZE, NCHAN>, SchedulerData<BUFSIZE, NCHAN>)>,
+    >,
+    contexts: sync::Arc<DashMap<String, ContextGeneratorIds>>,
+}
+
+impl<const BUFSIZE: usize, const NCHAN: usize> RunningSession<BUFSIZE, NCHAN> {
+    pub fn new(session: &Session<BUFSIZE, NCHAN>) -> Self {
+        RunningSession {
+            schedulers: session.schedulers.clone(),
+            contexts: session.contexts.clone(),
+        }
+    }
+}
+
+impl<const BUFSIZE: usize, const NCHAN: usize> SessionView for RunningSession<BUFSIZE, NCHAN> {
+    fn generators(&self) -> Vec<GeneratorInfo> {
+        // collect first, so the scheduler map isn't locked
+        // while waiting for the generators
+        let running: Vec<_> = self
+            .schedulers
+            .iter()
+            .map(|sc| {
+                let (id_tags, (_, data)) = sc.pair();
+                (
+                    id_tags.clone(),
+                    data.generator.clone(),
+                    data.shift.load() * 1000.0,
+                    data.block_tags
+                        .iter()
+                        .map(|t| t.key().clone())
+                        .collect::<BTreeSet<_>>(),
+                    data.solo_tags
+                        .iter()
+                        .map(|t| t.key().clone())
+                        .collect::<BTreeSet<_>>(),
+                )
+            })
+            .collect();
+
+        let mut infos = Vec::new();
+        for (id_tags, gen, shift, block_tags, solo_tags) in running {
+            let gen = gen.lock();
+            let root = &gen.root_generator;
+
+            // the labels are more readable than the internal chars
+            let label = |c: &char| {
+                root.label_mapping
+                    .as_ref()
+                    .and_then(|m| m.get(c).cloned())
+                    .unwrap_or_else(|| c.to_string())
+            };
+
+            let pfa = &root.generator;
+            let state = pfa
+                .current_state
+                .and_then(|h| pfa.labels.get(&h))
+                .map(|l| l.iter().map(label).collect::<Vec<_>>().join("-"));
+
+            infos.push(GeneratorInfo {
+                context: self
+                    .contexts
+                    .iter()
+                    .find(|ctx| ctx.root.contains(&id_tags))
+                    .map(|ctx| ctx.key().clone()),
+                id_tags,
+                state,
+                last_symbol: pfa.current_symbol.as_ref().map(label),
+                processors: gen.processors.iter().map(|p| p.name()).collect(),
+                shift,
+                block_tags,
+                solo_tags,
+            });
+        }
+        infos.sort_by(|a, b| a.id_tags.cmp(&b.id_tags));
+        infos
+    }
+
+    fn contexts(&self) -> Vec<ContextInfo> {
+        let mut infos: Vec<ContextInfo> = self
+            .contexts
+            .iter()
+            .map(|ctx| ContextInfo {
+                name: ctx.key().clone(),
+                generators: ctx.root.iter().cloned().collect(),
+                sync_to: ctx.sync_to.clone(),
+                shift: ctx.shift,
+            })
+            .collect();
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        infos
+    }
+}
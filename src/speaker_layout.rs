@@ -0,0 +1,381 @@
+//! Speaker layouts for multichannel setups that aren't an evenly spaced ring.
+//!
+//! A layout lists the output channel, azimuth and elevation of each speaker.
+//! The speakers are grouped in rings of equal elevation and sorted by azimuth,
+//! and the ruffbox channels are mapped onto that order, so that the ruffbox
+//! panner (which pans between neighbouring channels) moves around the rings.
+//! Each ring gets an extra ruffbox channel that is routed to its first speaker,
+//! so the panner can wrap around.
+//!
+//! `pos` then addresses the speakers by their index in that order, while
+//! `azi` (and `ele`) are mapped onto the speakers by pairwise (2D) VBAP within
+//! the ring closest to the elevation.
+
+use anyhow::{anyhow, bail, Result};
+use ruffbox_synth::building_blocks::{SynthParameterLabel, SynthParameterValue};
+use std::collections::HashMap;
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+use std::path::Path;
+
+use crate::parameter::ParameterAddress;
+use crate::session::OutputMode;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Speaker {
+    // the output channel (starting at 0)
+    pub channel: usize,
+    // radians, counter-clockwise, 0 is the front
+    pub azimuth: f32,
+    // radians, 0 is ear level
+    pub elevation: f32,
+}
+
+// speakers with the same elevation
+#[derive(Clone, Debug)]
+struct Ring {
+    elevation: f32,
+    // sorted, from 0 to 2pi
+    azimuths: Vec<f32>,
+    // the first ruffbox channel of this ring
+    offset: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct SpeakerLayout {
+    // sorted by ring, then azimuth
+    speakers: Vec<Speaker>,
+    rings: Vec<Ring>,
+    // the output channel each ruffbox channel is routed to
+    routing: Vec<Option<usize>>,
+}
+
+// the counter-clockwise distance from a to b
+fn ccw(a: f32, b: f32) -> f32 {
+    (b - a).rem_euclid(TAU)
+}
+
+impl Ring {
+    /// the position (from the first speaker of the ring) of a source at
+    /// the given azimuth, with the fractional part being the ruffbox
+    /// pan between two neighbouring speakers
+    fn position(&self, azimuth: f32) -> f32 {
+        let num = self.azimuths.len();
+        if num == 1 {
+            return 0.0;
+        }
+
+        let azimuth = azimuth.rem_euclid(TAU);
+        // the pair of speakers the source is in between, the last
+        // one wraps around to the first
+        let idx = self
+            .azimuths
+            .iter()
+            .rposition(|a| *a <= azimuth)
+            .unwrap_or(num - 1);
+        let a1 = self.azimuths[idx];
+        let a2 = self.azimuths[(idx + 1) % num];
+
+        let span = if idx + 1 == num {
+            ccw(a1, a2).clamp(f32::EPSILON, TAU)
+        } else {
+            a2 - a1
+        };
+        let offset = ccw(a1, azimuth);
+
+        let fraction = if span < PI {
+            // vbap gains for the speaker pair ...
+            let g1 = (a2 - azimuth).sin().max(0.0);
+            let g2 = (azimuth - a1).sin().max(0.0);
+            // ... as equal-power pan position
+            g2.atan2(g1) / FRAC_PI_2
+        } else {
+            // vbap doesn't work for pairs that are a half circle or
+            // more apart, interpolate the angle instead
+            offset / span
+        };
+
+        idx as f32 + fraction.clamp(0.0, 1.0)
+    }
+}
+
+impl SpeakerLayout {
+    pub fn new(speakers: Vec<Speaker>) -> Result<Self> {
+        if speakers.len() < 3 {
+            bail!("a speaker layout needs at least three speakers");
+        }
+
+        let max_channel = speakers.iter().map(|s| s.channel).max().unwrap_or(0);
+        let mut channels: Vec<usize> = speakers.iter().map(|s| s.channel).collect();
+        channels.sort_unstable();
+        channels.dedup();
+        if channels.len() != speakers.len() {
+            bail!("each speaker needs its own channel");
+        }
+
+        // group by elevation (to the degree)
+        let mut speakers = speakers;
+        let ring_key = |s: &Speaker| s.elevation.to_degrees().round() as i32;
+        for s in speakers.iter_mut() {
+            s.azimuth = s.azimuth.rem_euclid(TAU);
+        }
+        speakers.sort_by(|a, b| {
+            ring_key(a)
+                .cmp(&ring_key(b))
+                .then(a.azimuth.total_cmp(&b.azimuth))
+        });
+
+        let mut groups: Vec<Vec<&Speaker>> = Vec::new();
+        for s in speakers.iter() {
+            match groups.last_mut() {
+                Some(g) if ring_key(g[0]) == ring_key(s) => g.push(s),
+                _ => groups.push(vec![s]),
+            }
+        }
+
+        // a single ring that fills all channels wraps around
+        // in the ruffbox panner itself
+        let wraps = groups.len() == 1
+            && [4, 8, 16].contains(&speakers.len())
+            && max_channel < speakers.len();
+        let num_ruffbox = if wraps {
+            speakers.len()
+        } else {
+            speakers.len() + groups.len()
+        };
+
+        let num_channels = [4, 8, 16]
+            .into_iter()
+            .find(|n| *n >= num_ruffbox && *n > max_channel)
+            .ok_or_else(|| {
+                anyhow!("speaker layout needs more than the 16 channels megra can handle")
+            })?;
+
+        let mut routing = Vec::new();
+        let mut rings = Vec::new();
+        for g in groups.iter() {
+            rings.push(Ring {
+                elevation: g[0].elevation,
+                azimuths: g.iter().map(|s| s.azimuth).collect(),
+                offset: routing.len(),
+            });
+            routing.extend(g.iter().map(|s| Some(s.channel)));
+            if !wraps {
+                routing.push(Some(g[0].channel));
+            }
+        }
+        routing.resize(num_channels, None);
+
+        Ok(SpeakerLayout {
+            speakers,
+            rings,
+            routing,
+        })
+    }
+
+    /// Parse a layout file, one speaker per line, with the output
+    /// channel (starting at 1), the azimuth and the (optional) elevation
+    /// in degrees, like `1 30 0`. Lines starting with `#` are ignored.
+    pub fn parse(src: &str) -> Result<Self> {
+        let mut speakers = Vec::new();
+        for (num, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let parsed: Option<Vec<f32>> = fields.iter().map(|f| f.parse().ok()).collect();
+            match parsed.as_deref() {
+                Some(&[ch, azi, ref ele @ ..]) if ch >= 1.0 && ele.len() <= 1 => {
+                    speakers.push(Speaker {
+                        channel: ch as usize - 1,
+                        azimuth: azi.to_radians(),
+                        elevation: ele.first().copied().unwrap_or(0.0).to_radians(),
+                    })
+                }
+                _ => bail!("invalid speaker in line {}: {line}", num + 1),
+            }
+        }
+        SpeakerLayout::new(speakers)
+    }
+
+    /// A list of azimuths (with optional elevations) in degrees, one for
+    /// each channel, like `30,-30,110,-110` or `45:0,135:0,0:90`.
+    pub fn from_list(list: &str) -> Result<Self> {
+        let mut speakers = Vec::new();
+        for (channel, entry) in list.split(',').enumerate() {
+            let mut angles = entry.split(':').map(|a| a.trim().parse::<f32>());
+            match (angles.next(), angles.next(), angles.next()) {
+                (Some(Ok(azi)), ele, None) => speakers.push(Speaker {
+                    channel,
+                    azimuth: azi.to_radians(),
+                    elevation: match ele {
+                        Some(Ok(ele)) => ele.to_radians(),
+                        None => 0.0,
+                        Some(Err(_)) => bail!("invalid speaker {entry}"),
+                    },
+                }),
+                _ => bail!("invalid speaker {entry}"),
+            }
+        }
+        SpeakerLayout::new(speakers)
+    }
+
+    /// The `--speakers` option, either a layout file or a list.
+    pub fn from_option(opt: &str) -> Result<Self> {
+        let path = Path::new(opt);
+        if path.is_file() {
+            SpeakerLayout::parse(&std::fs::read_to_string(path)?)
+        } else {
+            SpeakerLayout::from_list(opt)
+        }
+    }
+
+    pub fn speakers(&self) -> &[Speaker] {
+        &self.speakers
+    }
+
+    pub fn num_speakers(&self) -> usize {
+        self.speakers.len()
+    }
+
+    /// the (smallest) output mode with enough channels
+    pub fn output_mode(&self) -> OutputMode {
+        match self.routing.len() {
+            4 => OutputMode::FourChannel,
+            8 => OutputMode::EightChannel,
+            _ => OutputMode::SixteenChannel,
+        }
+    }
+
+    /// The ruffbox position for a source at the given azimuth and
+    /// elevation (radians). The elevation only picks the closest ring,
+    /// there's no panning between rings.
+    pub fn position(&self, azimuth: f32, elevation: f32) -> f32 {
+        let ring = self
+            .rings
+            .iter()
+            .min_by(|a, b| {
+                (a.elevation - elevation)
+                    .abs()
+                    .total_cmp(&(b.elevation - elevation).abs())
+            })
+            .unwrap();
+        ring.offset as f32 + ring.position(azimuth)
+    }
+
+    /// The ruffbox position for a speaker position (the speaker index,
+    /// with the fractional part panning to the next speaker in the ring).
+    pub fn ring_position(&self, pos: f32) -> f32 {
+        let pos = pos.rem_euclid(self.speakers.len() as f32);
+        let mut idx = pos.floor() as usize;
+        for ring in self.rings.iter() {
+            if idx < ring.azimuths.len() {
+                return ring.offset as f32 + idx as f32 + pos.fract();
+            }
+            idx -= ring.azimuths.len();
+        }
+        0.0
+    }
+
+    /// The positions to spread a number of generators evenly across all
+    /// speakers, as used by `spread` and `xspread`.
+    pub fn spread_positions(num_speakers: usize, num_gens: usize) -> Vec<f32> {
+        (0..num_gens)
+            .map(|i| i as f32 * num_speakers as f32 / num_gens as f32)
+            .collect()
+    }
+
+    /// Replace `azi` and `ele` (if they're plain values) with the
+    /// position on the layout and translate `pos` to the ruffbox channels.
+    pub fn pan(&self, params: &mut HashMap<ParameterAddress, SynthParameterValue>) {
+        let azi_addr: ParameterAddress = SynthParameterLabel::AmbisonicAzimuth.into();
+        let ele_addr: ParameterAddress = SynthParameterLabel::AmbisonicElevation.into();
+        let scalar = |addr: &ParameterAddress| match params.get(addr) {
+            Some(SynthParameterValue::ScalarF32(v)) => Some(*v),
+            _ => None,
+        };
+        let azi = scalar(&azi_addr);
+        let ele = scalar(&ele_addr);
+
+        if azi.is_some() || ele.is_some() {
+            params.remove(&azi_addr);
+            params.remove(&ele_addr);
+            params.insert(
+                SynthParameterLabel::ChannelPosition.into(),
+                SynthParameterValue::ScalarF32(
+                    self.position(azi.unwrap_or(0.0), ele.unwrap_or(0.0)),
+                ),
+            );
+        } else if let Some(SynthParameterValue::ScalarF32(pos)) =
+            params.get_mut(&SynthParameterLabel::ChannelPosition.into())
+        {
+            *pos = self.ring_position(*pos);
+        }
+    }
+
+    /// Route the ruffbox channels to the speaker channels.
+    pub fn route<const BUFSIZE: usize, const NCHAN: usize>(
+        &self,
+        block: &[[f32; BUFSIZE]; NCHAN],
+    ) -> [[f32; BUFSIZE]; NCHAN] {
+        let mut out = [[0.0; BUFSIZE]; NCHAN];
+        for (ch, target) in self.routing.iter().enumerate().take(NCHAN) {
+            if let Some(target) = target.filter(|t| *t < NCHAN) {
+                for (o, i) in out[target].iter_mut().zip(block[ch].iter()) {
+                    *o += i;
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speaker_layout() {
+        // 5.0 without center, plus two height speakers
+        let layout = SpeakerLayout::parse(
+            "# front
+             1 30
+             2 -30 0
+             3 110
+             4 -110
+             5 45 40
+             6 -45 40",
+        )
+        .unwrap();
+
+        assert_eq!(layout.output_mode(), OutputMode::EightChannel);
+        // sorted by azimuth, the height ring starts at ruffbox channel 5
+        assert_eq!(
+            layout.routing,
+            vec![
+                Some(0),
+                Some(2),
+                Some(3),
+                Some(1),
+                Some(0),
+                Some(4),
+                Some(5),
+                Some(4)
+            ]
+        );
+
+        // right on a speaker
+        assert!((layout.position(30f32.to_radians(), 0.0) - 0.0).abs() < 0.001);
+        assert!((layout.position(110f32.to_radians(), 0.0) - 1.0).abs() < 0.001);
+        // in the middle of the front pair, panned equally
+        assert!((layout.position(0.0, 0.0) - 3.5).abs() < 0.001);
+        // closer to the height ring
+        assert!((layout.position(45f32.to_radians(), 0.5) - 5.0).abs() < 0.001);
+
+        assert_eq!(layout.ring_position(4.5), 5.5);
+        assert_eq!(layout.ring_position(7.0), 1.0);
+
+        assert!(SpeakerLayout::from_list("0,90").is_err());
+        assert!(SpeakerLayout::from_list("0,90,180,270").is_ok());
+    }
+}
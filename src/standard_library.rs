@@ -8,6 +8,10 @@ pub fn define_standard_library() -> FunctionMap {
     let standard_library = FunctionMap::new();
     // session
     standard_library.std_lib.insert("sx".to_string(), eval::session::sync_context::sync_context);
+    standard_library.std_lib.insert("quantize".to_string(), eval::session::sync_context::quantize);
+    standard_library.std_lib.insert("running".to_string(), eval::session::inspect::running);
+    standard_library.std_lib.insert("contexts".to_string(), eval::session::inspect::contexts);
+    standard_library.std_lib.insert("inspect".to_string(), eval::session::inspect::inspect);
 
     // constructors
     standard_library.std_lib.insert("nuc".to_string(), eval::constructors::nuc::nuc);
@@ -17,6 +21,7 @@ pub fn define_standard_library() -> FunctionMap {
     standard_library.std_lib.insert("linear".to_string(), eval::constructors::linear::linear);
     standard_library.std_lib.insert("loop".to_string(), eval::constructors::r#loop::a_loop);
     standard_library.std_lib.insert("chop".to_string(), eval::constructors::chop::chop);
+    standard_library.std_lib.insert("slice".to_string(), eval::constructors::slice::slice);
     standard_library.std_lib.insert("infer".to_string(), eval::constructors::infer::infer);
     standard_library.std_lib.insert("rule".to_string(), eval::constructors::infer::rule);
     standard_library.std_lib.insert("learn".to_string(), eval::constructors::learn::learn);
@@ -48,7 +53,12 @@ pub fn define_standard_library() -> FunctionMap {
     standard_library.std_lib.insert("global-resources".to_string(), eval::commands::globres);
     standard_library.std_lib.insert("reverb".to_string(), eval::commands::reverb);
     standard_library.std_lib.insert("delay".to_string(), eval::commands::delay);
+    standard_library.std_lib.insert("ramp".to_string(), eval::commands::ramp);
+    standard_library.std_lib.insert("ramp-bpm".to_string(), eval::commands::ramp_bpm);
+    standard_library.std_lib.insert("ramp-stop".to_string(), eval::commands::ramp_stop);
     standard_library.std_lib.insert("export-dot".to_string(), eval::commands::export_dot);
+    standard_library.std_lib.insert("export-midi".to_string(), eval::commands::export_midi);
+    standard_library.std_lib.insert("export-score".to_string(), eval::commands::export_score);
     standard_library.std_lib.insert("once".to_string(), eval::commands::once);
     standard_library.std_lib.insert("step-part".to_string(), eval::commands::step_part);
     standard_library.std_lib.insert("clear".to_string(), eval::commands::clear);
@@ -58,11 +68,26 @@ pub fn define_standard_library() -> FunctionMap {
     standard_library.std_lib.insert("import-sample-set".to_string(), eval::commands::import_sample_set);
     standard_library.std_lib.insert("print".to_string(), eval::commands::print);
     standard_library.std_lib.insert("load-file".to_string(), eval::commands::load_file);
+    standard_library.std_lib.insert("import".to_string(), eval::commands::import);
+    standard_library.std_lib.insert("peer-sync".to_string(), eval::commands::peer_sync);
+    standard_library.std_lib.insert("peer-sync-stop".to_string(), eval::commands::peer_sync_stop);
+    standard_library.std_lib.insert("eval-server".to_string(), eval::commands::eval_server);
+    standard_library.std_lib.insert("eval-server-stop".to_string(), eval::commands::eval_server_stop);
+    standard_library.std_lib.insert("seed".to_string(), eval::commands::seed);
+    standard_library.std_lib.insert("save-session".to_string(), eval::commands::save_session);
+    standard_library.std_lib.insert("load-session".to_string(), eval::commands::load_session);
+    standard_library.std_lib.insert("save-generator".to_string(), eval::commands::save_generator);
+    standard_library.std_lib.insert("load-generator".to_string(), eval::commands::load_generator);
 
     
     // progn and other constructs
     standard_library.std_lib.insert("progn".to_string(), eval::progn::progn);
     standard_library.std_lib.insert("match".to_string(), eval::megra_match::megra_match);
+
+    // higher-order functions
+    standard_library.std_lib.insert("filter".to_string(), eval::functional::filter);
+    standard_library.std_lib.insert("reduce".to_string(), eval::functional::reduce);
+    standard_library.std_lib.insert("call".to_string(), eval::functional::call);
     
     // control event
     standard_library.std_lib.insert("ctrl".to_string(), eval::events::control::control);
@@ -277,6 +302,9 @@ pub fn define_standard_library() -> FunctionMap {
     standard_library.std_lib.insert("start-mul".to_string(), eval::events::parameters::parameter);
     standard_library.std_lib.insert("start-sub".to_string(), eval::events::parameters::parameter);
     standard_library.std_lib.insert("start-div".to_string(), eval::events::parameters::parameter);
+    standard_library.std_lib.insert("onset".to_string(), eval::events::parameters::parameter);
+    standard_library.std_lib.insert("onset-add".to_string(), eval::events::parameters::parameter);
+    standard_library.std_lib.insert("onset-sub".to_string(), eval::events::parameters::parameter);
 
     standard_library.std_lib.insert("rate".to_string(), eval::events::parameters::parameter);
     standard_library.std_lib.insert("rate-add".to_string(), eval::events::parameters::parameter);
@@ -437,6 +465,10 @@ pub fn define_standard_library() -> FunctionMap {
     // midi
     standard_library.std_lib.insert("list-midi-ports".to_string(), eval::midi::eval_list_midi_ports);
     standard_library.std_lib.insert("open-midi-port".to_string(), eval::midi::open_midi_port);
+    standard_library.std_lib.insert("open-midi-out-port".to_string(), eval::midi::open_midi_out_port);
+    standard_library.std_lib.insert("midi-clock-start".to_string(), eval::midi::midi_clock_start);
+    standard_library.std_lib.insert("midi-clock-stop".to_string(), eval::midi::midi_clock_stop);
+    standard_library.std_lib.insert("midi-clock-follow".to_string(), eval::midi::midi_clock_follow);
         
     // types for osc and other stuff
     standard_library.std_lib.insert("f64".to_string(), eval::types::double);
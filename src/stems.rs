@@ -0,0 +1,206 @@
+//! Stem buses, to record generators or tagged events to separate files.
+//!
+//! Ruffbox mixes everything into a single output, so each stem bus is a
+//! ruffbox instance of its own, running next to the main one. The outputs
+//! are summed in the audio callback, so nothing changes soundwise, but each
+//! bus can be recorded on its own. The number of buses is fixed on startup
+//! (`--stem-buses`), as each one keeps a copy of all loaded samples (in the
+//! same buffers as the main instance) and runs its own reverb and delay.
+
+use parking_lot::{Mutex, MutexGuard, RwLock};
+use ruffbox_synth::building_blocks::{SynthParameterLabel, SynthParameterValue};
+use ruffbox_synth::ruffbox::{RuffboxControls, RuffboxPlayhead};
+use std::collections::BTreeSet;
+use std::sync;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::event::StaticEvent;
+use crate::real_time_streaming::Throw;
+use crate::speaker_layout::SpeakerLayout;
+
+pub struct StemBus<const BUFSIZE: usize, const NCHAN: usize> {
+    pub ruffbox: sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+    // the generator id or event tag that is routed here
+    pub tag: RwLock<Option<String>>,
+    // set if the bus lost track of the sample buffers,
+    // nothing is routed here anymore
+    pub disabled: AtomicBool,
+}
+
+impl<const BUFSIZE: usize, const NCHAN: usize> StemBus<BUFSIZE, NCHAN> {
+    fn is_enabled(&self) -> bool {
+        !self.disabled.load(Ordering::SeqCst)
+    }
+
+    // the tag routed here, if the bus is enabled
+    fn routes(&self, f: impl Fn(&String) -> bool) -> bool {
+        self.is_enabled() && self.tag.read().as_ref().map(f).unwrap_or(false)
+    }
+}
+
+pub struct StemBuses<const BUFSIZE: usize, const NCHAN: usize> {
+    pub buses: Vec<StemBus<BUFSIZE, NCHAN>>,
+    // samples need to end up in the same buffer in every instance
+    loading: Mutex<()>,
+}
+
+impl<const BUFSIZE: usize, const NCHAN: usize> StemBuses<BUFSIZE, NCHAN> {
+    pub fn new(ruffboxes: Vec<RuffboxControls<BUFSIZE, NCHAN>>) -> Self {
+        StemBuses {
+            buses: ruffboxes
+                .into_iter()
+                .map(|r| StemBus {
+                    ruffbox: sync::Arc::new(r),
+                    tag: RwLock::new(None),
+                    disabled: AtomicBool::new(false),
+                })
+                .collect(),
+            loading: Mutex::new(()),
+        }
+    }
+
+    /// hold this while loading a sample into the main instance and the buses
+    pub fn loading(&self) -> MutexGuard<'_, ()> {
+        self.loading.lock()
+    }
+
+    /// Route the generators or events with the given tags to the buses
+    /// (in order), the tags that don't get a bus are returned.
+    pub fn assign(&self, tags: &[String]) -> Vec<String> {
+        let mut tags = tags.iter();
+        for bus in self.buses.iter().filter(|bus| bus.is_enabled()) {
+            let Some(tag) = tags.next() else {
+                break;
+            };
+            *bus.tag.write() = Some(tag.clone());
+        }
+        tags.cloned().collect()
+    }
+
+    /// Take a bus out of the routing, i.e. if a sample ended up in a
+    /// different buffer than in the main instance, so it would play
+    /// the wrong samples.
+    pub fn disable(&self, idx: usize) {
+        if let Some(bus) = self.buses.get(idx) {
+            bus.disabled.store(true, Ordering::SeqCst);
+            *bus.tag.write() = None;
+        }
+    }
+
+    /// everything is played on the main instance again
+    pub fn release(&self) {
+        for bus in self.buses.iter() {
+            *bus.tag.write() = None;
+        }
+    }
+
+    /// the bus a generator is routed to
+    pub fn bus_for_generator(&self, id_tags: &BTreeSet<String>) -> Option<usize> {
+        self.buses
+            .iter()
+            .position(|bus| bus.routes(|t| id_tags.contains(t)))
+    }
+
+    /// The ruffbox to play an event on: the bus of its generator, the bus
+    /// of one of its tags, or the main instance.
+    pub fn select<'a>(
+        &'a self,
+        main: &'a sync::Arc<RuffboxControls<BUFSIZE, NCHAN>>,
+        generator_bus: Option<usize>,
+        event: &StaticEvent,
+    ) -> &'a sync::Arc<RuffboxControls<BUFSIZE, NCHAN>> {
+        // live and freeze buffers only exist in the main instance
+        if event.name == "livesampler" || event.name == "frozensampler" {
+            return main;
+        }
+        let bus = generator_bus.or_else(|| {
+            self.buses
+                .iter()
+                .position(|bus| bus.routes(|t| event.tags.contains(t)))
+        });
+        match bus.map(|idx| &self.buses[idx]) {
+            Some(bus) if bus.is_enabled() => &bus.ruffbox,
+            _ => main,
+        }
+    }
+
+    /// keep the master effects of the buses the same as the main ones
+    pub fn set_master_parameter(&self, label: SynthParameterLabel, value: SynthParameterValue) {
+        for bus in self.buses.iter() {
+            bus.ruffbox.set_master_parameter(label, value.clone());
+        }
+    }
+}
+
+/// the audio thread side of the stem buses
+pub struct StemOutput<const BUFSIZE: usize, const NCHAN: usize> {
+    pub playheads: Vec<RuffboxPlayhead<BUFSIZE, NCHAN>>,
+    // the mix, the main instance, then the buses
+    pub throws: Vec<Throw<BUFSIZE, NCHAN>>,
+    pub is_recording: sync::Arc<AtomicBool>,
+}
+
+impl<const BUFSIZE: usize, const NCHAN: usize> StemOutput<BUFSIZE, NCHAN> {
+    /// Process the buses and add them to the output of the main instance,
+    /// recording each of them and the mix (routed to the speakers) if needed.
+    /// There's only one flag for all of them, so they're aligned to the sample.
+    pub fn mix(
+        &mut self,
+        main: &[[f32; BUFSIZE]; NCHAN],
+        speaker_layout: Option<&SpeakerLayout>,
+    ) -> [[f32; BUFSIZE]; NCHAN] {
+        let recording = self.is_recording.load(Ordering::SeqCst);
+        let record = |throw: Option<&Throw<BUFSIZE, NCHAN>>, block: &[[f32; BUFSIZE]; NCHAN]| {
+            if let Some(throw) = throw.filter(|_| recording) {
+                match speaker_layout {
+                    Some(layout) => throw.write_samples(&layout.route(block), BUFSIZE),
+                    None => throw.write_samples(block, BUFSIZE),
+                }
+            }
+        };
+
+        let mut mix = *main;
+        record(self.throws.get(1), main);
+
+        for (idx, playhead) in self.playheads.iter_mut().enumerate() {
+            let out = playhead.process(0.0, true);
+            for (mix_ch, out_ch) in mix.iter_mut().zip(out.iter()) {
+                for (m, o) in mix_ch.iter_mut().zip(out_ch.iter()) {
+                    *m += o;
+                }
+            }
+            record(self.throws.get(idx + 2), &out);
+        }
+
+        record(self.throws.first(), &mix);
+        mix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ruffbox_synth::ruffbox::{init_ruffbox, ReverbMode};
+
+    #[test]
+    fn test_disabled_bus() {
+        let buses = StemBuses::new(
+            (0..2)
+                .map(|_| {
+                    init_ruffbox::<128, 2>(1, 1.0, &ReverbMode::FreeVerb, 44100.0, 10, 1, false).0
+                })
+                .collect(),
+        );
+
+        buses.disable(0);
+
+        // the tags skip the disabled bus
+        let rest = buses.assign(&["drums".to_string(), "bass".to_string()]);
+        assert_eq!(rest, vec!["bass".to_string()]);
+        assert_eq!(
+            buses.bus_for_generator(&BTreeSet::from(["drums".to_string()])),
+            Some(1)
+        );
+        assert!(buses.buses[0].tag.read().is_none());
+    }
+}